@@ -18,20 +18,22 @@ mod processor;
 pub mod tree_serializer;
 
 use std::{
-    collections::BTreeSet,
+    collections::{BTreeSet, HashMap},
     path::PathBuf,
     sync::{
         Arc,
         atomic::{AtomicBool, Ordering},
     },
+    time::{Duration, Instant},
 };
 
 use anyhow::{Result, anyhow, bail};
-use chrono::Local;
-use tree_serializer::finalize_if_complete;
+use chrono::{DateTime, Local};
+use parking_lot::RwLock;
+use tree_serializer::{PendingTree, finalize_if_complete};
 
 use crate::{
-    global::ID,
+    global::{FileType, ID, SaveID, defaults::SHORT_SNAPSHOT_ID_LEN},
     repository::{
         RepositoryBackend,
         snapshot::Snapshot,
@@ -43,6 +45,15 @@ use crate::{
     ui::snapshot_progress::SnapshotProgressReporter,
 };
 
+/// Tag added to the checkpoint snapshots saved while a snapshot is in progress
+pub const CHECKPOINT_TAG: &str = "checkpoint";
+
+/// Returns true if a snapshot is a checkpoint saved while taking a snapshot of `paths` in
+/// `hostname`. The next snapshot of the same paths in the same host supersedes it.
+pub fn is_checkpoint_of(snapshot: &Snapshot, paths: &[PathBuf], hostname: &str) -> bool {
+    snapshot.tags.contains(CHECKPOINT_TAG) && snapshot.paths == paths && snapshot.has_host(hostname)
+}
+
 pub struct SnapshotOptions {
     pub absolute_source_paths: Vec<PathBuf>,
     pub snapshot_root_path: PathBuf,
@@ -50,6 +61,11 @@ pub struct SnapshotOptions {
     pub parent_snapshot: Option<(ID, Snapshot)>,
     pub tags: BTreeSet<String>,
     pub description: Option<String>,
+    /// Name of the host recorded in the snapshot
+    pub hostname: String,
+    /// Save a checkpoint snapshot with the data processed so far every time this interval
+    /// elapses. No checkpoints are saved if None.
+    pub checkpoint_interval: Option<Duration>,
 }
 
 pub struct Archiver {
@@ -58,6 +74,7 @@ pub struct Archiver {
     read_concurrency: usize,
     write_concurrency: usize,
     progress_reporter: Arc<SnapshotProgressReporter>,
+    start_time: DateTime<Local>,
 }
 
 impl Archiver {
@@ -73,6 +90,7 @@ impl Archiver {
             read_concurrency,
             write_concurrency,
             progress_reporter,
+            start_time: Local::now(),
         }
     }
 
@@ -82,7 +100,11 @@ impl Archiver {
     /// the workflow.Dedicated threads handle generating the difference stream, processing
     /// individual file and directory changes, and serializing the resulting tree structure
     /// bottom-up to create the final snapshot.
-    pub fn snapshot(self) -> Result<Snapshot> {
+    ///
+    /// Returns the new snapshot, which is not saved yet, and the ID of the last checkpoint
+    /// snapshot saved in the process, if any. The caller should remove the checkpoint once the
+    /// new snapshot is saved.
+    pub fn snapshot(self) -> Result<(Snapshot, Option<ID>)> {
         let arch = Arc::from(self);
 
         // Extract parent snapshot tree id
@@ -122,6 +144,11 @@ impl Archiver {
 
         let error_flag = Arc::new(AtomicBool::new(false));
 
+        // Processors hold this lock while they save an item. A checkpoint takes it exclusively,
+        // so no blob referenced by the checkpoint is still being saved when the packers are
+        // flushed.
+        let checkpoint_gate = Arc::new(RwLock::new(()));
+
         // Diff thread. This thread iterates the NodeDiffStreamer and passes the
         // items to the item processor thread.
         let error_flag_clone = error_flag.clone();
@@ -158,6 +185,7 @@ impl Archiver {
         let error_flag_clone = error_flag.clone();
         let processor_progress_reporter_clone = arch.progress_reporter.clone();
         let snapshot_root_path_clone = arch.snapshot_options.snapshot_root_path.clone();
+        let checkpoint_gate_clone = checkpoint_gate.clone();
 
         let pool = rayon::ThreadPoolBuilder::new()
             .num_threads(arch.read_concurrency)
@@ -176,6 +204,7 @@ impl Archiver {
                     let inner_error_flag_clone = error_flag_clone.clone();
                     let inner_progress_reporter_clone = processor_progress_reporter_clone.clone();
                     let inner_snapshot_root_path_clone = snapshot_root_path_clone.clone();
+                    let inner_checkpoint_gate_clone = checkpoint_gate_clone.clone();
 
                     s.spawn(move |_| {
                         let stripped_path = path.strip_prefix(&inner_snapshot_root_path_clone).unwrap().to_path_buf();
@@ -184,11 +213,15 @@ impl Archiver {
                         );
 
 
+                        // The gate is released before sending the item. The serializer may be
+                        // waiting for it to save a checkpoint.
+                        let checkpoint_guard = inner_checkpoint_gate_clone.read();
                         let processed_item_result = processor::process_item(
                             (path, prev, next, diff),
                             inner_repo_clone,
                             inner_progress_reporter_clone,
                         );
+                        drop(checkpoint_guard);
 
                         match processed_item_result {
                             Ok(Some(processed_item)) => {
@@ -225,8 +258,10 @@ impl Archiver {
         let serializer_progress_reporter_clone = arch.progress_reporter.clone();
         let serializer_snapshot_root_path_clone = arch.snapshot_options.snapshot_root_path.clone();
         let arch_clone = arch.clone();
-        let tree_serializer_thread = std::thread::spawn(move || -> Option<ID> {
+        let tree_serializer_thread = std::thread::spawn(move || -> (Option<ID>, Option<ID>) {
             let mut final_root_tree_id: Option<ID> = None;
+            let mut checkpoint_id: Option<ID> = None;
+            let mut last_checkpoint = Instant::now();
             let mut pending_trees = tree_serializer::init_pending_trees(
                 &serializer_snapshot_root_path_clone,
                 &arch_clone.snapshot_options.absolute_source_paths,
@@ -263,32 +298,63 @@ impl Archiver {
                         break;
                     }
                 }
+
+                if let Some(interval) = arch_clone.snapshot_options.checkpoint_interval
+                    && last_checkpoint.elapsed() >= interval
+                    && final_root_tree_id.is_none()
+                {
+                    let checkpoint_guard = checkpoint_gate.write();
+                    let checkpoint_result = arch_clone.save_checkpoint(&pending_trees);
+                    drop(checkpoint_guard);
+
+                    match checkpoint_result {
+                        Ok(Some(new_checkpoint_id)) => {
+                            // Only the latest checkpoint is kept
+                            if let Some(old_checkpoint_id) =
+                                checkpoint_id.replace(new_checkpoint_id)
+                                && let Err(e) = repo_clone.remove_snapshot(&old_checkpoint_id)
+                            {
+                                ui::cli::warning!("Could not remove checkpoint: {}", e);
+                            }
+                        }
+                        Ok(None) => {}
+                        Err(e) => {
+                            error_flag_clone.store(true, Ordering::Release);
+                            ui::cli::error!(
+                                "Archiver serializer thread errored saving checkpoint: {:?}",
+                                e.to_string()
+                            );
+                            break;
+                        }
+                    }
+                    last_checkpoint = Instant::now();
+                }
             }
 
             // After the loop, if no error occurred, finalize the root tree.
-            if !error_flag_clone.load(Ordering::Acquire) {
-                if let Err(e) = finalize_if_complete(
+            if !error_flag_clone.load(Ordering::Acquire)
+                && let Err(e) = finalize_if_complete(
                     serializer_snapshot_root_path_clone.clone(),
                     repo_clone.as_ref(),
                     &mut pending_trees,
                     &mut final_root_tree_id,
                     &serializer_snapshot_root_path_clone,
-                ) {
-                    error_flag_clone.store(true, Ordering::Release);
-                    ui::cli::error!(
-                        "Archiver serializer thread errored finalizing root tree: {:?}",
-                        e.to_string()
-                    );
-                }
+                )
+            {
+                error_flag_clone.store(true, Ordering::Release);
+                ui::cli::error!(
+                    "Archiver serializer thread errored finalizing root tree: {:?}",
+                    e.to_string()
+                );
             }
 
-            final_root_tree_id
+            (final_root_tree_id, checkpoint_id)
         });
 
         // Join threads
         let _ = diff_thread.join();
         let _ = processor_thread.join();
-        let root_tree_id = tree_serializer_thread.join().unwrap();
+        let (root_tree_id, checkpoint_id) = tree_serializer_thread.join().unwrap();
 
         // Unwrap the archiver Arc to avoid cloning the contents.
         // Archiver cannot implement Debug, so unwrap is not available.
@@ -308,19 +374,7 @@ impl Archiver {
         archiver.repo.finalize_pack_saver();
 
         match root_tree_id {
-            Some(tree_id) => Ok(Snapshot {
-                timestamp: Local::now(),
-                parent: archiver
-                    .snapshot_options
-                    .parent_snapshot
-                    .map(|(id, _)| id.clone()),
-                tree: tree_id,
-                root: archiver.snapshot_options.snapshot_root_path,
-                paths: archiver.snapshot_options.absolute_source_paths,
-                tags: archiver.snapshot_options.tags,
-                description: archiver.snapshot_options.description,
-                summary: archiver.progress_reporter.get_summary(),
-            }),
+            Some(tree_id) => Ok((archiver.new_snapshot(tree_id, false), checkpoint_id)),
             None => {
                 if error_flag.load(Ordering::Acquire) {
                     Err(anyhow!("Snapshot creation failed due to a previous error."))
@@ -332,6 +386,89 @@ impl Archiver {
             }
         }
     }
+
+    /// Creates a snapshot of the source paths with a root tree
+    fn new_snapshot(&self, tree: ID, checkpoint: bool) -> Snapshot {
+        let mut tags = self.snapshot_options.tags.clone();
+        if checkpoint {
+            tags.insert(CHECKPOINT_TAG.to_string());
+        }
+
+        let timestamp = Local::now();
+        let mut summary = self.progress_reporter.get_summary();
+        summary.start_time = Some(self.start_time);
+        summary.duration_secs = Some(
+            (timestamp - self.start_time)
+                .to_std()
+                .unwrap_or_default()
+                .as_secs_f64(),
+        );
+
+        Snapshot {
+            timestamp,
+            // A checkpoint of the same paths and host used as parent is superseded by this
+            // snapshot, so it inherits the checkpoint's parent
+            parent: self
+                .snapshot_options
+                .parent_snapshot
+                .as_ref()
+                .and_then(|(id, parent)| {
+                    match is_checkpoint_of(
+                        parent,
+                        &self.snapshot_options.absolute_source_paths,
+                        &self.snapshot_options.hostname,
+                    ) {
+                        true => parent.parent.clone(),
+                        false => Some(id.clone()),
+                    }
+                }),
+            tree,
+            root: self.snapshot_options.snapshot_root_path.clone(),
+            paths: self.snapshot_options.absolute_source_paths.clone(),
+            tags,
+            description: self.snapshot_options.description.clone(),
+            hostname: Some(self.snapshot_options.hostname.clone()),
+            username: Some(whoami::username()),
+            program_version: Some(env!("CARGO_PKG_VERSION").to_string()),
+            summary,
+        }
+    }
+
+    /// Saves a checkpoint snapshot with the trees serialized so far. The packers are flushed
+    /// so that every blob referenced by the checkpoint is stored and indexed.
+    /// Returns the ID of the checkpoint, if one was saved.
+    fn save_checkpoint(&self, pending_trees: &HashMap<PathBuf, PendingTree>) -> Result<Option<ID>> {
+        let (root_tree_id, (raw_tree_size, encoded_tree_size)) =
+            tree_serializer::serialize_partial(
+                pending_trees,
+                self.repo.as_ref(),
+                &self.snapshot_options.snapshot_root_path,
+            )?;
+        self.progress_reporter
+            .written_meta_bytes(raw_tree_size, encoded_tree_size);
+        let Some(root_tree_id) = root_tree_id else {
+            return Ok(None);
+        };
+
+        let (flushed_raw_meta_size, flushed_encoded_meta_size) = self.repo.flush()?;
+        self.progress_reporter
+            .written_meta_bytes(flushed_raw_meta_size, flushed_encoded_meta_size);
+
+        let checkpoint = self.new_snapshot(root_tree_id, true);
+        let (checkpoint_id, raw_size, encoded_size) = self.repo.save_file(
+            FileType::Snapshot,
+            serde_json::to_string(&checkpoint)?.as_bytes(),
+            SaveID::CalculateID,
+        )?;
+        self.progress_reporter
+            .written_meta_bytes(raw_size, encoded_size);
+
+        ui::cli::verbose_1!(
+            "Saved checkpoint {}",
+            checkpoint_id.to_short_hex(SHORT_SNAPSHOT_ID_LEN)
+        );
+        Ok(Some(checkpoint_id))
+    }
 }
 
 #[cfg(test)]
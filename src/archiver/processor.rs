@@ -28,6 +28,7 @@ use crate::{
     global::{self, BlobType, ID},
     repository::{
         RepositoryBackend,
+        manifest::ChunkerParams,
         streamers::{NodeDiff, StreamNode},
         tree::{Node, NodeType},
     },
@@ -141,8 +142,10 @@ pub(crate) fn save_file(
     node: &Node,
     progress_reporter: Arc<SnapshotProgressReporter>,
 ) -> Result<Vec<ID>> {
+    let chunker_params = repo.chunker_params();
+
     // Do not chunk if the file is smaller than the minimum chunk size
-    if node.metadata.size < global::defaults::MIN_CHUNK_SIZE {
+    if node.metadata.size < chunker_params.min_size as u64 {
         let data = std::fs::read(src_path)?;
         let (id, (raw_data_size, encoded_data_size), (raw_meta_size, encoded_meta_size)) =
             repo.save_blob(BlobType::Data, data, global::SaveID::CalculateID)?;
@@ -152,7 +155,7 @@ pub(crate) fn save_file(
 
         Ok(vec![id])
     } else {
-        chunk_and_save_blobs(repo, src_path, progress_reporter)
+        chunk_and_save_blobs(repo, src_path, &chunker_params, progress_reporter)
     }
 }
 
@@ -160,6 +163,7 @@ pub(crate) fn save_file(
 fn chunk_and_save_blobs(
     repo: Arc<dyn RepositoryBackend>,
     src_path: &Path,
+    chunker_params: &ChunkerParams,
     progress_reporter: Arc<SnapshotProgressReporter>,
 ) -> Result<Vec<ID>> {
     let source = File::open(src_path)
@@ -168,20 +172,27 @@ fn chunk_and_save_blobs(
 
     let mut chunk_ids = Vec::new();
 
-    // The chunker parameters must remain stable across versions, otherwise
-    // same contents will no longer produce same chunks and IDs.
+    // The chunker parameters are read from the repository manifest, so the same
+    // contents always produce the same chunks and IDs in a repository.
+    let normalization = match chunker_params.normalization {
+        0 => Normalization::Level0,
+        1 => Normalization::Level1,
+        2 => Normalization::Level2,
+        3 => Normalization::Level3,
+        level => bail!("Invalid chunker normalization level {}", level),
+    };
     let chunker = StreamCDC::with_level(
         reader,
-        global::defaults::MIN_CHUNK_SIZE as u32,
-        global::defaults::AVG_CHUNK_SIZE as u32,
-        global::defaults::MAX_CHUNK_SIZE as u32,
-        Normalization::Level1,
+        chunker_params.min_size,
+        chunker_params.avg_size,
+        chunker_params.max_size,
+        normalization,
     );
 
     for result in chunker {
         let chunk = result.with_context(|| "Failed to chunk file")?;
 
-        let id: ID = ID::from_content(&chunk.data);
+        let id: ID = repo.blob_id(&chunk.data);
         chunk_ids.push(id.clone());
 
         let repo_clone = repo.clone();
@@ -222,3 +222,183 @@ fn insert_finalized_node(
         });
     parent_pending_tree.children.insert(node.name.clone(), node);
 }
+
+/// Serializes the trees received so far without consuming them. This is used to save
+/// checkpoint snapshots while the snapshot is in progress.
+///
+/// Every pending directory is saved with the children it has received so far, plus the
+/// partial trees of its pending subdirectories. Directories whose own node has not been
+/// processed yet are left out. Returns the root tree ID and the written sizes.
+pub(crate) fn serialize_partial(
+    pending_trees: &HashMap<PathBuf, PendingTree>,
+    repo: &dyn RepositoryBackend,
+    snapshot_root_path: &Path,
+) -> Result<(Option<ID>, (u64, u64))> {
+    // Deepest directories first, so subdirectories are serialized before their parents
+    let mut paths: Vec<&PathBuf> = pending_trees.keys().collect();
+    paths.sort_by_key(|path| std::cmp::Reverse(path.components().count()));
+
+    let mut partial_children: HashMap<PathBuf, Vec<Node>> = HashMap::new();
+    let mut root_tree_id = None;
+    let mut raw_size = 0;
+    let mut encoded_size = 0;
+
+    for path in paths {
+        let pending_tree = &pending_trees[path];
+        if path != snapshot_root_path && pending_tree.node.is_none() {
+            continue;
+        }
+
+        let mut nodes: Vec<Node> = pending_tree.children.values().cloned().collect();
+        nodes.extend(partial_children.remove(path).unwrap_or_default());
+        let (tree_id, (raw_tree_size, encoded_tree_size)) = Tree { nodes }.save_to_repo(repo)?;
+        raw_size += raw_tree_size;
+        encoded_size += encoded_tree_size;
+
+        match &pending_tree.node {
+            Some(node) if path != snapshot_root_path => {
+                let mut node = node.clone();
+                node.tree = Some(tree_id);
+                let parent_path = utils::extract_parent(path).with_context(|| {
+                    format!("Could not extract parent path for {}", path.display())
+                })?;
+                partial_children.entry(parent_path).or_default().push(node);
+            }
+            _ => root_tree_id = Some(tree_id),
+        }
+    }
+
+    Ok((root_tree_id, (raw_size, encoded_size)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tempfile::tempdir;
+
+    use crate::{
+        backend::{StorageBackend, localfs::LocalFS},
+        repository::{self, try_open},
+    };
+
+    use super::*;
+
+    fn node(name: &str, node_type: NodeType) -> Node {
+        Node {
+            name: name.to_string(),
+            node_type,
+            metadata: Default::default(),
+            symlink_info: None,
+            blobs: None,
+            tree: None,
+        }
+    }
+
+    fn pending_tree(
+        node: Option<Node>,
+        num_expected_children: ExpectedChildren,
+        children: Vec<Node>,
+    ) -> PendingTree {
+        PendingTree {
+            num_expected_children,
+            node,
+            children: children
+                .into_iter()
+                .map(|child| (child.name.clone(), child))
+                .collect(),
+        }
+    }
+
+    fn sorted_names(tree: &Tree) -> Vec<&str> {
+        let mut names: Vec<&str> = tree.nodes.iter().map(|node| node.name.as_str()).collect();
+        names.sort();
+        names
+    }
+
+    /// Test that pending directories are saved with the children received so far and that
+    /// directories whose own node is missing are left out
+    #[test]
+    fn test_serialize_partial() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let password = Some(String::from("mapachito"));
+        let backend: Arc<dyn StorageBackend> = Arc::new(LocalFS::new(temp_dir.path().join("repo")));
+        repository::init(password.clone(), None, backend.clone())?;
+        let (repo, _) = try_open(password, None, backend)?;
+        repo.init_pack_saver(1);
+
+        let root = PathBuf::from("/root");
+        let mut pending_trees = init_pending_trees(&root, &[root.join("a"), root.join("dir")]);
+        pending_trees
+            .get_mut(&root)
+            .unwrap()
+            .children
+            .insert(String::from("a"), node("a", NodeType::File));
+        // A directory that received some of its children
+        pending_trees.insert(
+            root.join("dir"),
+            pending_tree(
+                Some(node("dir", NodeType::Directory)),
+                ExpectedChildren::Known(3),
+                vec![node("b", NodeType::File)],
+            ),
+        );
+        // A subdirectory that received a child before its own node
+        pending_trees.insert(
+            root.join("dir").join("sub"),
+            pending_tree(
+                None,
+                ExpectedChildren::Unknown,
+                vec![node("c", NodeType::File)],
+            ),
+        );
+        // A complete subdirectory that is waiting for nothing else
+        pending_trees.insert(
+            root.join("dir").join("empty"),
+            pending_tree(
+                Some(node("empty", NodeType::Directory)),
+                ExpectedChildren::Known(0),
+                Vec::new(),
+            ),
+        );
+
+        let (root_tree_id, _) = serialize_partial(&pending_trees, repo.as_ref(), &root)?;
+        repo.flush()?;
+        repo.finalize_pack_saver();
+
+        // The pending trees are not consumed
+        assert_eq!(pending_trees.len(), 4);
+
+        let root_tree = Tree::load_from_repo(repo.as_ref(), &root_tree_id.unwrap())?;
+        assert_eq!(sorted_names(&root_tree), vec!["a", "dir"]);
+
+        let dir_node = root_tree.nodes.iter().find(|n| n.name == "dir").unwrap();
+        let dir_tree = Tree::load_from_repo(repo.as_ref(), dir_node.tree.as_ref().unwrap())?;
+        assert_eq!(sorted_names(&dir_tree), vec!["b", "empty"]);
+
+        let empty_node = dir_tree.nodes.iter().find(|n| n.name == "empty").unwrap();
+        let empty_tree = Tree::load_from_repo(repo.as_ref(), empty_node.tree.as_ref().unwrap())?;
+        assert!(empty_tree.nodes.is_empty());
+
+        Ok(())
+    }
+
+    /// Test that no root tree is emitted if the snapshot root is not pending
+    #[test]
+    fn test_serialize_partial_without_root() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let password = Some(String::from("mapachito"));
+        let backend: Arc<dyn StorageBackend> = Arc::new(LocalFS::new(temp_dir.path().join("repo")));
+        repository::init(password.clone(), None, backend.clone())?;
+        let (repo, _) = try_open(password, None, backend)?;
+        repo.init_pack_saver(1);
+
+        let root = PathBuf::from("/root");
+        let (root_tree_id, sizes) = serialize_partial(&HashMap::new(), repo.as_ref(), &root)?;
+        repo.finalize_pack_saver();
+        assert!(root_tree_id.is_none());
+        assert_eq!(sizes, (0, 0));
+
+        Ok(())
+    }
+}
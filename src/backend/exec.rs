@@ -0,0 +1,615 @@
+// mapache is an incremental backup tool
+// Copyright (C) 2025  Javier Lancha Vázquez <javier.lancha@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! A backend that delegates storage to an external helper program.
+//!
+//! The helper reads requests from its stdin and writes responses to its stdout. Every request
+//! and every response is a message made of two frames:
+//! 1. A JSON header. Requests have an `op` field with the name of the `StorageBackend` method
+//!    and its arguments (e.g. `{"op":"seek_read","path":"index/ab","offset":0,"length":16}`).
+//!    Responses have a `status` field: `ok`, `bool` (with a `value`), `paths` (with a list of
+//!    `paths`) or `error` (with a `kind` and a `message`).
+//! 2. A binary payload, which carries the data of `write` requests and of `read` responses
+//!    and is empty otherwise.
+//!
+//! Each frame is prefixed with its length as a big-endian `u32`. Requests are answered in
+//! order, one at a time. The helper exits when its stdin is closed.
+
+use std::{
+    io::{BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+};
+
+use anyhow::{Context, Result, anyhow, bail};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+
+use super::StorageBackend;
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum Request {
+    Create,
+    RootExists,
+    Read {
+        path: PathBuf,
+    },
+    SeekRead {
+        path: PathBuf,
+        offset: u64,
+        length: u64,
+    },
+    SeekReadFromEnd {
+        path: PathBuf,
+        offset: i64,
+        length: u64,
+    },
+    Write {
+        path: PathBuf,
+    },
+    Rename {
+        from: PathBuf,
+        to: PathBuf,
+    },
+    RemoveFile {
+        path: PathBuf,
+    },
+    CreateDir {
+        path: PathBuf,
+    },
+    CreateDirAll {
+        path: PathBuf,
+    },
+    ReadDir {
+        path: PathBuf,
+    },
+    RemoveDir {
+        path: PathBuf,
+    },
+    RemoveDirAll {
+        path: PathBuf,
+    },
+    Exists {
+        path: PathBuf,
+    },
+    IsFile {
+        path: PathBuf,
+    },
+    IsDir {
+        path: PathBuf,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+enum Response {
+    Ok,
+    Bool { value: bool },
+    Paths { paths: Vec<PathBuf> },
+    Error { kind: ErrorKind, message: String },
+}
+
+/// Kind of a failed operation, so that the client can tell permanent errors apart
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum ErrorKind {
+    NotFound,
+    PermissionDenied,
+    Other,
+}
+
+impl From<std::io::ErrorKind> for ErrorKind {
+    fn from(kind: std::io::ErrorKind) -> Self {
+        match kind {
+            std::io::ErrorKind::NotFound => ErrorKind::NotFound,
+            std::io::ErrorKind::PermissionDenied => ErrorKind::PermissionDenied,
+            _ => ErrorKind::Other,
+        }
+    }
+}
+
+impl From<ErrorKind> for std::io::ErrorKind {
+    fn from(kind: ErrorKind) -> Self {
+        match kind {
+            ErrorKind::NotFound => std::io::ErrorKind::NotFound,
+            ErrorKind::PermissionDenied => std::io::ErrorKind::PermissionDenied,
+            ErrorKind::Other => std::io::ErrorKind::Other,
+        }
+    }
+}
+
+fn write_frame(writer: &mut impl Write, data: &[u8]) -> Result<()> {
+    let length = u32::try_from(data.len())
+        .map_err(|_| anyhow!("Frame of {} bytes is too large", data.len()))?;
+    writer.write_all(&length.to_be_bytes())?;
+    writer.write_all(data)?;
+    Ok(())
+}
+
+fn read_frame(reader: &mut impl Read) -> Result<Vec<u8>> {
+    let mut length = [0u8; 4];
+    reader.read_exact(&mut length)?;
+    let mut data = vec![0; u32::from_be_bytes(length) as usize];
+    reader.read_exact(&mut data)?;
+    Ok(data)
+}
+
+fn write_message<T: Serialize>(writer: &mut impl Write, header: &T, payload: &[u8]) -> Result<()> {
+    write_frame(writer, &serde_json::to_vec(header)?)?;
+    write_frame(writer, payload)?;
+    writer.flush()?;
+    Ok(())
+}
+
+fn read_message<T: for<'de> Deserialize<'de>>(reader: &mut impl Read) -> Result<(T, Vec<u8>)> {
+    let header = read_frame(reader)?;
+    let header = serde_json::from_slice(&header).with_context(|| "Invalid message header")?;
+    let payload = read_frame(reader)?;
+    Ok((header, payload))
+}
+
+/// The connection with the backend helper was lost. The helper can't be reached again.
+#[derive(Debug)]
+pub struct ChannelLost;
+
+impl std::fmt::Display for ChannelLost {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "The connection with the backend helper was lost")
+    }
+}
+
+impl std::error::Error for ChannelLost {}
+
+struct Channel {
+    writer: Box<dyn Write + Send>,
+    reader: Box<dyn Read + Send>,
+    /// Set when a message could not be exchanged. The streams are out of sync after that.
+    broken: bool,
+}
+
+/// A backend that forwards every operation to a helper program.
+///
+/// Requests are sent one at a time, so operations on this backend are serialized.
+pub struct ExecBackend {
+    channel: Mutex<Channel>,
+    child: Mutex<Option<Child>>,
+}
+
+impl ExecBackend {
+    /// Spawns a helper with the shell. The stderr of the helper is inherited.
+    pub fn new(command: &str) -> Result<Self> {
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()
+            .with_context(|| format!("Could not run backend helper \'{command}\'"))?;
+
+        let writer = child.stdin.take().unwrap();
+        let reader = child.stdout.take().unwrap();
+        let mut backend = Self::with_streams(BufReader::new(reader), BufWriter::new(writer));
+        backend.child = Mutex::new(Some(child));
+        Ok(backend)
+    }
+
+    /// Creates a backend that talks to a helper through a pair of streams
+    pub fn with_streams(
+        reader: impl Read + Send + 'static,
+        writer: impl Write + Send + 'static,
+    ) -> Self {
+        Self {
+            channel: Mutex::new(Channel {
+                writer: Box::new(writer),
+                reader: Box::new(reader),
+                broken: false,
+            }),
+            child: Mutex::new(None),
+        }
+    }
+
+    fn call(&self, request: Request, payload: &[u8]) -> Result<(Response, Vec<u8>)> {
+        let mut channel = self.channel.lock();
+        if channel.broken {
+            return Err(ChannelLost.into());
+        }
+
+        let result = write_message(&mut channel.writer, &request, payload)
+            .and_then(|_| read_message(&mut channel.reader));
+        match result {
+            Ok((Response::Error { kind, message }, _)) => {
+                Err(std::io::Error::new(kind.into(), message).into())
+            }
+            Ok(response) => Ok(response),
+            Err(e) => {
+                channel.broken = true;
+                Err(e.context(ChannelLost))
+            }
+        }
+    }
+
+    fn call_ok(&self, request: Request, payload: &[u8]) -> Result<Vec<u8>> {
+        match self.call(request, payload)? {
+            (Response::Ok, data) => Ok(data),
+            (response, _) => bail!("Unexpected response from backend helper: {:?}", response),
+        }
+    }
+
+    fn call_bool(&self, request: Request) -> bool {
+        matches!(
+            self.call(request, &[]),
+            Ok((Response::Bool { value: true }, _))
+        )
+    }
+}
+
+impl Drop for ExecBackend {
+    fn drop(&mut self) {
+        // Closing stdin tells the helper to exit
+        self.channel.lock().writer = Box::new(std::io::sink());
+        if let Some(mut child) = self.child.lock().take() {
+            let _ = child.wait();
+        }
+    }
+}
+
+impl StorageBackend for ExecBackend {
+    fn create(&self) -> Result<()> {
+        self.call_ok(Request::Create, &[])?;
+        Ok(())
+    }
+
+    fn root_exists(&self) -> bool {
+        self.call_bool(Request::RootExists)
+    }
+
+    fn read(&self, path: &Path) -> Result<Vec<u8>> {
+        self.call_ok(
+            Request::Read {
+                path: path.to_path_buf(),
+            },
+            &[],
+        )
+        .with_context(|| format!("Could not read \'{}\' from exec backend", path.display()))
+    }
+
+    fn seek_read(&self, path: &Path, offset: u64, length: u64) -> Result<Vec<u8>> {
+        self.call_ok(
+            Request::SeekRead {
+                path: path.to_path_buf(),
+                offset,
+                length,
+            },
+            &[],
+        )
+        .with_context(|| {
+            format!(
+                "Could not read {} bytes from offset {} in {}",
+                length,
+                offset,
+                path.display()
+            )
+        })
+    }
+
+    fn seek_read_from_end(&self, path: &Path, offset: i64, length: u64) -> Result<Vec<u8>> {
+        self.call_ok(
+            Request::SeekReadFromEnd {
+                path: path.to_path_buf(),
+                offset,
+                length,
+            },
+            &[],
+        )
+        .with_context(|| {
+            format!(
+                "Could not read {} bytes from offset (from End) {} in {}",
+                length,
+                offset,
+                path.display()
+            )
+        })
+    }
+
+    fn write(&self, path: &Path, contents: &[u8]) -> Result<()> {
+        self.call_ok(
+            Request::Write {
+                path: path.to_path_buf(),
+            },
+            contents,
+        )
+        .with_context(|| format!("Could not write \'{}\' to exec backend", path.display()))?;
+        Ok(())
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        self.call_ok(
+            Request::Rename {
+                from: from.to_path_buf(),
+                to: to.to_path_buf(),
+            },
+            &[],
+        )
+        .with_context(|| {
+            format!(
+                "Could not rename \'{}\' to \'{}\' in exec backend",
+                from.display(),
+                to.display()
+            )
+        })?;
+        Ok(())
+    }
+
+    fn remove_file(&self, file_path: &Path) -> Result<()> {
+        self.call_ok(
+            Request::RemoveFile {
+                path: file_path.to_path_buf(),
+            },
+            &[],
+        )
+        .with_context(|| {
+            format!(
+                "Could not remove \'{}\' from exec backend",
+                file_path.display()
+            )
+        })?;
+        Ok(())
+    }
+
+    fn create_dir(&self, path: &Path) -> Result<()> {
+        self.call_ok(
+            Request::CreateDir {
+                path: path.to_path_buf(),
+            },
+            &[],
+        )?;
+        Ok(())
+    }
+
+    fn create_dir_all(&self, path: &Path) -> Result<()> {
+        self.call_ok(
+            Request::CreateDirAll {
+                path: path.to_path_buf(),
+            },
+            &[],
+        )?;
+        Ok(())
+    }
+
+    fn read_dir(&self, path: &Path) -> Result<Vec<PathBuf>> {
+        let request = Request::ReadDir {
+            path: path.to_path_buf(),
+        };
+        match self.call(request, &[])? {
+            (Response::Paths { paths }, _) => Ok(paths),
+            (response, _) => bail!("Unexpected response from backend helper: {:?}", response),
+        }
+    }
+
+    fn remove_dir(&self, path: &Path) -> Result<()> {
+        self.call_ok(
+            Request::RemoveDir {
+                path: path.to_path_buf(),
+            },
+            &[],
+        )?;
+        Ok(())
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> Result<()> {
+        self.call_ok(
+            Request::RemoveDirAll {
+                path: path.to_path_buf(),
+            },
+            &[],
+        )?;
+        Ok(())
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.call_bool(Request::Exists {
+            path: path.to_path_buf(),
+        })
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        self.call_bool(Request::IsFile {
+            path: path.to_path_buf(),
+        })
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        self.call_bool(Request::IsDir {
+            path: path.to_path_buf(),
+        })
+    }
+}
+
+/// Runs a helper that serves a backend until the reader is closed. This is the helper side of
+/// the protocol spoken by [`ExecBackend`].
+pub fn serve(backend: &dyn StorageBackend, reader: impl Read, writer: impl Write) -> Result<()> {
+    let mut reader = BufReader::new(reader);
+    let mut writer = BufWriter::new(writer);
+
+    loop {
+        let mut length = [0u8; 4];
+        match reader.read_exact(&mut length) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e.into()),
+        }
+        let mut header = vec![0; u32::from_be_bytes(length) as usize];
+        reader.read_exact(&mut header)?;
+        let request: Request =
+            serde_json::from_slice(&header).with_context(|| "Invalid request header")?;
+        let payload = read_frame(&mut reader)?;
+
+        let (response, data) = match handle(backend, request, &payload) {
+            Ok(response) => response,
+            Err(e) => {
+                let kind = e
+                    .chain()
+                    .find_map(|cause| cause.downcast_ref::<std::io::Error>())
+                    .map_or(ErrorKind::Other, |e| e.kind().into());
+                let message = format!("{e:#}");
+                (Response::Error { kind, message }, Vec::new())
+            }
+        };
+        write_message(&mut writer, &response, &data)?;
+    }
+}
+
+fn handle(
+    backend: &dyn StorageBackend,
+    request: Request,
+    payload: &[u8],
+) -> Result<(Response, Vec<u8>)> {
+    let ok = (Response::Ok, Vec::new());
+    let response = match request {
+        Request::Create => backend.create().map(|_| ok)?,
+        Request::RootExists => bool_response(backend.root_exists()),
+        Request::Read { path } => (Response::Ok, backend.read(&path)?),
+        Request::SeekRead {
+            path,
+            offset,
+            length,
+        } => (Response::Ok, backend.seek_read(&path, offset, length)?),
+        Request::SeekReadFromEnd {
+            path,
+            offset,
+            length,
+        } => (
+            Response::Ok,
+            backend.seek_read_from_end(&path, offset, length)?,
+        ),
+        Request::Write { path } => backend.write(&path, payload).map(|_| ok)?,
+        Request::Rename { from, to } => backend.rename(&from, &to).map(|_| ok)?,
+        Request::RemoveFile { path } => backend.remove_file(&path).map(|_| ok)?,
+        Request::CreateDir { path } => backend.create_dir(&path).map(|_| ok)?,
+        Request::CreateDirAll { path } => backend.create_dir_all(&path).map(|_| ok)?,
+        Request::ReadDir { path } => (
+            Response::Paths {
+                paths: backend.read_dir(&path)?,
+            },
+            Vec::new(),
+        ),
+        Request::RemoveDir { path } => backend.remove_dir(&path).map(|_| ok)?,
+        Request::RemoveDirAll { path } => backend.remove_dir_all(&path).map(|_| ok)?,
+        Request::Exists { path } => bool_response(backend.exists(&path)),
+        Request::IsFile { path } => bool_response(backend.is_file(&path)),
+        Request::IsDir { path } => bool_response(backend.is_dir(&path)),
+    };
+    Ok(response)
+}
+
+fn bool_response(value: bool) -> (Response, Vec<u8>) {
+    (Response::Bool { value }, Vec::new())
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use crate::backend::localfs::LocalFS;
+
+    use super::*;
+
+    /// Connects an exec backend to a helper serving a local directory in another thread
+    fn connect(root: PathBuf) -> Result<ExecBackend> {
+        let (request_reader, request_writer) = std::io::pipe()?;
+        let (response_reader, response_writer) = std::io::pipe()?;
+        std::thread::spawn(move || {
+            let backend = LocalFS::new(root);
+            serve(&backend, request_reader, response_writer)
+        });
+        Ok(ExecBackend::with_streams(response_reader, request_writer))
+    }
+
+    #[test]
+    fn test_exec_backend() -> Result<()> {
+        let tmp_dir = tempdir()?;
+        let root = tmp_dir.path().join("repo");
+        let backend = connect(root.clone())?;
+
+        assert!(!backend.root_exists());
+        backend.create()?;
+        assert!(backend.root_exists());
+
+        backend.create_dir_all(Path::new("objects/00"))?;
+        assert!(backend.is_dir(Path::new("objects/00")));
+
+        let path = Path::new("objects/00/file");
+        backend.write(path, b"0123456789")?;
+        assert_eq!(std::fs::read(root.join(path))?, b"0123456789");
+        assert!(backend.exists(path));
+        assert!(backend.is_file(path));
+        assert!(!backend.is_dir(path));
+
+        assert_eq!(backend.read(path)?, b"0123456789");
+        assert_eq!(backend.seek_read(path, 2, 3)?, b"234");
+        assert_eq!(backend.seek_read_from_end(path, -4, 2)?, b"67");
+        assert!(backend.seek_read(path, 8, 4).is_err());
+
+        assert_eq!(
+            backend.read_dir(Path::new("objects/00"))?,
+            vec![PathBuf::from("objects/00/file")]
+        );
+
+        let renamed = Path::new("objects/00/renamed");
+        backend.rename(path, renamed)?;
+        assert!(!backend.exists(path));
+        backend.remove_file(renamed)?;
+        assert!(backend.read_dir(Path::new("objects/00"))?.is_empty());
+
+        backend.remove_dir(Path::new("objects/00"))?;
+        assert!(!backend.exists(Path::new("objects/00")));
+        backend.remove_dir_all(Path::new("objects"))?;
+        assert!(!backend.exists(Path::new("objects")));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_exec_backend_error_kind() -> Result<()> {
+        let tmp_dir = tempdir()?;
+        let backend = connect(tmp_dir.path().to_path_buf())?;
+
+        let err = backend.read(Path::new("missing")).unwrap_err();
+        let io_err = err
+            .chain()
+            .find_map(|cause| cause.downcast_ref::<std::io::Error>())
+            .expect("The error should carry an io::Error");
+        assert_eq!(io_err.kind(), std::io::ErrorKind::NotFound);
+
+        // The backend is still usable after an error
+        backend.write(Path::new("file"), b"data")?;
+        assert_eq!(backend.read(Path::new("file"))?, b"data");
+        Ok(())
+    }
+
+    #[test]
+    fn test_exec_backend_helper_exits() -> Result<()> {
+        let backend = ExecBackend::new("exit 0")?;
+        let err = backend.read(Path::new("file")).unwrap_err();
+        assert!(crate::backend::retry::is_permanent(&err));
+        assert!(!backend.root_exists());
+        let err = backend.read(Path::new("file")).unwrap_err();
+        assert!(crate::backend::retry::is_permanent(&err));
+        Ok(())
+    }
+}
@@ -34,6 +34,22 @@ impl LocalFS {
         Self { repo_path }
     }
 
+    /// Returns the size of a file in bytes
+    pub fn file_size(&self, path: &Path) -> Result<u64> {
+        let metadata = std::fs::metadata(self.full_path(path))
+            .with_context(|| format!("Could not stat \'{}\' in local backend", path.display()))?;
+        Ok(metadata.len())
+    }
+
+    /// Writes a file with the contents of a reader, without holding them in memory.
+    /// Returns the number of bytes written.
+    pub fn write_from(&self, path: &Path, reader: &mut dyn Read) -> Result<u64> {
+        let mut file = std::fs::File::create(self.full_path(path))
+            .with_context(|| format!("Could not create \'{}\' in local backend", path.display()))?;
+        std::io::copy(reader, &mut file)
+            .with_context(|| format!("Could not write to \'{}\' in local backend", path.display()))
+    }
+
     fn full_path(&self, path: &Path) -> PathBuf {
         self.repo_path.join(path)
     }
@@ -0,0 +1,694 @@
+// mapache is an incremental backup tool
+// Copyright (C) 2025  Javier Lancha Vázquez <javier.lancha@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, bail};
+use base64::Engine;
+use parking_lot::Mutex;
+use serde::Deserialize;
+use ureq::{Agent, Body, http::Response};
+
+use crate::repository::{
+    KEYS_DIR, LOCKS_DIR, MANIFEST_PATH,
+    repository_v1::{INDEX_DIR, OBJECTS_DIR, OBJECTS_DIR_FANOUT, SNAPSHOTS_DIR},
+};
+
+use super::{StorageBackend, http_status_error};
+
+/// Media type of the v2 directory listings of the restic REST protocol
+pub const REST_V2_MEDIA_TYPE: &str = "application/vnd.x.restic.rest.v2";
+
+/// The file that holds the manifest in the REST layout
+pub const CONFIG_PATH: &str = "config";
+/// The directory that holds all objects in the REST layout
+pub const DATA_DIR: &str = "data";
+/// Directories that have the same name in the repository and in the REST layout
+pub const SHARED_DIRS: [&str; 4] = [KEYS_DIR, LOCKS_DIR, SNAPSHOTS_DIR, INDEX_DIR];
+
+/// An entry of a directory listing. Servers speaking the v1 protocol only send the names.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum ListEntry {
+    V1(String),
+    V2 {
+        name: String,
+        #[allow(dead_code)]
+        size: u64,
+    },
+}
+
+impl ListEntry {
+    fn name(&self) -> &str {
+        match self {
+            ListEntry::V1(name) => name,
+            ListEntry::V2 { name, .. } => name,
+        }
+    }
+}
+
+/// Location of a repository path in the REST layout
+#[derive(Debug, PartialEq)]
+enum RestPath {
+    /// The repository root
+    Root,
+    /// A directory. All objects are listed together in `data`, so only the names starting
+    /// with `prefix` belong to the repository directory.
+    Dir { dir: &'static str, prefix: String },
+    /// A file, relative to the repository URL
+    File(String),
+}
+
+impl RestPath {
+    /// Maps a path of the repository to the REST layout. The manifest is stored as `config`,
+    /// and the objects, spread over subdirectories in the repository, are all stored in `data`.
+    /// Other files at the root are stored in `locks`.
+    fn new(path: &Path) -> Result<Self> {
+        let components = path
+            .components()
+            .map(|component| component.as_os_str().to_str())
+            .collect::<Option<Vec<_>>>()
+            .with_context(|| format!("Invalid path \'{}\'", path.display()))?;
+
+        let rest_path = match components.as_slice() {
+            [] => RestPath::Root,
+            [MANIFEST_PATH] => RestPath::File(CONFIG_PATH.to_string()),
+            [OBJECTS_DIR] => RestPath::Dir {
+                dir: DATA_DIR,
+                prefix: String::new(),
+            },
+            [OBJECTS_DIR, prefix] => RestPath::Dir {
+                dir: DATA_DIR,
+                prefix: prefix.to_string(),
+            },
+            [OBJECTS_DIR, _, name] => RestPath::File(format!("{DATA_DIR}/{name}")),
+            [dir] if SHARED_DIRS.contains(dir) => RestPath::Dir {
+                dir: SHARED_DIRS.into_iter().find(|d| d == dir).unwrap(),
+                prefix: String::new(),
+            },
+            [dir, name] if SHARED_DIRS.contains(dir) => RestPath::File(format!("{dir}/{name}")),
+            // The layout has no place for other files at the root, such as temporary files and
+            // the migration state. Locks can always be replaced and removed, even in append-only
+            // servers, and lock listings skip the names that are not IDs.
+            [name] => RestPath::File(format!("{LOCKS_DIR}/{name}")),
+            _ => bail!(
+                "\'{}\' is not part of the REST repository layout",
+                path.display()
+            ),
+        };
+        Ok(rest_path)
+    }
+}
+
+/// A backend that stores the repository in an HTTP server speaking the restic REST protocol,
+/// such as `rest-server` or the one run by `mapache serve`.
+///
+/// Files are addressed by their type and name relative to the base URL:
+/// - `GET` reads a file, or a range of it with a `Range` header.
+/// - `HEAD` checks that a file exists.
+/// - `POST` writes a file, and `DELETE` removes it.
+/// - `GET` on a type ending in `/` lists its files.
+///
+/// The manifest is stored as `config`, objects are stored in `data`, and other files at the
+/// root are stored in `locks`. Directories are implicit, so creating and removing them is a
+/// no-op.
+pub struct RestBackend {
+    base_url: String,
+    authorization: Option<String>,
+    agent: Agent,
+    /// Names of the objects, kept between the listings of the object subdirectories
+    data_listing: Mutex<Option<BTreeSet<String>>>,
+}
+
+impl RestBackend {
+    /// Creates a REST backend. `base_url` is the http(s) URL of the repository root.
+    /// Basic authentication is used if a username is provided.
+    pub fn new(base_url: String, username: Option<String>, password: Option<String>) -> Self {
+        let authorization = username.map(|username| {
+            let credentials = format!("{}:{}", username, password.unwrap_or_default());
+            format!(
+                "Basic {}",
+                base64::engine::general_purpose::STANDARD.encode(credentials)
+            )
+        });
+
+        let agent: Agent = Agent::config_builder()
+            .http_status_as_error(false)
+            .build()
+            .into();
+
+        Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            authorization,
+            agent,
+            data_listing: Mutex::new(None),
+        }
+    }
+
+    fn url(&self, rest_path: &str) -> String {
+        format!("{}/{}", self.base_url, rest_path)
+    }
+
+    /// Returns the location of a file in the REST layout
+    fn file_path(path: &Path) -> Result<String> {
+        match RestPath::new(path)? {
+            RestPath::File(rest_path) => Ok(rest_path),
+            _ => bail!("\'{}\' is not a file", path.display()),
+        }
+    }
+
+    /// Sends a request. `body` is sent with POST requests.
+    fn request(
+        &self,
+        method: &str,
+        url: &str,
+        headers: &[(&str, &str)],
+        body: Option<&[u8]>,
+    ) -> Result<Response<Body>> {
+        let mut builder = ureq::http::Request::builder().method(method).uri(url);
+        if let Some(authorization) = &self.authorization {
+            builder = builder.header("Authorization", authorization);
+        }
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+
+        let response = match body {
+            Some(body) => self.agent.run(builder.body(body)?),
+            None => self.agent.run(builder.body(())?),
+        };
+        response.with_context(|| format!("{method} request to {url} failed"))
+    }
+
+    /// Sends a request for a file and fails if the response is not successful
+    fn request_ok(
+        &self,
+        method: &str,
+        path: &Path,
+        headers: &[(&str, &str)],
+        body: Option<&[u8]>,
+    ) -> Result<Response<Body>> {
+        let url = self.url(&Self::file_path(path)?);
+        let response = self.request(method, &url, headers, body)?;
+        let status = response.status();
+        if !status.is_success() {
+            return Err(http_status_error(
+                status.as_u16(),
+                format!(
+                    "REST server returned {} for {} {}",
+                    status,
+                    method,
+                    path.display()
+                ),
+            ));
+        }
+        Ok(response)
+    }
+
+    fn read_body(response: Response<Body>) -> Result<Vec<u8>> {
+        let mut response = response;
+        response
+            .body_mut()
+            .with_config()
+            .limit(u64::MAX)
+            .read_to_vec()
+            .with_context(|| "Could not read the response body")
+    }
+
+    /// Lists a directory of the REST layout. Returns `None` if the directory does not exist.
+    fn list(&self, dir: &str) -> Result<Option<Vec<ListEntry>>> {
+        let response = self.request(
+            "GET",
+            &format!("{}/", self.url(dir)),
+            &[("Accept", REST_V2_MEDIA_TYPE)],
+            None,
+        )?;
+        let status = response.status();
+        if status == 404 {
+            return Ok(None);
+        } else if !status.is_success() {
+            return Err(http_status_error(
+                status.as_u16(),
+                format!("REST server returned {status} listing {dir}"),
+            ));
+        }
+
+        let body = Self::read_body(response)?;
+        let entries = serde_json::from_slice(&body)
+            .with_context(|| format!("Invalid directory listing for {dir}"))?;
+        Ok(Some(entries))
+    }
+
+    /// Lists the objects whose names start with `prefix`. The repository lists the object
+    /// subdirectories in order, so `data` is only listed again for the first one and the
+    /// others are filtered from the same listing.
+    fn list_data(&self, prefix: &str) -> Result<Vec<String>> {
+        let mut data_listing = self.data_listing.lock();
+        if data_listing.is_none() || prefix.bytes().all(|b| b == b'0') {
+            let entries = self.list(DATA_DIR)?.unwrap_or_default();
+            *data_listing = Some(
+                entries
+                    .iter()
+                    .map(|entry| entry.name().to_string())
+                    .collect(),
+            );
+        }
+
+        Ok(data_listing
+            .iter()
+            .flatten()
+            .filter(|name| name.starts_with(prefix))
+            .cloned()
+            .collect())
+    }
+
+    /// Keeps the listing of `data` up to date with the objects written and removed
+    fn update_data_listing(&self, rest_path: &str, exists: bool) {
+        if let Some(name) = rest_path
+            .strip_prefix(DATA_DIR)
+            .and_then(|name| name.strip_prefix('/'))
+            && let Some(data_listing) = self.data_listing.lock().as_mut()
+        {
+            match exists {
+                true => data_listing.insert(name.to_string()),
+                false => data_listing.remove(name),
+            };
+        }
+    }
+
+    /// Reads a range of a file. `range` is the value of the HTTP Range header.
+    fn read_range(&self, path: &Path, range: &str) -> Result<Vec<u8>> {
+        let response = self.request_ok("GET", path, &[("Range", range)], None)?;
+
+        // A server without support for ranges sends the whole file
+        if response.status() != 206 {
+            bail!(
+                "REST server ignored range {} reading {}",
+                range,
+                path.display()
+            );
+        }
+        Self::read_body(response)
+    }
+}
+
+impl StorageBackend for RestBackend {
+    fn create(&self) -> Result<()> {
+        let url = format!("{}/?create=true", self.base_url);
+        let response = self.request("POST", &url, &[], Some(&[]))?;
+        let status = response.status();
+        if !status.is_success() {
+            return Err(http_status_error(
+                status.as_u16(),
+                format!("REST server returned {status} creating the repository"),
+            ));
+        }
+        Ok(())
+    }
+
+    /// The protocol can't list the root, but creating a repository creates its directories
+    fn root_exists(&self) -> bool {
+        matches!(self.list(KEYS_DIR), Ok(Some(_)))
+    }
+
+    fn read(&self, path: &Path) -> Result<Vec<u8>> {
+        let response = self
+            .request_ok("GET", path, &[], None)
+            .with_context(|| format!("Could not read \'{}\' from REST backend", path.display()))?;
+        Self::read_body(response)
+    }
+    fn seek_read(&self, path: &Path, offset: u64, length: u64) -> Result<Vec<u8>> {
+        if length == 0 {
+            return Ok(Vec::new());
+        }
+
+        let range = format!("bytes={}-{}", offset, offset + length - 1);
+        let data = self.read_range(path, &range).with_context(|| {
+            format!(
+                "Could not read {} bytes from offset {} in {}",
+                length,
+                offset,
+                path.display()
+            )
+        })?;
+        if data.len() as u64 != length {
+            bail!(
+                "Read {} bytes from offset {} in {}, expected {}",
+                data.len(),
+                offset,
+                path.display(),
+                length
+            );
+        }
+        Ok(data)
+    }
+
+    fn seek_read_from_end(&self, path: &Path, offset: i64, length: u64) -> Result<Vec<u8>> {
+        if offset >= 0 {
+            bail!("Offset from the end of {} must be negative", path.display());
+        }
+
+        // A suffix range reads the last bytes of the file
+        let range = format!("bytes=-{}", offset.unsigned_abs());
+        let mut data = self.read_range(path, &range).with_context(|| {
+            format!(
+                "Could not read {} bytes from offset (from End) {} in {}",
+                length,
+                offset,
+                path.display()
+            )
+        })?;
+        if (data.len() as u64) < length {
+            bail!(
+                "Read {} bytes from offset (from End) {} in {}, expected {}",
+                data.len(),
+                offset,
+                path.display(),
+                length
+            );
+        }
+        data.truncate(length as usize);
+        Ok(data)
+    }
+
+    fn write(&self, path: &Path, contents: &[u8]) -> Result<()> {
+        self.request_ok("POST", path, &[], Some(contents))
+            .with_context(|| format!("Could not write \'{}\' to REST backend", path.display()))?;
+        self.update_data_listing(&Self::file_path(path)?, true);
+        Ok(())
+    }
+
+    /// The protocol has no rename. The file is copied and the original removed.
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        let data = self.read(from)?;
+        self.write(to, &data)?;
+        self.remove_file(from)
+    }
+
+    fn remove_file(&self, file_path: &Path) -> Result<()> {
+        self.request_ok("DELETE", file_path, &[], None)
+            .with_context(|| {
+                format!(
+                    "Could not remove \'{}\' from REST backend",
+                    file_path.display()
+                )
+            })?;
+        self.update_data_listing(&Self::file_path(file_path)?, false);
+        Ok(())
+    }
+
+    fn create_dir(&self, _path: &Path) -> Result<()> {
+        Ok(())
+    }
+
+    fn create_dir_all(&self, _path: &Path) -> Result<()> {
+        Ok(())
+    }
+
+    fn read_dir(&self, path: &Path) -> Result<Vec<PathBuf>> {
+        match RestPath::new(path)? {
+            RestPath::Root => bail!("The root of a REST repository cannot be listed"),
+            // Objects are listed in the subdirectories named after their prefixes
+            RestPath::Dir {
+                dir: DATA_DIR,
+                prefix,
+            } if prefix.is_empty() => {
+                let prefixes: BTreeSet<String> = self
+                    .list_data("")?
+                    .iter()
+                    .filter_map(|name| name.get(..OBJECTS_DIR_FANOUT))
+                    .map(str::to_string)
+                    .collect();
+                Ok(prefixes.iter().map(|prefix| path.join(prefix)).collect())
+            }
+            RestPath::Dir {
+                dir: DATA_DIR,
+                prefix,
+            } => Ok(self
+                .list_data(&prefix)?
+                .iter()
+                .map(|name| path.join(name))
+                .collect()),
+            // Directories are implicit, so a missing directory is just empty
+            RestPath::Dir { dir, .. } => Ok(self
+                .list(dir)?
+                .unwrap_or_default()
+                .iter()
+                .map(|entry| path.join(entry.name()))
+                .collect()),
+            RestPath::File(_) => bail!("\'{}\' is not a directory", path.display()),
+        }
+    }
+
+    fn remove_dir(&self, _path: &Path) -> Result<()> {
+        Ok(())
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> Result<()> {
+        match RestPath::new(path)? {
+            RestPath::Root => {
+                for dir in SHARED_DIRS.into_iter().chain([OBJECTS_DIR]) {
+                    self.remove_dir_all(Path::new(dir))?;
+                }
+                let manifest_path = Path::new(MANIFEST_PATH);
+                if self.is_file(manifest_path) {
+                    self.remove_file(manifest_path)?;
+                }
+            }
+            RestPath::Dir { .. } => {
+                for entry in self.read_dir(path)? {
+                    match RestPath::new(&entry)? {
+                        RestPath::File(_) => self.remove_file(&entry)?,
+                        _ => self.remove_dir_all(&entry)?,
+                    }
+                }
+            }
+            RestPath::File(_) => bail!("\'{}\' is not a directory", path.display()),
+        }
+        Ok(())
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.is_file(path) || self.is_dir(path)
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        Self::file_path(path).is_ok_and(|rest_path| {
+            self.request("HEAD", &self.url(&rest_path), &[], None)
+                .is_ok_and(|response| response.status().is_success())
+        })
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        match RestPath::new(path) {
+            Ok(RestPath::Root) => self.root_exists(),
+            Ok(RestPath::Dir { dir, .. }) => matches!(self.list(dir), Ok(Some(_))),
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, sync::Arc};
+
+    use parking_lot::Mutex;
+
+    use crate::server::http::{self, Request, Response};
+
+    use super::*;
+
+    type Files = Arc<Mutex<BTreeMap<String, Vec<u8>>>>;
+
+    /// Spawns a REST server that keeps the files of a repository at `/repo` in memory
+    fn spawn_server(authorization: Option<String>) -> (String, Files) {
+        let files: Files = Arc::new(Mutex::new(BTreeMap::new()));
+        let server_files = files.clone();
+        let url = http::spawn(move |request| {
+            if authorization.is_some()
+                && request.headers.get("authorization") != authorization.as_ref()
+            {
+                return Response::empty(401);
+            }
+            respond(request, &server_files)
+        });
+
+        (format!("{url}/repo"), files)
+    }
+
+    fn respond(mut request: Request, files: &Files) -> Response {
+        let path = request
+            .path
+            .trim_start_matches("/repo")
+            .trim_start_matches('/');
+        let mut files = files.lock();
+
+        // The root marks that the repository was created
+        if path.is_empty() || path.ends_with('/') {
+            return match request.method.as_str() {
+                "POST" => {
+                    files.insert(String::from(".root"), Vec::new());
+                    Response::empty(200)
+                }
+                _ if !files.contains_key(".root") => Response::empty(404),
+                _ => {
+                    let entries: Vec<serde_json::Value> = files
+                        .iter()
+                        .filter_map(|(name, data)| {
+                            let name = name.strip_prefix(path)?;
+                            (!name.contains('/') && !name.starts_with('.'))
+                                .then(|| serde_json::json!({"name": name, "size": data.len()}))
+                        })
+                        .collect();
+                    Response::new(200, serde_json::to_vec(&entries).unwrap())
+                }
+            };
+        }
+
+        match request.method.as_str() {
+            "POST" => {
+                files.insert(path.to_string(), request.read_body().unwrap());
+                Response::empty(200)
+            }
+            "DELETE" => match files.remove(path) {
+                Some(_) => Response::empty(200),
+                None => Response::empty(404),
+            },
+            _ => match (files.get(path), request.headers.get("range")) {
+                (None, _) => Response::empty(404),
+                (Some(data), None) => Response::new(200, data.clone()),
+                (Some(data), Some(range)) => match http::parse_range(range, data.len() as u64) {
+                    Some((start, end)) => {
+                        Response::new(206, data[start as usize..end as usize].to_vec())
+                    }
+                    None => Response::empty(416),
+                },
+            },
+        }
+    }
+
+    #[test]
+    fn test_rest_path() -> Result<()> {
+        let file = |path: &str| RestPath::File(path.to_string());
+        assert_eq!(RestPath::new(Path::new(""))?, RestPath::Root);
+        assert_eq!(RestPath::new(Path::new("manifest"))?, file("config"));
+        assert_eq!(
+            RestPath::new(Path::new("objects/ab/abcd"))?,
+            file("data/abcd")
+        );
+        assert_eq!(RestPath::new(Path::new("keys/abcd"))?, file("keys/abcd"));
+        assert_eq!(
+            RestPath::new(Path::new("snapshots/abcd.tmp"))?,
+            file("snapshots/abcd.tmp")
+        );
+        assert_eq!(
+            RestPath::new(Path::new("objects/ab"))?,
+            RestPath::Dir {
+                dir: DATA_DIR,
+                prefix: String::from("ab")
+            }
+        );
+        assert_eq!(
+            RestPath::new(Path::new("locks"))?,
+            RestPath::Dir {
+                dir: LOCKS_DIR,
+                prefix: String::new()
+            }
+        );
+        assert_eq!(
+            RestPath::new(Path::new("manifest.tmp"))?,
+            file("locks/manifest.tmp")
+        );
+        assert!(RestPath::new(Path::new("index/ab/abcd")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_rest_backend() -> Result<()> {
+        let (url, files) = spawn_server(None);
+        let backend = RestBackend::new(url, None, None);
+
+        assert!(!backend.root_exists());
+        backend.create()?;
+        assert!(backend.root_exists());
+
+        let path = Path::new("objects/00/00aa");
+        backend.create_dir_all(path.parent().unwrap())?;
+        backend.write(path, b"0123456789")?;
+        assert!(files.lock().contains_key("data/00aa"));
+        assert!(backend.exists(path));
+        assert!(backend.is_file(path));
+        assert!(!backend.is_file(Path::new("objects/00/00ff")));
+        let err = backend.read(Path::new("objects/00/00ff")).unwrap_err();
+        assert!(crate::backend::retry::is_permanent(&err));
+
+        assert_eq!(backend.read(path)?, b"0123456789");
+        assert_eq!(backend.seek_read(path, 2, 3)?, b"234");
+        assert_eq!(backend.seek_read_from_end(path, -4, 2)?, b"67");
+        assert!(backend.seek_read(path, 8, 4).is_err());
+
+        backend.write(Path::new("objects/ff/ffaa"), b"data")?;
+        assert_eq!(
+            backend.read_dir(Path::new("objects/00"))?,
+            vec![PathBuf::from("objects/00/00aa")]
+        );
+        assert_eq!(
+            backend.read_dir(Path::new("objects"))?,
+            vec![PathBuf::from("objects/00"), PathBuf::from("objects/ff")]
+        );
+
+        backend.rename(path, Path::new("objects/00/00bb"))?;
+        assert!(!backend.is_file(path));
+        assert_eq!(backend.read(Path::new("objects/00/00bb"))?, b"0123456789");
+
+        backend.remove_file(Path::new("objects/00/00bb"))?;
+        assert!(backend.read_dir(Path::new("objects/00"))?.is_empty());
+        assert!(backend.read(path).is_err());
+
+        backend.write(Path::new("manifest"), b"manifest")?;
+        backend.write(Path::new("snapshots/abcd"), b"snapshot")?;
+        assert!(files.lock().contains_key("config"));
+        backend.remove_dir_all(Path::new(""))?;
+        assert_eq!(
+            files.lock().keys().collect::<Vec<_>>(),
+            vec![&String::from(".root")]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rest_backend_basic_auth() -> Result<()> {
+        let credentials = base64::engine::general_purpose::STANDARD.encode("user:secret");
+        let (url, files) = spawn_server(Some(format!("Basic {credentials}")));
+
+        let backend = RestBackend::new(url.clone(), Some("user".into()), Some("wrong".into()));
+        assert!(backend.create().is_err());
+
+        let backend = RestBackend::new(url, Some("user".into()), Some("secret".into()));
+        backend.create()?;
+        backend.write(Path::new("manifest"), b"data")?;
+        assert_eq!(
+            files.lock().get("config").map(Vec::as_slice),
+            Some(&b"data"[..])
+        );
+
+        Ok(())
+    }
+}
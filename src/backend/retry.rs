@@ -0,0 +1,449 @@
+// mapache is an incremental backup tool
+// Copyright (C) 2025  Javier Lancha Vázquez <javier.lancha@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::{
+    cell::Cell,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{Error, Result};
+use rand::Rng;
+
+use crate::{global::defaults::MAX_RETRY_DELAY, ui};
+
+use super::{StorageBackend, exec::ChannelLost};
+
+/// SFTP status codes of errors that retrying cannot fix
+const SFTP_NO_SUCH_FILE: i32 = 2;
+const SFTP_PERMISSION_DENIED: i32 = 3;
+const SFTP_NO_SUCH_PATH: i32 = 10;
+
+/// How failed operations are retried
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    /// Number of retries after the first attempt
+    pub retries: u32,
+    /// Delay before the first retry. It doubles with every retry, up to `max_delay`.
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl RetryPolicy {
+    pub fn new(retries: u32, base_delay: Duration) -> Self {
+        Self {
+            retries,
+            base_delay,
+            max_delay: MAX_RETRY_DELAY.max(base_delay),
+        }
+    }
+
+    /// Delay before a retry, counting from 0. Half of the delay is random, so that
+    /// concurrent workers that failed together do not retry together.
+    fn delay(&self, retry: u32) -> Duration {
+        let delay = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(retry))
+            .min(self.max_delay);
+        let half = delay / 2;
+        half + half.mul_f64(rand::rng().random::<f64>())
+    }
+}
+
+/// A storage backend that sets itself before a remote backend, retrying the operations that
+/// failed with a transient error.
+///
+/// An attempt can succeed on the server even though its response was lost. Renames,
+/// removals and directory creations check whether that happened before being retried, so
+/// a retry does not fail because the previous attempt did its job.
+pub struct RetryBackend {
+    backend: Arc<dyn StorageBackend>,
+    policy: RetryPolicy,
+}
+
+impl RetryBackend {
+    pub fn new(backend: Arc<dyn StorageBackend>, policy: RetryPolicy) -> Self {
+        Self { backend, policy }
+    }
+
+    fn retry<T>(&self, what: &str, path: &Path, op: impl Fn() -> Result<T>) -> Result<T> {
+        let mut retry = 0;
+        loop {
+            match op() {
+                Ok(value) => return Ok(value),
+                Err(e) if retry >= self.policy.retries || is_permanent(&e) => return Err(e),
+                Err(e) => {
+                    let delay = self.policy.delay(retry);
+                    retry += 1;
+                    ui::cli::warning!(
+                        "Could not {} \'{}\' (retry {}/{} in {:.1}s): {:#}",
+                        what,
+                        path.display(),
+                        retry,
+                        self.policy.retries,
+                        delay.as_secs_f64(),
+                        e
+                    );
+                    std::thread::sleep(delay);
+                }
+            }
+        }
+    }
+
+    /// Retries an operation that is not idempotent. Before every retry, `done` tells
+    /// whether a previous attempt succeeded even though it reported an error.
+    fn retry_unless_done(
+        &self,
+        what: &str,
+        path: &Path,
+        op: impl Fn() -> Result<()>,
+        done: impl Fn() -> bool,
+    ) -> Result<()> {
+        let first_attempt = Cell::new(true);
+        self.retry(what, path, || {
+            if !first_attempt.replace(false) && done() {
+                return Ok(());
+            }
+            op()
+        })
+    }
+}
+
+/// Whether an error would happen again if the operation was retried. An unexpected end of
+/// file is transient, because remote backends report it when the connection drops.
+pub fn is_permanent(error: &Error) -> bool {
+    if error.downcast_ref::<ChannelLost>().is_some() {
+        return true;
+    }
+
+    error.chain().any(|cause| {
+        if let Some(e) = cause.downcast_ref::<std::io::Error>() {
+            matches!(
+                e.kind(),
+                ErrorKind::NotFound
+                    | ErrorKind::PermissionDenied
+                    | ErrorKind::AlreadyExists
+                    | ErrorKind::InvalidInput
+                    | ErrorKind::InvalidData
+                    | ErrorKind::NotADirectory
+                    | ErrorKind::IsADirectory
+                    | ErrorKind::DirectoryNotEmpty
+                    | ErrorKind::ReadOnlyFilesystem
+            )
+        } else if let Some(e) = cause.downcast_ref::<ssh2::Error>() {
+            matches!(
+                e.code(),
+                ssh2::ErrorCode::SFTP(
+                    SFTP_NO_SUCH_FILE | SFTP_PERMISSION_DENIED | SFTP_NO_SUCH_PATH
+                )
+            )
+        } else {
+            false
+        }
+    })
+}
+
+impl StorageBackend for RetryBackend {
+    fn create(&self) -> Result<()> {
+        self.retry("create", Path::new(""), || self.backend.create())
+    }
+
+    #[inline]
+    fn root_exists(&self) -> bool {
+        self.backend.root_exists()
+    }
+
+    fn read(&self, path: &Path) -> Result<Vec<u8>> {
+        self.retry("read", path, || self.backend.read(path))
+    }
+
+    fn seek_read(&self, path: &Path, offset: u64, length: u64) -> Result<Vec<u8>> {
+        self.retry("read", path, || {
+            self.backend.seek_read(path, offset, length)
+        })
+    }
+
+    fn seek_read_from_end(&self, path: &Path, offset: i64, length: u64) -> Result<Vec<u8>> {
+        self.retry("read", path, || {
+            self.backend.seek_read_from_end(path, offset, length)
+        })
+    }
+
+    fn write(&self, path: &Path, contents: &[u8]) -> Result<()> {
+        self.retry("write", path, || self.backend.write(path, contents))
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        self.retry_unless_done(
+            "rename",
+            from,
+            || self.backend.rename(from, to),
+            || !self.backend.exists(from) && self.backend.exists(to),
+        )
+    }
+
+    fn remove_file(&self, file_path: &Path) -> Result<()> {
+        self.retry_unless_done(
+            "remove",
+            file_path,
+            || self.backend.remove_file(file_path),
+            || !self.backend.exists(file_path),
+        )
+    }
+
+    fn create_dir(&self, path: &Path) -> Result<()> {
+        self.retry_unless_done(
+            "create directory",
+            path,
+            || self.backend.create_dir(path),
+            || self.backend.is_dir(path),
+        )
+    }
+
+    fn create_dir_all(&self, path: &Path) -> Result<()> {
+        self.retry("create directory", path, || {
+            self.backend.create_dir_all(path)
+        })
+    }
+
+    fn read_dir(&self, path: &Path) -> Result<Vec<PathBuf>> {
+        self.retry("list", path, || self.backend.read_dir(path))
+    }
+
+    fn remove_dir(&self, path: &Path) -> Result<()> {
+        self.retry_unless_done(
+            "remove directory",
+            path,
+            || self.backend.remove_dir(path),
+            || !self.backend.exists(path),
+        )
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> Result<()> {
+        self.retry_unless_done(
+            "remove directory",
+            path,
+            || self.backend.remove_dir_all(path),
+            || !self.backend.exists(path),
+        )
+    }
+
+    #[inline]
+    fn exists(&self, path: &Path) -> bool {
+        self.backend.exists(path)
+    }
+
+    #[inline]
+    fn is_file(&self, path: &Path) -> bool {
+        self.backend.is_file(path)
+    }
+
+    #[inline]
+    fn is_dir(&self, path: &Path) -> bool {
+        self.backend.is_dir(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use anyhow::{Context, bail};
+    use tempfile::tempdir;
+
+    use crate::backend::localfs::LocalFS;
+
+    use super::*;
+
+    /// A local backend whose operations fail a number of times before succeeding. Reads and
+    /// writes fail before doing anything. Renames and removals fail after doing their job,
+    /// like a request whose response was lost.
+    struct FlakyBackend {
+        backend: LocalFS,
+        failures: AtomicU32,
+        attempts: AtomicU32,
+    }
+
+    impl FlakyBackend {
+        fn attempt(&self) -> Result<()> {
+            self.attempts.fetch_add(1, Ordering::SeqCst);
+            let failures = self.failures.load(Ordering::SeqCst);
+            if failures > 0 {
+                self.failures.store(failures - 1, Ordering::SeqCst);
+                bail!("Connection reset");
+            }
+            Ok(())
+        }
+    }
+
+    impl StorageBackend for FlakyBackend {
+        fn create(&self) -> Result<()> {
+            self.backend.create()
+        }
+        fn root_exists(&self) -> bool {
+            self.backend.root_exists()
+        }
+        fn read(&self, path: &Path) -> Result<Vec<u8>> {
+            self.attempt()?;
+            self.backend.read(path)
+        }
+        fn seek_read(&self, path: &Path, offset: u64, length: u64) -> Result<Vec<u8>> {
+            self.backend.seek_read(path, offset, length)
+        }
+        fn seek_read_from_end(&self, path: &Path, offset: i64, length: u64) -> Result<Vec<u8>> {
+            self.backend.seek_read_from_end(path, offset, length)
+        }
+        fn write(&self, path: &Path, contents: &[u8]) -> Result<()> {
+            self.attempt()?;
+            self.backend.write(path, contents)
+        }
+        fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+            self.backend.rename(from, to)?;
+            self.attempt()
+        }
+        fn remove_file(&self, file_path: &Path) -> Result<()> {
+            self.backend.remove_file(file_path)?;
+            self.attempt()
+        }
+        fn create_dir(&self, path: &Path) -> Result<()> {
+            self.backend.create_dir(path)
+        }
+        fn create_dir_all(&self, path: &Path) -> Result<()> {
+            self.backend.create_dir_all(path)
+        }
+        fn read_dir(&self, path: &Path) -> Result<Vec<PathBuf>> {
+            self.backend.read_dir(path)
+        }
+        fn remove_dir(&self, path: &Path) -> Result<()> {
+            self.backend.remove_dir(path)
+        }
+        fn remove_dir_all(&self, path: &Path) -> Result<()> {
+            self.backend.remove_dir_all(path)
+        }
+        fn exists(&self, path: &Path) -> bool {
+            self.backend.exists(path)
+        }
+        fn is_file(&self, path: &Path) -> bool {
+            self.backend.is_file(path)
+        }
+        fn is_dir(&self, path: &Path) -> bool {
+            self.backend.is_dir(path)
+        }
+    }
+
+    fn flaky_backend(root: &Path, failures: u32) -> Arc<FlakyBackend> {
+        Arc::new(FlakyBackend {
+            backend: LocalFS::new(root.to_path_buf()),
+            failures: AtomicU32::new(failures),
+            attempts: AtomicU32::new(0),
+        })
+    }
+
+    fn policy(retries: u32) -> RetryPolicy {
+        RetryPolicy::new(retries, Duration::from_millis(1))
+    }
+
+    #[test]
+    fn test_retry_transient_errors() -> Result<()> {
+        let tmp_dir = tempdir()?;
+        let flaky = flaky_backend(tmp_dir.path(), 2);
+        let backend = RetryBackend::new(flaky.clone(), policy(3));
+
+        backend.write(Path::new("file"), b"data")?;
+        assert_eq!(flaky.attempts.load(Ordering::SeqCst), 3);
+
+        flaky.failures.store(4, Ordering::SeqCst);
+        flaky.attempts.store(0, Ordering::SeqCst);
+        assert!(backend.read(Path::new("file")).is_err());
+        assert_eq!(flaky.attempts.load(Ordering::SeqCst), 4);
+        assert_eq!(backend.read(Path::new("file"))?, b"data");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_no_retry_on_permanent_errors() -> Result<()> {
+        let tmp_dir = tempdir()?;
+        let flaky = flaky_backend(tmp_dir.path(), 0);
+        let backend = RetryBackend::new(flaky.clone(), policy(3));
+
+        assert!(backend.read(Path::new("missing")).is_err());
+        assert_eq!(flaky.attempts.load(Ordering::SeqCst), 1);
+
+        let lost = Error::new(ChannelLost).context("Could not read file");
+        assert!(is_permanent(&lost));
+
+        Ok(())
+    }
+
+    /// Renames and removals that succeeded but reported an error are not repeated
+    #[test]
+    fn test_retry_lost_responses() -> Result<()> {
+        let tmp_dir = tempdir()?;
+        let flaky = flaky_backend(tmp_dir.path(), 0);
+        let backend = RetryBackend::new(flaky.clone(), policy(3));
+        backend.write(Path::new("a"), b"data")?;
+
+        flaky.failures.store(1, Ordering::SeqCst);
+        flaky.attempts.store(0, Ordering::SeqCst);
+        backend.rename(Path::new("a"), Path::new("b"))?;
+        assert_eq!(flaky.attempts.load(Ordering::SeqCst), 1);
+        assert!(!backend.exists(Path::new("a")));
+        assert_eq!(backend.read(Path::new("b"))?, b"data");
+
+        flaky.failures.store(1, Ordering::SeqCst);
+        flaky.attempts.store(0, Ordering::SeqCst);
+        backend.remove_file(Path::new("b"))?;
+        assert_eq!(flaky.attempts.load(Ordering::SeqCst), 1);
+        assert!(!backend.exists(Path::new("b")));
+
+        // A rename whose source is missing is not a lost response
+        assert!(backend.rename(Path::new("a"), Path::new("b")).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_permanent() {
+        let not_found = Error::from(std::io::Error::from(ErrorKind::NotFound));
+        assert!(is_permanent(&not_found.context("Could not read file")));
+        let denied = Error::from(std::io::Error::from(ErrorKind::PermissionDenied));
+        assert!(is_permanent(&denied));
+        let reset = Error::from(std::io::Error::from(ErrorKind::ConnectionReset));
+        assert!(!is_permanent(&reset.context("Could not write file")));
+        assert!(!is_permanent(&anyhow::anyhow!("Server error")));
+        let timeout: Result<()> = Err(std::io::Error::from(ErrorKind::TimedOut).into());
+        assert!(!is_permanent(&timeout.context("Timed out").unwrap_err()));
+        let eof = Error::from(std::io::Error::from(ErrorKind::UnexpectedEof));
+        assert!(!is_permanent(&eof.context("Failed to read file")));
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        let policy = RetryPolicy {
+            retries: 10,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(1),
+        };
+        for retry in 0..10 {
+            let expected = (Duration::from_millis(100) * 2u32.pow(retry)).min(policy.max_delay);
+            let delay = policy.delay(retry);
+            assert!(delay >= expected / 2 && delay <= expected);
+        }
+    }
+}
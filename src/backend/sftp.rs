@@ -15,7 +15,7 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 use std::{
-    io::{Read, Seek, SeekFrom, Write},
+    io::{ErrorKind, Read, Seek, SeekFrom, Write},
     net::TcpStream,
     path::{Path, PathBuf},
     sync::Arc,
@@ -23,7 +23,7 @@ use std::{
 
 use anyhow::{Context, Result, anyhow, bail};
 use crossbeam_channel::{Receiver, Sender, bounded};
-use ssh2::{RenameFlags, Session, Sftp};
+use ssh2::{FileStat, RenameFlags, Session, Sftp};
 
 use crate::ui;
 
@@ -108,12 +108,17 @@ impl SftpConnection {
     }
 }
 
-/// A pool of SFTP connections.
-pub struct SftpConnectionPool {
-    sender: Sender<SftpConnection>,
-    receiver: Receiver<SftpConnection>,
+/// A pool of connections. Connections that break are discarded and created again the next
+/// time they are needed.
+pub struct ConnectionPool<C> {
+    sender: Sender<Option<C>>,
+    receiver: Receiver<Option<C>>,
+    connect: Box<dyn Fn() -> Result<C> + Send + Sync>,
 }
 
+/// A pool of SFTP connections.
+pub type SftpConnectionPool = ConnectionPool<SftpConnection>;
+
 impl SftpConnectionPool {
     /// Creates a new connection pool with a specified capacity.
     pub fn new(
@@ -121,14 +126,27 @@ impl SftpConnectionPool {
         username: String,
         host: String,
         port: u16,
-        auth_method: &AuthMethod,
+        auth_method: AuthMethod,
+    ) -> Result<Self> {
+        Self::with_connect(capacity, move || {
+            SftpConnection::new(&username, &host, port, &auth_method)
+        })
+    }
+}
+
+impl<C> ConnectionPool<C> {
+    /// Creates a new connection pool with a specified capacity, opening the connections
+    /// with `connect`.
+    pub fn with_connect(
+        capacity: usize,
+        connect: impl Fn() -> Result<C> + Send + Sync + 'static,
     ) -> Result<Self> {
         let mut connections = Vec::new();
 
         const MAX_CONNECTION_RETRIES: u32 = 3;
         let mut connection_retry_count = 0;
         for _ in 0..capacity {
-            match SftpConnection::new(&username, &host, port, auth_method) {
+            match connect() {
                 Ok(conn) => connections.push(conn),
                 Err(e) => {
                     // We could not establish a connection. That could mean that we reached a limit
@@ -156,57 +174,99 @@ impl SftpConnectionPool {
         let (sender, receiver) = bounded(num_established_connections);
         for connection in connections {
             sender
-                .send(connection)
+                .send(Some(connection))
                 .expect("Failed to populate connection pool");
         }
 
-        Ok(Self { sender, receiver })
+        Ok(Self {
+            sender,
+            receiver,
+            connect: Box::new(connect),
+        })
     }
 
-    /// Gets an SFTP connection from the pool, blocking until one is available.
-    pub fn get(&self) -> Result<PooledSftpConnection> {
+    /// Gets a connection from the pool, blocking until one is available. A connection is
+    /// opened again if the previous one was discarded.
+    pub fn get(&self) -> Result<PooledConnection<C>> {
         let conn = self
             .receiver
             .recv()
             .with_context(|| "Failed to get connection from pool")?;
-        Ok(PooledSftpConnection {
+        let conn = match conn {
+            Some(conn) => conn,
+            None => match (self.connect)() {
+                Ok(conn) => conn,
+                Err(e) => {
+                    // Give the slot back so a later call can try again
+                    let _ = self.sender.send(None);
+                    return Err(e.context("Failed to reconnect to SFTP server"));
+                }
+            },
+        };
+        Ok(PooledConnection {
             connection: Some(conn),
             pool_sender: self.sender.clone(),
         })
     }
 }
 
+/// A wrapper for a connection obtained from the pool.
+/// When dropped, the connection is returned to the pool unless it was discarded.
+pub struct PooledConnection<C> {
+    connection: Option<C>,
+    pool_sender: Sender<Option<C>>,
+}
+
 /// A wrapper for an SFTP connection obtained from the pool.
-/// When dropped, the connection is returned to the pool.
-pub struct PooledSftpConnection {
-    connection: Option<SftpConnection>,
-    pool_sender: Sender<SftpConnection>,
+pub type PooledSftpConnection = PooledConnection<SftpConnection>;
+
+impl<C> PooledConnection<C> {
+    /// Closes the connection instead of returning it to the pool. The pool opens a new one
+    /// when it is needed.
+    pub fn discard(mut self) {
+        self.connection = None;
+    }
 }
 
-impl std::ops::Deref for PooledSftpConnection {
-    type Target = SftpConnection;
+impl<C> std::ops::Deref for PooledConnection<C> {
+    type Target = C;
 
     fn deref(&self) -> &Self::Target {
         self.connection.as_ref().unwrap()
     }
 }
 
-impl std::ops::DerefMut for PooledSftpConnection {
+impl<C> std::ops::DerefMut for PooledConnection<C> {
     fn deref_mut(&mut self) -> &mut Self::Target {
         self.connection.as_mut().unwrap()
     }
 }
 
-impl Drop for PooledSftpConnection {
+impl<C> Drop for PooledConnection<C> {
     fn drop(&mut self) {
-        if let Some(conn) = self.connection.take() {
-            self.pool_sender
-                .send(conn)
-                .expect("Failed to return connection to pool");
-        }
+        self.pool_sender
+            .send(self.connection.take())
+            .expect("Failed to return connection to pool");
     }
 }
 
+/// Whether an error leaves the connection usable. Errors reported by the SFTP server for a
+/// request keep the connection alive. Any other error may come from a dead session.
+fn is_request_error(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        if let Some(e) = cause.downcast_ref::<ssh2::Error>() {
+            matches!(e.code(), ssh2::ErrorCode::SFTP(_))
+        } else if let Some(e) = cause.downcast_ref::<std::io::Error>() {
+            matches!(
+                e.kind(),
+                ErrorKind::NotFound | ErrorKind::PermissionDenied | ErrorKind::AlreadyExists
+            )
+        } else {
+            false
+        }
+    })
+}
+
 pub struct SftpBackend {
     repo_path: PathBuf,
     pool: Arc<SftpConnectionPool>,
@@ -225,12 +285,31 @@ impl SftpBackend {
             username,
             host,
             port,
-            &auth_method,
+            auth_method,
         )?);
 
         Ok(Self { repo_path, pool })
     }
 
+    /// Runs an operation with a connection from the pool. The connection is discarded if
+    /// the operation fails with an error that may have broken it.
+    fn with_connection<T>(&self, op: impl FnOnce(&Sftp) -> Result<T>) -> Result<T> {
+        let conn = self.pool.get()?;
+        let result = op(conn.sftp());
+        if let Err(e) = &result
+            && !is_request_error(e)
+        {
+            ui::cli::verbose_2!("Discarding SFTP connection: {:#}", e);
+            conn.discard();
+        }
+        result
+    }
+
+    /// Returns the metadata of an exact path, or None if it cannot be read
+    fn lstat_exact(&self, path: &Path) -> Option<FileStat> {
+        self.with_connection(|sftp| Ok(sftp.lstat(path)?)).ok()
+    }
+
     #[inline]
     fn full_path(&self, path: &Path) -> PathBuf {
         self.repo_path.join(path)
@@ -274,10 +353,10 @@ impl SftpBackend {
             }
         }
 
-        if let Some(parent) = path.parent() {
-            if parent != Path::new("") {
-                self.create_dir_all_internal(parent, sftp)?;
-            }
+        if let Some(parent) = path.parent()
+            && parent != Path::new("")
+        {
+            self.create_dir_all_internal(parent, sftp)?;
         }
 
         sftp.mkdir(path, 0o755)
@@ -320,107 +399,104 @@ impl SftpBackend {
 
 impl StorageBackend for SftpBackend {
     fn create(&self) -> Result<()> {
-        let conn = self.pool.get()?;
-        self.create_dir_all_internal(&self.repo_path, conn.sftp())
+        self.with_connection(|sftp| self.create_dir_all_internal(&self.repo_path, sftp))
     }
 
     fn root_exists(&self) -> bool {
-        let conn = self.pool.get().unwrap();
-        self.exists_exact(&self.repo_path, conn.sftp())
+        self.lstat_exact(&self.repo_path).is_some()
     }
 
     fn read(&self, path: &Path) -> Result<Vec<u8>> {
         let full_path = self.full_path(path);
 
-        let conn = self.pool.get()?;
-        let mut file = conn.sftp().open(full_path).with_context(|| {
-            format!("Failed to open file {path:?}\' in sftp backend for reading")
-        })?;
-        let mut contents = Vec::new();
-        file.read_to_end(&mut contents)
-            .with_context(|| format!("Failed to read file {path:?}\' in sftp backend"))?;
-        Ok(contents)
+        self.with_connection(|sftp| {
+            let mut file = sftp.open(full_path).with_context(|| {
+                format!("Failed to open file {path:?}\' in sftp backend for reading")
+            })?;
+            let mut contents = Vec::new();
+            file.read_to_end(&mut contents)
+                .with_context(|| format!("Failed to read file {path:?}\' in sftp backend"))?;
+            Ok(contents)
+        })
     }
 
     fn seek_read(&self, path: &Path, offset: u64, length: u64) -> Result<Vec<u8>> {
         let full_path = self.full_path(path);
 
-        let conn = self.pool.get()?;
-        let mut file = conn.sftp().open(full_path).with_context(|| {
-            format!("Failed to open file {path:?}\' in sftp backend for ranged reading")
-        })?;
+        self.with_connection(|sftp| {
+            let mut file = sftp.open(full_path).with_context(|| {
+                format!("Failed to open file {path:?}\' in sftp backend for ranged reading")
+            })?;
 
-        // Read into preallocated vector
-        let mut contents = vec![0; length as usize];
+            // Read into preallocated vector
+            let mut contents = vec![0; length as usize];
 
-        if offset > 0 {
-            let _ = file.seek(SeekFrom::Start(offset));
-        }
+            if offset > 0 {
+                let _ = file.seek(SeekFrom::Start(offset));
+            }
 
-        file.read_exact(&mut contents)
-            .with_context(|| format!("Failed to seek read file {path:?}\' in sftp backend"))?;
-        Ok(contents)
+            file.read_exact(&mut contents)
+                .with_context(|| format!("Failed to seek read file {path:?}\' in sftp backend"))?;
+            Ok(contents)
+        })
     }
 
     fn write(&self, path: &Path, contents: &[u8]) -> Result<()> {
         let full_path = self.full_path(path);
 
-        let conn = self.pool.get()?;
-        let mut file = conn
-            .sftp()
-            .create(&full_path)
-            .with_context(|| format!("Failed to create file for writing: {path:?}"))?;
-        file.write_all(contents)
-            .with_context(|| format!("Failed to write to file: {path:?}"))?;
-        Ok(())
+        self.with_connection(|sftp| {
+            let mut file = sftp
+                .create(&full_path)
+                .with_context(|| format!("Failed to create file for writing: {path:?}"))?;
+            file.write_all(contents)
+                .with_context(|| format!("Failed to write to file: {path:?}"))?;
+            Ok(())
+        })
     }
 
     fn rename(&self, from: &Path, to: &Path) -> Result<()> {
         let full_path_from = self.full_path(from);
         let full_path_from_to = self.full_path(to);
 
-        let conn = self.pool.get()?;
-        conn.sftp()
-            .rename(
+        self.with_connection(|sftp| {
+            sftp.rename(
                 &full_path_from,
                 &full_path_from_to,
                 Some(RenameFlags::all()),
             )
             .with_context(|| format!("Failed to rename {from:?}\' to {to:?}\' in sftp backend"))
+        })
     }
 
     fn remove_file(&self, file_path: &Path) -> Result<()> {
         let full_path = self.full_path(file_path);
 
-        let conn = self.pool.get()?;
-        conn.sftp()
-            .unlink(&full_path)
-            .with_context(|| format!("Failed to remove file {file_path:?}\' in sftp backend"))
+        self.with_connection(|sftp| {
+            sftp.unlink(&full_path)
+                .with_context(|| format!("Failed to remove file {file_path:?}\' in sftp backend"))
+        })
     }
 
     fn create_dir(&self, path: &Path) -> Result<()> {
         let full_path = self.full_path(path);
 
-        let conn = self.pool.get()?;
-        self.create_dir_exact(&full_path, conn.sftp())
+        self.with_connection(|sftp| self.create_dir_exact(&full_path, sftp))
     }
 
     #[inline]
     fn create_dir_all(&self, path: &Path) -> Result<()> {
         let full_path = self.full_path(path);
 
-        let conn = self.pool.get()?;
-        self.create_dir_all_internal(&full_path, conn.sftp())
+        self.with_connection(|sftp| self.create_dir_all_internal(&full_path, sftp))
     }
 
     fn read_dir(&self, path: &Path) -> Result<Vec<PathBuf>> {
         let full_path = self.full_path(path);
 
-        let conn = self.pool.get()?;
-        let entries = conn
-            .sftp()
-            .readdir(full_path)
-            .with_context(|| format!("Could not list directory {path:?}\' in sftp backend"))?;
+        let entries = self.with_connection(|sftp| {
+            sftp.readdir(full_path)
+                .with_context(|| format!("Could not list directory {path:?}\' in sftp backend"))
+        })?;
 
         Ok(entries
             .iter()
@@ -431,63 +507,102 @@ impl StorageBackend for SftpBackend {
     fn remove_dir(&self, path: &Path) -> Result<()> {
         let full_path = self.full_path(path);
 
-        let conn = self.pool.get()?;
-        conn.sftp()
-            .rmdir(&full_path)
-            .with_context(|| format!("Failed to remove dir {path:?}\' in sftp backend"))
+        self.with_connection(|sftp| {
+            sftp.rmdir(&full_path)
+                .with_context(|| format!("Failed to remove dir {path:?}\' in sftp backend"))
+        })
     }
 
     fn remove_dir_all(&self, path: &Path) -> Result<()> {
         let full_path = self.full_path(path);
 
-        let conn = self.pool.get()?;
-        self.remove_dir_all_internal(&full_path, conn.sftp())
+        self.with_connection(|sftp| self.remove_dir_all_internal(&full_path, sftp))
     }
 
     fn exists(&self, path: &Path) -> bool {
-        let full_path = self.full_path(path);
-
-        let conn = self.pool.get().unwrap();
-        self.exists_exact(&full_path, conn.sftp())
+        self.lstat_exact(&self.full_path(path)).is_some()
     }
 
     fn is_file(&self, path: &Path) -> bool {
-        let full_path = self.full_path(path);
-
-        let conn = self.pool.get().unwrap();
-        match conn.sftp().lstat(&full_path) {
-            Ok(stat) => stat.is_file(),
-            Err(_) => false,
-        }
+        self.lstat_exact(&self.full_path(path))
+            .is_some_and(|stat| stat.is_file())
     }
 
     fn is_dir(&self, path: &Path) -> bool {
-        let full_path = self.full_path(path);
-
-        let conn = self.pool.get().unwrap();
-        match conn.sftp().lstat(&full_path) {
-            Ok(stat) => stat.is_dir(),
-            Err(_) => false,
-        }
+        self.lstat_exact(&self.full_path(path))
+            .is_some_and(|stat| stat.is_dir())
     }
 
     fn seek_read_from_end(&self, path: &Path, offset: i64, length: u64) -> Result<Vec<u8>> {
         let full_path = self.full_path(path);
 
-        let conn = self.pool.get()?;
-        let mut file = conn.sftp().open(full_path).with_context(|| {
-            format!("Failed to open file {path:?}\' in sftp backend for ranged reading")
-        })?;
+        self.with_connection(|sftp| {
+            let mut file = sftp.open(full_path).with_context(|| {
+                format!("Failed to open file {path:?}\' in sftp backend for ranged reading")
+            })?;
 
-        // Read into preallocated vector
-        let mut contents = vec![0; length as usize];
+            // Read into preallocated vector
+            let mut contents = vec![0; length as usize];
 
-        if offset > 0 {
-            let _ = file.seek(SeekFrom::End(offset));
-        }
+            if offset > 0 {
+                let _ = file.seek(SeekFrom::End(offset));
+            }
+
+            file.read_exact(&mut contents)
+                .with_context(|| format!("Failed to seek read file {path:?}\' in sftp backend"))?;
+            Ok(contents)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use super::*;
+
+    /// Test that discarded connections are opened again and healthy ones are reused
+    #[test]
+    fn test_pool_discards_broken_connections() -> Result<()> {
+        let num_connects = Arc::new(AtomicU32::new(0));
+        let connects = num_connects.clone();
+        let pool =
+            ConnectionPool::with_connect(2, move || Ok(connects.fetch_add(1, Ordering::SeqCst)))?;
+        assert_eq!(num_connects.load(Ordering::SeqCst), 2);
+
+        // Healthy connections go back to the pool
+        drop(pool.get()?);
+        drop(pool.get()?);
+        drop(pool.get()?);
+        assert_eq!(num_connects.load(Ordering::SeqCst), 2);
+
+        // A dropped connection is replaced by a new one the next time it is needed
+        let conn = pool.get()?;
+        let dropped = *conn;
+        conn.discard();
+        assert_eq!(num_connects.load(Ordering::SeqCst), 2);
+        let first = pool.get()?;
+        let second = pool.get()?;
+        assert_eq!(num_connects.load(Ordering::SeqCst), 3);
+        assert!(*first != dropped && *second != dropped);
+
+        Ok(())
+    }
 
-        file.read_exact(&mut contents)
-            .with_context(|| format!("Failed to seek read file {path:?}\' in sftp backend"))?;
-        Ok(contents)
+    #[test]
+    fn test_is_request_error() {
+        let sftp_error = ssh2::Error::new(ssh2::ErrorCode::SFTP(2), "No such file");
+        assert!(is_request_error(
+            &anyhow::Error::from(sftp_error).context("Could not read file")
+        ));
+        let not_found = std::io::Error::from(ErrorKind::NotFound);
+        assert!(is_request_error(&anyhow::Error::from(not_found)));
+
+        let session_error = ssh2::Error::new(ssh2::ErrorCode::Session(-43), "Socket recv");
+        assert!(!is_request_error(&anyhow::Error::from(session_error)));
+        let eof = std::io::Error::from(ErrorKind::UnexpectedEof);
+        assert!(!is_request_error(
+            &anyhow::Error::from(eof).context("Could not read file")
+        ));
     }
 }
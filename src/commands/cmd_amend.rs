@@ -23,6 +23,7 @@ use colored::Colorize;
 
 use crate::archiver::tree_serializer::init_pending_trees;
 use crate::commands::{EMPTY_TAG_MARK, parse_tags};
+use crate::repository::lock::LockKind;
 use crate::repository::snapshot::SnapshotStreamer;
 use crate::utils::format_size;
 use crate::{
@@ -71,7 +72,8 @@ pub struct CmdArgs {
 pub fn run(global_args: &GlobalArgs, args: &CmdArgs) -> Result<()> {
     let pass = utils::get_password_from_file(&global_args.password_file)?;
     let backend = new_backend_with_prompt(global_args, false)?;
-    let (repo, _) = repository::try_open(pass, global_args.key.as_ref(), backend)?;
+    let (_lock, repo, _) =
+        repository::try_open_locked(pass, global_args.key.as_ref(), backend, LockKind::Exclusive)?;
 
     let mut snapshots: Vec<(ID, Snapshot)> = Vec::new();
 
@@ -80,7 +82,7 @@ pub fn run(global_args: &GlobalArgs, args: &CmdArgs) -> Result<()> {
         let mut all_snapshots: Vec<(ID, Snapshot)> = snapshot_streamer.collect();
         snapshots.append(&mut all_snapshots);
     } else {
-        match find_use_snapshot(repo.clone(), &args.snapshot) {
+        match find_use_snapshot(repo.clone(), &args.snapshot, None) {
             Ok(Some((id, snap))) => snapshots.push((id, snap)),
             Ok(None) | Err(_) => bail!("Snapshot not found"),
         }
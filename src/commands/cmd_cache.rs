@@ -0,0 +1,108 @@
+// mapache is an incremental backup tool
+// Copyright (C) 2025  Javier Lancha Vázquez <javier.lancha@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::time::Duration;
+
+use anyhow::{Result, anyhow};
+use chrono::{DateTime, Local};
+use clap::Args;
+use colored::Colorize;
+
+use crate::{
+    global::defaults::{DEFAULT_CACHE_MAX_AGE_DAYS, SHORT_REPO_ID_LEN},
+    repository::cache,
+    ui::{
+        self,
+        table::{Alignment, Table},
+    },
+    utils,
+};
+
+use super::GlobalArgs;
+
+#[derive(Args, Debug)]
+#[clap(
+    about = "Manage the local cache",
+    long_about = "List the local caches of all repositories, or remove the caches that were not \
+                  used recently. The repository is not opened by this command."
+)]
+pub struct CmdArgs {
+    /// Remove caches not used within the maximum age
+    #[clap(long, default_value_t = false)]
+    pub cleanup: bool,
+
+    /// Maximum age in days of the caches kept by --cleanup
+    #[clap(long, default_value_t = DEFAULT_CACHE_MAX_AGE_DAYS)]
+    pub max_age: u64,
+}
+
+pub fn run(global_args: &GlobalArgs, args: &CmdArgs) -> Result<()> {
+    let cache_dir = global_args
+        .cache_dir
+        .clone()
+        .or_else(cache::default_cache_dir)
+        .ok_or_else(|| anyhow!("Could not find a cache directory. Use --cache-dir."))?;
+
+    if args.cleanup {
+        let max_age = Duration::from_secs(args.max_age * 24 * 3600);
+        let removed = cache::cleanup(&cache_dir, max_age)?;
+        for cache in &removed {
+            ui::cli::verbose_1!("Removed cache {}", cache.path.display());
+        }
+        ui::cli::log!(
+            "Removed {} ({})",
+            utils::format_count(removed.len(), "cache", "caches"),
+            utils::format_size(removed.iter().map(|cache| cache.size).sum(), 3)
+        );
+        return Ok(());
+    }
+
+    let caches = cache::list_caches(&cache_dir)?;
+    ui::cli::log!("Cache directory: {}", cache_dir.display());
+    if caches.is_empty() {
+        ui::cli::log!("No caches found");
+        return Ok(());
+    }
+
+    let mut table =
+        Table::new_with_alignments(vec![Alignment::Left, Alignment::Center, Alignment::Right]);
+    table.set_headers(vec![
+        "Repository".bold().to_string(),
+        "Last used ▼".bold().to_string(),
+        "Size".bold().to_string(),
+    ]);
+    for cache in &caches {
+        table.add_row(vec![
+            cache
+                .repo_id
+                .to_short_hex(SHORT_REPO_ID_LEN)
+                .yellow()
+                .to_string(),
+            cache
+                .last_used
+                .map(|time| {
+                    DateTime::<Local>::from(time)
+                        .format("%Y-%m-%d %H:%M:%S %Z")
+                        .to_string()
+                })
+                .unwrap_or_else(|| String::from("-")),
+            utils::format_size(cache.size, 3),
+        ]);
+    }
+    ui::cli::log!("{}", table.render());
+
+    Ok(())
+}
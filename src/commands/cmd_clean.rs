@@ -31,6 +31,7 @@ use crate::{
     repository::{
         self, RepositoryBackend,
         gc::{self},
+        lock::LockKind,
         verify::verify_snapshot_links,
     },
     ui::{
@@ -64,7 +65,8 @@ pub struct CmdArgs {
 pub fn run(global_args: &GlobalArgs, args: &CmdArgs) -> Result<()> {
     let pass = utils::get_password_from_file(&global_args.password_file)?;
     let backend = new_backend_with_prompt(global_args, args.dry_run)?;
-    let (repo, _) = repository::try_open(pass, global_args.key.as_ref(), backend)?;
+    let (_lock, repo, _) =
+        repository::try_open_locked(pass, global_args.key.as_ref(), backend, LockKind::Exclusive)?;
 
     run_with_repo(global_args, args, repo)
 }
@@ -89,7 +91,7 @@ pub fn run_with_repo(
     ]);
     plan_table.add_row(vec![
         "Referenced blobs".bold().to_string(),
-        plan.referenced_blobs.len().to_string(),
+        plan.referenced_blobs.count().to_string(),
     ]);
     plan_table.add_row(vec![
         "Referenced packs".bold().to_string(),
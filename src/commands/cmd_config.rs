@@ -0,0 +1,186 @@
+// mapache is an incremental backup tool
+// Copyright (C) 2025  Javier Lancha Vázquez <javier.lancha@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use anyhow::{Context, Result, anyhow};
+use chrono::Local;
+use clap::{Args, Subcommand, ValueEnum};
+use colored::Colorize;
+
+use crate::{
+    backend::new_backend_with_prompt,
+    global::defaults::SHORT_REPO_ID_LEN,
+    repository::{
+        self,
+        lock::RepositoryLock,
+        manifest::{self, Manifest},
+        storage::CompressionAlgorithm,
+    },
+    ui::{
+        self,
+        table::{Alignment, Table},
+    },
+    utils,
+};
+
+use super::GlobalArgs;
+
+#[derive(Args, Debug)]
+#[clap(about = "Show or change the repository parameters")]
+pub struct CmdArgs {
+    #[command(subcommand)]
+    pub command: ConfigCommand,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ConfigCommand {
+    /// Show the repository parameters
+    Show,
+
+    /// Change a repository parameter
+    Set(SetArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct SetArgs {
+    /// Parameter to change
+    #[clap(value_enum)]
+    pub key: ConfigKey,
+
+    /// New value
+    pub value: String,
+}
+
+/// Repository parameters that can be changed after the repository is created.
+/// Chunker parameters cannot be changed, as that would break deduplication.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ConfigKey {
+    /// Target size of pack files (e.g. 32MiB)
+    PackSize,
+
+    /// Compression algorithm for blob data (none, zstd)
+    Compression,
+
+    /// Compression level
+    CompressionLevel,
+
+    /// Store blobs uncompressed when compression does not reduce their size (true, false)
+    AutoCompression,
+}
+
+pub fn run(global_args: &GlobalArgs, args: &CmdArgs) -> Result<()> {
+    let pass = utils::get_password_from_file(&global_args.password_file)?;
+    let backend = new_backend_with_prompt(global_args, false)?;
+    let secure_storage =
+        repository::unlock_secure_storage(pass, global_args.key.as_ref(), backend.clone())?;
+
+    match &args.command {
+        ConfigCommand::Show => {
+            let manifest = repository::read_manifest(backend.as_ref(), &secure_storage)?;
+            show(&manifest);
+        }
+        ConfigCommand::Set(set_args) => {
+            let _lock = RepositoryLock::exclusive(backend.clone(), secure_storage.clone())?;
+
+            let mut manifest = repository::read_manifest(backend.as_ref(), &secure_storage)?;
+            set(&mut manifest, set_args.key, &set_args.value)?;
+            repository::write_manifest(&manifest, backend.as_ref(), &secure_storage)?;
+
+            ui::cli::log!(
+                "Set {} to {}",
+                set_args.key.to_possible_value().unwrap().get_name().bold(),
+                set_args.value.bold().green()
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Changes a parameter in the manifest
+pub fn set(manifest: &mut Manifest, key: ConfigKey, value: &str) -> Result<()> {
+    match key {
+        ConfigKey::PackSize => {
+            let pack_size = utils::parse_size_string(value)?;
+            manifest::validate_pack_size(pack_size)?;
+            manifest.pack_size = pack_size;
+        }
+        ConfigKey::Compression => {
+            manifest.compression.algorithm = CompressionAlgorithm::from_str(value, true)
+                .map_err(|_| anyhow!("Invalid compression algorithm \'{}\'", value))?;
+        }
+        ConfigKey::CompressionLevel => {
+            manifest.compression.level = value
+                .parse()
+                .with_context(|| format!("Invalid compression level \'{value}\'"))?;
+        }
+        ConfigKey::AutoCompression => {
+            manifest.compression.auto = value
+                .parse()
+                .with_context(|| format!("Invalid value \'{value}\'. Use true or false."))?;
+        }
+    }
+
+    manifest.compression.validate()?;
+    manifest.compression.check_version(manifest.version)
+}
+
+fn show(manifest: &Manifest) {
+    let mut table = Table::new_with_alignments(vec![Alignment::Left, Alignment::Left]);
+
+    let mut add_row = |key: &str, value: String| {
+        table.add_row(vec![key.bold().to_string(), value]);
+    };
+
+    add_row(
+        "id",
+        manifest
+            .id
+            .to_short_hex(SHORT_REPO_ID_LEN)
+            .yellow()
+            .to_string(),
+    );
+    add_row("version", manifest.version.to_string());
+    add_row(
+        "created",
+        manifest
+            .created_time
+            .with_timezone(&Local)
+            .format("%Y-%m-%d %H:%M:%S %Z")
+            .to_string(),
+    );
+    add_row("pack-size", utils::format_size(manifest.pack_size, 0));
+    add_row("compression", manifest.compression.algorithm.to_string());
+    add_row("compression-level", manifest.compression.level.to_string());
+    add_row("auto-compression", manifest.compression.auto.to_string());
+    add_row(
+        "chunk-min-size",
+        utils::format_size(manifest.chunker.min_size as u64, 0),
+    );
+    add_row(
+        "chunk-avg-size",
+        utils::format_size(manifest.chunker.avg_size as u64, 0),
+    );
+    add_row(
+        "chunk-max-size",
+        utils::format_size(manifest.chunker.max_size as u64, 0),
+    );
+    add_row(
+        "chunk-normalization",
+        manifest.chunker.normalization.to_string(),
+    );
+
+    ui::cli::log!("{}", table.render());
+}
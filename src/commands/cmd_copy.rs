@@ -0,0 +1,210 @@
+// mapache is an incremental backup tool
+// Copyright (C) 2025  Javier Lancha Vázquez <javier.lancha@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::PathBuf,
+};
+
+use anyhow::{Context, Result, bail};
+use chrono::{DateTime, Local};
+use clap::Args;
+use colored::Colorize;
+
+use crate::{
+    backend::new_backend_with_prompt,
+    global::{FileType, ID, defaults::SHORT_SNAPSHOT_ID_LEN},
+    repository::{
+        self, RepositoryBackend, lock::LockKind, rewriter::BlobRewriter, snapshot::Snapshot,
+    },
+    ui, utils,
+};
+
+use super::{FromRepoArgs, GlobalArgs};
+
+#[derive(Args, Debug)]
+#[clap(
+    about = "Copy snapshots from another repository",
+    long_about = "Copy snapshots from another repository into this repository. Only the blobs \
+                  missing in this repository are read from the source repository. Snapshots \
+                  that were already copied are skipped."
+)]
+pub struct CmdArgs {
+    /// Source repository path
+    #[clap(long, value_parser)]
+    pub from_repo: String,
+
+    #[clap(flatten)]
+    pub from: FromRepoArgs,
+
+    /// IDs (or ID prefixes) of the snapshots to copy. All snapshots are copied if none is given.
+    #[clap(value_parser)]
+    pub snapshots: Vec<String>,
+
+    /// Dry run. Displays what this command would do without
+    /// making changes to the repository.
+    #[clap(long, default_value_t = false)]
+    pub dry_run: bool,
+}
+
+pub fn run(global_args: &GlobalArgs, args: &CmdArgs) -> Result<()> {
+    let src_global_args = args.from.global_args(&args.from_repo, global_args);
+    let src_pass = utils::get_password_from_file(&src_global_args.password_file)?;
+    let src_backend = new_backend_with_prompt(&src_global_args, false)?;
+    let (_src_lock, src_repo, _) = repository::try_open_locked(
+        src_pass,
+        src_global_args.key.as_ref(),
+        src_backend,
+        LockKind::Shared,
+    )
+    .context("Could not open the source repository")?;
+
+    let pass = utils::get_password_from_file(&global_args.password_file)?;
+    let backend = new_backend_with_prompt(global_args, args.dry_run)?;
+    let (_lock, repo, _) =
+        repository::try_open_locked(pass, global_args.key.as_ref(), backend, LockKind::Shared)?;
+
+    let src_manifest = src_repo.load_manifest()?;
+    let manifest = repo.load_manifest()?;
+    if src_manifest.id == manifest.id {
+        bail!("The source and destination repositories are the same");
+    }
+
+    let snapshot_ids: Vec<ID> = if args.snapshots.is_empty() {
+        src_repo.list_snapshot_ids()?
+    } else {
+        args.snapshots
+            .iter()
+            .map(|prefix| src_repo.find(FileType::Snapshot, prefix).map(|(id, _)| id))
+            .collect::<Result<_>>()?
+    };
+    let mut snapshots = snapshot_ids
+        .into_iter()
+        .map(|id| src_repo.load_snapshot(&id).map(|snapshot| (id, snapshot)))
+        .collect::<Result<Vec<_>>>()?;
+
+    // Copy parents before their children
+    snapshots.sort_by_key(|(_, snapshot)| snapshot.timestamp);
+
+    // Source snapshot ID -> destination snapshot ID
+    let mut copied: BTreeMap<ID, ID> = BTreeMap::new();
+    let existing = existing_snapshots(repo.as_ref())?;
+    let mut to_copy = Vec::new();
+    for (id, snapshot) in snapshots {
+        match existing.get(&SnapshotKey::new(&snapshot)) {
+            Some(dst_id) => {
+                ui::cli::verbose_1!(
+                    "Snapshot {} already copied as {}",
+                    id.to_short_hex(SHORT_SNAPSHOT_ID_LEN).bold().yellow(),
+                    dst_id.to_short_hex(SHORT_SNAPSHOT_ID_LEN).bold().yellow()
+                );
+                copied.insert(id, dst_id.clone());
+            }
+            None => to_copy.push((id, snapshot)),
+        }
+    }
+
+    if to_copy.is_empty() {
+        ui::cli::log!("All snapshots are already copied. Nothing to do.");
+        return Ok(());
+    }
+
+    if args.dry_run {
+        for (id, _) in &to_copy {
+            ui::cli::log!(
+                "Would copy snapshot {}",
+                id.to_short_hex(SHORT_SNAPSHOT_ID_LEN).bold().yellow()
+            );
+        }
+        ui::cli::log!("{} No snapshots copied", "[DRY RUN]".bold().purple());
+        return Ok(());
+    }
+
+    // Blobs keep their IDs if both repositories calculate them the same way, that is, with
+    // the same format version and ID key. Otherwise, all blobs are read and saved with the
+    // IDs of the destination.
+    let keep_ids =
+        src_manifest.version == manifest.version && src_manifest.id_key == manifest.id_key;
+    let mut rewriter = BlobRewriter::new(src_repo.as_ref(), repo.as_ref(), keep_ids);
+
+    let num_snapshots = to_copy.len();
+    for (i, (id, mut snapshot)) in to_copy.into_iter().enumerate() {
+        let short_id = id.to_short_hex(SHORT_SNAPSHOT_ID_LEN).bold().yellow();
+        ui::cli::log!(
+            "Copying snapshot {} ({}/{})",
+            short_id,
+            i + 1,
+            num_snapshots
+        );
+
+        // The parent is only kept if it exists in the destination
+        if snapshot
+            .parent
+            .as_ref()
+            .is_some_and(|parent| !copied.contains_key(parent))
+        {
+            snapshot.parent = None;
+        }
+
+        let new_id = rewriter
+            .rewrite_snapshot(&mut snapshot, &copied)
+            .with_context(|| format!("Could not copy snapshot {}", id.to_hex()))?;
+        ui::cli::verbose_1!(
+            "Snapshot {} copied as {}",
+            short_id,
+            new_id.to_short_hex(SHORT_SNAPSHOT_ID_LEN).bold().green()
+        );
+        copied.insert(id, new_id);
+    }
+
+    let (raw, encoded) = rewriter.added_size;
+    ui::cli::log!(
+        "Copied {} snapshots. Added {} ({} encoded)",
+        num_snapshots,
+        utils::format_size(raw, 3).bold().yellow(),
+        utils::format_size(encoded, 3).bold().yellow()
+    );
+
+    Ok(())
+}
+
+/// Identifies a snapshot across repositories. The tree IDs cannot be compared if the
+/// repositories calculate IDs differently.
+#[derive(Debug, PartialEq, Eq, Hash)]
+struct SnapshotKey {
+    timestamp: DateTime<Local>,
+    root: PathBuf,
+    paths: Vec<PathBuf>,
+}
+
+impl SnapshotKey {
+    fn new(snapshot: &Snapshot) -> Self {
+        Self {
+            timestamp: snapshot.timestamp,
+            root: snapshot.root.clone(),
+            paths: snapshot.paths.clone(),
+        }
+    }
+}
+
+fn existing_snapshots(repo: &dyn RepositoryBackend) -> Result<HashMap<SnapshotKey, ID>> {
+    let mut snapshots = HashMap::new();
+    for id in repo.list_snapshot_ids()? {
+        let snapshot = repo.load_snapshot(&id)?;
+        snapshots.insert(SnapshotKey::new(&snapshot), id);
+    }
+    Ok(snapshots)
+}
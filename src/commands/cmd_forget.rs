@@ -25,6 +25,7 @@ use crate::backend::new_backend_with_prompt;
 use crate::commands::parse_tags;
 use crate::global::defaults::DEFAULT_GC_TOLERANCE;
 use crate::global::{self, FileType, ID};
+use crate::repository::lock::LockKind;
 use crate::repository::snapshot::{Snapshot, SnapshotStreamer};
 use crate::ui::table::{Alignment, Table};
 use crate::{commands, repository, ui, utils};
@@ -50,6 +51,10 @@ pub struct CmdArgs {
     #[arg(long = "tags", value_parser)]
     pub tags_str: Option<String>,
 
+    /// Only consider snapshots created in this host
+    #[arg(long)]
+    pub host: Option<String>,
+
     /// Keep the last N snapshots.
     #[arg(long, group = "retention_rules")]
     pub keep_last: Option<usize>,
@@ -141,14 +146,18 @@ pub fn parse_retention_number(s: &str) -> Result<usize> {
 pub fn run(global_args: &GlobalArgs, args: &CmdArgs) -> Result<()> {
     let pass = utils::get_password_from_file(&global_args.password_file)?;
     let backend = new_backend_with_prompt(global_args, args.dry_run)?;
-    let (repo, _) = repository::try_open(pass, global_args.key.as_ref(), backend)?;
+    let (_lock, repo, _) =
+        repository::try_open_locked(pass, global_args.key.as_ref(), backend, LockKind::Exclusive)?;
 
-    // All sapshots, filter by tags and sorted by timestamp
+    // All sapshots, filter by tags and host and sorted by timestamp
     let mut snapshots_sorted: Vec<(ID, Snapshot)> = SnapshotStreamer::new(repo.clone())?.collect();
     if let Some(tags) = &args.tags_str {
         let tags = parse_tags(Some(tags));
         snapshots_sorted.retain(|(_id, sn)| sn.has_tags(&tags));
     }
+    if let Some(host) = &args.host {
+        snapshots_sorted.retain(|(_id, sn)| sn.has_host(host));
+    }
     snapshots_sorted.sort_by_key(|(_id, snapshot)| snapshot.timestamp);
 
     let mut ids_to_keep: HashSet<ID> = HashSet::new();
@@ -404,7 +413,7 @@ pub fn apply_retention_rules(
 
 #[cfg(test)]
 mod tests {
-    use std::{collections::BTreeSet, path::PathBuf};
+    use std::path::PathBuf;
 
     use chrono::{NaiveDate, TimeZone};
 
@@ -419,401 +428,71 @@ mod tests {
         )
     }
 
+    fn local_time(
+        year: i32,
+        month: u32,
+        day: u32,
+        hour: u32,
+        min: u32,
+        sec: u32,
+    ) -> DateTime<Local> {
+        Local
+            .from_local_datetime(
+                &NaiveDate::from_ymd_opt(year, month, day)
+                    .unwrap()
+                    .and_hms_opt(hour, min, sec)
+                    .unwrap(),
+            )
+            .unwrap()
+    }
+
+    /// Days after 2023-01-01
+    fn day(days: i64) -> DateTime<Local> {
+        local_time(2023, 1, 1, 0, 0, 0) + Duration::days(days)
+    }
+
+    /// A snapshot whose ID and tree ID are both `id`
+    fn snapshot(id: u32, timestamp: DateTime<Local>, tags: &[&str]) -> (ID, Snapshot) {
+        let id = ID::from_hex(&format!("{id:064x}")).unwrap();
+        let snapshot = Snapshot {
+            timestamp,
+            parent: None,
+            tree: id.clone(),
+            root: PathBuf::from("/"),
+            paths: Vec::new(),
+            tags: tags.iter().map(|s| s.to_string()).collect(),
+            description: None,
+            hostname: None,
+            username: None,
+            program_version: None,
+            summary: Default::default(),
+        };
+        (id, snapshot)
+    }
+
     fn create_mock_snapshots() -> Vec<(ID, Snapshot)> {
-        let snapshots = vec![
+        vec![
             // Daily snapshots for a few days
-            (
-                ID::from_hex("0000000000000000000000000000000000000000000000000000000000000000")
-                    .unwrap(),
-                Snapshot {
-                    timestamp: Local
-                        .from_local_datetime(
-                            &NaiveDate::from_ymd_opt(2023, 1, 1)
-                                .unwrap()
-                                .and_hms_opt(0, 0, 0)
-                                .unwrap(),
-                        )
-                        .unwrap()
-                        + Duration::days(21),
-                    parent: None,
-                    tree: ID::from_hex(
-                        "0000000000000000000000000000000000000000000000000000000000000000",
-                    )
-                    .unwrap(),
-                    root: PathBuf::from("/"),
-                    paths: Vec::new(),
-                    tags: ["tag0".to_string(), "tag1".to_string()]
-                        .into_iter()
-                        .map(|s| s.to_string())
-                        .collect(),
-                    description: None,
-                    summary: Default::default(),
-                },
-            ),
-            (
-                ID::from_hex("0000000000000000000000000000000000000000000000000000000000000001")
-                    .unwrap(),
-                Snapshot {
-                    timestamp: Local
-                        .from_local_datetime(
-                            &NaiveDate::from_ymd_opt(2023, 1, 1)
-                                .unwrap()
-                                .and_hms_opt(0, 0, 0)
-                                .unwrap(),
-                        )
-                        .unwrap()
-                        + Duration::days(1),
-                    parent: None,
-                    tree: ID::from_hex(
-                        "0000000000000000000000000000000000000000000000000000000000000001",
-                    )
-                    .unwrap(),
-                    root: PathBuf::from("/"),
-                    paths: Vec::new(),
-                    tags: ["tag0".to_string()]
-                        .into_iter()
-                        .map(|s| s.to_string())
-                        .collect(),
-                    description: None,
-                    summary: Default::default(),
-                },
-            ),
-            (
-                ID::from_hex("0000000000000000000000000000000000000000000000000000000000000002")
-                    .unwrap(),
-                Snapshot {
-                    timestamp: Local
-                        .from_local_datetime(
-                            &NaiveDate::from_ymd_opt(2023, 1, 1)
-                                .unwrap()
-                                .and_hms_opt(0, 0, 0)
-                                .unwrap(),
-                        )
-                        .unwrap()
-                        + Duration::days(2),
-                    parent: None,
-                    tree: ID::from_hex(
-                        "0000000000000000000000000000000000000000000000000000000000000002",
-                    )
-                    .unwrap(),
-                    root: PathBuf::from("/"),
-                    paths: Vec::new(),
-                    tags: BTreeSet::new(),
-                    description: None,
-                    summary: Default::default(),
-                },
-            ),
-            (
-                ID::from_hex("0000000000000000000000000000000000000000000000000000000000000003")
-                    .unwrap(),
-                Snapshot {
-                    timestamp: Local
-                        .from_local_datetime(
-                            &NaiveDate::from_ymd_opt(2023, 1, 1)
-                                .unwrap()
-                                .and_hms_opt(0, 0, 0)
-                                .unwrap(),
-                        )
-                        .unwrap()
-                        + Duration::days(3),
-                    parent: None,
-                    tree: ID::from_hex(
-                        "0000000000000000000000000000000000000000000000000000000000000003",
-                    )
-                    .unwrap(),
-                    root: PathBuf::from("/"),
-                    paths: Vec::new(),
-                    tags: BTreeSet::new(),
-                    description: None,
-                    summary: Default::default(),
-                },
-            ),
-            (
-                ID::from_hex("0000000000000000000000000000000000000000000000000000000000000004")
-                    .unwrap(),
-                Snapshot {
-                    timestamp: Local
-                        .from_local_datetime(
-                            &NaiveDate::from_ymd_opt(2023, 1, 1)
-                                .unwrap()
-                                .and_hms_opt(0, 0, 0)
-                                .unwrap(),
-                        )
-                        .unwrap()
-                        + Duration::days(4),
-                    parent: None,
-                    tree: ID::from_hex(
-                        "0000000000000000000000000000000000000000000000000000000000000004",
-                    )
-                    .unwrap(),
-                    root: PathBuf::from("/"),
-                    paths: Vec::new(),
-                    tags: BTreeSet::new(),
-                    description: None,
-                    summary: Default::default(),
-                },
-            ),
+            snapshot(0x0, day(21), &["tag0", "tag1"]),
+            snapshot(0x1, day(1), &["tag0"]),
+            snapshot(0x2, day(2), &[]),
+            snapshot(0x3, day(3), &[]),
+            snapshot(0x4, day(4), &[]),
             // Weekly snapshots (e.g., one per week, starting from week 1, 2023)
-            (
-                ID::from_hex("0000000000000000000000000000000000000000000000000000000000000005")
-                    .unwrap(),
-                Snapshot {
-                    // End of Week 1
-                    timestamp: Local
-                        .from_local_datetime(
-                            &NaiveDate::from_ymd_opt(2023, 1, 1)
-                                .unwrap()
-                                .and_hms_opt(0, 0, 0)
-                                .unwrap(),
-                        )
-                        .unwrap()
-                        + Duration::days(7),
-                    parent: None,
-                    tree: ID::from_hex(
-                        "0000000000000000000000000000000000000000000000000000000000000005",
-                    )
-                    .unwrap(),
-                    root: PathBuf::from("/"),
-                    paths: Vec::new(),
-                    tags: BTreeSet::new(),
-                    description: None,
-                    summary: Default::default(),
-                },
-            ),
-            (
-                ID::from_hex("0000000000000000000000000000000000000000000000000000000000000006")
-                    .unwrap(),
-                Snapshot {
-                    // End of Week 2
-                    timestamp: Local
-                        .from_local_datetime(
-                            &NaiveDate::from_ymd_opt(2023, 1, 1)
-                                .unwrap()
-                                .and_hms_opt(0, 0, 0)
-                                .unwrap(),
-                        )
-                        .unwrap()
-                        + Duration::days(14),
-                    parent: None,
-                    tree: ID::from_hex(
-                        "0000000000000000000000000000000000000000000000000000000000000006",
-                    )
-                    .unwrap(),
-                    root: PathBuf::from("/"),
-                    paths: Vec::new(),
-                    tags: BTreeSet::new(),
-                    description: None,
-                    summary: Default::default(),
-                },
-            ),
-            (
-                ID::from_hex("0000000000000000000000000000000000000000000000000000000000000106")
-                    .unwrap(),
-                Snapshot {
-                    // Week 3
-                    timestamp: Local
-                        .from_local_datetime(
-                            &NaiveDate::from_ymd_opt(2023, 1, 1)
-                                .unwrap()
-                                .and_hms_opt(0, 0, 0)
-                                .unwrap(),
-                        )
-                        .unwrap()
-                        + Duration::days(15),
-                    parent: None,
-                    tree: ID::from_hex(
-                        "0000000000000000000000000000000000000000000000000000000000000106",
-                    )
-                    .unwrap(),
-                    root: PathBuf::from("/"),
-                    paths: Vec::new(),
-                    tags: BTreeSet::new(),
-                    description: None,
-                    summary: Default::default(),
-                },
-            ),
-            (
-                ID::from_hex("0000000000000000000000000000000000000000000000000000000000000206")
-                    .unwrap(),
-                Snapshot {
-                    // Week 3
-                    timestamp: Local
-                        .from_local_datetime(
-                            &NaiveDate::from_ymd_opt(2023, 1, 1)
-                                .unwrap()
-                                .and_hms_opt(0, 0, 0)
-                                .unwrap(),
-                        )
-                        .unwrap()
-                        + Duration::days(16),
-                    parent: None,
-                    tree: ID::from_hex(
-                        "0000000000000000000000000000000000000000000000000000000000000206",
-                    )
-                    .unwrap(),
-                    root: PathBuf::from("/"),
-                    paths: Vec::new(),
-                    tags: BTreeSet::new(),
-                    description: None,
-                    summary: Default::default(),
-                },
-            ),
-            (
-                ID::from_hex("0000000000000000000000000000000000000000000000000000000000000007")
-                    .unwrap(),
-                Snapshot {
-                    // End of Week 3
-                    timestamp: Local
-                        .from_local_datetime(
-                            &NaiveDate::from_ymd_opt(2023, 1, 1)
-                                .unwrap()
-                                .and_hms_opt(0, 0, 0)
-                                .unwrap(),
-                        )
-                        .unwrap()
-                        + Duration::days(21),
-                    parent: None,
-                    tree: ID::from_hex(
-                        "0000000000000000000000000000000000000000000000000000000000000007",
-                    )
-                    .unwrap(),
-                    root: PathBuf::from("/"),
-                    paths: Vec::new(),
-                    tags: BTreeSet::new(),
-                    description: None,
-                    summary: Default::default(),
-                },
-            ),
+            snapshot(0x5, day(7), &[]),    // End of Week 1
+            snapshot(0x6, day(14), &[]),   // End of Week 2
+            snapshot(0x106, day(15), &[]), // Week 3
+            snapshot(0x206, day(16), &[]), // Week 3
+            snapshot(0x7, day(21), &[]),   // End of Week 3
             // Monthly snapshots
-            (
-                ID::from_hex("0000000000000000000000000000000000000000000000000000000000000008")
-                    .unwrap(),
-                Snapshot {
-                    // End of Jan
-                    timestamp: Local
-                        .from_local_datetime(
-                            &NaiveDate::from_ymd_opt(2023, 1, 28)
-                                .unwrap()
-                                .and_hms_opt(23, 59, 59)
-                                .unwrap(),
-                        )
-                        .unwrap(),
-                    parent: None,
-                    tree: ID::from_hex(
-                        "0000000000000000000000000000000000000000000000000000000000000008",
-                    )
-                    .unwrap(),
-                    root: PathBuf::from("/"),
-                    paths: Vec::new(),
-                    tags: BTreeSet::new(),
-                    description: None,
-                    summary: Default::default(),
-                },
-            ),
-            (
-                ID::from_hex("0000000000000000000000000000000000000000000000000000000000000009")
-                    .unwrap(),
-                Snapshot {
-                    // End of Feb
-                    timestamp: Local
-                        .from_local_datetime(
-                            &NaiveDate::from_ymd_opt(2023, 2, 28)
-                                .unwrap()
-                                .and_hms_opt(23, 59, 0)
-                                .unwrap(),
-                        )
-                        .unwrap(),
-                    parent: None,
-                    tree: ID::from_hex(
-                        "0000000000000000000000000000000000000000000000000000000000000009",
-                    )
-                    .unwrap(),
-                    root: PathBuf::from("/"),
-                    paths: Vec::new(),
-                    tags: BTreeSet::new(),
-                    description: None,
-                    summary: Default::default(),
-                },
-            ),
+            snapshot(0x8, local_time(2023, 1, 28, 23, 59, 59), &[]), // End of Jan
+            snapshot(0x9, local_time(2023, 2, 28, 23, 59, 0), &[]),  // End of Feb
             // Yearly snapshots
-            (
-                ID::from_hex("000000000000000000000000000000000000000000000000000000000000000A")
-                    .unwrap(),
-                Snapshot {
-                    // End of 2023
-                    timestamp: Local
-                        .from_local_datetime(
-                            &NaiveDate::from_ymd_opt(2023, 12, 31)
-                                .unwrap()
-                                .and_hms_opt(23, 59, 0)
-                                .unwrap(),
-                        )
-                        .unwrap(),
-                    parent: None,
-                    tree: ID::from_hex(
-                        "000000000000000000000000000000000000000000000000000000000000000A",
-                    )
-                    .unwrap(),
-                    root: PathBuf::from("/"),
-                    paths: Vec::new(),
-                    tags: BTreeSet::new(),
-                    description: None,
-                    summary: Default::default(),
-                },
-            ),
-            (
-                ID::from_hex("000000000000000000000000000000000000000000000000000000000000000B")
-                    .unwrap(),
-                Snapshot {
-                    // End of 2024
-                    timestamp: Local
-                        .from_local_datetime(
-                            &NaiveDate::from_ymd_opt(2024, 12, 31)
-                                .unwrap()
-                                .and_hms_opt(23, 59, 0)
-                                .unwrap(),
-                        )
-                        .unwrap(),
-                    parent: None,
-                    tree: ID::from_hex(
-                        "000000000000000000000000000000000000000000000000000000000000000B",
-                    )
-                    .unwrap(),
-                    root: PathBuf::from("/"),
-                    paths: Vec::new(),
-                    tags: BTreeSet::new(),
-                    description: None,
-                    summary: Default::default(),
-                },
-            ),
-            (
-                ID::from_hex("000000000000000000000000000000000000000000000000000000000000000C")
-                    .unwrap(),
-                Snapshot {
-                    // Current time (for testing KeepWithin)
-                    timestamp: Local
-                        .from_local_datetime(
-                            &NaiveDate::from_ymd_opt(2025, 5, 25)
-                                .unwrap()
-                                .and_hms_opt(20, 29, 46)
-                                .unwrap(),
-                        )
-                        .unwrap(),
-                    parent: None,
-                    tree: ID::from_hex(
-                        "000000000000000000000000000000000000000000000000000000000000000C",
-                    )
-                    .unwrap(),
-                    root: PathBuf::from("/"),
-                    paths: Vec::new(),
-                    tags: BTreeSet::new(),
-                    description: None,
-                    summary: Default::default(),
-                },
-            ),
-        ];
-
-        snapshots
+            snapshot(0xA, local_time(2023, 12, 31, 23, 59, 0), &[]), // End of 2023
+            snapshot(0xB, local_time(2024, 12, 31, 23, 59, 0), &[]), // End of 2024
+            // Current time (for testing KeepWithin)
+            snapshot(0xC, local_time(2025, 5, 25, 20, 29, 46), &[]),
+        ]
     }
 
     #[test]
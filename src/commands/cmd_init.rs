@@ -14,16 +14,18 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use clap::Args;
 use colored::Colorize;
 
 use crate::backend::new_backend_with_prompt;
+use crate::global::defaults::SHORT_REPO_ID_LEN;
+use crate::repository::manifest::{self, RepositoryParams};
 use crate::repository::{LATEST_REPOSITORY_VERSION, RepoVersion};
 use crate::ui;
 use crate::{repository, utils};
 
-use super::GlobalArgs;
+use super::{ChunkerArgs, CompressionArgs, FromRepoArgs, GlobalArgs, KdfArgs};
 
 #[derive(Args, Debug)]
 #[clap(about = "Initialize a new repository")]
@@ -31,19 +33,101 @@ pub struct CmdArgs {
     /// Repository version
     #[clap(long, default_value_t = LATEST_REPOSITORY_VERSION)]
     pub repository_version: RepoVersion,
+
+    #[clap(flatten)]
+    pub kdf: KdfArgs,
+
+    #[clap(flatten)]
+    pub compression: CompressionArgs,
+
+    #[clap(flatten)]
+    pub chunker: ChunkerArgs,
+
+    /// Target size of pack files (e.g. 32MiB) [default: 16MiB]
+    #[clap(long, value_parser = utils::parse_size_string)]
+    pub pack_size: Option<u64>,
+
+    /// Copy the version, chunker parameters and blob ID key from another repository, so
+    /// snapshots taken to either repository produce the same blob IDs
+    #[clap(
+        long,
+        value_parser,
+        conflicts_with_all = [
+            "repository_version",
+            "chunk_min_size",
+            "chunk_avg_size",
+            "chunk_max_size",
+            "chunk_normalization",
+        ]
+    )]
+    pub copy_params_from: Option<String>,
+
+    #[clap(flatten)]
+    pub from: FromRepoArgs,
 }
 
 pub fn run(global_args: &GlobalArgs, args: &CmdArgs) -> Result<()> {
+    let defaults = RepositoryParams::default();
+    let pack_size = args.pack_size.unwrap_or(defaults.pack_size);
+    manifest::validate_pack_size(pack_size)?;
+    let params = RepositoryParams {
+        compression: args.compression.apply(defaults.compression)?,
+        chunker: args.chunker.apply(defaults.chunker)?,
+        pack_size,
+    };
+
+    // Read the source manifest before prompting for the new password
+    let source_manifest = match &args.copy_params_from {
+        Some(source_repo) => {
+            let src_global_args = args.from.global_args(source_repo, global_args);
+            let src_pass = utils::get_password_from_file(&src_global_args.password_file)?;
+            let src_backend = new_backend_with_prompt(&src_global_args, false)?;
+            let src_secure_storage = repository::unlock_secure_storage(
+                src_pass,
+                src_global_args.key.as_ref(),
+                src_backend.clone(),
+            )
+            .context("Could not open the source repository")?;
+            Some(repository::read_manifest(
+                src_backend.as_ref(),
+                &src_secure_storage,
+            )?)
+        }
+        None => None,
+    };
+
     let pass = utils::get_password_from_file(&global_args.password_file)?;
     let backend = new_backend_with_prompt(global_args, false)?;
 
     ui::cli::log!("Initializing a new repository in \'{}\'", &global_args.repo);
-    repository::init_repository_with_version(
-        pass,
-        global_args.key.as_ref(),
-        args.repository_version,
-        backend,
-    )?;
+    match &source_manifest {
+        Some(source_manifest) => {
+            ui::cli::log!(
+                "Copying parameters from repository {}",
+                source_manifest
+                    .id
+                    .to_short_hex(SHORT_REPO_ID_LEN)
+                    .bold()
+                    .yellow()
+            );
+            repository::init_repository_with_params_from(
+                pass,
+                global_args.key.as_ref(),
+                args.kdf.params(),
+                params,
+                source_manifest,
+                backend,
+            )?;
+        }
+        None => repository::init_repository_with_version(
+            pass,
+            global_args.key.as_ref(),
+            args.repository_version,
+            args.kdf.params(),
+            params,
+            backend,
+        )?,
+    }
 
     ui::cli::warning!(
         "{}\n{}",
@@ -0,0 +1,310 @@
+// mapache is an incremental backup tool
+// Copyright (C) 2025  Javier Lancha Vázquez <javier.lancha@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::{path::PathBuf, sync::Arc};
+
+use anyhow::{Context, Result, bail};
+use chrono::Local;
+use clap::{Args, Subcommand};
+use colored::Colorize;
+use zstd::DEFAULT_COMPRESSION_LEVEL;
+
+use crate::{
+    backend::{StorageBackend, new_backend_with_prompt},
+    global::{ID, defaults::SHORT_REPO_ID_LEN},
+    repository::{
+        keys::{self, generate_key_file},
+        lock::RepositoryLock,
+        storage::SecureStorage,
+    },
+    ui::{
+        self,
+        table::{Alignment, Table},
+    },
+    utils,
+};
+
+use super::{GlobalArgs, KdfArgs};
+
+#[derive(Args, Debug)]
+#[clap(
+    about = "Manage repository keys",
+    long_about = "Manage repository keys. Every key holds a copy of the master key encrypted \
+                  with a different password, so any of them can be used to open the repository."
+)]
+pub struct CmdArgs {
+    #[command(subcommand)]
+    pub command: KeyCommand,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum KeyCommand {
+    /// List all keys in the repository
+    List,
+
+    /// Add a new key with a new password
+    Add(AddArgs),
+
+    /// Remove a key from the repository
+    Remove(RemoveArgs),
+
+    /// Change the password of the key used to open the repository
+    Passwd(PasswdArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct AddArgs {
+    /// A label to identify the new key
+    #[clap(long)]
+    pub label: Option<String>,
+
+    /// Path to a file to read the new password
+    #[clap(long, value_parser)]
+    pub new_password_file: Option<PathBuf>,
+
+    #[clap(flatten)]
+    pub kdf: KdfArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct RemoveArgs {
+    /// ID (or ID prefix) of the key to remove
+    #[clap(value_parser)]
+    pub id: String,
+}
+
+#[derive(Args, Debug)]
+pub struct PasswdArgs {
+    /// Path to a file to read the new password
+    #[clap(long, value_parser)]
+    pub new_password_file: Option<PathBuf>,
+}
+
+pub fn run(global_args: &GlobalArgs, args: &CmdArgs) -> Result<()> {
+    let backend = new_backend_with_prompt(global_args, false)?;
+    if !backend.root_exists() {
+        bail!("Could not open a repository. The path does not exist.");
+    }
+
+    let (current_key_id, master_key) = unlock_master_key(global_args, backend.clone())?;
+
+    // Commands that change the keys hold an exclusive lock, so they don't race each other
+    let _lock = match &args.command {
+        KeyCommand::List => None,
+        KeyCommand::Add(_) | KeyCommand::Remove(_) | KeyCommand::Passwd(_) => {
+            let secure_storage = Arc::new(
+                SecureStorage::build()
+                    .with_compression(DEFAULT_COMPRESSION_LEVEL)
+                    .with_key(master_key.clone()),
+            );
+            Some(RepositoryLock::exclusive(backend.clone(), secure_storage)?)
+        }
+    };
+
+    match &args.command {
+        KeyCommand::List => list(backend, current_key_id.as_ref()),
+        KeyCommand::Add(add_args) => add(backend, master_key, add_args),
+        KeyCommand::Remove(remove_args) => remove(backend, current_key_id.as_ref(), remove_args),
+        KeyCommand::Passwd(passwd_args) => passwd(
+            global_args,
+            backend,
+            current_key_id,
+            master_key,
+            passwd_args,
+        ),
+    }
+}
+
+/// Retrieves the master key. Returns the ID of the key that matched the password,
+/// unless the master key was read from an external KeyFile.
+fn unlock_master_key(
+    global_args: &GlobalArgs,
+    backend: Arc<dyn StorageBackend>,
+) -> Result<(Option<ID>, Vec<u8>)> {
+    let pass = match utils::get_password_from_file(&global_args.password_file)? {
+        Some(p) => p,
+        None => ui::cli::request_password("Enter repository password"),
+    };
+
+    match &global_args.key {
+        Some(path) => {
+            let master_key = keys::retrieve_master_key(&pass, Some(path), backend)
+                .with_context(|| "Incorrect password.")?;
+            Ok((None, master_key))
+        }
+        None => {
+            let (id, master_key) =
+                keys::find_matching_key(&pass, backend).with_context(|| "Incorrect password.")?;
+            Ok((Some(id), master_key))
+        }
+    }
+}
+
+fn request_new_password(new_password_file: &Option<PathBuf>) -> Result<String> {
+    match utils::get_password_from_file(new_password_file)? {
+        Some(p) => Ok(p),
+        None => Ok(ui::cli::request_password_with_confirmation(
+            "Enter new password",
+            "Confirm password",
+            "Passwords don't match",
+        )),
+    }
+}
+
+fn list(backend: Arc<dyn StorageBackend>, current_key_id: Option<&ID>) -> Result<()> {
+    let mut table = Table::new_with_alignments(vec![
+        Alignment::Left,
+        Alignment::Left,
+        Alignment::Center,
+        Alignment::Left,
+        Alignment::Left,
+        Alignment::Left,
+    ]);
+    table.set_headers(vec![
+        String::new(),
+        "ID".bold().to_string(),
+        "Created".bold().to_string(),
+        "Host".bold().to_string(),
+        "User".bold().to_string(),
+        "Label".bold().to_string(),
+    ]);
+
+    for (id, keyfile) in keys::list_keys(backend)? {
+        let current_mark = if Some(&id) == current_key_id {
+            "*".bold().green().to_string()
+        } else {
+            String::new()
+        };
+
+        table.add_row(vec![
+            current_mark,
+            id.to_short_hex(SHORT_REPO_ID_LEN)
+                .bold()
+                .yellow()
+                .to_string(),
+            keyfile
+                .created
+                .with_timezone(&Local)
+                .format("%Y-%m-%d %H:%M:%S %Z")
+                .to_string(),
+            keyfile.hostname.unwrap_or_default(),
+            keyfile.username.unwrap_or_default(),
+            keyfile.label.unwrap_or_default(),
+        ]);
+    }
+
+    ui::cli::log!("{}", table.render());
+
+    Ok(())
+}
+
+fn add(backend: Arc<dyn StorageBackend>, master_key: Vec<u8>, args: &AddArgs) -> Result<()> {
+    let new_pass = request_new_password(&args.new_password_file)?;
+
+    let mut keyfile = generate_key_file(&new_pass, master_key, args.kdf.params())?;
+    keyfile.label = args.label.clone();
+    let id = keys::save_key_file(&keyfile, backend)?;
+
+    ui::cli::log!(
+        "Added key {}",
+        id.to_short_hex(SHORT_REPO_ID_LEN).bold().yellow()
+    );
+
+    Ok(())
+}
+
+/// Removes a key other than the one used to open the repository. That key is known to
+/// open the repository, so at least one usable key is left.
+fn remove(
+    backend: Arc<dyn StorageBackend>,
+    current_key_id: Option<&ID>,
+    args: &RemoveArgs,
+) -> Result<()> {
+    // The keys are listed while holding the lock, so no other process removes keys meanwhile
+    let keys = keys::list_keys(backend.clone())?;
+
+    let mut matches = keys
+        .iter()
+        .filter(|(id, _)| id.to_hex().starts_with(&args.id.to_lowercase()));
+    let id = match (matches.next(), matches.next()) {
+        (Some((id, _)), None) => id,
+        (None, _) => bail!("No key found with ID \'{}\'", args.id),
+        (Some(_), Some(_)) => bail!("Multiple keys found with ID prefix \'{}\'", args.id),
+    };
+
+    if Some(id) == current_key_id {
+        bail!(
+            "Cannot remove the key used to open the repository. \
+             Open the repository with another key to remove this one."
+        );
+    }
+
+    // With an external KeyFile, the keys left in the repository can't be checked
+    if current_key_id.is_none() && keys.len() <= 1 {
+        bail!("Cannot remove the last key of the repository");
+    }
+
+    keys::remove_key_file(id, backend)?;
+
+    ui::cli::log!(
+        "Removed key {}",
+        id.to_short_hex(SHORT_REPO_ID_LEN).bold().yellow()
+    );
+
+    Ok(())
+}
+
+fn passwd(
+    global_args: &GlobalArgs,
+    backend: Arc<dyn StorageBackend>,
+    current_key_id: Option<ID>,
+    master_key: Vec<u8>,
+    args: &PasswdArgs,
+) -> Result<()> {
+    let old_keyfile = match (&global_args.key, &current_key_id) {
+        (Some(path), _) => keys::read_key_file(path)?,
+        (None, Some(old_id)) => keys::list_keys(backend.clone())?
+            .into_iter()
+            .find(|(id, _)| id == old_id)
+            .map(|(_, keyfile)| keyfile)
+            .with_context(|| "Could not find the key used to open the repository")?,
+        (None, None) => bail!("Could not determine the key used to open the repository"),
+    };
+
+    // The new key keeps the label and KDF parameters of the old one
+    let new_pass = request_new_password(&args.new_password_file)?;
+    let mut keyfile = generate_key_file(&new_pass, master_key, old_keyfile.kdf)?;
+    keyfile.label = old_keyfile.label;
+
+    if let Some(path) = &global_args.key {
+        // The key was read from an external KeyFile. Overwrite it.
+        std::fs::write(path, keys::encode_key_file(&keyfile)?)
+            .with_context(|| format!("Could not write KeyFile to {}", path.display()))?;
+        ui::cli::log!("Changed password of KeyFile {}", path.display());
+    } else if let Some(old_id) = current_key_id {
+        // Save the new key before removing the old one so the repository
+        // never runs out of keys.
+        let new_id = keys::save_key_file(&keyfile, backend.clone())?;
+        keys::remove_key_file(&old_id, backend)?;
+        ui::cli::log!(
+            "Changed password. Key {} replaced by {}",
+            old_id.to_short_hex(SHORT_REPO_ID_LEN).bold().yellow(),
+            new_id.to_short_hex(SHORT_REPO_ID_LEN).bold().yellow()
+        );
+    }
+
+    Ok(())
+}
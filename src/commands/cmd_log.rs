@@ -14,6 +14,8 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+use std::time::Duration;
+
 use anyhow::{Context, Result};
 use chrono::Local;
 use clap::Args;
@@ -50,6 +52,10 @@ pub struct CmdArgs {
     /// Only consider snapshots with tags: tag[,tag,...]
     #[arg(long = "tags", value_parser)]
     pub tags_str: Option<String>,
+
+    /// Only consider snapshots created in this host
+    #[arg(long)]
+    pub host: Option<String>,
 }
 
 pub fn run(global_args: &GlobalArgs, args: &CmdArgs) -> Result<()> {
@@ -72,6 +78,9 @@ pub fn run(global_args: &GlobalArgs, args: &CmdArgs) -> Result<()> {
         let tags = parse_tags(Some(tags_str));
         snapshots_sorted.retain(|(_id, sn)| sn.has_tags(&tags));
     }
+    if let Some(host) = &args.host {
+        snapshots_sorted.retain(|(_id, sn)| sn.has_host(host));
+    }
     snapshots_sorted.sort_by_key(|(_id, snapshot)| snapshot.timestamp);
 
     if snapshots_sorted.is_empty() {
@@ -109,6 +118,22 @@ fn log(snapshots: &[(ID, Snapshot)]) {
             utils::format_size(snapshot.summary.processed_bytes, 3)
         );
         ui::cli::log!("{} {}", "Root:".bold(), &snapshot.root.display());
+        if let Some(hostname) = &snapshot.hostname {
+            ui::cli::log!("{} {}", "Host:".bold(), hostname);
+        }
+        if let Some(username) = &snapshot.username {
+            ui::cli::log!("{} {}", "User:".bold(), username);
+        }
+        if let Some(duration_secs) = snapshot.summary.duration_secs {
+            ui::cli::log!(
+                "{} {}",
+                "Duration:".bold(),
+                utils::pretty_print_duration(Duration::from_secs_f64(duration_secs))
+            );
+        }
+        if let Some(program_version) = &snapshot.program_version {
+            ui::cli::log!("{} mapache {}", "Program:".bold(), program_version);
+        }
 
         if !snapshot.tags.is_empty() {
             ui::cli::log!(
@@ -147,12 +172,17 @@ fn log(snapshots: &[(ID, Snapshot)]) {
 }
 
 fn log_compact(snapshots: &Vec<(ID, Snapshot)>) {
-    let mut table =
-        Table::new_with_alignments(vec![Alignment::Left, Alignment::Center, Alignment::Right]);
+    let mut table = Table::new_with_alignments(vec![
+        Alignment::Left,
+        Alignment::Center,
+        Alignment::Left,
+        Alignment::Right,
+    ]);
 
     table.set_headers(vec![
         "ID".bold().to_string(),
         "Date ▼".bold().to_string(),
+        "Host".bold().to_string(),
         "Size".bold().to_string(),
         "Tags".bold().to_string(),
     ]);
@@ -168,6 +198,7 @@ fn log_compact(snapshots: &Vec<(ID, Snapshot)>) {
                 .with_timezone(&Local)
                 .format("%Y-%m-%d %H:%M:%S %Z")
                 .to_string(),
+            snapshot.hostname.clone().unwrap_or_default(),
             utils::format_size(snapshot.size(), 3),
             snapshot
                 .tags
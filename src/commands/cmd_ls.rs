@@ -53,6 +53,11 @@ pub struct CmdArgs {
     /// List subdirectories recursively
     #[clap(short = 'R', long, value_parser)]
     pub recursive: bool,
+
+    /// Only consider snapshots created in this host when resolving 'latest'. Snapshots without
+    /// a hostname are considered if none was created in this host.
+    #[clap(long)]
+    pub host: Option<String>,
 }
 
 pub fn run(global_args: &GlobalArgs, args: &CmdArgs) -> Result<()> {
@@ -61,7 +66,7 @@ pub fn run(global_args: &GlobalArgs, args: &CmdArgs) -> Result<()> {
     let (repo, _) = repository::try_open(pass, global_args.key.as_ref(), backend)?;
 
     let (_snapshot_id, snapshot) = {
-        match find_use_snapshot(repo.clone(), &args.snapshot) {
+        match find_use_snapshot(repo.clone(), &args.snapshot, args.host.as_deref()) {
             Ok(Some((id, snap))) => (id, snap),
             Ok(None) | Err(_) => bail!("Snapshot not found"),
         }
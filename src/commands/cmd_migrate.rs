@@ -0,0 +1,108 @@
+// mapache is an incremental backup tool
+// Copyright (C) 2025  Javier Lancha Vázquez <javier.lancha@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use anyhow::Result;
+use clap::Args;
+use colored::Colorize;
+
+use crate::{
+    backend::new_backend_with_prompt,
+    repository::{self, lock::RepositoryLock, migrations},
+    ui::{
+        self,
+        table::{Alignment, Table},
+    },
+    utils,
+};
+
+use super::GlobalArgs;
+
+#[derive(Args, Debug)]
+#[clap(
+    about = "Upgrade the repository to a newer format version",
+    long_about = "Upgrade the repository to a newer format version. Without arguments, lists the \
+                  migrations available for the repository. Interrupted migrations can be resumed \
+                  by running them again."
+)]
+pub struct CmdArgs {
+    /// Name of the migration to run
+    #[clap(value_parser)]
+    pub migration: Option<String>,
+}
+
+pub fn run(global_args: &GlobalArgs, args: &CmdArgs) -> Result<()> {
+    let pass = utils::get_password_from_file(&global_args.password_file)?;
+    let backend = new_backend_with_prompt(global_args, false)?;
+    let secure_storage =
+        repository::unlock_secure_storage(pass, global_args.key.as_ref(), backend.clone())?;
+
+    match &args.migration {
+        None => {
+            let manifest = repository::read_manifest(backend.as_ref(), &secure_storage)?;
+            let available = migrations::available_migrations(manifest.version);
+
+            if available.is_empty() {
+                ui::cli::log!(
+                    "No migrations available for repository version {}",
+                    manifest.version
+                );
+                return Ok(());
+            }
+
+            let mut table = Table::new_with_alignments(vec![
+                Alignment::Left,
+                Alignment::Center,
+                Alignment::Left,
+            ]);
+            table.set_headers(vec![
+                "Name".bold().to_string(),
+                "Version".bold().to_string(),
+                "Description".bold().to_string(),
+            ]);
+            for migration in available {
+                table.add_row(vec![
+                    migration.name().bold().yellow().to_string(),
+                    format!(
+                        "{} -> {}",
+                        migration.source_version(),
+                        migration.target_version()
+                    ),
+                    migration.description().to_string(),
+                ]);
+            }
+            ui::cli::log!("{}", table.render());
+
+            if let Some(pending) = migrations::pending_migration(backend.as_ref(), &secure_storage)?
+            {
+                ui::cli::log!(
+                    "Migration {} was interrupted. Run it again to resume it.",
+                    pending.bold().yellow()
+                );
+            }
+        }
+        Some(name) => {
+            let _lock = RepositoryLock::exclusive(backend.clone(), secure_storage.clone())?;
+
+            let version = migrations::run_migration(name, backend, secure_storage)?;
+            ui::cli::log!(
+                "Repository migrated to version {}",
+                version.to_string().bold().green()
+            );
+        }
+    }
+
+    Ok(())
+}
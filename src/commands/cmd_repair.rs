@@ -0,0 +1,246 @@
+// mapache is an incremental backup tool
+// Copyright (C) 2025  Javier Lancha Vázquez <javier.lancha@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use anyhow::{Result, bail};
+use clap::{Args, Subcommand};
+use colored::Colorize;
+
+use crate::{
+    backend::new_backend_with_prompt,
+    global::{self, FileType, ID, defaults::SHORT_SNAPSHOT_ID_LEN},
+    repository::{
+        self,
+        lock::{LockKind, RepositoryLock},
+        repair::{self, BlobChecker, SnapshotDamage},
+    },
+    ui::{
+        self,
+        table::{Alignment, Table},
+    },
+    utils,
+};
+
+use super::GlobalArgs;
+
+#[derive(Args, Debug)]
+#[clap(about = "Repair a damaged repository")]
+pub struct CmdArgs {
+    #[command(subcommand)]
+    pub command: RepairCommand,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum RepairCommand {
+    /// Rebuild the index from the pack headers
+    Index(IndexArgs),
+
+    /// Salvage snapshots that reference missing blobs
+    Snapshots(SnapshotsArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct IndexArgs {
+    /// Number of pack headers to read in parallel
+    #[clap(long, default_value_t = global::defaults::DEFAULT_READ_CONCURRENCY)]
+    pub read_concurrency: usize,
+
+    /// Dry run. Displays what this command would do without
+    /// making changes to the repository.
+    #[clap(long, default_value_t = false)]
+    pub dry_run: bool,
+}
+
+#[derive(Args, Debug)]
+#[clap(
+    long_about = "Salvage snapshots that reference missing blobs. Directories whose tree is lost \
+                  are dropped, and files are truncated before their first missing blob (or \
+                  dropped if no data is left). The result is saved as a new snapshot tagged \
+                  'repaired'."
+)]
+pub struct SnapshotsArgs {
+    /// IDs (or ID prefixes) of the snapshots to repair. All snapshots are checked if none is given.
+    #[clap(value_parser)]
+    pub snapshots: Vec<String>,
+
+    /// Read all data blobs to find corrupt data, instead of only checking the index
+    #[clap(long, default_value_t = false)]
+    pub read_data: bool,
+
+    /// Remove the damaged snapshots after saving the repaired ones
+    #[clap(long, default_value_t = false)]
+    pub forget: bool,
+
+    /// Dry run. Displays what this command would do without
+    /// making changes to the repository.
+    #[clap(long, default_value_t = false)]
+    pub dry_run: bool,
+}
+
+pub fn run(global_args: &GlobalArgs, args: &CmdArgs) -> Result<()> {
+    // Damage is only found in the backend, so the cache is not used
+    global::disable_cache();
+
+    match &args.command {
+        RepairCommand::Index(index_args) => repair_index(global_args, index_args),
+        RepairCommand::Snapshots(snapshots_args) => repair_snapshots(global_args, snapshots_args),
+    }
+}
+
+fn repair_index(global_args: &GlobalArgs, args: &IndexArgs) -> Result<()> {
+    let pass = utils::get_password_from_file(&global_args.password_file)?;
+    let backend = new_backend_with_prompt(global_args, args.dry_run)?;
+    let secure_storage =
+        repository::unlock_secure_storage(pass, global_args.key.as_ref(), backend.clone())?;
+    let _lock = RepositoryLock::exclusive(backend.clone(), secure_storage.clone())?;
+
+    let summary = repair::repair_index(backend, secure_storage, args.read_concurrency)?;
+
+    let mut table = Table::new_with_alignments(vec![Alignment::Left, Alignment::Right]);
+    table.add_row(vec![
+        "Total packs".bold().to_string(),
+        summary.total_packs.to_string(),
+    ]);
+    table.add_row(vec![
+        "Broken index files".bold().to_string(),
+        summary.broken_indices.len().to_string(),
+    ]);
+    table.add_row(vec![
+        "Recovered packs".bold().to_string(),
+        summary.recovered_packs.to_string(),
+    ]);
+    table.add_row(vec![
+        "Recovered blobs".bold().to_string(),
+        summary.recovered_blobs.to_string(),
+    ]);
+    table.add_row(vec![
+        "Unreadable packs".bold().to_string(),
+        summary.unreadable_packs.len().to_string(),
+    ]);
+    ui::cli::log!("{}", table.render());
+
+    for pack_id in &summary.unreadable_packs {
+        ui::cli::warning!("Pack {} is damaged and could not be indexed", pack_id);
+    }
+
+    if args.dry_run {
+        ui::cli::log!("{} Index not changed", "[DRY RUN]".bold().purple());
+    } else if summary.broken_indices.is_empty() && summary.recovered_packs == 0 {
+        ui::cli::log!("The index is complete. Nothing to repair.");
+    } else {
+        ui::cli::log!("{}", "Index repaired".bold().green());
+    }
+
+    Ok(())
+}
+
+fn repair_snapshots(global_args: &GlobalArgs, args: &SnapshotsArgs) -> Result<()> {
+    let pass = utils::get_password_from_file(&global_args.password_file)?;
+    let backend = new_backend_with_prompt(global_args, args.dry_run)?;
+    let (_lock, repo, _) =
+        repository::try_open_locked(pass, global_args.key.as_ref(), backend, LockKind::Exclusive)?;
+
+    let snapshot_ids: Vec<ID> = if args.snapshots.is_empty() {
+        repo.list_snapshot_ids()?
+    } else {
+        args.snapshots
+            .iter()
+            .map(|prefix| repo.find(FileType::Snapshot, prefix).map(|(id, _)| id))
+            .collect::<Result<_>>()?
+    };
+
+    let mut checker = BlobChecker::new(repo.as_ref(), args.read_data);
+    let mut num_repaired = 0;
+    let mut num_unrecoverable = 0;
+
+    for id in snapshot_ids {
+        let short_id = id.to_short_hex(SHORT_SNAPSHOT_ID_LEN).bold().yellow();
+
+        // A snapshot that cannot be loaded or scanned does not prevent repairing the rest
+        let scanned = repo.load_snapshot(&id).and_then(|snapshot| {
+            let damage = repair::scan_snapshot(&mut checker, &snapshot)?;
+            Ok((snapshot, damage))
+        });
+        let (mut snapshot, damage) = match scanned {
+            Ok(scanned) => scanned,
+            Err(e) => {
+                ui::cli::error!("Snapshot {} is unrecoverable: {:#}", short_id, e);
+                num_unrecoverable += 1;
+                continue;
+            }
+        };
+        if damage.is_empty() {
+            ui::cli::log!("Snapshot {} {}", short_id, "[OK]".bold().green());
+            continue;
+        }
+
+        ui::cli::log!("Snapshot {} is damaged:", short_id);
+        report_damage(&damage);
+
+        if args.dry_run {
+            ui::cli::log!();
+            continue;
+        }
+
+        let new_id = repair::rewrite_snapshot(repo.clone(), &mut snapshot, &damage)?;
+        num_repaired += 1;
+        ui::cli::log!(
+            "Saved repaired snapshot {}",
+            new_id.to_short_hex(SHORT_SNAPSHOT_ID_LEN).bold().green()
+        );
+
+        if args.forget {
+            repo.remove_snapshot(&id)?;
+            ui::cli::log!("Removed damaged snapshot {}", short_id);
+        }
+        ui::cli::log!();
+    }
+
+    if args.dry_run {
+        ui::cli::log!("{} No snapshots saved", "[DRY RUN]".bold().purple());
+    } else if num_repaired > 0 {
+        ui::cli::log!(
+            "Repaired {} snapshots. Run {} to remove unused data.",
+            num_repaired,
+            "clean".bold()
+        );
+    }
+
+    if num_unrecoverable > 0 {
+        bail!("{} snapshots could not be repaired", num_unrecoverable);
+    }
+
+    Ok(())
+}
+
+fn report_damage(damage: &SnapshotDamage) {
+    for path in &damage.lost_dirs {
+        ui::cli::log!("  {} {}", "lost directory".red(), path.display());
+    }
+    for path in &damage.dropped_files {
+        ui::cli::log!("  {} {}", "dropped file".red(), path.display());
+    }
+    let mut truncated_files: Vec<_> = damage.truncated_files.iter().collect();
+    truncated_files.sort();
+    for (path, (intact, total)) in truncated_files {
+        ui::cli::log!(
+            "  {} {} ({}/{} blobs kept)",
+            "truncated file".yellow(),
+            path.display(),
+            intact,
+            total
+        );
+    }
+}
@@ -29,7 +29,9 @@ use crate::{
     backend::new_backend_with_prompt,
     commands::{GlobalArgs, UseSnapshot, find_use_snapshot},
     global::defaults::SHORT_SNAPSHOT_ID_LEN,
-    repository::{self, streamers::SerializedNodeStreamer, verify::verify_snapshot_links},
+    repository::{
+        self, lock::LockKind, streamers::SerializedNodeStreamer, verify::verify_snapshot_links,
+    },
     restorer::{self, Resolution, Restorer},
     ui::{
         self, PROGRESS_REFRESH_RATE_HZ, SPINNER_TICK_CHARS, cli, default_bar_draw_target,
@@ -91,17 +93,24 @@ pub struct CmdArgs {
     /// Dry run
     #[clap(long, default_value_t = false)]
     pub dry_run: bool,
+
+    /// Only consider snapshots created in this host when resolving 'latest'. Snapshots without
+    /// a hostname are considered if none was created in this host.
+    #[clap(long)]
+    pub host: Option<String>,
 }
 
 pub fn run(global_args: &GlobalArgs, args: &CmdArgs) -> Result<()> {
     let pass = utils::get_password_from_file(&global_args.password_file)?;
     let backend = new_backend_with_prompt(global_args, args.dry_run)?;
-    let (repo, _) = repository::try_open(pass, global_args.key.as_ref(), backend)?;
-
-    let (snapshot_id, snapshot) = match find_use_snapshot(repo.clone(), &args.snapshot) {
-        Ok(Some((id, snap))) => (id, snap),
-        Ok(None) | Err(_) => bail!("Snapshot not found"),
-    };
+    let (_lock, repo, _) =
+        repository::try_open_locked(pass, global_args.key.as_ref(), backend, LockKind::Shared)?;
+
+    let (snapshot_id, snapshot) =
+        match find_use_snapshot(repo.clone(), &args.snapshot, args.host.as_deref()) {
+            Ok(Some((id, snap))) => (id, snap),
+            Ok(None) | Err(_) => bail!("Snapshot not found"),
+        };
 
     let common_prefix: Option<PathBuf> = if args.strip_prefix {
         args.include
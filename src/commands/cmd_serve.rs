@@ -0,0 +1,131 @@
+// mapache is an incremental backup tool
+// Copyright (C) 2025  Javier Lancha Vázquez <javier.lancha@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::{net::TcpListener, path::PathBuf};
+
+use anyhow::{Context, Result};
+use clap::Args;
+
+use crate::{
+    backend::{exec, localfs::LocalFS},
+    global::defaults::{DEFAULT_MAX_CONNECTIONS, DEFAULT_MAX_REQUEST_BODY_SIZE},
+    server::{
+        http::{self, ServerLimits},
+        rest::{self, RestServer, ServerOptions},
+    },
+    ui, utils,
+};
+
+use super::GlobalArgs;
+
+#[derive(Args, Debug)]
+#[clap(
+    about = "Serve repositories over HTTP",
+    long_about = "Serve the repositories in a local directory over HTTP with the restic REST protocol. \
+                  Clients access them with a rest://<host>:<port>/<repository> URL. The \
+                  --repo option is not used. With --stdio, a single repository is served to an \
+                  exec:<command> repository instead."
+)]
+pub struct CmdArgs {
+    /// Directory with the repositories
+    #[clap(value_parser)]
+    pub path: PathBuf,
+
+    /// Address to listen on
+    #[clap(long, default_value = "127.0.0.1:8000")]
+    pub listen: String,
+
+    /// File with one `username:password` line per user. Enables basic authentication.
+    #[clap(long, value_parser)]
+    pub users_file: Option<PathBuf>,
+
+    /// Only allow each user to access the repositories under their username
+    #[clap(long, requires = "users_file")]
+    pub private_repos: bool,
+
+    /// Refuse to delete or overwrite files, except locks
+    #[clap(long)]
+    pub append_only: bool,
+
+    /// Largest request body accepted (e.g. 512MiB) [default: 2GiB]
+    #[clap(long, value_parser = utils::parse_size_string)]
+    pub max_body_size: Option<u64>,
+
+    /// Number of clients served at the same time
+    #[clap(long, default_value_t = DEFAULT_MAX_CONNECTIONS)]
+    pub max_connections: usize,
+
+    /// Serve the repository at PATH on stdin and stdout, as a helper for an exec: repository
+    #[clap(
+        long,
+        conflicts_with_all = [
+            "listen",
+            "users_file",
+            "private_repos",
+            "append_only",
+            "max_body_size",
+            "max_connections",
+        ]
+    )]
+    pub stdio: bool,
+}
+
+pub fn run(_global_args: &GlobalArgs, args: &CmdArgs) -> Result<()> {
+    // Nothing else can be printed to stdout in this mode, so errors are reported on stderr
+    // instead of being returned
+    if args.stdio {
+        let backend = LocalFS::new(args.path.clone());
+        if let Err(e) = exec::serve(&backend, std::io::stdin().lock(), std::io::stdout().lock()) {
+            eprintln!("Error: {e:#}");
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    let users = match &args.users_file {
+        Some(users_file) => {
+            let contents = std::fs::read_to_string(users_file).with_context(|| {
+                format!("Could not read users file \'{}\'", users_file.display())
+            })?;
+            Some(rest::parse_users(&contents)?)
+        }
+        None => None,
+    };
+
+    let server = RestServer::new(
+        args.path.clone(),
+        ServerOptions {
+            users,
+            private_repos: args.private_repos,
+            append_only: args.append_only,
+        },
+    )?;
+
+    let listener = TcpListener::bind(&args.listen)
+        .with_context(|| format!("Could not listen on {}", args.listen))?;
+    ui::cli::log!(
+        "Serving {} on http://{}",
+        args.path.display(),
+        listener.local_addr()?
+    );
+
+    let limits = ServerLimits {
+        max_body_size: args.max_body_size.unwrap_or(DEFAULT_MAX_REQUEST_BODY_SIZE),
+        max_connections: args.max_connections,
+        ..Default::default()
+    };
+    http::serve(listener, server, limits)
+}
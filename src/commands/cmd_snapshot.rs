@@ -27,13 +27,16 @@ use colored::Colorize;
 use indicatif::{ProgressBar, ProgressStyle};
 
 use crate::{
-    archiver::{Archiver, SnapshotOptions},
-    backend::new_backend_with_prompt,
+    archiver::{self, Archiver, SnapshotOptions},
+    backend::{StorageBackend, new_backend_with_prompt},
     commands::{EMPTY_TAG_MARK, find_use_snapshot, parse_tags},
     global::{self, ID, SaveID, defaults::SHORT_SNAPSHOT_ID_LEN},
     repository::{
-        self,
-        snapshot::{SnapshotSummary, SnapshotTuple},
+        self, RepositoryBackend,
+        lock::{self, LockKind, RepositoryLock},
+        repair,
+        snapshot::{Snapshot, SnapshotStreamer, SnapshotSummary, SnapshotTuple},
+        storage::SecureStorage,
         streamers::FSNodeStreamer,
     },
     ui::{
@@ -44,7 +47,7 @@ use crate::{
     utils::{self, format_size},
 };
 
-use super::{GlobalArgs, UseSnapshot};
+use super::{CompressionArgs, GlobalArgs, UseSnapshot};
 
 #[derive(Args, Debug)]
 #[clap(group = ArgGroup::new("scan_mode").multiple(false))]
@@ -86,12 +89,43 @@ pub struct CmdArgs {
     /// Dry run
     #[clap(long, default_value_t = false)]
     pub dry_run: bool,
+
+    /// Hostname recorded in the snapshot. Defaults to the name of this host.
+    /// The parent snapshot is the latest snapshot created in this host, or the latest snapshot
+    /// without a hostname if there is none.
+    #[clap(long, value_parser)]
+    pub host: Option<String>,
+
+    /// Save a checkpoint snapshot with the data processed so far at this interval (e.g. 30m, 1h).
+    /// If the snapshot is interrupted, the last checkpoint can be restored or used as parent.
+    #[clap(long, value_parser = parse_checkpoint_interval)]
+    pub checkpoint_interval: Option<Duration>,
+
+    // Overrides the repository compression settings for this snapshot
+    #[clap(flatten)]
+    pub compression: CompressionArgs,
 }
 
 pub fn run(global_args: &GlobalArgs, args: &CmdArgs) -> Result<()> {
     let pass = utils::get_password_from_file(&global_args.password_file)?;
     let backend = new_backend_with_prompt(global_args, args.dry_run)?;
-    let (repo, _) = repository::try_open(pass, global_args.key.as_ref(), backend)?;
+    let (lock, repo, secure_storage) = repository::try_open_locked(
+        pass,
+        global_args.key.as_ref(),
+        backend.clone(),
+        LockKind::Shared,
+    )?;
+    repo.set_compression(args.compression.apply(repo.compression())?)?;
+
+    if !args.dry_run {
+        index_interrupted_packs(
+            backend.as_ref(),
+            &secure_storage,
+            &lock,
+            repo.as_ref(),
+            args.write_concurrency,
+        )?;
+    }
 
     let mut tags: BTreeSet<String> = parse_tags(Some(&args.tags_str));
     tags.retain(|tag| tag != EMPTY_TAG_MARK);
@@ -131,6 +165,7 @@ pub fn run(global_args: &GlobalArgs, args: &CmdArgs) -> Result<()> {
         ui::cli::warning!("No source paths provided. Creating empty snapshot.");
     };
     let snapshot_root_path = utils::calculate_lcp(&absolute_source_paths, false);
+    let hostname = args.host.clone().unwrap_or_else(utils::get_hostname);
 
     ui::cli::log!();
     let parent_snapshot_tuple: Option<SnapshotTuple> = match args.rescan {
@@ -138,7 +173,7 @@ pub fn run(global_args: &GlobalArgs, args: &CmdArgs) -> Result<()> {
             ui::cli::log!("Full scan");
             None
         }
-        false => match find_use_snapshot(repo.clone(), &args.parent) {
+        false => match find_use_snapshot(repo.clone(), &args.parent, Some(&hostname)) {
             Ok(Some((id, snap))) => {
                 ui::cli::log!(
                     "Using snapshot {} as parent",
@@ -222,11 +257,16 @@ pub fn run(global_args: &GlobalArgs, args: &CmdArgs) -> Result<()> {
             parent_snapshot: parent_snapshot_tuple,
             tags,
             description: args.description.clone(),
+            hostname,
+            checkpoint_interval: match args.dry_run {
+                true => None,
+                false => args.checkpoint_interval,
+            },
         },
         (args.read_concurrency, args.write_concurrency),
         progress_reporter.clone(),
     );
-    let new_snapshot = archiver.snapshot()?;
+    let (new_snapshot, checkpoint_id) = archiver.snapshot()?;
 
     let (snapshot_id, snapshot_raw_size, snapshot_encoded_size) = repo.save_file(
         global::FileType::Snapshot,
@@ -236,6 +276,22 @@ pub fn run(global_args: &GlobalArgs, args: &CmdArgs) -> Result<()> {
 
     progress_reporter.written_meta_bytes(snapshot_raw_size, snapshot_encoded_size);
 
+    // The new snapshot supersedes the checkpoints of this run and the ones left by interrupted
+    // runs of the same paths in this host, whichever parent was used
+    let mut superseded_checkpoints: BTreeSet<ID> = checkpoint_id.into_iter().collect();
+    if !args.dry_run {
+        superseded_checkpoints.extend(stale_checkpoints(repo.clone(), &new_snapshot)?);
+    }
+    for id in superseded_checkpoints {
+        if let Err(e) = repo.remove_snapshot(&id) {
+            ui::cli::warning!(
+                "Could not remove checkpoint {}: {}",
+                id.to_short_hex(SHORT_SNAPSHOT_ID_LEN),
+                e
+            );
+        }
+    }
+
     // Finalize reporter. This removes the progress bars.
     progress_reporter.finalize();
 
@@ -250,6 +306,71 @@ pub fn run(global_args: &GlobalArgs, args: &CmdArgs) -> Result<()> {
     Ok(())
 }
 
+/// Adds the packs left behind by an interrupted snapshot to the index, so their blobs are
+/// reused instead of saved again. Packs being written by other running processes are not
+/// indexed yet either, so nothing is done while other processes hold a lock.
+fn index_interrupted_packs(
+    backend: &dyn StorageBackend,
+    secure_storage: &SecureStorage,
+    own_lock: &RepositoryLock,
+    repo: &dyn RepositoryBackend,
+    concurrency: usize,
+) -> Result<()> {
+    let locks = lock::list_locks(backend, secure_storage)?;
+    let other_locks = !locks.unreadable.is_empty()
+        || locks
+            .locks
+            .into_iter()
+            .any(|(id, lock_file)| &id != own_lock.id() && !lock_file.is_stale());
+    if other_locks {
+        ui::cli::verbose_1!("The repository is in use. Skipping the search for unindexed packs.");
+        return Ok(());
+    }
+
+    let recovered = repair::index_unindexed_packs(repo, concurrency)?;
+    if recovered.packs > 0 {
+        ui::cli::log!(
+            "Resuming: found {} with {} from an interrupted snapshot",
+            utils::format_count(recovered.packs, "pack", "packs"),
+            utils::format_count(recovered.blobs, "blob", "blobs")
+        );
+    }
+    if !recovered.unreadable_packs.is_empty() {
+        ui::cli::warning!(
+            "{} packs could not be read. Run {} to remove them.",
+            recovered.unreadable_packs.len(),
+            "clean".bold()
+        );
+    }
+
+    Ok(())
+}
+
+/// Returns the checkpoints of the same paths and host as a new snapshot that were saved before
+/// it started, by runs that did not finish
+fn stale_checkpoints(repo: Arc<dyn RepositoryBackend>, snapshot: &Snapshot) -> Result<Vec<ID>> {
+    let Some(hostname) = &snapshot.hostname else {
+        return Ok(Vec::new());
+    };
+    let start_time = snapshot.summary.start_time.unwrap_or(snapshot.timestamp);
+
+    Ok(SnapshotStreamer::new(repo)?
+        .filter(|(_, checkpoint)| {
+            checkpoint.timestamp < start_time
+                && archiver::is_checkpoint_of(checkpoint, &snapshot.paths, hostname)
+        })
+        .map(|(id, _)| id)
+        .collect())
+}
+
+fn parse_checkpoint_interval(s: &str) -> Result<Duration> {
+    let interval = utils::parse_duration_string(s)?.to_std()?;
+    if interval.is_zero() {
+        bail!("The checkpoint interval must be greater than zero");
+    }
+    Ok(interval)
+}
+
 fn show_final_report(snapshot_id: &ID, summary: &SnapshotSummary, args: &CmdArgs) {
     ui::cli::log!("{}", "Changes since parent snapshot".bold());
     ui::cli::log!();
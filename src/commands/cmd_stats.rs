@@ -0,0 +1,177 @@
+// mapache is an incremental backup tool
+// Copyright (C) 2025  Javier Lancha Vázquez <javier.lancha@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use anyhow::Result;
+use clap::Args;
+use colored::Colorize;
+
+use crate::{
+    backend::new_backend_with_prompt,
+    global::defaults::SHORT_SNAPSHOT_ID_LEN,
+    repository::{
+        self,
+        lock::LockKind,
+        stats::{self, RepositoryStats},
+    },
+    ui::{
+        self,
+        table::{Alignment, Table},
+    },
+    utils,
+};
+
+use super::GlobalArgs;
+
+#[derive(Args, Debug)]
+#[clap(
+    about = "Show repository statistics",
+    long_about = "Show repository statistics: restore size, deduplication, the size only used by \
+                  each snapshot and the state of the pack files."
+)]
+pub struct CmdArgs {
+    /// Read all referenced blobs to calculate their uncompressed size
+    #[clap(long, default_value_t = false)]
+    pub read_data: bool,
+
+    /// Print the statistics in JSON format
+    #[clap(long, default_value_t = false)]
+    pub json: bool,
+}
+
+pub fn run(global_args: &GlobalArgs, args: &CmdArgs) -> Result<()> {
+    let pass = utils::get_password_from_file(&global_args.password_file)?;
+    let backend = new_backend_with_prompt(global_args, false)?;
+    let (_lock, repo, _) =
+        repository::try_open_locked(pass, global_args.key.as_ref(), backend, LockKind::Shared)?;
+
+    let stats = stats::collect(repo, args.read_data)?;
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&stats)?);
+        return Ok(());
+    }
+
+    if !stats.snapshots.is_empty() {
+        show_snapshots(&stats);
+    }
+    show_summary(&stats);
+
+    Ok(())
+}
+
+fn show_snapshots(stats: &RepositoryStats) {
+    let mut table = Table::new_with_alignments(vec![
+        Alignment::Left,
+        Alignment::Center,
+        Alignment::Right,
+        Alignment::Right,
+        Alignment::Right,
+    ]);
+    table.set_headers(vec![
+        "ID".bold().to_string(),
+        "Date ▼".bold().to_string(),
+        "Files".bold().to_string(),
+        "Restore size".bold().to_string(),
+        "Exclusive size".bold().to_string(),
+    ]);
+
+    // Newest snapshots first
+    for snapshot in stats.snapshots.iter().rev() {
+        table.add_row(vec![
+            snapshot
+                .id
+                .to_short_hex(SHORT_SNAPSHOT_ID_LEN)
+                .bold()
+                .yellow()
+                .to_string(),
+            snapshot
+                .timestamp
+                .format("%Y-%m-%d %H:%M:%S %Z")
+                .to_string(),
+            snapshot.files.to_string(),
+            utils::format_size(snapshot.restore_size, 3),
+            utils::format_size(snapshot.exclusive_size, 3),
+        ]);
+    }
+
+    ui::cli::log!("{}", table.render());
+}
+
+fn show_summary(stats: &RepositoryStats) {
+    let mut table = Table::new_with_alignments(vec![Alignment::Left, Alignment::Right]);
+    let mut add_row = |key: &str, value: String| {
+        table.add_row(vec![key.bold().to_string(), value]);
+    };
+
+    let format_ratio = |ratio: Option<f64>| match ratio {
+        Some(ratio) => format!("{ratio:.2}"),
+        None => String::from("-"),
+    };
+    let format_raw_size = |size: Option<u64>| match size {
+        Some(size) => utils::format_size(size, 3),
+        None => String::from("- (use --read-data)"),
+    };
+
+    add_row("Snapshots", stats.snapshots.len().to_string());
+    add_row("Restore size", utils::format_size(stats.restore_size, 3));
+    add_row("Data blobs", stats.blobs.data_blobs.to_string());
+    add_row("Data size", utils::format_size(stats.blobs.data_size, 3));
+    add_row(
+        "Data size (uncompressed)",
+        format_raw_size(stats.blobs.raw_data_size),
+    );
+    add_row("Tree blobs", stats.blobs.tree_blobs.to_string());
+    add_row("Tree size", utils::format_size(stats.blobs.tree_size, 3));
+    add_row(
+        "Tree size (uncompressed)",
+        format_raw_size(stats.blobs.raw_tree_size),
+    );
+    add_row("Storage ratio", format_ratio(stats.storage_ratio()));
+    add_row("Deduplication ratio", format_ratio(stats.dedup_ratio()));
+    add_row("Compression ratio", format_ratio(stats.compression_ratio()));
+    add_row("Packs", stats.packs.packs.to_string());
+    add_row(
+        "Average pack fill",
+        match stats.packs.average_fill() {
+            Some(fill) => format!("{:.1}%", 100.0 * fill),
+            None => String::from("-"),
+        },
+    );
+    add_row(
+        "Stored size",
+        utils::format_size(stats.packs.stored_size, 3),
+    );
+    add_row("Garbage blobs", stats.packs.garbage_blobs.to_string());
+    add_row(
+        "Garbage size",
+        utils::format_size(stats.packs.garbage_size, 3),
+    );
+    add_row(
+        "Packs with garbage",
+        stats.packs.packs_with_garbage.to_string(),
+    );
+    add_row("Unindexed packs", stats.packs.unindexed_packs.to_string());
+
+    ui::cli::log!("{}", table.render());
+
+    if stats.blobs.missing_blobs > 0 {
+        ui::cli::warning!(
+            "{} referenced blobs are missing from the index. Run {} to find damaged snapshots.",
+            stats.blobs.missing_blobs,
+            "verify".bold()
+        );
+    }
+}
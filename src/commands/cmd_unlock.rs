@@ -0,0 +1,53 @@
+// mapache is an incremental backup tool
+// Copyright (C) 2025  Javier Lancha Vázquez <javier.lancha@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use anyhow::Result;
+use clap::Args;
+
+use crate::backend::new_backend_with_prompt;
+use crate::repository::lock;
+use crate::{repository, ui, utils};
+
+use super::GlobalArgs;
+
+#[derive(Args, Debug)]
+#[clap(
+    about = "Remove stale locks from the repository",
+    long_about = "Remove stale locks from the repository. A lock is stale if it has not been \
+                  refreshed in a while, or if the process that created it on this host is \
+                  no longer running."
+)]
+pub struct CmdArgs {
+    /// Remove all locks, including those held by running processes
+    #[clap(long, default_value_t = false)]
+    pub remove_all: bool,
+}
+
+pub fn run(global_args: &GlobalArgs, args: &CmdArgs) -> Result<()> {
+    let pass = utils::get_password_from_file(&global_args.password_file)?;
+    let backend = new_backend_with_prompt(global_args, false)?;
+    let secure_storage =
+        repository::unlock_secure_storage(pass, global_args.key.as_ref(), backend.clone())?;
+
+    let num_removed =
+        lock::remove_locks(backend.as_ref(), secure_storage.as_ref(), args.remove_all)?;
+    ui::cli::log!(
+        "Removed {}",
+        utils::format_count(num_removed, "lock", "locks")
+    );
+
+    Ok(())
+}
@@ -22,15 +22,17 @@ use colored::Colorize;
 use indicatif::{ProgressBar, ProgressState, ProgressStyle};
 
 use crate::{
-    backend::new_backend_with_prompt,
+    backend::{StorageBackend, new_backend_with_prompt},
     commands::GlobalArgs,
-    global::{ID, defaults::SHORT_SNAPSHOT_ID_LEN},
+    global::{self, ID, defaults::SHORT_SNAPSHOT_ID_LEN},
     repository::{
         self, RepositoryBackend,
+        lock::LockKind,
         snapshot::SnapshotStreamer,
+        storage::SecureStorage,
         streamers::SerializedNodeStreamer,
         tree::NodeType,
-        verify::{verify_blob, verify_pack, verify_snapshot_links},
+        verify::{DataSubset, verify_blob, verify_pack, verify_snapshot_links},
     },
     ui::{self, default_bar_draw_target},
     utils,
@@ -51,72 +53,61 @@ pub struct CmdArgs {
     /// Read all packs and discover unreferenced blobs
     #[clap(long, value_parser, default_value_t = false)]
     pub unreferenced: bool,
+
+    /// Read and verify only a subset of the packs: n/t (e.g. 1/7) selects a deterministic
+    /// bucket of packs by ID, and p% (e.g. 5%) selects a random sample of packs
+    #[clap(long, value_parser, conflicts_with = "unreferenced")]
+    pub read_data_subset: Option<DataSubset>,
 }
 
 pub fn run(global_args: &GlobalArgs, args: &CmdArgs) -> Result<()> {
+    // Cached files are not verified, so everything is read from the backend
+    global::disable_cache();
+
     let pass = utils::get_password_from_file(&global_args.password_file)?;
     let backend = new_backend_with_prompt(global_args, false)?;
-    let (repo, secure_storage) =
-        repository::try_open(pass, global_args.key.as_ref(), backend.clone())?;
+    let (_lock, repo, secure_storage) = repository::try_open_locked(
+        pass,
+        global_args.key.as_ref(),
+        backend.clone(),
+        LockKind::Shared,
+    )?;
 
     let snapshot_streamer = SnapshotStreamer::new(repo.clone())?;
     let mut visited_blobs = BTreeSet::new();
+    let mut num_damaged_packs = 0;
 
     if args.unreferenced {
-        let packs = repo.list_objects()?;
-
-        let bar = ProgressBar::new(packs.len() as u64);
-        bar.set_draw_target(default_bar_draw_target());
-        bar.set_style(
-            ProgressStyle::default_bar()
-                .template(
-                    "[{custom_elapsed}] [{bar:20.cyan/white}] Reading packs: {pos} / {len}  [ETA: {custom_eta}]",
-                )
-                .unwrap()
-                .progress_chars("=> ")
-                .with_key(
-                    "custom_elapsed",
-                    move |state: &ProgressState, w: &mut dyn std::fmt::Write| {
-                        let elapsed = state.elapsed();
-                        let custom_elapsed = utils::pretty_print_duration(elapsed);
-                        let _ = w.write_str(&custom_elapsed);
-                    },
-                )
-                .with_key(
-                    "custom_eta",
-                    move |state: &ProgressState, w: &mut dyn std::fmt::Write| {
-                        let eta = state.eta();
-                        let custom_eta = utils::pretty_print_duration(eta);
-                        let _ = w.write_str(&custom_eta);
-                    },
-                ),
+        let packs: Vec<ID> = repo.list_objects()?.into_iter().collect();
+        let (num_dangling_blobs, num_damaged) = verify_packs(
+            repo.as_ref(),
+            backend.as_ref(),
+            secure_storage.as_ref(),
+            &packs,
+            &mut visited_blobs,
         );
-
-        let mut num_dangling_blobs = 0;
-        for pack_id in &packs {
-            let verify_res = verify_pack(
-                repo.as_ref(),
-                backend.as_ref(),
-                secure_storage.as_ref(),
-                pack_id,
-                &mut visited_blobs,
-            );
-
-            if let Ok(dangling_blobs) = verify_res {
-                num_dangling_blobs += dangling_blobs;
-            }
-            bar.inc(1);
+        if num_dangling_blobs > 0 {
+            ui::cli::log!("Found {} unreferenced blobs", num_dangling_blobs);
         }
+        num_damaged_packs = num_damaged;
 
-        bar.finish_and_clear();
+        ui::cli::log!();
+    } else if let Some(subset) = &args.read_data_subset {
+        let all_packs = repo.list_objects()?;
+        let packs = subset.select(&all_packs);
         ui::cli::log!(
-            "Verified {} blobs from {} packs",
-            visited_blobs.len(),
-            packs.len()
+            "Reading subset {} ({} of {} packs)",
+            subset.to_string().bold(),
+            packs.len(),
+            all_packs.len()
+        );
+        (_, num_damaged_packs) = verify_packs(
+            repo.as_ref(),
+            backend.as_ref(),
+            secure_storage.as_ref(),
+            &packs,
+            &mut visited_blobs,
         );
-        if num_dangling_blobs > 0 {
-            ui::cli::log!("Found {} unreferenced blobs", num_dangling_blobs);
-        }
 
         ui::cli::log!();
     }
@@ -164,10 +155,91 @@ pub fn run(global_args: &GlobalArgs, args: &CmdArgs) -> Result<()> {
     if error_counter > 0 {
         ui::cli::log!("{} {}", error_counter, "[ERROR]".bold().red());
     }
+    if num_damaged_packs > 0 {
+        ui::cli::log!(
+            "{} {}",
+            utils::format_count(num_damaged_packs, "damaged pack", "damaged packs"),
+            "[ERROR]".bold().red()
+        );
+    }
+
+    if error_counter > 0 || num_damaged_packs > 0 {
+        bail!(
+            "Verification failed: {} and {} have errors",
+            utils::format_count(error_counter, "snapshot", "snapshots"),
+            utils::format_count(num_damaged_packs, "pack", "packs")
+        );
+    }
 
     Ok(())
 }
 
+/// Reads and verifies a list of packs. Damaged packs are reported.
+/// Returns the number of blobs found in the packs that are not listed in the index and
+/// the number of damaged packs.
+fn verify_packs(
+    repo: &dyn RepositoryBackend,
+    backend: &dyn StorageBackend,
+    secure_storage: &SecureStorage,
+    packs: &[ID],
+    visited_blobs: &mut BTreeSet<ID>,
+) -> (usize, usize) {
+    let bar = ProgressBar::new(packs.len() as u64);
+    bar.set_draw_target(default_bar_draw_target());
+    bar.set_style(
+        ProgressStyle::default_bar()
+            .template(
+                "[{custom_elapsed}] [{bar:20.cyan/white}] Reading packs: {pos} / {len}  [ETA: {custom_eta}]",
+            )
+            .unwrap()
+            .progress_chars("=> ")
+            .with_key(
+                "custom_elapsed",
+                move |state: &ProgressState, w: &mut dyn std::fmt::Write| {
+                    let elapsed = state.elapsed();
+                    let custom_elapsed = utils::pretty_print_duration(elapsed);
+                    let _ = w.write_str(&custom_elapsed);
+                },
+            )
+            .with_key(
+                "custom_eta",
+                move |state: &ProgressState, w: &mut dyn std::fmt::Write| {
+                    let eta = state.eta();
+                    let custom_eta = utils::pretty_print_duration(eta);
+                    let _ = w.write_str(&custom_eta);
+                },
+            ),
+    );
+
+    let num_visited_blobs = visited_blobs.len();
+    let mut num_dangling_blobs = 0;
+    let mut damaged_packs = Vec::new();
+    for pack_id in packs {
+        match verify_pack(repo, backend, secure_storage, pack_id, visited_blobs) {
+            Ok(dangling_blobs) => num_dangling_blobs += dangling_blobs,
+            Err(e) => damaged_packs.push((pack_id, e)),
+        }
+        bar.inc(1);
+    }
+    bar.finish_and_clear();
+
+    ui::cli::log!(
+        "Verified {} blobs from {} packs",
+        visited_blobs.len() - num_visited_blobs,
+        packs.len()
+    );
+    for (pack_id, e) in &damaged_packs {
+        ui::cli::log!(
+            "{} Pack {}: {}",
+            "[ERROR]".bold().red(),
+            pack_id,
+            e.to_string()
+        );
+    }
+
+    (num_dangling_blobs, damaged_packs.len())
+}
+
 /// Verify the checksum and contents of a snapshot with a known ID in the repository.
 /// This function will verify the checksum of the Snapshot object and the contents of all blobs
 /// referenced by it.
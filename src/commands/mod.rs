@@ -14,29 +14,49 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use std::{collections::BTreeSet, path::PathBuf, str::FromStr, sync::Arc};
+use std::{collections::BTreeSet, path::PathBuf, str::FromStr, sync::Arc, time::Duration};
 
 use anyhow::{Error, Result, anyhow, bail};
-use clap::{ArgGroup, Parser, Subcommand};
+use clap::{ArgGroup, Args, Parser, Subcommand};
 
 use crate::{
-    global::{FileType, ID},
+    backend::retry::RetryPolicy,
+    global::{
+        FileType, ID,
+        defaults::{
+            DEFAULT_KDF_ITERATIONS, DEFAULT_KDF_MEMORY_COST, DEFAULT_KDF_PARALLELISM,
+            DEFAULT_RETRIES, DEFAULT_RETRY_DELAY_MS,
+        },
+    },
     repository::{
         RepositoryBackend,
+        keys::{KdfAlgorithm, KdfParams},
+        manifest::ChunkerParams,
         snapshot::{Snapshot, SnapshotStreamer},
+        storage::{CompressionAlgorithm, CompressionSettings},
     },
+    utils,
 };
 
 pub mod cmd_amend;
+pub mod cmd_cache;
 pub mod cmd_cat;
 pub mod cmd_clean;
+pub mod cmd_config;
+pub mod cmd_copy;
 pub mod cmd_diff;
 pub mod cmd_forget;
 pub mod cmd_init;
+pub mod cmd_key;
 pub mod cmd_log;
 pub mod cmd_ls;
+pub mod cmd_migrate;
+pub mod cmd_repair;
 pub mod cmd_restore;
+pub mod cmd_serve;
 pub mod cmd_snapshot;
+pub mod cmd_stats;
+pub mod cmd_unlock;
 pub mod cmd_verify;
 
 // CLI arguments
@@ -69,13 +89,28 @@ pub enum Command {
     Diff(cmd_diff::CmdArgs),
     Cat(cmd_cat::CmdArgs),
     Verify(cmd_verify::CmdArgs),
+    Key(cmd_key::CmdArgs),
+    Unlock(cmd_unlock::CmdArgs),
+    Config(cmd_config::CmdArgs),
+    Migrate(cmd_migrate::CmdArgs),
+    Repair(cmd_repair::CmdArgs),
+    Copy(cmd_copy::CmdArgs),
+    Stats(cmd_stats::CmdArgs),
+    Cache(cmd_cache::CmdArgs),
+    Serve(cmd_serve::CmdArgs),
 }
 
 #[derive(Parser, Debug)]
 #[clap(group = ArgGroup::new("verbosity_group").multiple(true))]
 pub struct GlobalArgs {
     /// Repository path
-    #[clap(short = 'r', long = "repo", value_parser)]
+    #[clap(
+        short = 'r',
+        long = "repo",
+        value_parser,
+        default_value = "",
+        hide_default_value = true
+    )]
     pub repo: String,
 
     /// SSH public key
@@ -94,6 +129,17 @@ pub struct GlobalArgs {
     #[clap(short = 'k', long = "key-file", value_parser)]
     pub key: Option<PathBuf>,
 
+    /// Directory of the local cache [default: $XDG_CACHE_HOME/mapache]
+    #[clap(long, value_parser)]
+    pub cache_dir: Option<PathBuf>,
+
+    /// Do not use the local cache
+    #[clap(long, value_parser)]
+    pub no_cache: bool,
+
+    #[clap(flatten)]
+    pub retry: RetryArgs,
+
     /// Disable logging (verbosity = 0)
     #[clap(long, value_parser, group = "verbosity_group")]
     pub quiet: bool,
@@ -103,6 +149,180 @@ pub struct GlobalArgs {
     pub verbosity: Option<u32>,
 }
 
+/// Credentials for a second repository used by commands that read from another repository.
+/// The SSH keys are shared with the main repository.
+#[derive(Args, Debug, Clone, Default)]
+pub struct FromRepoArgs {
+    /// Path to a file to read the password of the source repository
+    #[clap(long, value_parser)]
+    pub from_password_file: Option<PathBuf>,
+
+    /// Path to a KeyFile for the source repository
+    #[clap(long = "from-key-file", value_parser)]
+    pub from_key: Option<PathBuf>,
+}
+
+impl FromRepoArgs {
+    /// Global arguments to open the source repository at `repo`
+    pub fn global_args(&self, repo: &str, global_args: &GlobalArgs) -> GlobalArgs {
+        GlobalArgs {
+            repo: repo.to_string(),
+            ssh_pubkey: global_args.ssh_pubkey.clone(),
+            ssh_privatekey: global_args.ssh_privatekey.clone(),
+            password_file: self.from_password_file.clone(),
+            key: self.from_key.clone(),
+            cache_dir: global_args.cache_dir.clone(),
+            no_cache: global_args.no_cache,
+            retry: global_args.retry.clone(),
+            quiet: global_args.quiet,
+            verbosity: global_args.verbosity,
+        }
+    }
+}
+
+/// Retries of failed backend operations
+#[derive(Args, Debug, Clone)]
+pub struct RetryArgs {
+    /// Times a failed operation on a remote repository is retried
+    #[clap(long, default_value_t = DEFAULT_RETRIES)]
+    pub retries: u32,
+
+    /// Delay before the first retry in ms, doubled on each retry
+    #[clap(long, default_value_t = DEFAULT_RETRY_DELAY_MS)]
+    pub retry_delay: u64,
+}
+
+impl Default for RetryArgs {
+    fn default() -> Self {
+        Self {
+            retries: DEFAULT_RETRIES,
+            retry_delay: DEFAULT_RETRY_DELAY_MS,
+        }
+    }
+}
+
+impl RetryArgs {
+    pub fn policy(&self) -> RetryPolicy {
+        RetryPolicy::new(self.retries, Duration::from_millis(self.retry_delay))
+    }
+}
+
+/// Key derivation arguments for commands that create keys
+#[derive(Args, Debug, Clone)]
+pub struct KdfArgs {
+    /// Key derivation function used to encrypt the master key
+    #[clap(long, value_enum, default_value_t = KdfAlgorithm::Argon2id)]
+    pub kdf: KdfAlgorithm,
+
+    /// KDF memory cost in KiB
+    #[clap(long, default_value_t = DEFAULT_KDF_MEMORY_COST)]
+    pub kdf_memory: u32,
+
+    /// KDF number of iterations
+    #[clap(long, default_value_t = DEFAULT_KDF_ITERATIONS)]
+    pub kdf_iterations: u32,
+
+    /// KDF degree of parallelism
+    #[clap(long, default_value_t = DEFAULT_KDF_PARALLELISM)]
+    pub kdf_parallelism: u32,
+}
+
+impl Default for KdfArgs {
+    fn default() -> Self {
+        let params = KdfParams::default();
+        Self {
+            kdf: params.algorithm,
+            kdf_memory: params.memory_cost,
+            kdf_iterations: params.iterations,
+            kdf_parallelism: params.parallelism,
+        }
+    }
+}
+
+impl KdfArgs {
+    pub fn params(&self) -> KdfParams {
+        KdfParams {
+            algorithm: self.kdf,
+            memory_cost: self.kdf_memory,
+            iterations: self.kdf_iterations,
+            parallelism: self.kdf_parallelism,
+        }
+    }
+}
+
+/// Compression arguments. Unset arguments keep the current settings.
+#[derive(Args, Debug, Clone, Default)]
+pub struct CompressionArgs {
+    /// Compression algorithm for blob data
+    #[clap(long, value_enum)]
+    pub compression: Option<CompressionAlgorithm>,
+
+    /// Compression level
+    #[clap(long, allow_negative_numbers = true)]
+    pub compression_level: Option<i32>,
+
+    /// Store blobs uncompressed when compression does not reduce their size
+    #[clap(long, num_args = 0..=1, default_missing_value = "true")]
+    pub auto_compression: Option<bool>,
+}
+
+impl CompressionArgs {
+    /// Applies the arguments on top of some base settings
+    pub fn apply(&self, base: CompressionSettings) -> Result<CompressionSettings> {
+        let settings = CompressionSettings {
+            algorithm: self.compression.unwrap_or(base.algorithm),
+            level: self.compression_level.unwrap_or(base.level),
+            auto: self.auto_compression.unwrap_or(base.auto),
+        };
+        settings.validate()?;
+        Ok(settings)
+    }
+}
+
+/// Chunker arguments for commands that create repositories.
+/// Unset arguments keep the default parameters.
+#[derive(Args, Debug, Clone, Default)]
+pub struct ChunkerArgs {
+    /// Minimum chunk size (e.g. 64KiB) [default: 512KiB]
+    #[clap(long, value_parser = utils::parse_size_string)]
+    pub chunk_min_size: Option<u64>,
+
+    /// Average chunk size [default: 1MiB]
+    #[clap(long, value_parser = utils::parse_size_string)]
+    pub chunk_avg_size: Option<u64>,
+
+    /// Maximum chunk size [default: 8MiB]
+    #[clap(long, value_parser = utils::parse_size_string)]
+    pub chunk_max_size: Option<u64>,
+
+    /// FastCDC normalization level [0-3] [default: 1]
+    #[clap(long)]
+    pub chunk_normalization: Option<u8>,
+}
+
+impl ChunkerArgs {
+    /// Applies the arguments on top of some base parameters
+    pub fn apply(&self, base: ChunkerParams) -> Result<ChunkerParams> {
+        let to_u32 = |size: Option<u64>, default: u32| -> Result<u32> {
+            match size {
+                Some(size) => {
+                    u32::try_from(size).map_err(|_| anyhow!("Chunk size {} is too large", size))
+                }
+                None => Ok(default),
+            }
+        };
+
+        let params = ChunkerParams {
+            min_size: to_u32(self.chunk_min_size, base.min_size)?,
+            avg_size: to_u32(self.chunk_avg_size, base.avg_size)?,
+            max_size: to_u32(self.chunk_max_size, base.max_size)?,
+            normalization: self.chunk_normalization.unwrap_or(base.normalization),
+        };
+        params.validate()?;
+        Ok(params)
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum UseSnapshot {
     Latest,
@@ -132,14 +352,30 @@ impl std::fmt::Display for UseSnapshot {
     }
 }
 
+/// Finds the snapshot to use. If a host is given, 'latest' resolves to the most recent
+/// snapshot created in that host. Snapshots without a hostname, saved before hostnames
+/// were recorded, are used if no snapshot was created in that host.
 pub(crate) fn find_use_snapshot(
     repo: Arc<dyn RepositoryBackend>,
     use_snapshot: &UseSnapshot,
+    host: Option<&str>,
 ) -> Result<Option<(ID, Snapshot)>> {
     match use_snapshot {
         UseSnapshot::Latest => {
             let mut snapshots = SnapshotStreamer::new(repo.clone())?;
-            Ok(snapshots.latest())
+            let Some(host) = host else {
+                return Ok(snapshots.latest());
+            };
+
+            let (with_host, without_host): (Vec<_>, Vec<_>) = snapshots
+                .filter(|(_, snapshot)| snapshot.has_host(host) || snapshot.hostname.is_none())
+                .partition(|(_, snapshot)| snapshot.has_host(host));
+            let latest = |snapshots: Vec<(ID, Snapshot)>| {
+                snapshots
+                    .into_iter()
+                    .max_by_key(|(_, snapshot)| snapshot.timestamp)
+            };
+            Ok(latest(with_host).or_else(|| latest(without_host)))
         }
         UseSnapshot::SnapshotId(prefix) => {
             let (id, _path) = repo.find(FileType::Snapshot, prefix)?;
@@ -184,5 +420,14 @@ pub fn run(args: &Cli) -> Result<()> {
         Command::Diff(cmd_args) => cmd_diff::run(&args.global_args, cmd_args),
         Command::Cat(cmd_args) => cmd_cat::run(&args.global_args, cmd_args),
         Command::Verify(cmd_args) => cmd_verify::run(&args.global_args, cmd_args),
+        Command::Key(cmd_args) => cmd_key::run(&args.global_args, cmd_args),
+        Command::Unlock(cmd_args) => cmd_unlock::run(&args.global_args, cmd_args),
+        Command::Config(cmd_args) => cmd_config::run(&args.global_args, cmd_args),
+        Command::Migrate(cmd_args) => cmd_migrate::run(&args.global_args, cmd_args),
+        Command::Repair(cmd_args) => cmd_repair::run(&args.global_args, cmd_args),
+        Command::Copy(cmd_args) => cmd_copy::run(&args.global_args, cmd_args),
+        Command::Stats(cmd_args) => cmd_stats::run(&args.global_args, cmd_args),
+        Command::Cache(cmd_args) => cmd_cache::run(&args.global_args, cmd_args),
+        Command::Serve(cmd_args) => cmd_serve::run(&args.global_args, cmd_args),
     }
 }
@@ -23,21 +23,64 @@ pub(crate) const DEFAULT_READ_CONCURRENCY: usize = 4;
 pub(crate) const DEFAULT_WRITE_CONCURRENCY: usize = 5;
 
 // -- Index --
-pub(crate) const INDEX_FLUSH_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+/// Maximum time a pending index collects packs before it is saved. Packs written after the
+/// last saved index are recovered from their headers by the next snapshot.
+pub(crate) const INDEX_FLUSH_TIMEOUT: Duration = Duration::from_secs(5 * 60);
 pub(crate) const BLOBS_PER_INDEX_FILE: usize = 65535;
 
+// -- Locks --
+/// Time between refreshes of a held lock.
+pub(crate) const LOCK_REFRESH_INTERVAL: Duration = Duration::from_secs(5 * 60);
+/// Locks not refreshed within this time are considered stale.
+pub(crate) const STALE_LOCK_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+
+// -- Backend retries --
+/// Number of times a failed backend operation is retried
+pub(crate) const DEFAULT_RETRIES: u32 = 5;
+/// Delay before the first retry of a failed backend operation, in milliseconds
+pub(crate) const DEFAULT_RETRY_DELAY_MS: u64 = 500;
+/// Upper bound of the delay between retries
+pub(crate) const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+// -- Server --
+/// Largest request body accepted by `serve`. It leaves room for packs of the largest size.
+pub(crate) const DEFAULT_MAX_REQUEST_BODY_SIZE: u64 = 2 * size::GiB;
+/// Number of connections `serve` handles at the same time
+pub(crate) const DEFAULT_MAX_CONNECTIONS: usize = 64;
+/// Time `serve` waits for a client to send or receive data before closing the connection.
+/// It is longer than the time clients keep idle connections open.
+pub(crate) const SERVER_TIMEOUT: Duration = Duration::from_secs(60);
+
+// -- Key derivation --
+/// Argon2 memory cost in KiB
+pub(crate) const DEFAULT_KDF_MEMORY_COST: u32 = argon2::Params::DEFAULT_M_COST;
+/// Argon2 number of iterations
+pub(crate) const DEFAULT_KDF_ITERATIONS: u32 = argon2::Params::DEFAULT_T_COST;
+/// Argon2 degree of parallelism
+pub(crate) const DEFAULT_KDF_PARALLELISM: u32 = argon2::Params::DEFAULT_P_COST;
+
 // -- Packing --
-/// Minimum pack size before flushing to the backend.
-pub const MAX_PACK_SIZE: u64 = 16 * size::MiB;
+/// Default pack size. Packs are flushed to the backend once they grow beyond this size.
+pub const DEFAULT_PACK_SIZE: u64 = 16 * size::MiB;
+/// Smallest configurable pack size
+pub(crate) const MIN_PACK_SIZE: u64 = size::MiB;
+/// Largest configurable pack size. Blob offsets within a pack are 32-bit.
+pub(crate) const MAX_PACK_SIZE: u64 = size::GiB;
 pub(crate) const HEADER_BLOB_MULTIPLE: usize = 64;
 
 // -- Chunking --
-/// Minimum chunk size
+/// Default minimum chunk size
 pub(crate) const MIN_CHUNK_SIZE: u64 = 512 * size::KiB;
-/// Average chunk size
+/// Default average chunk size
 pub(crate) const AVG_CHUNK_SIZE: u64 = size::MiB;
-/// Maximum chunk size
+/// Default maximum chunk size
 pub(crate) const MAX_CHUNK_SIZE: u64 = 8 * size::MiB;
+/// Default FastCDC normalization level
+pub(crate) const DEFAULT_CHUNK_NORMALIZATION: u8 = 1;
+
+// -- Cache --
+/// Caches not used within this number of days are removed by `cache --cleanup`
+pub(crate) const DEFAULT_CACHE_MAX_AGE_DAYS: u64 = 30;
 
 // -- Display --
 pub(crate) const SHORT_REPO_ID_LEN: usize = 5;
@@ -47,4 +90,4 @@ pub(crate) const DEFAULT_VERBOSITY: u32 = 1;
 
 // -- Garbage collection --
 pub(crate) const DEFAULT_GC_TOLERANCE: f32 = 0.05; // In [0-100] %
-pub(crate) const DEFAULT_MIN_PACK_SIZE_FACTOR: f32 = 0.05; // Repack files smaller than this factor of the max pack size
+pub(crate) const DEFAULT_MIN_PACK_SIZE_FACTOR: f32 = 0.05; // Repack files smaller than this factor of the pack size
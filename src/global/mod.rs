@@ -16,7 +16,7 @@
 
 pub mod defaults;
 
-use std::sync::LazyLock;
+use std::{path::PathBuf, sync::LazyLock};
 
 use anyhow::{Context, Result, bail};
 use num_enum::FromPrimitive;
@@ -24,19 +24,23 @@ use parking_lot::{RwLock, RwLockReadGuard};
 use rand::{TryRngCore, rngs::OsRng};
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
-use crate::{commands::GlobalArgs, global::defaults::DEFAULT_VERBOSITY, utils};
+use crate::{commands::GlobalArgs, global::defaults::DEFAULT_VERBOSITY, repository::cache, utils};
 
 pub const ID_LENGTH: usize = 32;
 pub type Hash256 = [u8; ID_LENGTH];
 
 pub struct GlobalOpts {
     pub verbosity: u32,
+
+    /// Base directory of the local cache. The cache is disabled if None.
+    pub cache_dir: Option<PathBuf>,
 }
 
 impl Default for GlobalOpts {
     fn default() -> Self {
         Self {
             verbosity: DEFAULT_VERBOSITY,
+            cache_dir: None,
         }
     }
 }
@@ -53,12 +57,32 @@ pub fn set_global_opts_with_args(global_args: &GlobalArgs) {
         DEFAULT_VERBOSITY
     };
 
-    let new_opts = GlobalOpts { verbosity };
+    let cache_dir = if global_args.no_cache {
+        None
+    } else {
+        global_args
+            .cache_dir
+            .clone()
+            .or_else(cache::default_cache_dir)
+    };
+
+    let new_opts = GlobalOpts {
+        verbosity,
+        cache_dir,
+    };
 
     let mut opts_guard = GLOBAL_OPTS.write();
     *opts_guard = Some(new_opts);
 }
 
+/// Disables the local cache for the rest of the program. Used by commands that must read
+/// everything from the backend to detect damage in the repository.
+pub fn disable_cache() {
+    if let Some(opts) = GLOBAL_OPTS.write().as_mut() {
+        opts.cache_dir = None;
+    }
+}
+
 pub fn global_opts() -> RwLockReadGuard<'static, Option<GlobalOpts>> {
     GLOBAL_OPTS.read()
 }
@@ -87,6 +111,11 @@ impl ID {
         Self(utils::calculate_hash(data))
     }
 
+    /// Constructs an ID from the keyed hash of some content.
+    pub fn from_keyed_content<T: AsRef<[u8]>>(key: &Hash256, data: T) -> Self {
+        Self(utils::calculate_keyed_hash(key, data))
+    }
+
     /// Converts the ID to a hex String.
     pub fn to_hex(&self) -> String {
         utils::bytes_to_hex(&self.0)
@@ -122,7 +151,7 @@ impl ID {
             ));
         }
 
-        if hex_len % 2 != 0 {
+        if !hex_len.is_multiple_of(2) {
             bail!("Hex string has an odd length");
         }
 
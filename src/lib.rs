@@ -4,5 +4,6 @@ pub mod commands;
 pub mod global;
 pub mod repository;
 pub mod restorer;
+pub mod server;
 pub mod ui;
 pub mod utils;
@@ -0,0 +1,332 @@
+// mapache is an incremental backup tool
+// Copyright (C) 2025  Javier Lancha Vázquez <javier.lancha@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::{
+    collections::BTreeSet,
+    io::{Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{Context, Result, bail};
+
+use crate::{
+    global::{FileType, ID, global_opts},
+    ui,
+};
+
+/// Name of the cache directory inside the user cache directory
+const CACHE_DIR_NAME: &str = "mapache";
+
+/// Marks the cache directory so backup tools can skip it.
+/// See https://bford.info/cachedir/
+const CACHEDIR_TAG_NAME: &str = "CACHEDIR.TAG";
+const CACHEDIR_TAG: &str = "Signature: 8a477f597d28d172789f06886806bc55\n\
+                            # This file is a cache directory tag created by mapache.\n";
+
+/// File updated every time the cache of a repository is opened
+const LAST_USED_NAME: &str = "last-used";
+
+/// Returns the default cache directory: `$XDG_CACHE_HOME/mapache`, falling back to
+/// `$HOME/.cache/mapache` (or `%LOCALAPPDATA%\mapache` on Windows).
+pub fn default_cache_dir() -> Option<PathBuf> {
+    let non_empty = |var: &str| std::env::var_os(var).filter(|value| !value.is_empty());
+
+    if let Some(xdg_cache_home) = non_empty("XDG_CACHE_HOME") {
+        return Some(PathBuf::from(xdg_cache_home).join(CACHE_DIR_NAME));
+    }
+    if cfg!(windows)
+        && let Some(local_app_data) = non_empty("LOCALAPPDATA")
+    {
+        return Some(PathBuf::from(local_app_data).join(CACHE_DIR_NAME));
+    }
+    non_empty("HOME").map(|home| PathBuf::from(home).join(".cache").join(CACHE_DIR_NAME))
+}
+
+/// A local cache of repository files.
+///
+/// The cache stores index files, snapshot files and tree packs exactly as they are stored in
+/// the backend, so the cached data is encrypted. Each repository has its own directory named
+/// after the repository ID. All files are content-addressed, so a cached file is valid as long
+/// as it exists in the repository. Files removed from the repository are removed from the
+/// cache when it is validated.
+pub struct Cache {
+    path: PathBuf,
+}
+
+impl Cache {
+    /// Opens the cache of a repository in the cache directory from the global options.
+    /// Returns None if caching is disabled or the cache cannot be created.
+    pub fn from_global_opts(repo_id: &ID) -> Option<Self> {
+        let base_dir = global_opts().as_ref()?.cache_dir.clone()?;
+        match Self::open(&base_dir, repo_id) {
+            Ok(cache) => Some(cache),
+            Err(e) => {
+                ui::cli::warning!("Could not open the cache in {}: {}", base_dir.display(), e);
+                None
+            }
+        }
+    }
+
+    /// Opens (or creates) the cache of a repository in a base cache directory
+    pub fn open(base_dir: &Path, repo_id: &ID) -> Result<Self> {
+        std::fs::create_dir_all(base_dir)?;
+        let tag_path = base_dir.join(CACHEDIR_TAG_NAME);
+        if !tag_path.exists() {
+            std::fs::write(&tag_path, CACHEDIR_TAG)?;
+        }
+
+        let path = base_dir.join(repo_id.to_hex());
+        for file_type in [FileType::Index, FileType::Snapshot, FileType::Object] {
+            std::fs::create_dir_all(path.join(Self::dir_name(file_type)?))?;
+        }
+        std::fs::write(path.join(LAST_USED_NAME), b"")?;
+
+        Ok(Self { path })
+    }
+
+    /// Returns the contents of a cached file, if it is cached
+    pub fn load(&self, file_type: FileType, id: &ID) -> Option<Vec<u8>> {
+        let path = self.file_path(file_type, id).ok()?;
+        std::fs::read(path).ok()
+    }
+
+    /// Returns `length` bytes of a cached file starting at `offset`, if it is cached
+    pub fn load_range(
+        &self,
+        file_type: FileType,
+        id: &ID,
+        offset: u64,
+        length: u64,
+    ) -> Option<Vec<u8>> {
+        let path = self.file_path(file_type, id).ok()?;
+        let mut file = std::fs::File::open(path).ok()?;
+        file.seek(SeekFrom::Start(offset)).ok()?;
+        let mut data = vec![0; length as usize];
+        file.read_exact(&mut data).ok()?;
+        Some(data)
+    }
+
+    /// Saves a file in the cache
+    pub fn save(&self, file_type: FileType, id: &ID, data: &[u8]) -> Result<()> {
+        let path = self.file_path(file_type, id)?;
+
+        // Write to a unique temporary file so concurrent processes don't clobber each other
+        let tmp_path = path.with_extension(format!("{}.tmp", ID::new_random().to_short_hex(8)));
+        std::fs::write(&tmp_path, data)?;
+        std::fs::rename(&tmp_path, &path).inspect_err(|_| {
+            let _ = std::fs::remove_file(&tmp_path);
+        })?;
+
+        Ok(())
+    }
+
+    /// Removes a file from the cache
+    pub fn remove(&self, file_type: FileType, id: &ID) {
+        if let Ok(path) = self.file_path(file_type, id) {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+
+    /// Lists the IDs of all cached files of a type
+    pub fn list(&self, file_type: FileType) -> Result<Vec<ID>> {
+        let mut ids = Vec::new();
+        for entry in std::fs::read_dir(self.path.join(Self::dir_name(file_type)?))? {
+            let entry = entry?;
+            if let Some(id) = entry
+                .file_name()
+                .to_str()
+                .and_then(|name| ID::from_hex(name).ok())
+            {
+                ids.push(id);
+            }
+        }
+        Ok(ids)
+    }
+
+    /// Removes all cached files of a type that are not in `valid`.
+    /// Returns the number of removed files.
+    pub fn retain(&self, file_type: FileType, valid: &BTreeSet<ID>) -> Result<usize> {
+        let mut num_removed = 0;
+        for id in self.list(file_type)? {
+            if !valid.contains(&id) {
+                self.remove(file_type, &id);
+                num_removed += 1;
+            }
+        }
+        Ok(num_removed)
+    }
+
+    fn file_path(&self, file_type: FileType, id: &ID) -> Result<PathBuf> {
+        Ok(self.path.join(Self::dir_name(file_type)?).join(id.to_hex()))
+    }
+
+    fn dir_name(file_type: FileType) -> Result<&'static str> {
+        match file_type {
+            FileType::Index => Ok("index"),
+            FileType::Snapshot => Ok("snapshots"),
+            FileType::Object => Ok("objects"),
+            FileType::Key | FileType::Manifest => bail!("{:?} files are not cached", file_type),
+        }
+    }
+}
+
+/// Information about the cache of a repository
+#[derive(Debug)]
+pub struct CacheInfo {
+    pub repo_id: ID,
+    pub path: PathBuf,
+    pub size: u64,
+    pub last_used: Option<SystemTime>,
+}
+
+impl CacheInfo {
+    /// The cache was not used within `max_age`
+    pub fn is_older_than(&self, max_age: Duration) -> bool {
+        match self.last_used {
+            Some(last_used) => last_used.elapsed().is_ok_and(|elapsed| elapsed > max_age),
+            None => true,
+        }
+    }
+}
+
+/// Lists the caches of all repositories in a base cache directory
+pub fn list_caches(base_dir: &Path) -> Result<Vec<CacheInfo>> {
+    let mut caches = Vec::new();
+    if !base_dir.exists() {
+        return Ok(caches);
+    }
+
+    for entry in std::fs::read_dir(base_dir)
+        .with_context(|| format!("Could not read cache directory {}", base_dir.display()))?
+    {
+        let entry = entry?;
+        let Some(repo_id) = entry
+            .file_name()
+            .to_str()
+            .and_then(|name| ID::from_hex(name).ok())
+        else {
+            continue;
+        };
+        if !entry.file_type()?.is_dir() {
+            continue;
+        }
+
+        let path = entry.path();
+        let last_used = std::fs::metadata(path.join(LAST_USED_NAME))
+            .and_then(|metadata| metadata.modified())
+            .ok();
+        caches.push(CacheInfo {
+            repo_id,
+            size: dir_size(&path)?,
+            path,
+            last_used,
+        });
+    }
+
+    caches.sort_by_key(|cache| cache.last_used);
+    Ok(caches)
+}
+
+/// Removes the caches that were not used within `max_age`. Returns the removed caches.
+pub fn cleanup(base_dir: &Path, max_age: Duration) -> Result<Vec<CacheInfo>> {
+    let mut removed = Vec::new();
+    for cache in list_caches(base_dir)? {
+        if cache.is_older_than(max_age) {
+            std::fs::remove_dir_all(&cache.path)
+                .with_context(|| format!("Could not remove cache {}", cache.path.display()))?;
+            removed.push(cache);
+        }
+    }
+    Ok(removed)
+}
+
+fn dir_size(path: &Path) -> Result<u64> {
+    let mut size = 0;
+    for entry in std::fs::read_dir(path)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if metadata.is_dir() {
+            size += dir_size(&entry.path())?;
+        } else {
+            size += metadata.len();
+        }
+    }
+    Ok(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn test_cache_files() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let cache = Cache::open(temp_dir.path(), &ID::new_random())?;
+        assert!(temp_dir.path().join(CACHEDIR_TAG_NAME).exists());
+
+        let id_a = ID::new_random();
+        let id_b = ID::new_random();
+        cache.save(FileType::Snapshot, &id_a, b"mapache")?;
+        cache.save(FileType::Snapshot, &id_b, b"mapachito")?;
+        assert_eq!(
+            cache.load(FileType::Snapshot, &id_a),
+            Some(b"mapache".to_vec())
+        );
+        assert_eq!(cache.load(FileType::Index, &id_a), None);
+        assert_eq!(
+            cache.load_range(FileType::Snapshot, &id_b, 4, 5),
+            Some(b"chito".to_vec())
+        );
+        assert_eq!(cache.load_range(FileType::Snapshot, &id_b, 4, 6), None);
+        assert!(cache.save(FileType::Key, &id_a, b"mapache").is_err());
+
+        // Files not in the repository are removed
+        let valid = BTreeSet::from([id_b.clone()]);
+        assert_eq!(cache.retain(FileType::Snapshot, &valid)?, 1);
+        assert_eq!(cache.list(FileType::Snapshot)?, vec![id_b]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_cleanup() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let repo_id = ID::new_random();
+        let cache = Cache::open(temp_dir.path(), &repo_id)?;
+        cache.save(FileType::Index, &ID::new_random(), b"mapache")?;
+
+        let caches = list_caches(temp_dir.path())?;
+        assert_eq!(caches.len(), 1);
+        assert_eq!(caches[0].repo_id, repo_id);
+        assert_eq!(caches[0].size, 7);
+
+        let day = Duration::from_secs(24 * 3600);
+        assert!(cleanup(temp_dir.path(), day)?.is_empty());
+
+        // Last used two days ago
+        std::fs::File::options()
+            .write(true)
+            .open(caches[0].path.join(LAST_USED_NAME))?
+            .set_modified(SystemTime::now() - 2 * day)?;
+        assert_eq!(cleanup(temp_dir.path(), day)?.len(), 1);
+        assert!(list_caches(temp_dir.path())?.is_empty());
+
+        Ok(())
+    }
+}
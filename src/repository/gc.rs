@@ -27,14 +27,12 @@ use indicatif::{ProgressBar, ProgressStyle};
 use rayon::iter::{IntoParallelIterator, IntoParallelRefIterator, ParallelIterator};
 
 use crate::{
-    global::{
-        self, ID,
-        defaults::{DEFAULT_MIN_PACK_SIZE_FACTOR, MAX_PACK_SIZE},
-    },
+    global::{self, ID, defaults::DEFAULT_MIN_PACK_SIZE_FACTOR},
     repository::{
         RepositoryBackend, snapshot::SnapshotStreamer, streamers::SerializedNodeStreamer,
     },
     ui::{self, PROGRESS_REFRESH_RATE_HZ, SPINNER_TICK_CHARS, default_bar_draw_target},
+    utils::bitmap::Bitmap,
 };
 
 /// The cleanup plan. This struct contains lists of items that are valid, unused or need some work.
@@ -42,14 +40,14 @@ use crate::{
 /// object is consumed and cannot be used again. This is an intended safety measure.
 pub struct Plan {
     pub repo: Arc<dyn RepositoryBackend>,
-    pub total_packs: usize, // Total number of blobs in the repository
-    pub referenced_blobs: BTreeSet<ID>, // Blobs referenced by existing snapshots
+    pub total_packs: usize,       // Total number of blobs in the repository
+    pub referenced_blobs: Bitmap, // Index entries referenced by existing snapshots
     pub referenced_packs: BTreeSet<ID>, // Packs referenced by the referenced blobs
     pub obsolete_packs: BTreeSet<ID>, // Packs containing non-referenced blobs
     pub small_packs: BTreeSet<ID>, // Small packs marked to be repacked (to merge)
     pub tolerated_packs: BTreeSet<ID>, // Packs containing garbage, but keep due to tolerance
     pub unused_packs: BTreeSet<ID>, // Packs not referenced by any snapshot or index
-    pub index_ids: BTreeSet<ID>, // Current index IDs
+    pub index_ids: BTreeSet<ID>,  // Current index IDs
 }
 
 /// Scan the repository and make a plan of what needs to be cleaned.
@@ -74,6 +72,8 @@ pub fn scan(repo: Arc<dyn RepositoryBackend>, tolerance: f32) -> Result<Plan> {
         small_packs: BTreeSet::new(),
     };
 
+    let pack_size = repo.pack_size();
+
     // Count garbage bytes in each pack
     let mut kept_pack_size: HashMap<ID, u64> = HashMap::new();
     let mut pack_garbage: HashMap<ID, u64> = HashMap::new();
@@ -89,7 +89,7 @@ pub fn scan(repo: Arc<dyn RepositoryBackend>, tolerance: f32) -> Result<Plan> {
     spinner.enable_steady_tick(Duration::from_millis(
         (1000.0f32 / PROGRESS_REFRESH_RATE_HZ as f32) as u64,
     ));
-    for (id, locator) in repo.index().read().iter_ids() {
+    for (pos, (_, locator)) in repo.index().read().iter_ids().enumerate() {
         kept_pack_size
             .entry(locator.pack_id.clone())
             .and_modify(|size| {
@@ -97,7 +97,7 @@ pub fn scan(repo: Arc<dyn RepositoryBackend>, tolerance: f32) -> Result<Plan> {
             })
             .or_default();
 
-        if !plan.referenced_blobs.contains(id) {
+        if !plan.referenced_blobs.get(pos) {
             pack_garbage
                 .entry(locator.pack_id)
                 .and_modify(|size| *size += locator.length as u64)
@@ -108,7 +108,7 @@ pub fn scan(repo: Arc<dyn RepositoryBackend>, tolerance: f32) -> Result<Plan> {
 
     // Find small packs to repack
     for (pack_id, size) in kept_pack_size {
-        if (size as f32 / MAX_PACK_SIZE as f32) < DEFAULT_MIN_PACK_SIZE_FACTOR {
+        if (size as f32 / pack_size as f32) < DEFAULT_MIN_PACK_SIZE_FACTOR {
             plan.small_packs.insert(pack_id);
         }
     }
@@ -133,7 +133,7 @@ pub fn scan(repo: Arc<dyn RepositoryBackend>, tolerance: f32) -> Result<Plan> {
         (1000.0f32 / PROGRESS_REFRESH_RATE_HZ as f32) as u64,
     ));
     for (pack_id, garbage_bytes) in pack_garbage.into_iter() {
-        if (garbage_bytes as f32 / MAX_PACK_SIZE as f32) > tolerance {
+        if (garbage_bytes as f32 / pack_size as f32) > tolerance {
             keep_packs.remove(&pack_id);
             plan.obsolete_packs.insert(pack_id);
         } else {
@@ -204,14 +204,9 @@ impl Plan {
         // Collect information about the blobs to repack. Since we will rewrite the index, we will
         // lose this information.
         let mut repack_blob_info = HashMap::new();
-        for referenced_blob_id in &self.referenced_blobs {
-            if let Some((pack_id, blob_type, offset, length)) =
-                self.repo.index().read().get(referenced_blob_id)
-            {
-                if self.obsolete_packs.contains(&pack_id) {
-                    repack_blob_info
-                        .insert(referenced_blob_id, (pack_id, blob_type, offset, length));
-                }
+        for (pos, (blob_id, locator)) in self.repo.index().read().iter_ids().enumerate() {
+            if self.referenced_blobs.get(pos) && self.obsolete_packs.contains(&locator.pack_id) {
+                repack_blob_info.insert(blob_id.clone(), locator);
             }
         }
 
@@ -241,23 +236,23 @@ impl Plan {
             .build()
             .expect("Failed to build thread pool");
         let process_result: Result<()> = pool.install(|| {
-            repack_blob_info.into_par_iter().try_for_each(
-                |(blob_id, (pack_id, blob_type, offset, length))| {
-                    let data = self.repo.read_from_file(
-                        global::FileType::Object,
-                        &pack_id,
-                        offset as u64,
-                        length as u64,
+            repack_blob_info
+                .into_par_iter()
+                .try_for_each(|(blob_id, locator)| {
+                    let data = self.repo.load_from_pack(
+                        &locator.pack_id,
+                        locator.offset,
+                        locator.length,
+                        locator.uncompressed,
                     )?;
                     self.repo.save_blob(
-                        blob_type,
+                        locator.blob_type,
                         data,
-                        global::SaveID::WithID(blob_id.clone()),
+                        global::SaveID::WithID(blob_id),
                     )?;
                     repack_bar.inc(1);
                     Ok(())
-                },
-            )
+                })
         });
         repack_bar.finish_and_clear();
         ui::cli::log!("Repacked {} blobs", repack_bar.position());
@@ -327,13 +322,16 @@ impl Plan {
     }
 }
 
-/// Returns all blobs and packs referenced by all existing snapshots in the repository.
+/// Returns the index entries and packs referenced by all existing snapshots in the repository.
+///
+/// The referenced entries are marked in a bitmap over the positions of the master index, which
+/// uses one bit per blob instead of a set of IDs. All copies of a referenced blob are marked.
 fn get_referenced_blobs_and_packs(
     repo: Arc<dyn RepositoryBackend>,
-) -> Result<(BTreeSet<ID>, BTreeSet<ID>)> {
-    let mut referenced_blobs = BTreeSet::new();
-    let mut referenced_packs = BTreeSet::new();
+) -> Result<(Bitmap, BTreeSet<ID>)> {
     let index = repo.index();
+    let mut referenced_blobs = Bitmap::new(index.read().len());
+    let mut referenced_packs = BTreeSet::new();
 
     let snapshot_streamer = SnapshotStreamer::new(repo.clone())?;
 
@@ -348,24 +346,29 @@ fn get_referenced_blobs_and_packs(
         (1000.0_f32 / PROGRESS_REFRESH_RATE_HZ as f32) as u64,
     ));
 
+    // Marks all copies of a blob as referenced. Returns false if the blob is not in the index.
+    let mut mark_referenced = |blob_id: &ID| -> bool {
+        let index = index.read();
+        let mut found = false;
+        for (pos, pack_id) in index.find_all(blob_id) {
+            found = true;
+            if referenced_blobs.set(pos) {
+                referenced_packs.insert(pack_id.clone());
+                spinner.set_position(referenced_blobs.count() as u64);
+            }
+        }
+        found
+    };
+
     for (_snapshot_id, snapshot) in snapshot_streamer {
         let tree_id = snapshot.tree.clone();
 
         // Tree blob of the snapshot
-        if referenced_blobs.insert(tree_id.clone()) {
-            spinner.set_position(referenced_blobs.len() as u64);
-        }
-
-        match index.read().get(&tree_id) {
-            Some((pack_id, _, _, _)) => {
-                referenced_packs.insert(pack_id);
-            }
-            None => {
-                ui::cli::warning!(
-                    "Snapshot tree {} is referenced but not found in index",
-                    tree_id
-                );
-            }
+        if !mark_referenced(&tree_id) {
+            ui::cli::warning!(
+                "Snapshot tree {} is referenced but not found in index",
+                tree_id
+            );
         }
 
         // Stream all nodes in the snapshot
@@ -381,35 +384,17 @@ fn get_referenced_blobs_and_packs(
                     let node = &stream_node.node;
 
                     // Tree blobs
-                    if let Some(tree) = &node.tree {
-                        if referenced_blobs.insert(tree.clone()) {
-                            spinner.set_position(referenced_blobs.len() as u64);
-                        }
-
-                        match index.read().get(tree) {
-                            Some((pack_id, _, _, _)) => {
-                                referenced_packs.insert(pack_id);
-                            }
-                            None => {
-                                missing_tree_blobs += 1;
-                            }
-                        }
+                    if let Some(tree) = &node.tree
+                        && !mark_referenced(tree)
+                    {
+                        missing_tree_blobs += 1;
                     }
 
                     // Data blobs
                     if let Some(blobs) = &node.blobs {
                         for blob_id in blobs {
-                            if referenced_blobs.insert(blob_id.clone()) {
-                                spinner.set_position(referenced_blobs.len() as u64);
-                            }
-
-                            match index.read().get(blob_id) {
-                                Some((pack_id, _, _, _)) => {
-                                    referenced_packs.insert(pack_id);
-                                }
-                                None => {
-                                    missing_data_blobs += 1;
-                                }
+                            if !mark_referenced(blob_id) {
+                                missing_data_blobs += 1;
                             }
                         }
                     }
@@ -438,7 +423,7 @@ fn get_referenced_blobs_and_packs(
     spinner.finish_and_clear();
     ui::cli::log!(
         "Found {} referenced blobs and {} packs",
-        referenced_blobs.len(),
+        referenced_blobs.count(),
         referenced_packs.len()
     );
 
@@ -15,29 +15,134 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 use std::{
-    collections::{BTreeSet, HashMap, HashSet},
+    collections::{BTreeSet, HashSet},
     time::Instant,
 };
 
-use anyhow::Result;
+use anyhow::{Context, Result, bail};
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    global::{self, BlobType, ID},
+    global::{self, BlobType, ID, ID_LENGTH},
     utils::indexset::IndexSet,
 };
 
-use super::{RepositoryBackend, packer::PackedBlobDescriptor};
+use super::{
+    BINARY_INDEX_FILES_VERSION, RepoVersion, RepositoryBackend,
+    packer::{PackedBlobDescriptor, UNCOMPRESSED_BLOB_FLAG},
+};
+
+/// Magic bytes at the start of a binary index file. JSON index files start with '{'.
+const BINARY_INDEX_MAGIC: &[u8; 4] = b"MPIX";
+/// Version of the binary index encoding
+const BINARY_INDEX_VERSION: u8 = 1;
+/// Magic, version, number of packs and number of blobs
+const BINARY_INDEX_HEADER_LEN: usize = 4 + 1 + 4 + 4;
+/// Blob ID, pack index, offset, length and type
+const BINARY_INDEX_BLOB_LEN: usize = ID_LENGTH + 4 + 4 + 4 + 1;
+
+/// Bit of `IndexEntry::pack` set for tree blobs
+const TREE_BLOB_FLAG: u32 = 1 << 31;
+/// Bit of `IndexEntry::pack` set for blobs stored uncompressed
+const UNCOMPRESSED_FLAG: u32 = 1 << 30;
+/// Bits of `IndexEntry::pack` that hold the pack index
+const PACK_INDEX_MASK: u32 = UNCOMPRESSED_FLAG - 1;
 
 /// Represents the location and size of a blob within a pack file.
+///
+/// Entries are stored in flat vectors instead of hash maps, so every blob in the index costs
+/// exactly `size_of::<IndexEntry>()` bytes. The blob type and the compression flag are stored
+/// in the high bits of the pack index.
 #[derive(Debug, Clone)]
-struct BlobLocation {
-    /// The index into the `pack_ids` `IndexSet` for the pack containing this blob. See Index.
-    pub pack_array_index: u32,
+struct IndexEntry {
+    id: ID,
+    /// The index into a pack ID table for the pack containing this blob, plus flags.
+    pack: u32,
     /// The offset of the blob within its pack file.
-    pub offset: u32,
+    offset: u32,
     /// The length of the blob within its pack file.
-    pub length: u32,
+    length: u32,
+}
+
+impl IndexEntry {
+    fn new(
+        id: ID,
+        pack_index: usize,
+        blob_type: &BlobType,
+        offset: u32,
+        length: u32,
+        uncompressed: bool,
+    ) -> Self {
+        assert!(
+            pack_index <= PACK_INDEX_MASK as usize,
+            "Too many packs in index"
+        );
+
+        let mut pack = pack_index as u32;
+        if *blob_type == BlobType::Tree {
+            pack |= TREE_BLOB_FLAG;
+        }
+        if uncompressed {
+            pack |= UNCOMPRESSED_FLAG;
+        }
+
+        Self {
+            id,
+            pack,
+            offset,
+            length,
+        }
+    }
+
+    #[inline]
+    fn pack_index(&self) -> usize {
+        (self.pack & PACK_INDEX_MASK) as usize
+    }
+
+    /// Returns a copy of this entry pointing to a different position in the pack table
+    #[inline]
+    fn with_pack_index(&self, pack_index: usize) -> Self {
+        Self {
+            pack: (self.pack & !PACK_INDEX_MASK) | pack_index as u32,
+            ..self.clone()
+        }
+    }
+
+    #[inline]
+    fn blob_type(&self) -> BlobType {
+        match self.pack & TREE_BLOB_FLAG {
+            0 => BlobType::Data,
+            _ => BlobType::Tree,
+        }
+    }
+
+    #[inline]
+    fn uncompressed(&self) -> bool {
+        self.pack & UNCOMPRESSED_FLAG != 0
+    }
+
+    fn locator(&self, pack_ids: &IndexSet<ID>) -> BlobLocator {
+        BlobLocator {
+            pack_id: pack_ids
+                .get_value(self.pack_index())
+                .expect("pack_index should always be valid for an existing blob")
+                .clone(),
+            blob_type: self.blob_type(),
+            offset: self.offset,
+            length: self.length,
+            uncompressed: self.uncompressed(),
+        }
+    }
+
+    fn to_index_file_blob(&self) -> IndexFileBlob {
+        IndexFileBlob {
+            id: self.id.clone(),
+            blob_type: self.blob_type(),
+            offset: self.offset,
+            length: self.length,
+            uncompressed: self.uncompressed(),
+        }
+    }
 }
 
 /// Represents the location and size of a blob within a pack file.
@@ -45,20 +150,21 @@ struct BlobLocation {
 #[derive(Debug, Clone)]
 pub struct BlobLocator {
     pub pack_id: ID,
+    pub blob_type: BlobType,
     pub offset: u32,
     pub length: u32,
+    pub uncompressed: bool,
 }
 
-/// Manages the mapping of blob IDs to their locations within pack files.
+/// The contents of a single index file.
 /// An `Index` can be in a 'pending' state, indicating it's still being built.
 #[derive(Debug, Clone)]
 pub struct Index {
-    /// blob ID -> BlobLocation map. This is the core lookup table.
-    data_ids: HashMap<ID, BlobLocation>,
-    tree_ids: HashMap<ID, BlobLocation>,
+    /// The blobs in this index, in insertion order.
+    entries: Vec<IndexEntry>,
 
     /// The Pack IDs referenced in this index. Using an `IndexSet` allows us
-    /// to store a small `usize` index in `BlobLocation` instead of the full `ID`,
+    /// to store a small `u32` index in `IndexEntry` instead of the full `ID`,
     /// significantly reducing memory usage.
     pack_ids: IndexSet<ID>,
 
@@ -80,8 +186,7 @@ impl Default for Index {
 impl Index {
     pub fn new() -> Self {
         Self {
-            data_ids: HashMap::new(),
-            tree_ids: HashMap::new(),
+            entries: Vec::new(),
             pack_ids: IndexSet::new(),
             is_pending: true,
             create_time: Instant::now(),
@@ -96,13 +201,6 @@ impl Index {
         self.is_pending = false;
     }
 
-    /// Marks the index as pending.
-    #[inline]
-    pub fn set_pending(&mut self) {
-        self.is_pending = true;
-        self.id = None;
-    }
-
     /// Returns the id of this index
     #[inline]
     pub fn id(&self) -> Option<ID> {
@@ -134,88 +232,48 @@ impl Index {
         let mut index = Self::new();
         // An index loaded from a file is considered complete and not pending.
         index.is_pending = false;
+        index
+            .entries
+            .reserve_exact(index_file.packs.iter().map(|pack| pack.blobs.len()).sum());
 
         for pack in index_file.packs {
-            let pack_index = index.pack_ids.insert(pack.id.clone());
+            let pack_index = index.pack_ids.insert(pack.id);
             for blob in pack.blobs {
-                let map = match blob.blob_type {
-                    BlobType::Data => &mut index.data_ids,
-                    BlobType::Tree => &mut index.tree_ids,
-                    BlobType::Padding => continue,
-                };
+                if blob.blob_type == BlobType::Padding {
+                    continue;
+                }
 
-                map.insert(
+                index.entries.push(IndexEntry::new(
                     blob.id,
-                    BlobLocation {
-                        pack_array_index: pack_index as u32,
-                        offset: blob.offset,
-                        length: blob.length,
-                    },
-                );
+                    pack_index,
+                    &blob.blob_type,
+                    blob.offset,
+                    blob.length,
+                    blob.uncompressed,
+                ));
             }
         }
         index
     }
 
-    /// Checks if the index contains the given object ID.
-    #[inline]
-    pub fn contains(&self, id: &ID) -> bool {
-        self.data_ids.contains_key(id) || self.tree_ids.contains_key(id)
-    }
-
-    /// Retrieves the pack ID, offset, and length for a given blob ID, if it exists.
-    /// Returns `None` if the blob ID is not found.
-    pub fn get(&self, id: &ID) -> Option<(ID, BlobType, u32, u32)> {
-        self.data_ids
-            .get(id)
-            .map(|location| {
-                let pack_id = self
-                    .pack_ids
-                    .get_value(location.pack_array_index as usize)
-                    .expect("pack_index should always be valid for an existing blob");
-                (
-                    pack_id.clone(),
-                    BlobType::Data,
-                    location.offset,
-                    location.length,
-                )
-            })
-            .or_else(|| {
-                self.tree_ids.get(id).map(|location| {
-                    let pack_id = self
-                        .pack_ids
-                        .get_value(location.pack_array_index as usize)
-                        .expect("pack_index should always be valid for an existing blob");
-                    (
-                        pack_id.clone(),
-                        BlobType::Tree,
-                        location.offset,
-                        location.length,
-                    )
-                })
-            })
-    }
-
     /// Adds all blob descriptors from a specific pack to the index.
     /// This method is optimized for adding multiple blobs from the same pack,
     /// as it only needs to look up the pack ID once.
     pub fn add_pack(&mut self, pack_id: &ID, packed_blob_descriptors: &[PackedBlobDescriptor]) {
         let pack_index = self.pack_ids.insert(pack_id.clone());
         for blob in packed_blob_descriptors {
-            let map = match blob.blob_type {
-                BlobType::Data => &mut self.data_ids,
-                BlobType::Tree => &mut self.tree_ids,
-                BlobType::Padding => continue,
-            };
+            if blob.blob_type == BlobType::Padding {
+                continue;
+            }
 
-            map.insert(
+            self.entries.push(IndexEntry::new(
                 blob.id.clone(),
-                BlobLocation {
-                    pack_array_index: pack_index as u32,
-                    offset: blob.offset,
-                    length: blob.length,
-                },
-            );
+                pack_index,
+                &blob.blob_type,
+                blob.offset,
+                blob.length,
+                blob.uncompressed,
+            ));
         }
     }
 
@@ -225,53 +283,28 @@ impl Index {
         self.finalize();
 
         // Don't do anything if the index is empty.
-        if self.data_ids.is_empty() && self.tree_ids.is_empty() {
+        if self.entries.is_empty() {
             return Ok((0, 0));
         }
 
-        let mut packs_with_blobs: HashMap<usize, Vec<IndexFileBlob>> = HashMap::new();
-        for (blob_id, location) in &self.data_ids {
-            let entry = packs_with_blobs
-                .entry(location.pack_array_index as usize)
-                .or_default();
-            entry.push(IndexFileBlob {
-                id: blob_id.clone(),
-                blob_type: BlobType::Data,
-                offset: location.offset,
-                length: location.length,
-            });
-        }
-        for (blob_id, location) in &self.tree_ids {
-            let entry = packs_with_blobs
-                .entry(location.pack_array_index as usize)
-                .or_default();
-            entry.push(IndexFileBlob {
-                id: blob_id.clone(),
-                blob_type: BlobType::Tree,
-                offset: location.offset,
-                length: location.length,
-            });
-        }
-
-        let mut index_file = IndexFile::new();
-
-        // Iterate through packs in the order they were inserted into `pack_ids`.
+        // Keep the packs in the order they were inserted into `pack_ids`.
         // This ensures a consistent ordering of packs in the generated index files.
-        for (pack_index, pack_id) in self.pack_ids.iter().enumerate() {
-            if let Some(blobs) = packs_with_blobs.remove(&pack_index) {
-                let index_pack_file = IndexFilePack {
-                    id: pack_id.clone(),
-                    blobs,
-                };
-                index_file.packs.push(index_pack_file);
-            }
+        let mut packs: Vec<IndexFilePack> = self
+            .pack_ids
+            .iter()
+            .map(|pack_id| IndexFilePack {
+                id: pack_id.clone(),
+                blobs: Vec::new(),
+            })
+            .collect();
+        for entry in &self.entries {
+            packs[entry.pack_index()]
+                .blobs
+                .push(entry.to_index_file_blob());
         }
+        packs.retain(|pack| !pack.blobs.is_empty());
 
-        let (id, raw_size, encoded_size) = repo.save_file(
-            global::FileType::Index,
-            serde_json::to_string(&index_file)?.as_bytes(),
-            global::SaveID::CalculateID,
-        )?;
+        let (id, raw_size, encoded_size) = save_index_file(repo, &IndexFile { packs })?;
         self.id = Some(id);
 
         Ok((raw_size, encoded_size))
@@ -279,7 +312,7 @@ impl Index {
 
     #[inline]
     pub fn num_blobs(&self) -> usize {
-        self.data_ids.len() + self.tree_ids.len()
+        self.entries.len()
     }
 
     #[inline]
@@ -293,54 +326,48 @@ impl Index {
     }
 
     pub fn iter_ids(&self) -> impl Iterator<Item = (&ID, BlobLocator)> {
-        self.data_ids
+        self.entries
             .iter()
-            .chain(self.tree_ids.iter())
-            .map(|(id, loc)| {
-                (
-                    id,
-                    BlobLocator {
-                        pack_id: self
-                            .pack_ids
-                            .get_value(loc.pack_array_index as usize)
-                            .unwrap()
-                            .clone(),
-                        offset: loc.offset,
-                        length: loc.length,
-                    },
-                )
-            })
+            .map(|entry| (&entry.id, entry.locator(&self.pack_ids)))
     }
 
-    fn remove_pack(&mut self, target_pack_id: &ID) {
-        let mut blobs_to_remove = Vec::new();
-
-        for (blob_id, blob_location) in self.data_ids.iter().chain(self.tree_ids.iter()) {
-            if let Some(pack_id) = self
-                .pack_ids
-                .get_value(blob_location.pack_array_index as usize)
-            {
-                if target_pack_id == pack_id {
-                    blobs_to_remove.push(blob_id.clone());
-                }
-            }
-        }
-
-        // Clean up
-        for blob_id in blobs_to_remove.into_iter() {
-            self.data_ids.remove(&blob_id);
-            self.tree_ids.remove(&blob_id);
+    fn remove_packs(&mut self, target_pack_ids: &BTreeSet<ID>) {
+        let pack_indices: HashSet<usize> = target_pack_ids
+            .iter()
+            .filter_map(|pack_id| self.pack_ids.get_index(pack_id).copied())
+            .collect();
+        self.entries
+            .retain(|entry| !pack_indices.contains(&entry.pack_index()));
+        for pack_id in target_pack_ids {
+            self.pack_ids.remove(pack_id);
         }
-        self.pack_ids.remove(target_pack_id);
     }
 }
 
-/// Manages a collection of `Index` instances, providing a unified view
+/// Manages all finalized index files and the index being built, providing a unified view
 /// over all known blobs in the repository.
+///
+/// The entries of all finalized indices are merged into a few tables sorted by blob ID
+/// (see `push_table`), so lookups are binary searches and the memory used per blob is the
+/// size of an `IndexEntry`. Every entry has a stable position while the index is not
+/// modified, which allows marking blobs with a `Bitmap` instead of a set of IDs.
 #[derive(Debug, Clone)]
 pub struct MasterIndex {
-    /// A list of individual indices, some of which might be pending.
-    indices: Vec<Index>,
+    /// The pack IDs referenced by the entries in `tables`.
+    pack_ids: IndexSet<ID>,
+
+    /// Tables of entries sorted by blob ID, in decreasing order of size.
+    tables: Vec<Vec<IndexEntry>>,
+
+    /// The IDs of the index files that contain the entries in `tables`.
+    index_ids: BTreeSet<ID>,
+
+    /// The tables must be written to new index files on the next save. This happens when
+    /// the index is cleaned up.
+    needs_rewrite: bool,
+
+    /// The index receiving new packs. It is merged into the tables once saved.
+    pending_index: Option<Index>,
 
     /// Stores the IDs of blobs that are waiting to be serialized into a pack file.
     pending_blobs: HashSet<ID>,
@@ -356,7 +383,11 @@ impl MasterIndex {
     /// Creates a new, empty `MasterIndex`.
     pub fn new() -> Self {
         Self {
-            indices: Vec::with_capacity(1),
+            pack_ids: IndexSet::new(),
+            tables: Vec::new(),
+            index_ids: BTreeSet::new(),
+            needs_rewrite: false,
+            pending_index: None,
             pending_blobs: HashSet::new(),
         }
     }
@@ -365,24 +396,106 @@ impl MasterIndex {
     /// or is currently a pending blob.
     pub fn contains(&self, id: &ID) -> bool {
         // Check finalized indices first
-        self.indices
+        self.tables
             .iter()
-            .any(|idx| !idx.is_pending && idx.contains(id))
+            .any(|table| Self::search(table, id).is_some())
             || self.pending_blobs.contains(id) // Then check pending blobs
     }
 
     /// Retrieves an entry for a given blob ID by searching through finalized indices.
     /// Pending blobs (those not yet packed) cannot be retrieved via this method.
-    pub fn get(&self, id: &ID) -> Option<(ID, BlobType, u32, u32)> {
-        self.indices
-            .iter()
-            .find_map(|idx| if !idx.is_pending { idx.get(id) } else { None })
+    pub fn get(&self, id: &ID) -> Option<(ID, BlobType, u32, u32, bool)> {
+        self.tables.iter().find_map(|table| {
+            Self::search(table, id).map(|pos| {
+                let locator = table[pos].locator(&self.pack_ids);
+                (
+                    locator.pack_id,
+                    locator.blob_type,
+                    locator.offset,
+                    locator.length,
+                    locator.uncompressed,
+                )
+            })
+        })
+    }
+
+    /// Returns the positions of all entries of a blob, together with the ID of the pack
+    /// containing each copy. Positions are in the range `0..len()` and follow the order of
+    /// `iter_ids`, so the entries of the pending index come after the finalized ones.
+    pub fn find_all<'a>(&'a self, id: &'a ID) -> impl Iterator<Item = (usize, &'a ID)> + 'a {
+        let mut table_start = 0;
+        let finalized = self.tables.iter().flat_map(move |table| {
+            let start = table_start;
+            table_start += table.len();
+
+            let first = table.partition_point(|entry| entry.id < *id);
+            table[first..]
+                .iter()
+                .take_while(move |entry| entry.id == *id)
+                .enumerate()
+                .map(move |(i, entry)| {
+                    let pack_id = self
+                        .pack_ids
+                        .get_value(entry.pack_index())
+                        .expect("pack_index should always be valid for an existing blob");
+                    (start + first + i, pack_id)
+                })
+        });
+
+        // The pending index is small and not sorted
+        let pending_start = self.num_finalized();
+        let pending = self.pending_index.iter().flat_map(move |index| {
+            index
+                .entries
+                .iter()
+                .enumerate()
+                .filter(move |(_, entry)| entry.id == *id)
+                .map(move |(i, entry)| {
+                    let pack_id = index
+                        .pack_ids
+                        .get_value(entry.pack_index())
+                        .expect("pack_index should always be valid for an existing blob");
+                    (pending_start + i, pack_id)
+                })
+        });
+
+        finalized.chain(pending)
+    }
+
+    /// Returns the number of entries, including those of the pending index
+    pub fn len(&self) -> usize {
+        self.num_finalized() + self.pending_index.as_ref().map_or(0, Index::num_blobs)
+    }
+
+    /// Returns `true` if there are no entries, including those of the pending index
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
     }
 
-    /// Adds a fully constructed `Index` to the master index.
-    /// This is typically used for adding loaded, finalized indices.
+    /// Returns the number of entries in the finalized indices
+    fn num_finalized(&self) -> usize {
+        self.tables.iter().map(|table| table.len()).sum()
+    }
+
+    /// Adds a loaded index to the master index. Its entries are merged into the tables.
     pub fn add_index(&mut self, index: Index) {
-        self.indices.push(index);
+        if let Some(id) = index.id() {
+            self.index_ids.insert(id);
+        }
+
+        let pack_map: Vec<usize> = index
+            .pack_ids
+            .iter()
+            .map(|pack_id| self.pack_ids.insert(pack_id.clone()))
+            .collect();
+
+        let mut table: Vec<IndexEntry> = index
+            .entries
+            .into_iter()
+            .map(|entry| entry.with_pack_index(pack_map[entry.pack_index()]))
+            .collect();
+        table.sort_by(|a, b| a.id.cmp(&b.id));
+        self.push_table(table);
     }
 
     /// Adds a blob ID to the set of blobs that are waiting to be packed.
@@ -391,155 +504,210 @@ impl MasterIndex {
         self.pending_blobs.insert(id)
     }
 
-    /// Processes a newly created pack of blobs. It removes these blobs from the
-    /// `pending_blobs` set and adds them to all currently pending `Index` instances.
+    /// Processes a newly created pack of blobs. It adds them to the pending `Index`, which
+    /// is created if needed. The pending index is saved when it is full.
     ///
-    /// It's assumed that there is at least one pending index that should receive these blobs,
-    /// or that a new one will be created as part of the overall backup process if needed.
+    /// The blobs stay in the `pending_blobs` set until the pending index is saved, so they
+    /// are not saved again in the meantime.
     pub fn add_pack(
         &mut self,
         repo: &dyn RepositoryBackend,
         pack_id: &ID,
         packed_blob_descriptors: Vec<PackedBlobDescriptor>, // Take ownership as it's consumed
     ) -> Result<(u64, u64)> {
-        // Remove processed blobs from the pending set
-        for blob in &packed_blob_descriptors {
-            self.pending_blobs.remove(&blob.id);
-        }
-
-        // Add the pack's blobs to all currently pending indices.
-        for idx in &mut self.indices {
-            if idx.is_pending() {
-                idx.add_pack(pack_id, &packed_blob_descriptors);
+        let pending_index = self.pending_index.get_or_insert_with(Index::new);
+        pending_index.add_pack(pack_id, &packed_blob_descriptors);
 
-                return match idx.is_full() {
-                    true => idx.finalize_and_save(repo),
-                    false => Ok((0, 0)), // Nothing was added to the repository
-                };
-            }
+        match pending_index.is_full() {
+            true => self.save_pending_index(repo),
+            false => Ok((0, 0)), // Nothing was added to the repository
         }
-
-        // There were no pending indices. Create a new empty pending index and add the pack.
-        let mut new_pending_index = Index::new();
-        new_pending_index.add_pack(pack_id, &packed_blob_descriptors);
-        self.indices.push(new_pending_index);
-
-        Ok((0, 0)) // Nothing was added to the repository
     }
 
-    /// Saves all pending indices managed by the `MasterIndex` to the repository.
-    /// Finalized indices are not saved again.
+    /// Saves the pending index to the repository. If the master index was cleaned up, the
+    /// finalized entries are also written to new index files.
     ///
     /// Returns the total raw and encoded sizes of the saved index files.
     pub fn save(&mut self, repo: &dyn RepositoryBackend) -> Result<(u64, u64)> {
-        let mut uncompressed_size: u64 = 0;
-        let mut compressed_size: u64 = 0;
-
-        for idx in &mut self.indices {
-            if idx.is_pending() {
-                let (uncompressed, compressed) = idx.finalize_and_save(repo)?;
-                uncompressed_size += uncompressed;
-                compressed_size += compressed;
-            }
-        }
+        let (mut uncompressed_size, mut compressed_size) = match self.needs_rewrite {
+            true => self.rewrite(repo)?,
+            false => (0, 0),
+        };
+
+        let (uncompressed, compressed) = self.save_pending_index(repo)?;
+        uncompressed_size += uncompressed;
+        compressed_size += compressed;
 
         Ok((uncompressed_size, compressed_size))
     }
 
+    /// Iterates over all entries in the master index, including the pending index.
+    /// The finalized entries come first, in the order of their positions.
     pub fn iter_ids(&self) -> impl Iterator<Item = (&ID, BlobLocator)> {
-        // We start with an "empty" chain or the first iterator
-        let mut chained_iterator: Box<dyn Iterator<Item = (&ID, BlobLocator)>> =
-            Box::new(std::iter::empty());
-
-        for index in &self.indices {
-            // Chain each index's iterator to the accumulating chained_iterator
-            chained_iterator = Box::new(chained_iterator.chain(index.iter_ids()));
-        }
-        chained_iterator
+        self.tables
+            .iter()
+            .flatten()
+            .map(|entry| (&entry.id, entry.locator(&self.pack_ids)))
+            .chain(self.pending_index.iter().flat_map(|index| index.iter_ids()))
     }
 
     /// Returns the IDs of all finalized (serialized) indices
     pub fn ids(&self) -> BTreeSet<ID> {
-        let mut ids = BTreeSet::new();
-        for idx in &self.indices {
-            if idx.is_pending() {
-                continue;
+        self.index_ids.clone()
+    }
+
+    /// Removes obsolete packs from all indices. All index files must be rewritten after
+    /// this, which happens on the next call to `save`.
+    pub fn cleanup(&mut self, obsolete_packs: Option<&BTreeSet<ID>>) {
+        if let Some(packs_to_remove) = obsolete_packs {
+            let pack_indices: HashSet<usize> = packs_to_remove
+                .iter()
+                .filter_map(|pack_id| self.pack_ids.get_index(pack_id).copied())
+                .collect();
+            for table in &mut self.tables {
+                table.retain(|entry| !pack_indices.contains(&entry.pack_index()));
             }
-            if let Some(id) = idx.id() {
-                ids.insert(id);
+            for pack_id in packs_to_remove {
+                self.pack_ids.remove(pack_id);
+            }
+
+            if let Some(pending_index) = &mut self.pending_index {
+                pending_index.remove_packs(packs_to_remove);
             }
         }
-        ids
+
+        self.tables.retain(|table| !table.is_empty());
+        self.index_ids.clear();
+        self.needs_rewrite = true;
     }
 
-    /// Removes obsolete packs from all indices
-    pub fn cleanup(&mut self, obsolete_packs: Option<&BTreeSet<ID>>) {
-        if let Some(packs_to_remove) = obsolete_packs {
-            for idx in &mut self.indices {
-                idx.set_pending();
-                for pack_id in packs_to_remove {
-                    idx.remove_pack(pack_id);
-                }
+    /// Binary search of a blob in a sorted table
+    #[inline]
+    fn search(table: &[IndexEntry], id: &ID) -> Option<usize> {
+        table.binary_search_by(|entry| entry.id.cmp(id)).ok()
+    }
+
+    /// Adds a sorted table. Tables are merged like in a binary counter: a table is merged
+    /// with the last one while the last one is not larger. This keeps the number of tables
+    /// logarithmic in the number of entries while every entry is merged a logarithmic number
+    /// of times.
+    fn push_table(&mut self, mut table: Vec<IndexEntry>) {
+        if table.is_empty() {
+            return;
+        }
+
+        while let Some(last) = self.tables.last()
+            && last.len() <= table.len()
+        {
+            let mut last = self.tables.pop().unwrap();
+            last.append(&mut table);
+            // Both halves are sorted. The stable sort detects the two runs and merges them.
+            last.sort_by(|a, b| a.id.cmp(&b.id));
+            table = last;
+        }
+        self.tables.push(table);
+    }
+
+    /// Merges all tables into one
+    fn compact(&mut self) {
+        let mut tables = std::mem::take(&mut self.tables).into_iter();
+        if let Some(mut merged) = tables.next() {
+            for mut table in tables {
+                merged.append(&mut table);
             }
+            merged.sort_by(|a, b| a.id.cmp(&b.id));
+            self.tables.push(merged);
         }
-        self.merge_index();
     }
 
-    /// Merges all current indices into a new collection of full indices.
-    /// This function can be used to defragment the current master index into
-    /// a small set of full index files then it becomes fragmented into many
-    /// small files.
-    fn merge_index(&mut self) {
-        let mut new_indices = Vec::new();
-        let mut pack_ids = BTreeSet::new();
+    /// Saves the pending index, if any, and merges its entries into the tables
+    fn save_pending_index(&mut self, repo: &dyn RepositoryBackend) -> Result<(u64, u64)> {
+        let Some(mut pending_index) = self.pending_index.take() else {
+            return Ok((0, 0));
+        };
 
-        let mut current_index = Index::new();
-        for idx in &mut self.indices {
-            for pack_id in idx.pack_ids.iter() {
-                if pack_ids.contains(pack_id) {
-                    continue;
-                }
+        let sizes = pending_index.finalize_and_save(repo)?;
+        for entry in &pending_index.entries {
+            self.pending_blobs.remove(&entry.id);
+        }
+        self.add_index(pending_index);
+        Ok(sizes)
+    }
+
+    /// Writes all finalized entries to new index files of up to `BLOBS_PER_INDEX_FILE` blobs,
+    /// grouped by pack. The files are built one at a time to bound the memory used.
+    fn rewrite(&mut self, repo: &dyn RepositoryBackend) -> Result<(u64, u64)> {
+        self.compact();
+        self.index_ids.clear();
+        self.needs_rewrite = false;
+
+        let Some(table) = self.tables.first() else {
+            return Ok((0, 0));
+        };
 
-                if current_index.is_full() {
-                    // Important: The index is not saved yet, so it must not be finalized
-                    new_indices.push(current_index);
-                    current_index = Index::new();
+        // Positions of the entries sorted by pack
+        let mut order: Vec<u32> = (0..table.len() as u32).collect();
+        order.sort_by_key(|&pos| table[pos as usize].pack_index());
+
+        let mut uncompressed_size = 0;
+        let mut compressed_size = 0;
+        let mut index_file = IndexFile::new();
+        let mut num_blobs = 0;
+        let mut current_pack = None;
+
+        for pos in order {
+            let entry = &table[pos as usize];
+            if current_pack != Some(entry.pack_index()) {
+                // Only split index files between packs
+                if num_blobs >= global::defaults::BLOBS_PER_INDEX_FILE {
+                    let (id, raw, encoded) = save_index_file(repo, &index_file)?;
+                    self.index_ids.insert(id);
+                    uncompressed_size += raw;
+                    compressed_size += encoded;
+                    index_file = IndexFile::new();
+                    num_blobs = 0;
                 }
 
-                pack_ids.insert(pack_id);
-                let mut packed_blob_descriptors = Vec::new();
-
-                let mut process_blobs =
-                    |blob_map: &HashMap<ID, BlobLocation>, blob_type: BlobType| {
-                        for (blob_id, _) in blob_map.iter() {
-                            let (blob_pack_id, _, offset, length) = idx.get(blob_id).unwrap();
-                            if blob_pack_id == *pack_id {
-                                let blob_descriptor = PackedBlobDescriptor {
-                                    id: blob_id.clone(),
-                                    blob_type: blob_type.clone(),
-                                    offset,
-                                    length,
-                                };
-                                packed_blob_descriptors.push(blob_descriptor);
-                            }
-                        }
-                    };
-
-                process_blobs(&idx.data_ids, BlobType::Data);
-                process_blobs(&idx.tree_ids, BlobType::Tree);
-                current_index.add_pack(pack_id, &packed_blob_descriptors);
+                current_pack = Some(entry.pack_index());
+                index_file.packs.push(IndexFilePack {
+                    id: self
+                        .pack_ids
+                        .get_value(entry.pack_index())
+                        .expect("pack_index should always be valid for an existing blob")
+                        .clone(),
+                    blobs: Vec::new(),
+                });
             }
+
+            index_file
+                .packs
+                .last_mut()
+                .unwrap()
+                .blobs
+                .push(entry.to_index_file_blob());
+            num_blobs += 1;
         }
 
-        if !current_index.is_empty() {
-            new_indices.push(current_index);
+        if num_blobs > 0 {
+            let (id, raw, encoded) = save_index_file(repo, &index_file)?;
+            self.index_ids.insert(id);
+            uncompressed_size += raw;
+            compressed_size += encoded;
         }
 
-        self.indices.clear();
-        self.indices = new_indices;
+        Ok((uncompressed_size, compressed_size))
     }
 }
 
+/// Saves an index file to the repository. Returns its ID and raw and encoded sizes.
+fn save_index_file(repo: &dyn RepositoryBackend, index_file: &IndexFile) -> Result<(ID, u64, u64)> {
+    repo.save_file(
+        global::FileType::Index,
+        &index_file.encode_for_version(repo.version())?,
+        global::SaveID::CalculateID,
+    )
+}
+
 /// Represents the on-disk format for an index file.
 /// This structure is used for serialization and deserialization of index data.
 #[derive(Debug, Default, Serialize, Deserialize)]
@@ -552,6 +720,134 @@ impl IndexFile {
     pub fn new() -> Self {
         Self::default()
     }
+
+    /// Encodes the index file in the format of a repository version. Versions older than 3
+    /// can't read binary index files, so they are stored as JSON.
+    pub fn encode_for_version(&self, version: RepoVersion) -> Result<Vec<u8>> {
+        match version >= BINARY_INDEX_FILES_VERSION {
+            true => Ok(self.encode()),
+            false => serde_json::to_vec(self).with_context(|| "Could not serialize JSON index"),
+        }
+    }
+
+    /// Encodes the index file in the binary format.
+    ///
+    /// The binary format consists of a header followed by two tables:
+    /// - Header: magic (4 bytes), version (1 byte), number of packs (u32) and number of blobs (u32).
+    /// - Pack table: the raw pack IDs, sorted.
+    /// - Blob table: one entry per blob, sorted by blob ID. Every entry contains the raw blob ID,
+    ///   the position of its pack in the pack table (u32), offset (u32), length (u32) and the
+    ///   blob type (u8), with the high bit set if the blob is stored uncompressed.
+    ///
+    /// All integers are little-endian.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut pack_ids: Vec<&ID> = self.packs.iter().map(|pack| &pack.id).collect();
+        pack_ids.sort();
+        pack_ids.dedup();
+
+        let mut blobs = Vec::new();
+        for pack in &self.packs {
+            let pack_index = pack_ids.binary_search(&&pack.id).unwrap() as u32;
+            for blob in &pack.blobs {
+                blobs.push((pack_index, blob));
+            }
+        }
+        blobs.sort_by(|(_, a), (_, b)| a.id.cmp(&b.id));
+
+        let mut data = Vec::with_capacity(
+            BINARY_INDEX_HEADER_LEN
+                + ID_LENGTH * pack_ids.len()
+                + BINARY_INDEX_BLOB_LEN * blobs.len(),
+        );
+        data.extend_from_slice(BINARY_INDEX_MAGIC);
+        data.push(BINARY_INDEX_VERSION);
+        data.extend_from_slice(&(pack_ids.len() as u32).to_le_bytes());
+        data.extend_from_slice(&(blobs.len() as u32).to_le_bytes());
+
+        for pack_id in pack_ids {
+            data.extend_from_slice(pack_id.as_slice());
+        }
+
+        for (pack_index, blob) in blobs {
+            data.extend_from_slice(blob.id.as_slice());
+            data.extend_from_slice(&pack_index.to_le_bytes());
+            data.extend_from_slice(&blob.offset.to_le_bytes());
+            data.extend_from_slice(&blob.length.to_le_bytes());
+
+            let mut blob_type = blob.blob_type.clone() as u8;
+            if blob.uncompressed {
+                blob_type |= UNCOMPRESSED_BLOB_FLAG;
+            }
+            data.push(blob_type);
+        }
+
+        data
+    }
+
+    /// Decodes an index file. Both the binary and the JSON formats are accepted.
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        if !data.starts_with(BINARY_INDEX_MAGIC) {
+            return serde_json::from_slice(data).with_context(|| "Could not parse JSON index");
+        }
+
+        if data.len() < BINARY_INDEX_HEADER_LEN {
+            bail!("Index is invalid: data too short for header");
+        }
+
+        let version = data[4];
+        if version != BINARY_INDEX_VERSION {
+            bail!("Unsupported index version {}", version);
+        }
+
+        let read_u32 = |pos: usize| u32::from_le_bytes(data[pos..pos + 4].try_into().unwrap());
+        let num_packs = read_u32(5) as usize;
+        let num_blobs = read_u32(9) as usize;
+
+        let expected_len =
+            BINARY_INDEX_HEADER_LEN + ID_LENGTH * num_packs + BINARY_INDEX_BLOB_LEN * num_blobs;
+        if data.len() != expected_len {
+            bail!(
+                "Index is invalid: expected {} bytes for {} packs and {} blobs, got {}",
+                expected_len,
+                num_packs,
+                num_blobs,
+                data.len()
+            );
+        }
+
+        let mut packs = Vec::with_capacity(num_packs);
+        let mut pos = BINARY_INDEX_HEADER_LEN;
+        for _ in 0..num_packs {
+            let id: [u8; ID_LENGTH] = data[pos..pos + ID_LENGTH].try_into().unwrap();
+            packs.push(IndexFilePack {
+                id: ID::from_bytes(id),
+                blobs: Vec::new(),
+            });
+            pos += ID_LENGTH;
+        }
+
+        for _ in 0..num_blobs {
+            let id: [u8; ID_LENGTH] = data[pos..pos + ID_LENGTH].try_into().unwrap();
+            let pack_index = read_u32(pos + ID_LENGTH) as usize;
+            let offset = read_u32(pos + ID_LENGTH + 4);
+            let length = read_u32(pos + ID_LENGTH + 8);
+            let type_byte = data[pos + ID_LENGTH + 12];
+            pos += BINARY_INDEX_BLOB_LEN;
+
+            let pack = packs.get_mut(pack_index).with_context(|| {
+                format!("Index is invalid: pack index {pack_index} out of range")
+            })?;
+            pack.blobs.push(IndexFileBlob {
+                id: ID::from_bytes(id),
+                blob_type: (type_byte & !UNCOMPRESSED_BLOB_FLAG).into(),
+                offset,
+                length,
+                uncompressed: type_byte & UNCOMPRESSED_BLOB_FLAG != 0,
+            });
+        }
+
+        Ok(Self { packs })
+    }
 }
 
 /// Represents a pack's entry within an `IndexFile`.
@@ -570,4 +866,245 @@ pub struct IndexFileBlob {
     pub blob_type: BlobType,
     pub offset: u32,
     pub length: u32,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub uncompressed: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::utils::bitmap::Bitmap;
+
+    use super::*;
+
+    fn test_index_file() -> IndexFile {
+        let mut packs = Vec::new();
+        for _ in 0..3 {
+            let blobs = (0..5)
+                .map(|i| IndexFileBlob {
+                    id: ID::new_random(),
+                    blob_type: if i % 2 == 0 {
+                        BlobType::Data
+                    } else {
+                        BlobType::Tree
+                    },
+                    offset: 100 * i,
+                    length: 100,
+                    uncompressed: i == 3,
+                })
+                .collect();
+            packs.push(IndexFilePack {
+                id: ID::new_random(),
+                blobs,
+            });
+        }
+        IndexFile { packs }
+    }
+
+    fn sorted_blobs(index_file: &IndexFile) -> Vec<(ID, ID, u8, u32, u32, bool)> {
+        let mut blobs: Vec<_> = index_file
+            .packs
+            .iter()
+            .flat_map(|pack| {
+                pack.blobs.iter().map(|blob| {
+                    (
+                        pack.id.clone(),
+                        blob.id.clone(),
+                        blob.blob_type.clone() as u8,
+                        blob.offset,
+                        blob.length,
+                        blob.uncompressed,
+                    )
+                })
+            })
+            .collect();
+        blobs.sort_by(|a, b| a.1.cmp(&b.1));
+        blobs
+    }
+
+    /// Test that an index file survives a round trip through the binary format
+    #[test]
+    fn test_binary_index_roundtrip() -> Result<()> {
+        let index_file = test_index_file();
+
+        let data = index_file.encode();
+        assert!(data.starts_with(BINARY_INDEX_MAGIC));
+        assert_eq!(
+            data.len(),
+            BINARY_INDEX_HEADER_LEN + 3 * ID_LENGTH + 15 * BINARY_INDEX_BLOB_LEN
+        );
+
+        let decoded = IndexFile::decode(&data)?;
+        assert_eq!(sorted_blobs(&decoded), sorted_blobs(&index_file));
+
+        // The pack table is sorted
+        let pack_ids: Vec<&ID> = decoded.packs.iter().map(|pack| &pack.id).collect();
+        assert!(pack_ids.windows(2).all(|w| w[0] < w[1]));
+
+        // The encoding is deterministic
+        assert_eq!(decoded.encode(), data);
+
+        // Truncated data is rejected
+        assert!(IndexFile::decode(&data[..data.len() - 1]).is_err());
+
+        Ok(())
+    }
+
+    /// Test that JSON index files are still readable
+    #[test]
+    fn test_json_index_decode() -> Result<()> {
+        let index_file = test_index_file();
+
+        let data = serde_json::to_vec(&index_file)?;
+        let decoded = IndexFile::decode(&data)?;
+        assert_eq!(sorted_blobs(&decoded), sorted_blobs(&index_file));
+
+        Ok(())
+    }
+
+    /// Test that only version 3 repositories get binary index files
+    #[test]
+    fn test_index_format_for_version() -> Result<()> {
+        let index_file = test_index_file();
+
+        let data = index_file.encode_for_version(2)?;
+        assert!(data.starts_with(b"{"));
+        assert_eq!(
+            sorted_blobs(&IndexFile::decode(&data)?),
+            sorted_blobs(&index_file)
+        );
+        assert!(
+            index_file
+                .encode_for_version(3)?
+                .starts_with(BINARY_INDEX_MAGIC)
+        );
+
+        Ok(())
+    }
+
+    /// Test that the master index finds all blobs after merging many index files
+    #[test]
+    fn test_master_index_lookup() {
+        let mut master_index = MasterIndex::new();
+        let mut index_files = Vec::new();
+        for _ in 0..9 {
+            let index_file = test_index_file();
+            index_files.push(sorted_blobs(&index_file));
+
+            let mut index = Index::from_index_file(index_file);
+            index.set_id(ID::new_random());
+            master_index.add_index(index);
+        }
+
+        // The tables are merged like a binary counter: 9 = 8 + 1
+        assert_eq!(master_index.tables.len(), 2);
+        assert_eq!(master_index.len(), 9 * 15);
+        assert_eq!(master_index.ids().len(), 9);
+        for table in &master_index.tables {
+            assert!(table.windows(2).all(|w| w[0].id <= w[1].id));
+        }
+
+        for (pack_id, blob_id, blob_type, offset, length, uncompressed) in
+            index_files.iter().flatten()
+        {
+            assert!(master_index.contains(blob_id));
+            let (found_pack_id, found_type, found_offset, found_length, found_uncompressed) =
+                master_index.get(blob_id).unwrap();
+            assert_eq!(&found_pack_id, pack_id);
+            assert_eq!(found_type as u8, *blob_type);
+            assert_eq!(found_offset, *offset);
+            assert_eq!(found_length, *length);
+            assert_eq!(found_uncompressed, *uncompressed);
+        }
+        assert!(!master_index.contains(&ID::new_random()));
+
+        // A blob stored in two packs has two positions
+        let (pack_id, blob_id, ..) = index_files[0][0].clone();
+        let other_pack_id = ID::new_random();
+        master_index.add_index(Index::from_index_file(IndexFile {
+            packs: vec![IndexFilePack {
+                id: other_pack_id.clone(),
+                blobs: vec![IndexFileBlob {
+                    id: blob_id.clone(),
+                    blob_type: BlobType::Data,
+                    offset: 0,
+                    length: 100,
+                    uncompressed: false,
+                }],
+            }],
+        }));
+        let copies: Vec<(usize, ID)> = master_index
+            .find_all(&blob_id)
+            .map(|(pos, pack_id)| (pos, pack_id.clone()))
+            .collect();
+        assert_eq!(copies.len(), 2);
+        let ids: Vec<(&ID, BlobLocator)> = master_index.iter_ids().collect();
+        for (pos, copy_pack_id) in &copies {
+            assert_eq!(ids[*pos].0, &blob_id);
+            assert_eq!(&ids[*pos].1.pack_id, copy_pack_id);
+        }
+        let copy_packs: BTreeSet<ID> = copies.into_iter().map(|(_, pack_id)| pack_id).collect();
+        assert_eq!(copy_packs, BTreeSet::from([pack_id.clone(), other_pack_id]));
+
+        // Removing a pack removes all its blobs and invalidates the index files
+        master_index.cleanup(Some(&BTreeSet::from([pack_id.clone()])));
+        assert_eq!(master_index.len(), 9 * 15 + 1 - 5);
+        assert!(master_index.ids().is_empty());
+        assert!(master_index.needs_rewrite);
+        assert!(master_index.contains(&blob_id));
+        assert!(
+            master_index
+                .iter_ids()
+                .all(|(_, locator)| locator.pack_id != pack_id)
+        );
+    }
+
+    /// Test that the positions of the pending index entries follow the finalized ones
+    #[test]
+    fn test_master_index_pending_positions() {
+        let mut master_index = MasterIndex::new();
+        let index_file = test_index_file();
+        let (_, finalized_blob_id, ..) = sorted_blobs(&index_file)[0].clone();
+        master_index.add_index(Index::from_index_file(index_file));
+
+        let pack_id = ID::new_random();
+        let mut pending_index = Index::new();
+        pending_index.add_pack(
+            &pack_id,
+            &[
+                PackedBlobDescriptor {
+                    id: ID::new_random(),
+                    blob_type: BlobType::Data,
+                    offset: 0,
+                    length: 10,
+                    uncompressed: false,
+                },
+                PackedBlobDescriptor {
+                    id: finalized_blob_id.clone(),
+                    blob_type: BlobType::Data,
+                    offset: 10,
+                    length: 10,
+                    uncompressed: false,
+                },
+            ],
+        );
+        master_index.pending_index = Some(pending_index);
+
+        assert_eq!(master_index.len(), 15 + 2);
+        assert_eq!(master_index.len(), master_index.iter_ids().count());
+
+        // Every position can be marked in a bitmap of `len()` bits
+        let ids: Vec<(&ID, BlobLocator)> = master_index.iter_ids().collect();
+        let mut bitmap = Bitmap::new(master_index.len());
+        let copies: Vec<(usize, ID)> = master_index
+            .find_all(&finalized_blob_id)
+            .map(|(pos, pack_id)| (pos, pack_id.clone()))
+            .collect();
+        assert_eq!(copies.len(), 2);
+        assert_eq!(copies[1], (16, pack_id));
+        for (pos, copy_pack_id) in &copies {
+            assert!(bitmap.set(*pos));
+            assert_eq!(ids[*pos].0, &finalized_blob_id);
+            assert_eq!(&ids[*pos].1.pack_id, copy_pack_id);
+        }
+    }
 }
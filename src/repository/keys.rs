@@ -25,18 +25,75 @@ use chrono::{DateTime, Utc};
 use rand::{TryRngCore, rngs::OsRng};
 use serde::{Deserialize, Serialize};
 
+use zstd::DEFAULT_COMPRESSION_LEVEL;
+
 use crate::{
     backend::StorageBackend,
+    global::{
+        ID,
+        defaults::{DEFAULT_KDF_ITERATIONS, DEFAULT_KDF_MEMORY_COST, DEFAULT_KDF_PARALLELISM},
+    },
     repository::{KEYS_DIR, storage::SecureStorage},
-    ui,
+    ui, utils,
 };
 
+/// Key derivation function used to derive a key from a password
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum KdfAlgorithm {
+    Argon2id,
+    Argon2i,
+    Argon2d,
+}
+
+/// Parameters of the key derivation function used to encrypt the master key.
+///
+/// KeyFiles created before these parameters were stored were derived with
+/// the defaults, so missing parameters are read as the default values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KdfParams {
+    pub algorithm: KdfAlgorithm,
+    /// Memory cost in KiB
+    pub memory_cost: u32,
+    /// Number of iterations
+    pub iterations: u32,
+    /// Degree of parallelism
+    pub parallelism: u32,
+}
+
+impl Default for KdfParams {
+    fn default() -> Self {
+        Self {
+            algorithm: KdfAlgorithm::Argon2id,
+            memory_cost: DEFAULT_KDF_MEMORY_COST,
+            iterations: DEFAULT_KDF_ITERATIONS,
+            parallelism: DEFAULT_KDF_PARALLELISM,
+        }
+    }
+}
+
 /// A metadata structure that contains information about a repository key
 #[derive(Serialize, Deserialize)]
 pub struct KeyFile {
     pub created: DateTime<Utc>,
     pub encrypted_key: String,
     pub salt: String,
+
+    /// Parameters used to derive the key that encrypts the master key
+    #[serde(default)]
+    pub kdf: KdfParams,
+
+    /// Host where the key was created
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+
+    /// User that created the key
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+
+    /// A user-defined label to identify the key
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
 }
 
 pub fn generate_new_master_key() -> Vec<u8> {
@@ -49,12 +106,12 @@ pub fn generate_new_master_key() -> Vec<u8> {
 }
 
 /// Generates a new KeyFile for the master key with a new password
-pub fn generate_key_file(password: &str, master_key: Vec<u8>) -> Result<KeyFile> {
+pub fn generate_key_file(password: &str, master_key: Vec<u8>, kdf: KdfParams) -> Result<KeyFile> {
     let create_time = Utc::now();
 
     const SALT_LENGTH: usize = 32;
     let salt = SecureStorage::generate_salt::<SALT_LENGTH>();
-    let intermediate_key = SecureStorage::derive_key(password, &salt);
+    let intermediate_key = SecureStorage::derive_key(password, &salt, &kdf)?;
 
     let encrypted_key = SecureStorage::encrypt_with_key(&intermediate_key, &master_key)?;
 
@@ -62,6 +119,10 @@ pub fn generate_key_file(password: &str, master_key: Vec<u8>) -> Result<KeyFile>
         created: create_time,
         encrypted_key: base64::engine::general_purpose::STANDARD.encode(encrypted_key),
         salt: base64::engine::general_purpose::STANDARD.encode(salt),
+        kdf,
+        hostname: Some(utils::get_hostname()),
+        username: Some(whoami::username()),
+        label: None,
     };
 
     Ok(key_file)
@@ -75,57 +136,110 @@ pub fn retrieve_master_key(
 ) -> Result<Vec<u8>> {
     match keyfile_path {
         Some(path) => {
-            let keyfile = std::fs::read(path)?;
-            let keyfile = SecureStorage::decompress(&keyfile)?;
-            let keyfile: KeyFile = serde_json::from_slice(&keyfile)
-                .with_context(|| format!("KeyFile at {path:?} is invalid"))?;
+            let keyfile = read_key_file(path)?;
+            decode_master_key(password, &keyfile)
+        }
+        None => find_matching_key(password, backend).map(|(_id, master_key)| master_key),
+    }
+}
+
+/// Reads a KeyFile stored outside of the repository.
+pub fn read_key_file(path: &Path) -> Result<KeyFile> {
+    let keyfile = std::fs::read(path)?;
+    let keyfile = SecureStorage::decompress(&keyfile)?;
+    serde_json::from_slice(&keyfile).with_context(|| format!("KeyFile at {path:?} is invalid"))
+}
 
-            decode_master_key(password, keyfile)
+/// Finds the key in the keys directory that can be opened with a password.
+/// Returns the ID of the KeyFile and the master key.
+pub fn find_matching_key(
+    password: &str,
+    backend: Arc<dyn StorageBackend>,
+) -> Result<(ID, Vec<u8>)> {
+    for (id, keyfile) in list_keys(backend)? {
+        if let Ok(master_key) = decode_master_key(password, &keyfile) {
+            return Ok((id, master_key));
         }
-        None => {
-            let keys_path = Path::new(KEYS_DIR);
-            let entries = backend.read_dir(keys_path)?;
-
-            for path in entries {
-                // The keys directory should only contain files. We can ignore anything
-                // that is not a file, but show a warning anyway.
-                if !backend.is_file(&path) {
-                    ui::cli::warning!(
-                        "Extraneous item \'{}\' in keys directory is not a file",
-                        path.display()
-                    );
-                    continue;
-                }
-
-                // Load keyfile
-                let keyfile = backend.read(&path)?;
-                let keyfile = SecureStorage::decompress(&keyfile)?;
-                let keyfile: KeyFile = match serde_json::from_slice(keyfile.as_slice()) {
-                    Ok(kf) => kf,
-                    Err(e) => {
-                        ui::cli::warning!("Failed to parse keyfile at {}: {}", path.display(), e);
-                        continue;
-                    }
-                };
-
-                if let Ok(master_key) = decode_master_key(password, keyfile) {
-                    return Ok(master_key);
-                }
-            }
+    }
 
-            Err(anyhow::anyhow!(
-                "No valid KeyFile found for the provided password in the keys directory."
-            ))
+    Err(anyhow::anyhow!(
+        "No valid KeyFile found for the provided password in the keys directory."
+    ))
+}
+
+/// Lists all valid KeyFiles in the keys directory, sorted by creation time.
+pub fn list_keys(backend: Arc<dyn StorageBackend>) -> Result<Vec<(ID, KeyFile)>> {
+    let keys_path = Path::new(KEYS_DIR);
+    let entries = backend.read_dir(keys_path)?;
+
+    let mut keys = Vec::new();
+    for path in entries {
+        // The keys directory should only contain files. We can ignore anything
+        // that is not a file, but show a warning anyway.
+        if !backend.is_file(&path) {
+            ui::cli::warning!(
+                "Extraneous item \'{}\' in keys directory is not a file",
+                path.display()
+            );
+            continue;
         }
+
+        let id = match path.file_name().and_then(|s| s.to_str()).map(ID::from_hex) {
+            Some(Ok(id)) => id,
+            _ => {
+                ui::cli::warning!("Invalid keyfile name \'{}\'", path.display());
+                continue;
+            }
+        };
+
+        // Load keyfile
+        let keyfile = backend.read(&path)?;
+        let keyfile = SecureStorage::decompress(&keyfile)?;
+        let keyfile: KeyFile = match serde_json::from_slice(keyfile.as_slice()) {
+            Ok(kf) => kf,
+            Err(e) => {
+                ui::cli::warning!("Failed to parse keyfile at {}: {}", path.display(), e);
+                continue;
+            }
+        };
+
+        keys.push((id, keyfile));
     }
+
+    keys.sort_by_key(|(_id, keyfile)| keyfile.created);
+    Ok(keys)
+}
+
+/// Serializes a KeyFile to its on-disk representation (compressed JSON).
+pub fn encode_key_file(keyfile: &KeyFile) -> Result<Vec<u8>> {
+    let keyfile_json = serde_json::to_string_pretty(keyfile)?;
+    SecureStorage::compress(keyfile_json.as_bytes(), DEFAULT_COMPRESSION_LEVEL)
+}
+
+/// Saves a KeyFile in the keys directory and returns its ID.
+pub fn save_key_file(keyfile: &KeyFile, backend: Arc<dyn StorageBackend>) -> Result<ID> {
+    let keyfile_data = encode_key_file(keyfile)?;
+    let keyfile_id = ID::from_content(&keyfile_data);
+    let path = Path::new(KEYS_DIR).join(keyfile_id.to_hex());
+    backend
+        .write(&path, &keyfile_data)
+        .with_context(|| format!("Could not write KeyFile {}", keyfile_id.to_hex()))?;
+
+    Ok(keyfile_id)
+}
+
+/// Removes a KeyFile from the keys directory.
+pub fn remove_key_file(id: &ID, backend: Arc<dyn StorageBackend>) -> Result<()> {
+    let path = Path::new(KEYS_DIR).join(id.to_hex());
+    backend.remove_file(&path)
 }
 
-fn decode_master_key(password: &str, keyfile: KeyFile) -> Result<Vec<u8>> {
+fn decode_master_key(password: &str, keyfile: &KeyFile) -> Result<Vec<u8>> {
     // Decode salt and key from base64
-    let salt = base64::engine::general_purpose::STANDARD.decode(keyfile.salt)?;
-    let encrypted_key = base64::engine::general_purpose::STANDARD.decode(keyfile.encrypted_key)?;
+    let salt = base64::engine::general_purpose::STANDARD.decode(&keyfile.salt)?;
+    let encrypted_key = base64::engine::general_purpose::STANDARD.decode(&keyfile.encrypted_key)?;
 
-    let intermediate_key = SecureStorage::derive_key(password, &salt);
+    let intermediate_key = SecureStorage::derive_key(password, &salt, &keyfile.kdf)?;
     SecureStorage::decrypt_with_key(&intermediate_key, &encrypted_key)
         .with_context(|| "Could not retrieve master key from this keyfile")
 }
@@ -0,0 +1,467 @@
+// mapache is an incremental backup tool
+// Copyright (C) 2025  Javier Lancha Vázquez <javier.lancha@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    thread::JoinHandle,
+};
+
+use anyhow::{Context, Result, bail};
+use chrono::{DateTime, Utc};
+use crossbeam_channel::{RecvTimeoutError, Sender};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    backend::StorageBackend,
+    global::{
+        ID,
+        defaults::{LOCK_REFRESH_INTERVAL, SHORT_REPO_ID_LEN, STALE_LOCK_TIMEOUT},
+    },
+    repository::{LOCKS_DIR, storage::SecureStorage},
+    ui, utils,
+};
+
+/// The kind of lock held on a repository.
+/// Shared locks can coexist with other shared locks. Exclusive locks can't
+/// coexist with any other lock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LockKind {
+    Shared,
+    Exclusive,
+}
+
+impl std::fmt::Display for LockKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LockKind::Shared => write!(f, "shared"),
+            LockKind::Exclusive => write!(f, "exclusive"),
+        }
+    }
+}
+
+/// The contents of a lock file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LockFile {
+    pub kind: LockKind,
+    pub hostname: String,
+    pub pid: u32,
+    pub created: DateTime<Utc>,
+    pub refreshed: DateTime<Utc>,
+}
+
+impl LockFile {
+    /// Creates a lock owned by this process.
+    pub fn new(kind: LockKind) -> Self {
+        let now = Utc::now();
+        Self {
+            kind,
+            hostname: utils::get_hostname(),
+            pid: std::process::id(),
+            created: now,
+            refreshed: now,
+        }
+    }
+
+    /// A lock is stale if it has not been refreshed in time, or if it was created
+    /// on this host by a process that is no longer running.
+    pub fn is_stale(&self) -> bool {
+        let elapsed = Utc::now().signed_duration_since(self.refreshed);
+        if elapsed.to_std().unwrap_or_default() > STALE_LOCK_TIMEOUT {
+            return true;
+        }
+
+        self.hostname == utils::get_hostname() && !utils::process_exists(self.pid)
+    }
+
+    /// Returns true if this lock prevents acquiring a lock of the given kind.
+    pub fn conflicts_with(&self, kind: LockKind) -> bool {
+        self.kind == LockKind::Exclusive || kind == LockKind::Exclusive
+    }
+}
+
+/// A lock held on a repository.
+///
+/// The lock file is refreshed periodically by a background thread and removed
+/// from the repository when the lock is dropped.
+pub struct RepositoryLock {
+    backend: Arc<dyn StorageBackend>,
+    id: ID,
+    stop_tx: Option<Sender<()>>,
+    refresh_handle: Option<JoinHandle<()>>,
+}
+
+impl RepositoryLock {
+    /// Acquires a shared lock on the repository.
+    pub fn shared(
+        backend: Arc<dyn StorageBackend>,
+        secure_storage: Arc<SecureStorage>,
+    ) -> Result<Self> {
+        Self::acquire(backend, secure_storage, LockKind::Shared)
+    }
+
+    /// Acquires an exclusive lock on the repository.
+    pub fn exclusive(
+        backend: Arc<dyn StorageBackend>,
+        secure_storage: Arc<SecureStorage>,
+    ) -> Result<Self> {
+        Self::acquire(backend, secure_storage, LockKind::Exclusive)
+    }
+
+    /// Acquires a lock of the given kind. Fails if another process holds a
+    /// conflicting lock that is not stale.
+    pub fn acquire(
+        backend: Arc<dyn StorageBackend>,
+        secure_storage: Arc<SecureStorage>,
+        kind: LockKind,
+    ) -> Result<Self> {
+        // Repositories created before locking existed don't have a locks directory
+        let locks_path = Path::new(LOCKS_DIR);
+        if !backend.exists(locks_path) {
+            backend.create_dir(locks_path)?;
+        }
+
+        check_conflicts(backend.as_ref(), secure_storage.as_ref(), kind, None)?;
+
+        let id = ID::new_random();
+        let mut lock = LockFile::new(kind);
+        write_lock(backend.as_ref(), secure_storage.as_ref(), &id, &lock)?;
+
+        // Another process could have written its lock between our check and our
+        // write. Check again now that our lock is visible to others.
+        if let Err(e) = check_conflicts(backend.as_ref(), secure_storage.as_ref(), kind, Some(&id))
+        {
+            let _ = backend.remove_file(&lock_path(&id));
+            return Err(e);
+        }
+
+        let (stop_tx, stop_rx) = crossbeam_channel::bounded::<()>(1);
+        let refresh_backend = backend.clone();
+        let refresh_id = id.clone();
+        let refresh_handle = std::thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stop_rx.recv_timeout(LOCK_REFRESH_INTERVAL) {
+                lock.refreshed = Utc::now();
+                if let Err(e) = write_lock(
+                    refresh_backend.as_ref(),
+                    secure_storage.as_ref(),
+                    &refresh_id,
+                    &lock,
+                ) {
+                    ui::cli::warning!("Could not refresh repository lock: {}", e);
+                }
+            }
+        });
+
+        Ok(Self {
+            backend,
+            id,
+            stop_tx: Some(stop_tx),
+            refresh_handle: Some(refresh_handle),
+        })
+    }
+
+    /// The ID of the lock file
+    pub fn id(&self) -> &ID {
+        &self.id
+    }
+}
+
+impl Drop for RepositoryLock {
+    fn drop(&mut self) {
+        // Dropping the sender wakes up the refresh thread
+        self.stop_tx.take();
+        if let Some(handle) = self.refresh_handle.take() {
+            let _ = handle.join();
+        }
+
+        if let Err(e) = self.backend.remove_file(&lock_path(&self.id)) {
+            ui::cli::warning!(
+                "Could not remove repository lock {}: {}",
+                self.id.to_short_hex(SHORT_REPO_ID_LEN),
+                e
+            );
+        }
+    }
+}
+
+/// The locks found in a repository
+#[derive(Default)]
+pub struct LockList {
+    pub locks: Vec<(ID, LockFile)>,
+    /// Lock files that could not be decoded, e.g. because they are corrupt
+    pub unreadable: Vec<ID>,
+}
+
+/// Lists all locks in the repository.
+pub fn list_locks(
+    backend: &dyn StorageBackend,
+    secure_storage: &SecureStorage,
+) -> Result<LockList> {
+    let locks_path = Path::new(LOCKS_DIR);
+    let mut list = LockList::default();
+    if !backend.exists(locks_path) {
+        return Ok(list);
+    }
+
+    for path in backend.read_dir(locks_path)? {
+        let Some(id) = path
+            .file_name()
+            .and_then(|s| s.to_str())
+            .and_then(|s| ID::from_hex(s).ok())
+        else {
+            continue;
+        };
+
+        match read_lock(backend, secure_storage, &path) {
+            Ok(lock) => list.locks.push((id, lock)),
+            Err(e) => {
+                ui::cli::verbose_1!("Could not read lock {}: {}", id.to_hex(), e);
+                list.unreadable.push(id);
+            }
+        }
+    }
+
+    Ok(list)
+}
+
+/// Removes stale locks from the repository, or all locks if `remove_all` is true.
+/// Locks that can't be read are only removed with `remove_all`.
+/// Returns the number of removed locks.
+pub fn remove_locks(
+    backend: &dyn StorageBackend,
+    secure_storage: &SecureStorage,
+    remove_all: bool,
+) -> Result<usize> {
+    let list = list_locks(backend, secure_storage)?;
+
+    let mut to_remove = Vec::new();
+    for (id, lock) in list.locks {
+        if remove_all || lock.is_stale() {
+            to_remove.push(id);
+        }
+    }
+    for id in list.unreadable {
+        if remove_all {
+            to_remove.push(id);
+        } else {
+            ui::cli::warning!(
+                "Lock {} could not be read. Use --remove-all to remove it.",
+                id.to_hex()
+            );
+        }
+    }
+
+    let mut count = 0;
+    for id in to_remove {
+        match backend.remove_file(&lock_path(&id)) {
+            Ok(()) => count += 1,
+            Err(e) => {
+                ui::cli::warning!("Could not remove lock {}: {:#}", id.to_hex(), e)
+            }
+        }
+    }
+
+    Ok(count)
+}
+
+fn check_conflicts(
+    backend: &dyn StorageBackend,
+    secure_storage: &SecureStorage,
+    kind: LockKind,
+    own_id: Option<&ID>,
+) -> Result<()> {
+    for (id, lock) in list_locks(backend, secure_storage)?.locks {
+        if Some(&id) == own_id || lock.is_stale() || !lock.conflicts_with(kind) {
+            continue;
+        }
+
+        bail!(
+            "Repository is already locked ({}) by PID {} on {} since {}. \
+             If that process is no longer running, remove the lock with 'unlock'.",
+            lock.kind,
+            lock.pid,
+            lock.hostname,
+            lock.created
+                .with_timezone(&chrono::Local)
+                .format("%Y-%m-%d %H:%M:%S")
+        );
+    }
+
+    Ok(())
+}
+
+fn lock_path(id: &ID) -> PathBuf {
+    Path::new(LOCKS_DIR).join(id.to_hex())
+}
+
+fn read_lock(
+    backend: &dyn StorageBackend,
+    secure_storage: &SecureStorage,
+    path: &Path,
+) -> Result<LockFile> {
+    let data = backend.read(path)?;
+    let data = secure_storage.decode(&data)?;
+    let lock = serde_json::from_slice(&data)?;
+    Ok(lock)
+}
+
+fn write_lock(
+    backend: &dyn StorageBackend,
+    secure_storage: &SecureStorage,
+    id: &ID,
+    lock: &LockFile,
+) -> Result<()> {
+    let data = serde_json::to_vec(lock)?;
+    let data = secure_storage.encode(&data)?;
+    backend
+        .write(&lock_path(id), &data)
+        .with_context(|| "Could not write lock file")
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use crate::{backend::localfs::LocalFS, repository::keys::generate_new_master_key};
+
+    use super::*;
+
+    fn setup(path: &Path) -> Result<(Arc<dyn StorageBackend>, Arc<SecureStorage>)> {
+        let backend: Arc<dyn StorageBackend> = Arc::new(LocalFS::new(path.to_path_buf()));
+        backend.create()?;
+        let secure_storage = Arc::new(SecureStorage::build().with_key(generate_new_master_key()));
+        Ok((backend, secure_storage))
+    }
+
+    /// Shared locks coexist, but exclusive locks conflict with any other lock
+    #[test]
+    fn test_lock_conflicts() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let (backend, secure_storage) = setup(temp_dir.path())?;
+
+        let shared_a = RepositoryLock::shared(backend.clone(), secure_storage.clone())?;
+        let shared_b = RepositoryLock::shared(backend.clone(), secure_storage.clone())?;
+        assert_eq!(
+            list_locks(backend.as_ref(), secure_storage.as_ref())?
+                .locks
+                .len(),
+            2
+        );
+        assert!(RepositoryLock::exclusive(backend.clone(), secure_storage.clone()).is_err());
+
+        drop(shared_a);
+        drop(shared_b);
+        assert!(
+            list_locks(backend.as_ref(), secure_storage.as_ref())?
+                .locks
+                .is_empty()
+        );
+
+        let exclusive = RepositoryLock::exclusive(backend.clone(), secure_storage.clone())?;
+        assert!(RepositoryLock::shared(backend.clone(), secure_storage.clone()).is_err());
+        assert_eq!(
+            list_locks(backend.as_ref(), secure_storage.as_ref())?
+                .locks
+                .len(),
+            1
+        );
+        drop(exclusive);
+
+        Ok(())
+    }
+
+    /// Stale locks are ignored when locking and removed by `remove_locks`
+    #[test]
+    fn test_stale_locks() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let (backend, secure_storage) = setup(temp_dir.path())?;
+        backend.create_dir(Path::new(LOCKS_DIR))?;
+
+        let mut expired = LockFile::new(LockKind::Exclusive);
+        expired.refreshed = Utc::now() - chrono::Duration::hours(1);
+        assert!(expired.is_stale());
+        write_lock(
+            backend.as_ref(),
+            secure_storage.as_ref(),
+            &ID::new_random(),
+            &expired,
+        )?;
+
+        let live = LockFile::new(LockKind::Shared);
+        assert!(!live.is_stale());
+        write_lock(
+            backend.as_ref(),
+            secure_storage.as_ref(),
+            &ID::new_random(),
+            &live,
+        )?;
+
+        // The expired exclusive lock does not prevent a shared lock
+        let shared = RepositoryLock::shared(backend.clone(), secure_storage.clone())?;
+        drop(shared);
+
+        assert_eq!(
+            remove_locks(backend.as_ref(), secure_storage.as_ref(), false)?,
+            1
+        );
+        assert_eq!(
+            list_locks(backend.as_ref(), secure_storage.as_ref())?
+                .locks
+                .len(),
+            1
+        );
+        assert_eq!(
+            remove_locks(backend.as_ref(), secure_storage.as_ref(), true)?,
+            1
+        );
+        assert!(
+            list_locks(backend.as_ref(), secure_storage.as_ref())?
+                .locks
+                .is_empty()
+        );
+
+        Ok(())
+    }
+
+    /// Locks that can't be decoded are reported and only removed with `remove_all`
+    #[test]
+    fn test_unreadable_locks() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let (backend, secure_storage) = setup(temp_dir.path())?;
+        backend.create_dir(Path::new(LOCKS_DIR))?;
+
+        let corrupt_id = ID::new_random();
+        backend.write(&lock_path(&corrupt_id), b"not a lock")?;
+
+        let list = list_locks(backend.as_ref(), secure_storage.as_ref())?;
+        assert!(list.locks.is_empty());
+        assert_eq!(list.unreadable, vec![corrupt_id.clone()]);
+
+        assert_eq!(
+            remove_locks(backend.as_ref(), secure_storage.as_ref(), false)?,
+            0
+        );
+        assert!(backend.exists(&lock_path(&corrupt_id)));
+        assert_eq!(
+            remove_locks(backend.as_ref(), secure_storage.as_ref(), true)?,
+            1
+        );
+        assert!(!backend.exists(&lock_path(&corrupt_id)));
+
+        Ok(())
+    }
+}
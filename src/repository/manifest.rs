@@ -14,10 +14,24 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+use anyhow::{Result, bail};
 use chrono::{DateTime, Utc};
+use fastcdc::v2020::{
+    AVERAGE_MAX, AVERAGE_MIN, MAXIMUM_MAX, MAXIMUM_MIN, MINIMUM_MAX, MINIMUM_MIN,
+};
 use serde::{Deserialize, Serialize};
 
-use crate::global::ID;
+use crate::{
+    global::{
+        ID,
+        defaults::{
+            AVG_CHUNK_SIZE, DEFAULT_CHUNK_NORMALIZATION, DEFAULT_PACK_SIZE, MAX_CHUNK_SIZE,
+            MAX_PACK_SIZE, MIN_CHUNK_SIZE, MIN_PACK_SIZE,
+        },
+    },
+    repository::storage::CompressionSettings,
+    utils,
+};
 
 /// Repository manifest. This struct contains metadata about the repository itself.
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -25,4 +39,187 @@ pub struct Manifest {
     pub version: u32,
     pub id: ID,
     pub created_time: DateTime<Utc>,
+
+    /// Key used to calculate keyed blob IDs, encoded in base64.
+    /// Only repositories with version 2 or later have an ID key.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id_key: Option<String>,
+
+    /// Compression settings for blob data. Repositories created before these settings existed
+    /// use the defaults.
+    #[serde(default)]
+    pub compression: CompressionSettings,
+
+    /// Chunker parameters. Repositories created before these parameters existed
+    /// use the defaults.
+    #[serde(default)]
+    pub chunker: ChunkerParams,
+
+    /// Target size of pack files in bytes
+    #[serde(default = "default_pack_size")]
+    pub pack_size: u64,
+}
+
+fn default_pack_size() -> u64 {
+    DEFAULT_PACK_SIZE
+}
+
+impl Manifest {
+    /// Returns the configurable parameters of the repository
+    pub fn params(&self) -> RepositoryParams {
+        RepositoryParams {
+            compression: self.compression,
+            chunker: self.chunker,
+            pack_size: self.pack_size,
+        }
+    }
+}
+
+/// Configurable repository parameters stored in the manifest
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RepositoryParams {
+    pub compression: CompressionSettings,
+    pub chunker: ChunkerParams,
+    pub pack_size: u64,
+}
+
+impl Default for RepositoryParams {
+    fn default() -> Self {
+        Self {
+            compression: CompressionSettings::default(),
+            chunker: ChunkerParams::default(),
+            pack_size: DEFAULT_PACK_SIZE,
+        }
+    }
+}
+
+impl RepositoryParams {
+    /// Checks that all parameters are valid
+    pub fn validate(&self) -> Result<()> {
+        self.compression.validate()?;
+        self.chunker.validate()?;
+        validate_pack_size(self.pack_size)
+    }
+}
+
+/// Checks that a pack size is within the supported limits
+pub fn validate_pack_size(pack_size: u64) -> Result<()> {
+    if !(MIN_PACK_SIZE..=MAX_PACK_SIZE).contains(&pack_size) {
+        bail!(
+            "Pack size must be between {} and {}",
+            utils::format_size(MIN_PACK_SIZE, 0),
+            utils::format_size(MAX_PACK_SIZE, 0)
+        );
+    }
+    Ok(())
+}
+
+/// Parameters of the content defined chunker (FastCDC).
+///
+/// The parameters must remain stable for the lifetime of a repository. Otherwise the same
+/// contents would no longer produce the same chunks and IDs, and deduplication would be lost.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChunkerParams {
+    pub min_size: u32,
+    pub avg_size: u32,
+    pub max_size: u32,
+
+    /// FastCDC normalization level [0-3]
+    pub normalization: u8,
+}
+
+impl Default for ChunkerParams {
+    fn default() -> Self {
+        Self {
+            min_size: MIN_CHUNK_SIZE as u32,
+            avg_size: AVG_CHUNK_SIZE as u32,
+            max_size: MAX_CHUNK_SIZE as u32,
+            normalization: DEFAULT_CHUNK_NORMALIZATION,
+        }
+    }
+}
+
+impl ChunkerParams {
+    /// Checks that the parameters are within the limits supported by the chunker
+    pub fn validate(&self) -> Result<()> {
+        if !(MINIMUM_MIN..=MINIMUM_MAX).contains(&self.min_size) {
+            bail!(
+                "Minimum chunk size must be between {} and {} bytes",
+                MINIMUM_MIN,
+                MINIMUM_MAX
+            );
+        }
+        if !(AVERAGE_MIN..=AVERAGE_MAX).contains(&self.avg_size) {
+            bail!(
+                "Average chunk size must be between {} and {} bytes",
+                AVERAGE_MIN,
+                AVERAGE_MAX
+            );
+        }
+        if !(MAXIMUM_MIN..=MAXIMUM_MAX).contains(&self.max_size) {
+            bail!(
+                "Maximum chunk size must be between {} and {} bytes",
+                MAXIMUM_MIN,
+                MAXIMUM_MAX
+            );
+        }
+        if self.min_size > self.avg_size || self.avg_size > self.max_size {
+            bail!("Chunk sizes must satisfy min <= avg <= max");
+        }
+        if self.normalization > 3 {
+            bail!("Chunker normalization level must be between 0 and 3");
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chunker_params_validation() {
+        assert!(ChunkerParams::default().validate().is_ok());
+
+        let small = ChunkerParams {
+            min_size: 16 * 1024,
+            avg_size: 64 * 1024,
+            max_size: 256 * 1024,
+            normalization: 2,
+        };
+        assert!(small.validate().is_ok());
+
+        let unordered = ChunkerParams {
+            min_size: 128 * 1024,
+            ..small
+        };
+        assert!(unordered.validate().is_err());
+
+        let too_small = ChunkerParams {
+            min_size: 16,
+            ..small
+        };
+        assert!(too_small.validate().is_err());
+
+        let bad_normalization = ChunkerParams {
+            normalization: 4,
+            ..small
+        };
+        assert!(bad_normalization.validate().is_err());
+    }
+
+    #[test]
+    fn test_manifest_without_params() -> Result<()> {
+        // A manifest written before the repository parameters were stored
+        let manifest: Manifest = serde_json::from_str(
+            r#"{"version":1,"id":"00112233445566778899aabbccddeeff00112233445566778899aabbccddeeff","created_time":"2025-07-01T00:00:00Z"}"#,
+        )?;
+        assert_eq!(manifest.compression, CompressionSettings::default());
+        assert_eq!(manifest.chunker, ChunkerParams::default());
+        assert_eq!(manifest.pack_size, DEFAULT_PACK_SIZE);
+        assert_eq!(manifest.params(), RepositoryParams::default());
+
+        Ok(())
+    }
 }
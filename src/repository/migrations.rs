@@ -0,0 +1,480 @@
+// mapache is an incremental backup tool
+// Copyright (C) 2025  Javier Lancha Vázquez <javier.lancha@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::{collections::BTreeMap, path::Path, sync::Arc};
+
+use anyhow::{Context, Result, bail};
+use base64::{Engine, engine::general_purpose};
+use colored::Colorize;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+
+use crate::{
+    backend::StorageBackend,
+    global::{FileType, ID, defaults::SHORT_SNAPSHOT_ID_LEN},
+    ui,
+};
+
+use super::{
+    RepoVersion, RepositoryBackend, keys::generate_new_master_key, manifest::Manifest,
+    read_manifest, repository_v1, rewriter::BlobRewriter, storage::SecureStorage, write_manifest,
+};
+
+/// Path of the file that records the progress of an unfinished migration
+const MIGRATION_STATE_PATH: &str = "migration";
+
+/// A migration upgrades a repository from one format version to the next one.
+pub trait Migration {
+    /// Name used to select the migration
+    fn name(&self) -> &'static str;
+
+    /// Short description of what the migration does
+    fn description(&self) -> &'static str;
+
+    /// Repository version the migration applies to
+    fn source_version(&self) -> RepoVersion;
+
+    /// Repository version after the migration
+    fn target_version(&self) -> RepoVersion;
+
+    /// Rewrites the repository contents. The manifest can be modified to add the fields
+    /// required by the new version, but the version itself is bumped by the caller once
+    /// the migration succeeds.
+    ///
+    /// Migrations must be resumable: running an interrupted migration again must finish it.
+    fn run(
+        &self,
+        backend: Arc<dyn StorageBackend>,
+        secure_storage: Arc<SecureStorage>,
+        manifest: &mut Manifest,
+    ) -> Result<()>;
+}
+
+/// Returns all known migrations
+pub fn all_migrations() -> Vec<Box<dyn Migration>> {
+    vec![Box::new(KeyedBlobIds), Box::new(UpgradeV3)]
+}
+
+/// Returns the migrations that can be applied to a repository version
+pub fn available_migrations(version: RepoVersion) -> Vec<Box<dyn Migration>> {
+    all_migrations()
+        .into_iter()
+        .filter(|migration| migration.source_version() == version)
+        .collect()
+}
+
+/// Runs a migration by name and bumps the manifest version. Returns the new version.
+/// The caller is responsible for holding an exclusive lock on the repository.
+pub fn run_migration(
+    name: &str,
+    backend: Arc<dyn StorageBackend>,
+    secure_storage: Arc<SecureStorage>,
+) -> Result<RepoVersion> {
+    let mut manifest = read_manifest(backend.as_ref(), &secure_storage)?;
+
+    let migration = match available_migrations(manifest.version)
+        .into_iter()
+        .find(|migration| migration.name() == name)
+    {
+        Some(migration) => migration,
+        None => bail!(
+            "Migration \'{}\' is not available for repository version {}",
+            name,
+            manifest.version
+        ),
+    };
+
+    let state_path = Path::new(MIGRATION_STATE_PATH);
+    match pending_migration(backend.as_ref(), &secure_storage)? {
+        Some(pending) if pending != name => bail!(
+            "Migration \'{}\' was interrupted. Run it again before starting a different one.",
+            pending
+        ),
+        Some(_) => (),
+        // Leftover state of a migration that was already committed
+        None if backend.exists(state_path) => backend.remove_file(state_path)?,
+        None => (),
+    }
+
+    migration.run(backend.clone(), secure_storage.clone(), &mut manifest)?;
+
+    // Replacing the manifest is the commit point of the migration
+    manifest.version = migration.target_version();
+    write_manifest(&manifest, backend.as_ref(), &secure_storage)?;
+
+    if backend.exists(state_path) {
+        backend.remove_file(state_path)?;
+    }
+
+    Ok(manifest.version)
+}
+
+/// Returns the name of the migration that was interrupted, if any
+pub fn pending_migration(
+    backend: &dyn StorageBackend,
+    secure_storage: &SecureStorage,
+) -> Result<Option<String>> {
+    let Some(state) = read_state::<serde_json::Value>(backend, secure_storage)? else {
+        return Ok(None);
+    };
+
+    // The state is left behind if the migration was interrupted right after committing it
+    let version = read_manifest(backend, secure_storage)?.version;
+    let committed = all_migrations()
+        .iter()
+        .any(|migration| migration.name() == state.name && migration.target_version() <= version);
+    Ok((!committed).then_some(state.name))
+}
+
+/// Fails if a migration was interrupted. The repository is only partially migrated until
+/// the migration is run again, so it must not be used in the meantime.
+pub fn ensure_no_pending_migration(
+    backend: &dyn StorageBackend,
+    secure_storage: &SecureStorage,
+) -> Result<()> {
+    if let Some(pending) = pending_migration(backend, secure_storage)? {
+        bail!(
+            "Migration \'{}\' was interrupted. Run `migrate {}` to finish it before using the repository.",
+            pending,
+            pending
+        );
+    }
+    Ok(())
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct MigrationState<T> {
+    name: String,
+    state: T,
+}
+
+fn read_state<T: DeserializeOwned>(
+    backend: &dyn StorageBackend,
+    secure_storage: &SecureStorage,
+) -> Result<Option<MigrationState<T>>> {
+    let state_path = Path::new(MIGRATION_STATE_PATH);
+    if !backend.exists(state_path) {
+        return Ok(None);
+    }
+
+    let state = backend.read(state_path)?;
+    let state = secure_storage
+        .decode(&state)
+        .with_context(|| "Could not decode the migration state")?;
+    Ok(Some(serde_json::from_slice(&state)?))
+}
+
+/// Loads the saved state of a migration, if the migration was interrupted
+fn load_state<T: DeserializeOwned>(
+    name: &str,
+    backend: &dyn StorageBackend,
+    secure_storage: &SecureStorage,
+) -> Result<Option<T>> {
+    match read_state::<T>(backend, secure_storage)? {
+        Some(state) if state.name == name => Ok(Some(state.state)),
+        Some(state) => bail!("Found state of a different migration \'{}\'", state.name),
+        None => Ok(None),
+    }
+}
+
+/// Saves the state of a migration so it can be resumed if interrupted
+fn save_state<T: Serialize>(
+    name: &str,
+    state: &T,
+    backend: &dyn StorageBackend,
+    secure_storage: &SecureStorage,
+) -> Result<()> {
+    let state = MigrationState {
+        name: name.to_string(),
+        state,
+    };
+    let state = serde_json::to_string(&state)?;
+    let state = secure_storage.encode(state.as_bytes())?;
+
+    let state_path = Path::new(MIGRATION_STATE_PATH);
+    let tmp_path = state_path.with_extension("tmp");
+    backend.write(&tmp_path, &state)?;
+    backend
+        .rename(&tmp_path, state_path)
+        .with_context(|| "Could not save the migration state")
+}
+
+/// Migrates a version 1 repository to version 2 by rewriting all blobs reachable from
+/// snapshots with keyed IDs. The old blobs become unreferenced and can be removed with `clean`.
+pub struct KeyedBlobIds;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct KeyedBlobIdsState {
+    /// Base64-encoded key for the new blob IDs
+    id_key: String,
+
+    /// Snapshots already migrated (old ID -> new ID)
+    snapshots: BTreeMap<ID, ID>,
+}
+
+impl Migration for KeyedBlobIds {
+    fn name(&self) -> &'static str {
+        "keyed-blob-ids"
+    }
+
+    fn description(&self) -> &'static str {
+        "Rewrite all blobs with keyed IDs"
+    }
+
+    fn source_version(&self) -> RepoVersion {
+        1
+    }
+
+    fn target_version(&self) -> RepoVersion {
+        2
+    }
+
+    fn run(
+        &self,
+        backend: Arc<dyn StorageBackend>,
+        secure_storage: Arc<SecureStorage>,
+        manifest: &mut Manifest,
+    ) -> Result<()> {
+        let mut state = match load_state::<KeyedBlobIdsState>(
+            self.name(),
+            backend.as_ref(),
+            &secure_storage,
+        )? {
+            Some(state) => {
+                ui::cli::log!(
+                    "Resuming migration ({} snapshots already migrated)",
+                    state.snapshots.len()
+                );
+                state
+            }
+            None => {
+                let state = KeyedBlobIdsState {
+                    id_key: general_purpose::STANDARD.encode(generate_new_master_key()),
+                    snapshots: BTreeMap::new(),
+                };
+                save_state(self.name(), &state, backend.as_ref(), &secure_storage)?;
+                state
+            }
+        };
+
+        // Open the repository as if it was already migrated, so new blobs get keyed IDs.
+        // The old blobs are still in the index and can be loaded with their plain IDs.
+        let mut target_manifest = manifest.clone();
+        target_manifest.version = self.target_version();
+        target_manifest.id_key = Some(state.id_key.clone());
+        let repo = repository_v1::Repository::open(
+            backend.clone(),
+            secure_storage.clone(),
+            target_manifest,
+        )?;
+
+        let mut snapshots = Vec::new();
+        for id in repo.list_snapshot_ids()? {
+            // Snapshots saved by an earlier run are already migrated
+            if state.snapshots.values().any(|new_id| *new_id == id) {
+                continue;
+            }
+            snapshots.push((id.clone(), repo.load_snapshot(&id)?));
+        }
+
+        // Migrate parents before their children
+        snapshots.sort_by_key(|(_, snapshot)| snapshot.timestamp);
+
+        let mut rewriter = BlobRewriter::new(repo.as_ref(), repo.as_ref(), false);
+        let num_snapshots = snapshots.len();
+        for (i, (id, mut snapshot)) in snapshots.into_iter().enumerate() {
+            ui::cli::log!(
+                "Migrating snapshot {} ({}/{})",
+                id.to_short_hex(SHORT_SNAPSHOT_ID_LEN).bold().yellow(),
+                i + 1,
+                num_snapshots
+            );
+
+            let new_id = rewriter.rewrite_snapshot(&mut snapshot, &state.snapshots)?;
+
+            state.snapshots.insert(id.clone(), new_id.clone());
+            save_state(self.name(), &state, backend.as_ref(), &secure_storage)?;
+
+            // Delete the old snapshot only after the new one is saved
+            if new_id != id {
+                repo.delete_file(FileType::Snapshot, &id)?;
+            }
+        }
+
+        manifest.id_key = Some(state.id_key);
+
+        ui::cli::log!(
+            "Run {} to remove the blobs with the old IDs",
+            "clean".bold()
+        );
+
+        Ok(())
+    }
+}
+
+/// Upgrades a version 2 repository to version 3, which can store blobs uncompressed and
+/// writes binary index files. Version 3 only adds formats, so the existing files are kept as
+/// they are.
+pub struct UpgradeV3;
+
+impl Migration for UpgradeV3 {
+    fn name(&self) -> &'static str {
+        "upgrade-v3"
+    }
+
+    fn description(&self) -> &'static str {
+        "Allow storing blobs uncompressed and write binary index files"
+    }
+
+    fn source_version(&self) -> RepoVersion {
+        2
+    }
+
+    fn target_version(&self) -> RepoVersion {
+        3
+    }
+
+    fn run(
+        &self,
+        _backend: Arc<dyn StorageBackend>,
+        _secure_storage: Arc<SecureStorage>,
+        _manifest: &mut Manifest,
+    ) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use crate::{
+        backend::localfs::LocalFS,
+        global::{BlobType, SaveID},
+        repository::{
+            init_repository_with_version,
+            keys::KdfParams,
+            manifest::RepositoryParams,
+            snapshot::Snapshot,
+            storage::{CompressionAlgorithm, CompressionSettings},
+            tree::Tree,
+            try_open,
+        },
+    };
+
+    use super::*;
+
+    /// Test that an interrupted migration can be resumed and gives the same result
+    #[test]
+    fn test_keyed_blob_ids_resume() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let password = Some(String::from("mapachito"));
+        let backend: Arc<dyn StorageBackend> = Arc::new(LocalFS::new(temp_dir.path().join("repo")));
+        init_repository_with_version(
+            password.clone(),
+            None,
+            1,
+            KdfParams::default(),
+            RepositoryParams::default(),
+            backend.clone(),
+        )?;
+
+        let (repo, secure_storage) = try_open(password.clone(), None, backend.clone())?;
+        repo.init_pack_saver(1);
+        let data = b"mapache".to_vec();
+        let (blob_id, _, _) = repo.save_blob(BlobType::Data, data.clone(), SaveID::CalculateID)?;
+        let mut tree = Tree::new();
+        tree.nodes.push(crate::repository::tree::Node {
+            name: String::from("file"),
+            node_type: crate::repository::tree::NodeType::File,
+            metadata: Default::default(),
+            symlink_info: None,
+            blobs: Some(vec![blob_id.clone()]),
+            tree: None,
+        });
+        let (tree_id, _) = tree.save_to_repo(repo.as_ref())?;
+        repo.flush()?;
+        repo.finalize_pack_saver();
+        let snapshot = Snapshot {
+            timestamp: chrono::Local::now(),
+            parent: None,
+            tree: tree_id,
+            root: Default::default(),
+            paths: Vec::new(),
+            tags: Default::default(),
+            description: None,
+            hostname: None,
+            username: None,
+            program_version: None,
+            summary: Default::default(),
+        };
+        repo.save_file(
+            FileType::Snapshot,
+            serde_json::to_string(&snapshot)?.as_bytes(),
+            SaveID::CalculateID,
+        )?;
+        drop(repo);
+
+        assert_eq!(available_migrations(1).len(), 1);
+        assert_eq!(available_migrations(2).len(), 1);
+        assert!(available_migrations(3).is_empty());
+
+        // Run the migration without committing it, as if it was interrupted
+        let mut manifest = read_manifest(backend.as_ref(), &secure_storage)?;
+        KeyedBlobIds.run(backend.clone(), secure_storage.clone(), &mut manifest)?;
+        assert_eq!(
+            pending_migration(backend.as_ref(), &secure_storage)?.as_deref(),
+            Some("keyed-blob-ids")
+        );
+        assert_eq!(read_manifest(backend.as_ref(), &secure_storage)?.version, 1);
+        assert!(try_open(password.clone(), None, backend.clone()).is_err());
+
+        // Resume it
+        assert_eq!(
+            run_migration("keyed-blob-ids", backend.clone(), secure_storage.clone())?,
+            2
+        );
+        assert!(pending_migration(backend.as_ref(), &secure_storage)?.is_none());
+
+        let (repo, _) = try_open(password.clone(), None, backend.clone())?;
+        let manifest = repo.load_manifest()?;
+        assert_eq!(manifest.version, 2);
+        assert!(manifest.id_key.is_some());
+
+        let snapshot_ids = repo.list_snapshot_ids()?;
+        assert_eq!(snapshot_ids.len(), 1);
+        let snapshot = repo.load_snapshot(&snapshot_ids[0])?;
+        let tree = Tree::load_from_repo(repo.as_ref(), &snapshot.tree)?;
+        let new_blob_id = &tree.nodes[0].blobs.as_ref().unwrap()[0];
+        assert_ne!(*new_blob_id, blob_id);
+        assert_eq!(*new_blob_id, repo.blob_id(&data));
+        assert_eq!(repo.load_blob(new_blob_id)?, data);
+        drop(repo);
+
+        // Version 3 keeps the blobs and allows storing them uncompressed
+        assert_eq!(
+            run_migration("upgrade-v3", backend.clone(), secure_storage.clone())?,
+            3
+        );
+        let (repo, _) = try_open(password, None, backend)?;
+        assert_eq!(repo.load_blob(new_blob_id)?, data);
+        repo.set_compression(CompressionSettings {
+            algorithm: CompressionAlgorithm::None,
+            ..Default::default()
+        })?;
+
+        Ok(())
+    }
+}
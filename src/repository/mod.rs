@@ -14,13 +14,19 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+pub mod cache;
 pub mod gc;
 pub mod index;
 pub mod keys;
+pub mod lock;
 pub mod manifest;
+pub mod migrations;
 pub mod packer;
+pub mod repair;
 pub mod repository_v1;
+pub mod rewriter;
 pub mod snapshot;
+pub mod stats;
 pub mod storage;
 pub mod streamers;
 pub mod tree;
@@ -31,28 +37,44 @@ use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 use anyhow::{Context, Result, bail};
+use base64::{Engine, engine::general_purpose};
+use chrono::Utc;
 use index::IndexFile;
-use manifest::Manifest;
+use manifest::{ChunkerParams, Manifest, RepositoryParams};
 use parking_lot::RwLock;
 use snapshot::Snapshot;
 use zstd::DEFAULT_COMPRESSION_LEVEL;
 
 use crate::{
     backend::StorageBackend,
-    global::{BlobType, FileType, ID, SaveID},
+    global::{BlobType, FileType, ID, SaveID, defaults::SHORT_REPO_ID_LEN},
     repository::{
         index::MasterIndex,
-        keys::{generate_key_file, generate_new_master_key, retrieve_master_key},
-        storage::SecureStorage,
+        keys::{
+            KdfParams, encode_key_file, generate_key_file, generate_new_master_key,
+            retrieve_master_key, save_key_file,
+        },
+        lock::{LockKind, RepositoryLock},
+        packer::PackedBlobDescriptor,
+        storage::{CompressionSettings, SecureStorage},
     },
     ui,
 };
 
 pub type RepoVersion = u32;
-pub const LATEST_REPOSITORY_VERSION: RepoVersion = 1;
+pub const LATEST_REPOSITORY_VERSION: RepoVersion = 3;
+
+/// First repository version that can store blobs uncompressed
+pub const UNCOMPRESSED_BLOBS_VERSION: RepoVersion = 3;
+/// First repository version that stores index files in the binary format
+pub const BINARY_INDEX_FILES_VERSION: RepoVersion = 3;
 
 pub const MANIFEST_PATH: &str = "manifest";
 pub const KEYS_DIR: &str = "keys";
+pub const LOCKS_DIR: &str = "locks";
+
+/// Context used to derive the blob ID key from the master key
+const ID_KEY_CONTEXT: &str = "mapache 2025-07 blob ID key";
 
 pub trait RepositoryBackend: Sync + Send {
     /// Create and initialize a new repository
@@ -64,6 +86,7 @@ pub trait RepositoryBackend: Sync + Send {
     fn open(
         backend: Arc<dyn StorageBackend>,
         secure_storage: Arc<SecureStorage>,
+        manifest: Manifest,
     ) -> Result<Arc<Self>>
     where
         Self: Sized;
@@ -87,9 +110,40 @@ pub trait RepositoryBackend: Sync + Send {
         id: SaveID,
     ) -> Result<(ID, (u64, u64), (u64, u64))>;
 
+    /// Calculates the ID of a blob from its contents.
+    fn blob_id(&self, data: &[u8]) -> ID;
+
     /// Loads a blob from the repository.
     fn load_blob(&self, id: &ID) -> Result<Vec<u8>>;
 
+    /// Loads a blob from a known location in a pack.
+    fn load_from_pack(
+        &self,
+        pack_id: &ID,
+        offset: u32,
+        length: u32,
+        uncompressed: bool,
+    ) -> Result<Vec<u8>>;
+
+    /// Reads the list of blobs stored in a pack from its header.
+    fn load_pack_header(&self, pack_id: &ID) -> Result<Vec<PackedBlobDescriptor>>;
+
+    /// Returns the format version of the repository.
+    fn version(&self) -> RepoVersion;
+
+    /// Returns the compression settings used to save blobs.
+    fn compression(&self) -> CompressionSettings;
+
+    /// Overrides the compression settings used to save blobs. Fails if the repository version
+    /// does not support them.
+    fn set_compression(&self, compression: CompressionSettings) -> Result<()>;
+
+    /// Returns the chunker parameters of the repository.
+    fn chunker_params(&self) -> ChunkerParams;
+
+    /// Returns the target size of pack files.
+    fn pack_size(&self) -> u64;
+
     /// Saves a file to the repository
     fn save_file(&self, file_type: FileType, data: &[u8], id: SaveID) -> Result<(ID, u64, u64)>;
 
@@ -143,7 +197,14 @@ pub fn init(
     keyfile_path: Option<&PathBuf>,
     backend: Arc<dyn StorageBackend>,
 ) -> Result<()> {
-    init_repository_with_version(password, keyfile_path, LATEST_REPOSITORY_VERSION, backend)
+    init_repository_with_version(
+        password,
+        keyfile_path,
+        LATEST_REPOSITORY_VERSION,
+        KdfParams::default(),
+        RepositoryParams::default(),
+        backend,
+    )
 }
 
 /// Initialize a repository with a version number.
@@ -152,13 +213,70 @@ pub fn init_repository_with_version(
     password: Option<String>,
     keyfile_path: Option<&PathBuf>,
     version: RepoVersion,
+    kdf: KdfParams,
+    params: RepositoryParams,
     backend: Arc<dyn StorageBackend>,
 ) -> Result<()> {
-    if version == 1 {
-        let secure_storage = init_common(password, keyfile_path, backend.clone())?;
-        repository_v1::Repository::init(backend, secure_storage)
-    } else {
-        bail!("Invalid repository version \'{}\'", version);
+    init_repository(password, keyfile_path, version, kdf, params, None, backend)
+}
+
+/// Initialize a repository that calculates the same blob IDs as the repository with the
+/// `source` manifest. The version, chunker parameters and ID key are copied from the source,
+/// so snapshots taken to either repository produce the same blobs.
+/// This function prompts for a password to create a master key.
+pub fn init_repository_with_params_from(
+    password: Option<String>,
+    keyfile_path: Option<&PathBuf>,
+    kdf: KdfParams,
+    params: RepositoryParams,
+    source: &Manifest,
+    backend: Arc<dyn StorageBackend>,
+) -> Result<()> {
+    let params = RepositoryParams {
+        chunker: source.chunker,
+        ..params
+    };
+    init_repository(
+        password,
+        keyfile_path,
+        source.version,
+        kdf,
+        params,
+        source.id_key.clone(),
+        backend,
+    )
+}
+
+/// Initialize a repository. Version 2 repositories use `id_key` as the ID key if given,
+/// or derive a new one from the master key.
+fn init_repository(
+    password: Option<String>,
+    keyfile_path: Option<&PathBuf>,
+    version: RepoVersion,
+    kdf: KdfParams,
+    params: RepositoryParams,
+    id_key: Option<String>,
+    backend: Arc<dyn StorageBackend>,
+) -> Result<()> {
+    params.validate()?;
+    params.compression.check_version(version)?;
+
+    match version {
+        // Version 2 shares the layout of version 1 and only changes how blob IDs are calculated.
+        // Version 3 adds blobs stored uncompressed and binary index files.
+        1..=3 => {
+            let secure_storage = init_common(
+                password,
+                keyfile_path,
+                version,
+                kdf,
+                params,
+                id_key,
+                backend.clone(),
+            )?;
+            repository_v1::Repository::init(backend, secure_storage)
+        }
+        _ => bail!("Invalid repository version \'{}\'", version),
     }
 }
 
@@ -167,6 +285,10 @@ pub fn init_repository_with_version(
 fn init_common(
     password: Option<String>,
     keyfile_path: Option<&PathBuf>,
+    version: RepoVersion,
+    kdf: KdfParams,
+    params: RepositoryParams,
+    id_key: Option<String>,
     backend: Arc<dyn StorageBackend>,
 ) -> Result<Arc<SecureStorage>> {
     let pass = match password {
@@ -189,41 +311,134 @@ fn init_common(
 
     let keys_path = PathBuf::from(KEYS_DIR);
     backend.create_dir(&keys_path)?;
+    backend.create_dir(Path::new(LOCKS_DIR))?;
 
     // Create new key
     let master_key = generate_new_master_key();
-    let keyfile =
-        generate_key_file(&pass, master_key.clone()).with_context(|| "Could not generate key")?;
+    let keyfile = generate_key_file(&pass, master_key.clone(), kdf)
+        .with_context(|| "Could not generate key")?;
+
+    // Blob IDs are keyed hashes since version 2
+    let id_key = (version >= 2).then(|| {
+        id_key.unwrap_or_else(|| {
+            let id_key = blake3::derive_key(ID_KEY_CONTEXT, &master_key);
+            general_purpose::STANDARD.encode(id_key)
+        })
+    });
+
     let secure_storage = Arc::new(
         SecureStorage::build()
             .with_compression(DEFAULT_COMPRESSION_LEVEL)
             .with_key(master_key),
     );
 
-    let keyfile_json = serde_json::to_string_pretty(&keyfile)?;
-    let keyfile_json = SecureStorage::compress(keyfile_json.as_bytes(), DEFAULT_COMPRESSION_LEVEL)?;
-    let keyfile_id = ID::from_content(&keyfile_json);
     match keyfile_path {
         Some(p) => {
-            std::fs::write(p, &keyfile_json)?;
+            std::fs::write(p, encode_key_file(&keyfile)?)?;
         }
         None => {
-            let p = keys_path.join(keyfile_id.to_hex());
-
-            backend.write(&p, &keyfile_json)?;
+            save_key_file(&keyfile, backend.clone())?;
         }
     }
 
+    // Save new manifest
+    let repo_id = ID::new_random();
+    let manifest = Manifest {
+        version,
+        id: repo_id.clone(),
+        created_time: Utc::now(),
+        id_key,
+        compression: params.compression,
+        chunker: params.chunker,
+        pack_size: params.pack_size,
+    };
+    write_manifest(&manifest, backend.as_ref(), &secure_storage)?;
+
+    ui::cli::log!(
+        "Created repo with id {}",
+        repo_id.to_short_hex(SHORT_REPO_ID_LEN)
+    );
+
     Ok(secure_storage)
 }
 
+/// Reads and decodes the repository manifest.
+pub fn read_manifest(
+    backend: &dyn StorageBackend,
+    secure_storage: &SecureStorage,
+) -> Result<Manifest> {
+    let manifest = backend
+        .read(Path::new(MANIFEST_PATH))
+        .with_context(|| "Could not load manifest file")?;
+    let manifest = secure_storage
+        .decode(&manifest)
+        .with_context(|| "Could not decode the manifest file")?;
+    let manifest: Manifest = serde_json::from_slice(&manifest)?;
+    Ok(manifest)
+}
+
+/// Encodes and writes the repository manifest, replacing the existing one.
+/// The manifest is written to a temporary file first and then renamed, so it is
+/// never left half-written.
+pub fn write_manifest(
+    manifest: &Manifest,
+    backend: &dyn StorageBackend,
+    secure_storage: &SecureStorage,
+) -> Result<()> {
+    let manifest = serde_json::to_string_pretty(manifest)?;
+    let manifest = secure_storage.encode(manifest.as_bytes())?;
+
+    let manifest_path = Path::new(MANIFEST_PATH);
+    let tmp_path = manifest_path.with_extension("tmp");
+    backend
+        .write(&tmp_path, &manifest)
+        .with_context(|| "Could not write manifest file")?;
+    backend
+        .rename(&tmp_path, manifest_path)
+        .with_context(|| "Could not replace manifest file")
+}
+
 /// Try to open a repository.
 /// This function prompts for a password to retrieve a master key.
 pub fn try_open(
-    mut password: Option<String>,
+    password: Option<String>,
     key_file_path: Option<&PathBuf>,
     backend: Arc<dyn StorageBackend>,
 ) -> Result<(Arc<dyn RepositoryBackend>, Arc<SecureStorage>)> {
+    let secure_storage = unlock_secure_storage(password, key_file_path, backend.clone())?;
+    let repo = open_with_secure_storage(backend, secure_storage.clone())?;
+
+    Ok((repo, secure_storage))
+}
+
+/// Try to open a repository holding a lock of the given kind.
+/// The lock is acquired before loading the repository and is released when dropped.
+/// It is returned first so that, when destructured, it outlives the repository.
+/// This function prompts for a password to retrieve a master key.
+pub fn try_open_locked(
+    password: Option<String>,
+    key_file_path: Option<&PathBuf>,
+    backend: Arc<dyn StorageBackend>,
+    lock_kind: LockKind,
+) -> Result<(
+    RepositoryLock,
+    Arc<dyn RepositoryBackend>,
+    Arc<SecureStorage>,
+)> {
+    let secure_storage = unlock_secure_storage(password, key_file_path, backend.clone())?;
+    let lock = RepositoryLock::acquire(backend.clone(), secure_storage.clone(), lock_kind)?;
+    let repo = open_with_secure_storage(backend, secure_storage.clone())?;
+
+    Ok((lock, repo, secure_storage))
+}
+
+/// Retrieves the master key and builds a SecureStorage with it.
+/// This function prompts for a password to retrieve a master key.
+pub fn unlock_secure_storage(
+    mut password: Option<String>,
+    key_file_path: Option<&PathBuf>,
+    backend: Arc<dyn StorageBackend>,
+) -> Result<Arc<SecureStorage>> {
     if !backend.root_exists() {
         bail!("Could not open a repository. The path does not exist.");
     }
@@ -256,48 +471,64 @@ pub fn try_open(
         }
     };
 
-    let secure_storage = Arc::new(
+    Ok(Arc::new(
         SecureStorage::build()
             .with_compression(DEFAULT_COMPRESSION_LEVEL)
             .with_key(master_key),
-    );
-
-    let manifest_path = Path::new(MANIFEST_PATH);
-
-    let manifest = backend
-        .read(manifest_path)
-        .with_context(|| "Could not load manifest file")?;
-    let manifest = secure_storage
-        .decode(&manifest)
-        .with_context(|| "Could not decode the manifest file")?;
-    let manifest: Manifest = serde_json::from_slice(&manifest)?;
-
-    let version = manifest.version;
+    ))
+}
 
-    let repo = open_repository_with_version(version, backend, secure_storage.clone())?;
+/// Opens a repository with an unlocked SecureStorage.
+fn open_with_secure_storage(
+    backend: Arc<dyn StorageBackend>,
+    secure_storage: Arc<SecureStorage>,
+) -> Result<Arc<dyn RepositoryBackend>> {
+    let manifest = read_manifest(backend.as_ref(), &secure_storage)?;
+    migrations::ensure_no_pending_migration(backend.as_ref(), &secure_storage)?;
+    open_repository_with_manifest(manifest, backend, secure_storage)
+}
 
-    Ok((repo, secure_storage))
+/// Opens a repository with an unlocked SecureStorage, skipping the index files that cannot
+/// be loaded. Returns the repository and the IDs of the skipped index files.
+/// This is only meant to be used to repair the index.
+pub fn open_skipping_broken_indices(
+    backend: Arc<dyn StorageBackend>,
+    secure_storage: Arc<SecureStorage>,
+) -> Result<(Arc<dyn RepositoryBackend>, Vec<ID>)> {
+    let manifest = read_manifest(backend.as_ref(), &secure_storage)?;
+    migrations::ensure_no_pending_migration(backend.as_ref(), &secure_storage)?;
+    match manifest.version {
+        1..=3 => {
+            let (repo, broken_indices) = repository_v1::Repository::open_skipping_broken_indices(
+                backend,
+                secure_storage,
+                manifest,
+            )?;
+            Ok((repo, broken_indices))
+        }
+        version => bail!("Invalid repository version \'{}\'", version),
+    }
 }
 
-fn open_repository_with_version(
-    version: RepoVersion,
+fn open_repository_with_manifest(
+    manifest: Manifest,
     backend: Arc<dyn StorageBackend>,
     secure_storage: Arc<SecureStorage>,
 ) -> Result<Arc<dyn RepositoryBackend>> {
-    if version == 1 {
-        let repo_v1 = repository_v1::Repository::open(backend, secure_storage)?;
-        return Ok(repo_v1);
+    match manifest.version {
+        1..=3 => {
+            let repo = repository_v1::Repository::open(backend, secure_storage, manifest)?;
+            Ok(repo)
+        }
+        version => bail!("Invalid repository version \'{}\'", version),
     }
-
-    bail!("Invalid repository version \'{}\'", version);
 }
 
 #[cfg(test)]
 mod tests {
-    use base64::{Engine, engine::general_purpose};
     use tempfile::tempdir;
 
-    use crate::{backend::localfs::LocalFS, utils};
+    use crate::{backend::localfs::LocalFS, global::Hash256, utils};
 
     use super::*;
 
@@ -337,20 +568,198 @@ mod tests {
         Ok(())
     }
 
+    /// Test that blob IDs are keyed hashes since version 2, while version 1 uses plain hashes
+    #[test]
+    fn test_blob_ids_by_version() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let password = Some(String::from("mapachito"));
+        let data = b"mapache";
+
+        let backend_v1 = Arc::new(LocalFS::new(temp_dir.path().join("repo_v1")));
+        init_repository_with_version(
+            password.clone(),
+            None,
+            1,
+            KdfParams::default(),
+            RepositoryParams::default(),
+            backend_v1.clone(),
+        )?;
+        let (repo_v1, _) = try_open(password.clone(), None, backend_v1)?;
+        assert!(repo_v1.load_manifest()?.id_key.is_none());
+        assert_eq!(repo_v1.blob_id(data), ID::from_content(data));
+
+        let backend_v2 = Arc::new(LocalFS::new(temp_dir.path().join("repo_v2")));
+        init_repository_with_version(
+            password.clone(),
+            None,
+            2,
+            KdfParams::default(),
+            RepositoryParams::default(),
+            backend_v2.clone(),
+        )?;
+        let (repo_v2, _) = try_open(password, None, backend_v2)?;
+        let id_key: Hash256 = general_purpose::STANDARD
+            .decode(repo_v2.load_manifest()?.id_key.unwrap())?
+            .try_into()
+            .unwrap();
+        assert_ne!(repo_v2.blob_id(data), ID::from_content(data));
+        assert_eq!(repo_v2.blob_id(data), ID::from_keyed_content(&id_key, data));
+
+        Ok(())
+    }
+
+    /// Test that the compression settings are stored in the manifest and that blobs stored
+    /// with and without compression can be read back
+    #[test]
+    fn test_compression_settings() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let password = Some(String::from("mapachito"));
+        let backend = Arc::new(LocalFS::new(temp_dir.path().join("repo")));
+
+        let compression = CompressionSettings {
+            algorithm: storage::CompressionAlgorithm::None,
+            level: 1,
+            auto: false,
+        };
+        init_repository_with_version(
+            password.clone(),
+            None,
+            LATEST_REPOSITORY_VERSION,
+            KdfParams::default(),
+            RepositoryParams {
+                compression,
+                ..Default::default()
+            },
+            backend.clone(),
+        )?;
+
+        let text = b"mapache ".repeat(32);
+        let (repo, _) = try_open(password.clone(), None, backend.clone())?;
+        assert_eq!(repo.load_manifest()?.compression, compression);
+        assert_eq!(repo.compression(), compression);
+        repo.init_pack_saver(1);
+        let (uncompressed_id, (_, encoded_size), _) =
+            repo.save_blob(BlobType::Data, text.clone(), SaveID::CalculateID)?;
+        assert!(encoded_size > text.len() as u64);
+
+        // Override the settings as a snapshot would
+        repo.set_compression(CompressionSettings::default())?;
+        let mut other_text = text.clone();
+        other_text.extend_from_slice(b" backup");
+        let (compressed_id, (_, encoded_size), _) =
+            repo.save_blob(BlobType::Data, other_text.clone(), SaveID::CalculateID)?;
+        assert!(encoded_size < other_text.len() as u64);
+        repo.flush()?;
+        repo.finalize_pack_saver();
+
+        let (repo, _) = try_open(password, None, backend)?;
+        assert_eq!(repo.compression(), compression);
+        assert!(repo.index().read().get(&uncompressed_id).unwrap().4);
+        assert!(!repo.index().read().get(&compressed_id).unwrap().4);
+        assert_eq!(repo.load_blob(&uncompressed_id)?, text);
+        assert_eq!(repo.load_blob(&compressed_id)?, other_text);
+
+        Ok(())
+    }
+
+    /// Test that repositories older than version 3 never store blobs uncompressed
+    #[test]
+    fn test_compression_settings_older_versions() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let password = Some(String::from("mapachito"));
+        let backend = Arc::new(LocalFS::new(temp_dir.path().join("repo")));
+
+        let uncompressed = CompressionSettings {
+            algorithm: storage::CompressionAlgorithm::None,
+            ..Default::default()
+        };
+        let init = |compression| {
+            init_repository_with_version(
+                password.clone(),
+                None,
+                2,
+                KdfParams::default(),
+                RepositoryParams {
+                    compression,
+                    ..Default::default()
+                },
+                backend.clone(),
+            )
+        };
+        assert!(init(uncompressed).is_err());
+        init(CompressionSettings::default())?;
+
+        let (repo, _) = try_open(password, None, backend)?;
+        assert!(repo.set_compression(uncompressed).is_err());
+
+        // Random data does not compress, but it can't be stored uncompressed
+        let data: Vec<u8> = (0..1024).map(|_| rand::random()).collect();
+        repo.init_pack_saver(1);
+        let (id, (_, encoded_size), _) =
+            repo.save_blob(BlobType::Data, data.clone(), SaveID::CalculateID)?;
+        assert!(encoded_size > data.len() as u64);
+        repo.flush()?;
+        repo.finalize_pack_saver();
+        assert!(!repo.index().read().get(&id).unwrap().4);
+        assert_eq!(repo.load_blob(&id)?, data);
+
+        Ok(())
+    }
+
     /// Test generation of master keys
     #[test]
     fn test_generate_key_file() -> Result<()> {
         let master_key = generate_new_master_key();
-        let keyfile = generate_key_file("mapachito", master_key.clone())?;
+        let keyfile = generate_key_file("mapachito", master_key.clone(), KdfParams::default())?;
 
         let salt = general_purpose::STANDARD.decode(keyfile.salt)?;
         let encrypted_key = general_purpose::STANDARD.decode(keyfile.encrypted_key)?;
 
-        let intermediate_key = SecureStorage::derive_key("mapachito", &salt);
+        let intermediate_key = SecureStorage::derive_key("mapachito", &salt, &keyfile.kdf)?;
         let decrypted_key = SecureStorage::decrypt_with_key(&intermediate_key, &encrypted_key)?;
 
         assert_eq!(master_key, decrypted_key.as_slice());
 
         Ok(())
     }
+
+    /// Test that KeyFiles use their own KDF parameters and that KeyFiles
+    /// without parameters are read with the defaults
+    #[test]
+    fn test_key_file_kdf_params() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let keyfile_path = temp_dir.path().join("keyfile");
+        let master_key = generate_new_master_key();
+        let backend = Arc::new(LocalFS::new(temp_dir.path().join("repo")));
+
+        let kdf = KdfParams {
+            algorithm: keys::KdfAlgorithm::Argon2d,
+            memory_cost: 4 * 1024,
+            iterations: 1,
+            parallelism: 2,
+        };
+        let keyfile = generate_key_file("mapachito", master_key.clone(), kdf)?;
+        std::fs::write(&keyfile_path, encode_key_file(&keyfile)?)?;
+        let key = retrieve_master_key("mapachito", Some(&keyfile_path), backend.clone())?;
+        assert_eq!(master_key, key);
+
+        // A KeyFile created before KDF parameters were stored
+        let keyfile = generate_key_file("mapachito", master_key.clone(), KdfParams::default())?;
+        let legacy_json = format!(
+            r#"{{"created":"{}","encrypted_key":"{}","salt":"{}"}}"#,
+            keyfile.created.to_rfc3339(),
+            keyfile.encrypted_key,
+            keyfile.salt
+        );
+        let legacy_keyfile = SecureStorage::compress(legacy_json.as_bytes(), 0)?;
+        std::fs::write(&keyfile_path, legacy_keyfile)?;
+        assert_eq!(
+            keys::read_key_file(&keyfile_path)?.kdf,
+            KdfParams::default()
+        );
+        let key = retrieve_master_key("mapachito", Some(&keyfile_path), backend)?;
+        assert_eq!(master_key, key);
+
+        Ok(())
+    }
 }
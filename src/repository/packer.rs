@@ -14,10 +14,11 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use std::{sync::Arc, thread::JoinHandle};
+use std::{path::Path, sync::Arc, thread::JoinHandle};
 
 use anyhow::{Context, Result, bail};
 use crossbeam_channel::Sender;
+use parking_lot::{Condvar, Mutex};
 use rand::Rng;
 
 use crate::{
@@ -29,6 +30,9 @@ use crate::{
 
 pub(crate) const HEADER_BLOB_LEN: usize = 32 + 4 + 1; // id (256 bits) + length (u32) + type (u8)
 
+/// Bit set in the header type byte of blobs stored uncompressed
+pub(crate) const UNCOMPRESSED_BLOB_FLAG: u8 = 0x80;
+
 /// Describes a single blob's location and size within a packed file.
 /// This metadata is crucial for retrieving individual blobs from a pack.
 #[derive(Debug, Clone, PartialEq)]
@@ -37,6 +41,7 @@ pub struct PackedBlobDescriptor {
     pub blob_type: BlobType,
     pub offset: u32,
     pub length: u32,
+    pub uncompressed: bool,
 }
 
 /// A tuple representing the flushed contents of a `Packer`:
@@ -57,7 +62,7 @@ pub struct FlushedPack {
 /// This design helps minimize memory reallocations by consolidating all blob
 /// data into a single `Vec<u8>` and tracking individual blob locations.
 pub struct Packer {
-    blobs: Vec<(ID, BlobType, Vec<u8>)>,
+    blobs: Vec<(ID, BlobType, Vec<u8>, bool)>,
     size: u64,
 }
 
@@ -97,11 +102,17 @@ impl Packer {
     ///
     /// The `blob_data` `Vec<u8>` is efficiently moved into the packer's internal
     /// buffer using `Vec::append`, avoiding a costly copy. After this call, `blob_data`
-    /// will be empty.
-    pub fn add_blob(&mut self, id: ID, blob_type: BlobType, blob_data: Vec<u8>) {
+    /// will be empty. `uncompressed` marks blobs that were encoded without compression.
+    pub fn add_blob(
+        &mut self,
+        id: ID,
+        blob_type: BlobType,
+        blob_data: Vec<u8>,
+        uncompressed: bool,
+    ) {
         let length = blob_data.len();
         self.size += length as u64;
-        self.blobs.push((id, blob_type, blob_data));
+        self.blobs.push((id, blob_type, blob_data, uncompressed));
     }
 
     /// Flushes the contents of the packer, returning the accumulated raw data
@@ -130,6 +141,7 @@ impl Packer {
                 blob_type: blob.1,
                 offset,
                 length,
+                uncompressed: blob.3,
             });
             data.append(&mut blob_data);
             offset += length;
@@ -155,9 +167,10 @@ impl Packer {
     /// Generates a pack header given a vector of blob descriptors.
     fn generate_header(descriptors: &mut Vec<PackedBlobDescriptor>) -> Vec<u8> {
         // blob[id (256 bits), lenght (u32), type (u8)] + header length (u32);
+        // The highest bit of the type byte is set if the blob is stored uncompressed.
         let mut pack_header = Vec::<u8>::with_capacity(HEADER_BLOB_LEN * descriptors.len());
 
-        if descriptors.len() % HEADER_BLOB_MULTIPLE > 0 {
+        if !descriptors.len().is_multiple_of(HEADER_BLOB_MULTIPLE) {
             let num_padding_blobs =
                 HEADER_BLOB_MULTIPLE - (descriptors.len() % HEADER_BLOB_MULTIPLE);
             for _ in 0..num_padding_blobs {
@@ -167,6 +180,7 @@ impl Packer {
                     blob_type: BlobType::Padding,
                     offset: rand::rng().random(),
                     length: rand::rng().random(),
+                    uncompressed: false,
                 });
             }
         }
@@ -178,8 +192,11 @@ impl Packer {
             let length = blob.length.to_le_bytes();
             pack_header.extend_from_slice(&length);
 
-            let blob_type: [u8; 1] = (blob.blob_type.to_owned() as u8).to_le_bytes();
-            pack_header.extend_from_slice(&blob_type);
+            let mut blob_type = blob.blob_type.to_owned() as u8;
+            if blob.uncompressed {
+                blob_type |= UNCOMPRESSED_BLOB_FLAG;
+            }
+            pack_header.push(blob_type);
         }
 
         pack_header
@@ -194,14 +211,23 @@ impl Packer {
         pack_id: &ID,
     ) -> Result<Vec<PackedBlobDescriptor>> {
         let (_id, pack_path) = repo.find(FileType::Object, &pack_id.to_hex())?;
+        Self::read_pack_header(backend, secure_storage, &pack_path)
+    }
+
+    /// Reads and parses the header of the pack file at a given path.
+    pub fn read_pack_header(
+        backend: &dyn StorageBackend,
+        secure_storage: &SecureStorage,
+        pack_path: &Path,
+    ) -> Result<Vec<PackedBlobDescriptor>> {
         let header_length_bytes: [u8; 4] = backend
-            .seek_read_from_end(&pack_path, -4, 4)?
+            .seek_read_from_end(pack_path, -4, 4)?
             .as_slice()
             .try_into()?;
         let encoded_header_length = u32::from_le_bytes(header_length_bytes) as usize;
 
         let header_data = backend.seek_read_from_end(
-            &pack_path,
+            pack_path,
             -(4 + encoded_header_length as i64),
             4 + encoded_header_length as u64,
         )?;
@@ -257,11 +283,13 @@ impl Packer {
         for i in 0..num_blobs {
             let blob_info = &header_blob_info[(i * HEADER_BLOB_LEN)..((i + 1) * HEADER_BLOB_LEN)];
 
-            let blob_type: BlobType = blob_info[36].into();
-            if matches!(blob_type, BlobType::Padding) {
+            let type_byte = blob_info[36];
+            if type_byte == BlobType::Padding as u8 {
                 // Ignore padding blobs. They "don't exist".
                 continue;
             }
+            let uncompressed = type_byte & UNCOMPRESSED_BLOB_FLAG != 0;
+            let blob_type: BlobType = (type_byte & !UNCOMPRESSED_BLOB_FLAG).into();
 
             let blob_id_bytes: [u8; 32] = blob_info[0..32].try_into().unwrap();
             let id = ID::from_bytes(blob_id_bytes);
@@ -277,6 +305,7 @@ impl Packer {
                 blob_type,
                 offset,
                 length,
+                uncompressed,
             };
             blob_descriptors.push(blob_descriptor);
         }
@@ -285,18 +314,28 @@ impl Packer {
     }
 }
 
-pub type QueueFn = Arc<dyn Fn(Vec<u8>, ID) + Send + Sync + 'static>;
+pub type QueueFn = Arc<dyn Fn(Vec<u8>, ID) -> Result<()> + Send + Sync + 'static>;
+
+/// Packs queued in a `PackSaver` that are not stored yet, and the packs that failed
+#[derive(Default)]
+struct SaveState {
+    in_flight: usize,
+    failed: Vec<ID>,
+}
 
 pub struct PackSaver {
     tx: Sender<(Vec<u8>, ID)>,
     join_handle: JoinHandle<()>,
+    state: Arc<(Mutex<SaveState>, Condvar)>,
 }
 
 impl PackSaver {
     pub fn new(concurrency: usize, queue_fn: QueueFn) -> Self {
-        let (tx, rx) = crossbeam_channel::bounded(concurrency);
+        let (tx, rx) = crossbeam_channel::bounded::<(Vec<u8>, ID)>(concurrency);
 
         let worker_queue_fn = Arc::clone(&queue_fn);
+        let state = Arc::new((Mutex::new(SaveState::default()), Condvar::new()));
+        let worker_state = Arc::clone(&state);
 
         let join_handle = std::thread::spawn(move || {
             let pool = rayon::ThreadPoolBuilder::new()
@@ -307,13 +346,25 @@ impl PackSaver {
             while let Ok((data, id)) = rx.recv() {
                 pool.scope(|s| {
                     s.spawn(|_| {
-                        worker_queue_fn(data, id);
+                        let result = worker_queue_fn(data, id.clone());
+
+                        let (lock, cvar) = &*worker_state;
+                        let mut state = lock.lock();
+                        state.in_flight -= 1;
+                        if result.is_err() {
+                            state.failed.push(id);
+                        }
+                        cvar.notify_all();
                     });
                 });
             }
         });
 
-        PackSaver { tx, join_handle }
+        PackSaver {
+            tx,
+            join_handle,
+            state,
+        }
     }
 
     pub fn save_pack(&self, packer_data: Vec<u8>, save_id: SaveID) -> Result<ID> {
@@ -322,13 +373,34 @@ impl PackSaver {
             SaveID::WithID(id) => id,
         };
 
-        self.tx
-            .send((packer_data, pack_id.clone()))
-            .with_context(|| "Failed to send pack data to PackSaver channel")?;
+        self.state.0.lock().in_flight += 1;
+        if let Err(e) = self.tx.send((packer_data, pack_id.clone())) {
+            self.state.0.lock().in_flight -= 1;
+            return Err(e).with_context(|| "Failed to send pack data to PackSaver channel");
+        }
 
         Ok(pack_id)
     }
 
+    /// Blocks until all queued packs are stored. Returns an error if any pack could not be
+    /// stored, so that no index file references a pack missing from the repository.
+    pub fn wait(&self) -> Result<()> {
+        let (lock, cvar) = &*self.state;
+        let mut state = lock.lock();
+        while state.in_flight > 0 {
+            cvar.wait(&mut state);
+        }
+
+        if let Some(id) = state.failed.first() {
+            bail!(
+                "{} packs could not be saved (first: {})",
+                state.failed.len(),
+                id.to_hex()
+            );
+        }
+        Ok(())
+    }
+
     pub fn finish(self) {
         drop(self.tx);
         self.join_handle
@@ -347,13 +419,13 @@ mod tests {
         let mut packer = Packer::new();
 
         let blob1: Vec<u8> = b"mapache".to_vec(); // 7 bytes
-        packer.add_blob(ID::from_content(&blob1), BlobType::Data, blob1);
+        packer.add_blob(ID::from_content(&blob1), BlobType::Data, blob1, false);
 
         let blob2: Vec<u8> = b"backup".to_vec(); // 6 bytes
-        packer.add_blob(ID::from_content(&blob2), BlobType::Data, blob2);
+        packer.add_blob(ID::from_content(&blob2), BlobType::Data, blob2, true);
 
         let blob3: Vec<u8> = b"rust".to_vec(); // 4 bytes
-        packer.add_blob(ID::from_content(&blob3), BlobType::Data, blob3);
+        packer.add_blob(ID::from_content(&blob3), BlobType::Data, blob3, false);
 
         assert_eq!(packer.size(), (7 + 6 + 4));
         assert!(!packer.is_empty());
@@ -372,6 +444,13 @@ mod tests {
         let header_descriptors = Packer::parse_header(&secure_storage, &flushed_pack.data)?;
         assert_eq!(flushed_pack.descriptors.len(), 64);
         assert_eq!(header_descriptors.len(), 3);
+        assert_eq!(
+            header_descriptors
+                .iter()
+                .map(|blob| blob.uncompressed)
+                .collect::<Vec<_>>(),
+            vec![false, true, false]
+        );
         assert_ne!(flushed_pack.descriptors, header_descriptors);
 
         Ok(())
@@ -0,0 +1,413 @@
+// mapache is an incremental backup tool
+// Copyright (C) 2025  Javier Lancha Vázquez <javier.lancha@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::{Context, Result};
+use indicatif::{ProgressBar, ProgressStyle};
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
+use crate::{
+    archiver::tree_serializer::{self, init_pending_trees},
+    backend::StorageBackend,
+    global::{FileType, ID, SaveID, defaults::DEFAULT_WRITE_CONCURRENCY},
+    ui::{self, default_bar_draw_target},
+    utils,
+};
+
+use super::{
+    RepositoryBackend, open_skipping_broken_indices, snapshot::Snapshot, storage::SecureStorage,
+    streamers::SerializedNodeStreamer, tree::Tree, verify::verify_blob,
+};
+
+/// Tag added to repaired snapshots
+pub const REPAIRED_TAG: &str = "repaired";
+
+/// Result of rebuilding the index
+#[derive(Debug, Default)]
+pub struct IndexRepairSummary {
+    /// Index files that could not be loaded and were removed
+    pub broken_indices: Vec<ID>,
+    /// Total number of packs in the repository
+    pub total_packs: usize,
+    /// Packs that were not covered by any valid index and were added from their headers
+    pub recovered_packs: usize,
+    /// Blobs found in the recovered packs
+    pub recovered_blobs: usize,
+    /// Packs whose header could not be read
+    pub unreadable_packs: Vec<ID>,
+}
+
+/// Packs added to the index from their headers
+#[derive(Debug, Default)]
+pub struct RecoveredPacks {
+    /// Packs whose blobs were added to the index
+    pub packs: usize,
+    /// Blobs found in the recovered packs
+    pub blobs: usize,
+    /// Packs whose header could not be read
+    pub unreadable_packs: Vec<ID>,
+}
+
+/// Rebuilds the index from the pack headers.
+///
+/// Packs that are not listed in any valid index file have their headers read in parallel and
+/// are added to new index files. Once the new index files are saved, the broken index files are
+/// removed. The caller is responsible for holding an exclusive lock on the repository.
+pub fn repair_index(
+    backend: Arc<dyn StorageBackend>,
+    secure_storage: Arc<SecureStorage>,
+    concurrency: usize,
+) -> Result<IndexRepairSummary> {
+    let (repo, broken_indices) = open_skipping_broken_indices(backend, secure_storage)?;
+
+    let total_packs = repo.list_objects()?.len();
+    let recovered = index_unindexed_packs(repo.as_ref(), concurrency)?;
+
+    // Remove the broken index files only after the new ones are saved.
+    // A rebuilt index can have the same ID as the broken file it replaces, in which
+    // case the broken file was already overwritten.
+    let new_index_ids = repo.index().read().ids();
+    for id in &broken_indices {
+        if !new_index_ids.contains(id) {
+            repo.delete_file(FileType::Index, id)?;
+        }
+    }
+
+    Ok(IndexRepairSummary {
+        broken_indices,
+        total_packs,
+        recovered_packs: recovered.packs,
+        recovered_blobs: recovered.blobs,
+        unreadable_packs: recovered.unreadable_packs,
+    })
+}
+
+/// Adds the packs that are not listed in any index file to the index, reading their headers
+/// in parallel, and saves the new index files.
+///
+/// These packs are left behind when a snapshot is interrupted before its index is saved.
+/// Their blobs can be reused instead of being saved again.
+pub fn index_unindexed_packs(
+    repo: &dyn RepositoryBackend,
+    concurrency: usize,
+) -> Result<RecoveredPacks> {
+    let indexed_packs: BTreeSet<ID> = repo
+        .index()
+        .read()
+        .iter_ids()
+        .map(|(_, locator)| locator.pack_id)
+        .collect();
+    let missing_packs: Vec<ID> = repo
+        .list_objects()?
+        .into_iter()
+        .filter(|id| !indexed_packs.contains(id))
+        .collect();
+    drop(indexed_packs);
+
+    let mut recovered = RecoveredPacks::default();
+    if missing_packs.is_empty() {
+        return Ok(recovered);
+    }
+
+    let header_bar =
+        ProgressBar::with_draw_target(Some(missing_packs.len() as u64), default_bar_draw_target())
+            .with_style(
+                ProgressStyle::default_bar()
+                    .template("[{bar:25.cyan/white}] Reading pack headers: {pos}/{len}")
+                    .unwrap()
+                    .progress_chars("=> "),
+            );
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(concurrency.max(1))
+        .build()
+        .expect("Failed to build thread pool");
+    let headers: Vec<_> = pool.install(|| {
+        missing_packs
+            .into_par_iter()
+            .map(|pack_id| {
+                let header = repo.load_pack_header(&pack_id);
+                header_bar.inc(1);
+                (pack_id, header)
+            })
+            .collect()
+    });
+    header_bar.finish_and_clear();
+
+    for (pack_id, header) in headers {
+        match header {
+            Ok(descriptors) => {
+                recovered.packs += 1;
+                recovered.blobs += descriptors.len();
+                repo.index().write().add_pack(repo, &pack_id, descriptors)?;
+            }
+            Err(e) => {
+                ui::cli::warning!("Could not read header of pack {}: {}", pack_id, e);
+                recovered.unreadable_packs.push(pack_id);
+            }
+        }
+    }
+    repo.index().write().save(repo)?;
+
+    Ok(recovered)
+}
+
+/// Parts of a snapshot that reference missing blobs
+#[derive(Debug, Default)]
+pub struct SnapshotDamage {
+    /// Directories whose tree is lost. They are dropped with all their contents.
+    pub lost_dirs: Vec<PathBuf>,
+
+    /// Files with no intact data before their first missing blob. They are dropped.
+    pub dropped_files: Vec<PathBuf>,
+
+    /// Files that are truncated before their first missing blob (intact blobs, total blobs)
+    pub truncated_files: HashMap<PathBuf, (usize, usize)>,
+}
+
+impl SnapshotDamage {
+    pub fn is_empty(&self) -> bool {
+        self.lost_dirs.is_empty()
+            && self.dropped_files.is_empty()
+            && self.truncated_files.is_empty()
+    }
+
+    /// Paths removed from the snapshot
+    fn dropped_paths(&self) -> Vec<PathBuf> {
+        self.lost_dirs
+            .iter()
+            .chain(self.dropped_files.iter())
+            .cloned()
+            .collect()
+    }
+}
+
+/// Checks whether blobs are intact, remembering the result for each blob
+pub struct BlobChecker<'a> {
+    repo: &'a dyn RepositoryBackend,
+    read_data: bool,
+    checked: HashMap<ID, bool>,
+}
+
+impl<'a> BlobChecker<'a> {
+    /// With `read_data`, blobs are read and verified. Otherwise they are only looked up in
+    /// the index.
+    pub fn new(repo: &'a dyn RepositoryBackend, read_data: bool) -> Self {
+        Self {
+            repo,
+            read_data,
+            checked: HashMap::new(),
+        }
+    }
+
+    pub fn is_intact(&mut self, id: &ID) -> bool {
+        if let Some(intact) = self.checked.get(id) {
+            return *intact;
+        }
+
+        let intact = if self.read_data {
+            verify_blob(self.repo, id).is_ok()
+        } else {
+            self.repo.index().read().contains(id)
+        };
+        self.checked.insert(id.clone(), intact);
+        intact
+    }
+}
+
+/// Walks the trees of a snapshot looking for lost trees and missing data blobs
+pub fn scan_snapshot(checker: &mut BlobChecker, snapshot: &Snapshot) -> Result<SnapshotDamage> {
+    let root_tree = Tree::load_from_repo(checker.repo, &snapshot.tree)
+        .with_context(|| "The root tree is lost. Nothing can be salvaged.")?;
+
+    let mut damage = SnapshotDamage::default();
+    scan_tree(checker, root_tree, &snapshot.root, &mut damage);
+    Ok(damage)
+}
+
+fn scan_tree(checker: &mut BlobChecker, tree: Tree, path: &Path, damage: &mut SnapshotDamage) {
+    for node in tree.nodes {
+        let node_path = path.join(&node.name);
+
+        if let Some(subtree_id) = &node.tree {
+            match Tree::load_from_repo(checker.repo, subtree_id) {
+                Ok(subtree) => scan_tree(checker, subtree, &node_path, damage),
+                Err(_) => damage.lost_dirs.push(node_path.clone()),
+            }
+        }
+
+        if let Some(blobs) = &node.blobs {
+            match blobs.iter().position(|id| !checker.is_intact(id)) {
+                None => (),
+                Some(0) => damage.dropped_files.push(node_path),
+                Some(intact) => {
+                    damage
+                        .truncated_files
+                        .insert(node_path, (intact, blobs.len()));
+                }
+            }
+        }
+    }
+}
+
+/// Rewrites the tree of a damaged snapshot without the lost parts and saves it as a new
+/// snapshot tagged `REPAIRED_TAG`. Returns the ID of the new snapshot.
+///
+/// The size of a truncated file is the size of its kept blobs as stored in the index, so the
+/// blobs are not read again.
+pub fn rewrite_snapshot(
+    repo: Arc<dyn RepositoryBackend>,
+    snapshot: &mut Snapshot,
+    damage: &SnapshotDamage,
+) -> Result<ID> {
+    let excludes = damage.dropped_paths();
+
+    // The number of children of the root must be known in advance, since the streamer
+    // does not emit the root node.
+    let root_tree = Tree::load_from_repo(repo.as_ref(), &snapshot.tree)?;
+    let root_children: Vec<PathBuf> = root_tree
+        .nodes
+        .iter()
+        .map(|node| snapshot.root.join(&node.name))
+        .filter(|path| utils::filter_path(path, None, Some(&excludes)))
+        .collect();
+
+    repo.init_pack_saver(DEFAULT_WRITE_CONCURRENCY);
+
+    let mut final_root_tree_id: Option<ID> = None;
+    let mut pending_trees = init_pending_trees(&snapshot.root, &root_children);
+    let node_streamer = SerializedNodeStreamer::new(
+        repo.clone(),
+        Some(snapshot.tree.clone()),
+        snapshot.root.clone(),
+        None,
+        Some(excludes),
+    )?;
+
+    for item in node_streamer {
+        let (path, mut stream_node) = item?;
+
+        if let Some((intact, _)) = damage.truncated_files.get(&path)
+            && let Some(blobs) = stream_node.node.blobs.as_mut()
+        {
+            blobs.truncate(*intact);
+
+            let index = repo.index();
+            let index = index.read();
+            let mut size = 0;
+            for blob_id in blobs.iter() {
+                let (_, _, _, length, _) = index
+                    .get(blob_id)
+                    .with_context(|| format!("Blob {blob_id} is not in the index"))?;
+                size += length as u64;
+            }
+            stream_node.node.metadata.size = size;
+        }
+
+        tree_serializer::handle_processed_item(
+            (path, stream_node),
+            repo.as_ref(),
+            &mut pending_trees,
+            &mut final_root_tree_id,
+            &snapshot.root,
+        )?;
+    }
+
+    snapshot.tree = match final_root_tree_id {
+        Some(tree_id) => tree_id,
+        // Nothing was left under the root
+        None => Tree::new().save_to_repo(repo.as_ref())?.0,
+    };
+
+    repo.flush()?;
+    repo.finalize_pack_saver();
+
+    snapshot.tags.insert(REPAIRED_TAG.to_string());
+    let (new_id, _, _) = repo.save_file(
+        FileType::Snapshot,
+        serde_json::to_string(&snapshot)?.as_bytes(),
+        SaveID::CalculateID,
+    )?;
+
+    Ok(new_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use crate::{
+        backend::localfs::LocalFS,
+        global::{BlobType, SaveID},
+        repository::{self, try_open},
+    };
+
+    use super::*;
+
+    /// Test that the index can be rebuilt after an index file is corrupted
+    #[test]
+    fn test_repair_index() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let password = Some(String::from("mapachito"));
+        let repo_path = temp_dir.path().join("repo");
+        let backend: Arc<dyn StorageBackend> = Arc::new(LocalFS::new(repo_path.clone()));
+        repository::init(password.clone(), None, backend.clone())?;
+
+        let (repo, secure_storage) = try_open(password.clone(), None, backend.clone())?;
+        repo.init_pack_saver(1);
+        let blobs: Vec<Vec<u8>> = (0..10).map(|i| format!("blob {i}").into_bytes()).collect();
+        let mut ids = Vec::new();
+        for blob in &blobs {
+            let (id, _, _) = repo.save_blob(BlobType::Data, blob.clone(), SaveID::CalculateID)?;
+            ids.push(id);
+        }
+        repo.flush()?;
+        repo.finalize_pack_saver();
+        let index_ids = repo.index().read().ids();
+        drop(repo);
+        assert_eq!(index_ids.len(), 1);
+
+        // Corrupt the index file
+        let broken_id = index_ids.first().unwrap();
+        let index_path = repo_path.join("index").join(broken_id.to_hex());
+        std::fs::write(&index_path, b"mapache")?;
+        assert!(try_open(password.clone(), None, backend.clone()).is_err());
+
+        // Files that are not named after an ID are skipped
+        let stray_path = repo_path.join("index").join("mapache");
+        std::fs::write(&stray_path, b"mapache")?;
+
+        let summary = repair_index(backend.clone(), secure_storage, 2)?;
+        assert_eq!(summary.broken_indices, vec![broken_id.clone()]);
+        assert_eq!(summary.total_packs, 1);
+        assert_eq!(summary.recovered_packs, 1);
+        assert_eq!(summary.recovered_blobs, blobs.len());
+        assert!(summary.unreadable_packs.is_empty());
+
+        std::fs::remove_file(&stray_path)?;
+        let (repo, _) = try_open(password, None, backend)?;
+        for (id, blob) in ids.iter().zip(blobs) {
+            assert_eq!(repo.load_blob(id)?, blob);
+        }
+
+        Ok(())
+    }
+}
@@ -20,39 +20,38 @@ use std::{
     sync::Arc,
 };
 
-use anyhow::{Context, Result, bail};
-use chrono::Utc;
+use anyhow::{Context, Result, anyhow, bail};
+use base64::{Engine, engine::general_purpose};
 use parking_lot::RwLock;
+use secrecy::{ExposeSecret, SecretBox};
 
 use crate::{
     backend::StorageBackend,
-    global::{
-        self, BlobType, FileType, SaveID,
-        defaults::{MAX_PACK_SIZE, SHORT_REPO_ID_LEN},
-    },
+    global::{self, BlobType, FileType, Hash256, SaveID},
     repository::{
-        MANIFEST_PATH,
-        packer::{PackSaver, Packer},
-        storage::SecureStorage,
+        MANIFEST_PATH, RepoVersion, UNCOMPRESSED_BLOBS_VERSION,
+        cache::Cache,
+        packer::{PackSaver, PackedBlobDescriptor, Packer},
+        storage::{CompressionAlgorithm, CompressionSettings, SecureStorage},
     },
     ui::{self, cli},
+    utils,
 };
 
 use super::{
-    ID, KEYS_DIR, RepoVersion, RepositoryBackend,
+    ID, KEYS_DIR, RepositoryBackend,
     index::{Index, IndexFile, MasterIndex},
     keys,
-    manifest::Manifest,
+    manifest::{ChunkerParams, Manifest},
     snapshot::Snapshot,
 };
 
-const REPO_VERSION: RepoVersion = 1;
-
-const OBJECTS_DIR: &str = "objects";
-const SNAPSHOTS_DIR: &str = "snapshots";
-const INDEX_DIR: &str = "index";
+pub const OBJECTS_DIR: &str = "objects";
+pub const SNAPSHOTS_DIR: &str = "snapshots";
+pub const INDEX_DIR: &str = "index";
 
-const OBJECTS_DIR_FANOUT: usize = 2;
+/// Number of hex digits of the object IDs used to name the subdirectories of `objects`
+pub const OBJECTS_DIR_FANOUT: usize = 2;
 
 pub struct Repository {
     backend: Arc<dyn StorageBackend>,
@@ -64,6 +63,17 @@ pub struct Repository {
 
     secure_storage: Arc<SecureStorage>,
 
+    // Format version of the repository, read from the manifest
+    version: RepoVersion,
+
+    // Key for keyed blob IDs. Version 1 repositories use plain hashes.
+    id_key: Option<SecretBox<Hash256>>,
+
+    // Blob compression settings. Initially read from the manifest.
+    compression: RwLock<CompressionSettings>,
+
+    chunker_params: ChunkerParams,
+
     // Packers.
     // By design, we pack blobs and trees separately so we can potentially cache trees
     // separately.
@@ -73,32 +83,19 @@ pub struct Repository {
     pack_saver: Arc<RwLock<Option<PackSaver>>>,
 
     index: Arc<RwLock<MasterIndex>>,
+
+    // Local cache of index files, snapshot files and tree packs
+    cache: Option<Cache>,
 }
 
 impl RepositoryBackend for Repository {
     /// Create and initialize a new repository
-    fn init(backend: Arc<dyn StorageBackend>, secure_storage: Arc<SecureStorage>) -> Result<()> {
-        let timestamp = Utc::now();
-
-        let repo_id = ID::new_random();
-
+    fn init(backend: Arc<dyn StorageBackend>, _secure_storage: Arc<SecureStorage>) -> Result<()> {
         // Init repository structure
         let objects_path = PathBuf::from(OBJECTS_DIR);
         let snapshot_path = PathBuf::from(SNAPSHOTS_DIR);
         let index_path = PathBuf::from(INDEX_DIR);
 
-        // Save new manifest
-        let manifest = Manifest {
-            version: REPO_VERSION,
-            id: repo_id.clone(),
-            created_time: timestamp,
-        };
-
-        let manifest_path = Path::new(MANIFEST_PATH);
-        let manifest = serde_json::to_string_pretty(&manifest)?;
-        let manifest = secure_storage.encode(manifest.as_bytes())?;
-        backend.write(manifest_path, &manifest)?;
-
         backend.create_dir(&objects_path)?;
         let num_folders: usize = 1 << (4 * OBJECTS_DIR_FANOUT);
         for n in 0x00..num_folders {
@@ -108,11 +105,6 @@ impl RepositoryBackend for Repository {
         backend.create_dir(&snapshot_path)?;
         backend.create_dir(&index_path)?;
 
-        ui::cli::log!(
-            "Created repo with id {}",
-            repo_id.to_short_hex(SHORT_REPO_ID_LEN)
-        );
-
         Ok(())
     }
 
@@ -120,36 +112,10 @@ impl RepositoryBackend for Repository {
     fn open(
         backend: Arc<dyn StorageBackend>,
         secure_storage: Arc<SecureStorage>,
+        manifest: Manifest,
     ) -> Result<Arc<Self>> {
-        let objects_path = PathBuf::from(OBJECTS_DIR);
-        let snapshot_path = PathBuf::from(SNAPSHOTS_DIR);
-        let index_path = PathBuf::from(INDEX_DIR);
-
-        // Packer defaults
-        let max_packer_size = MAX_PACK_SIZE;
-
-        let data_packer = Arc::new(RwLock::new(Packer::new()));
-        let tree_packer = Arc::new(RwLock::new(Packer::new()));
-
-        let index = Arc::new(RwLock::new(MasterIndex::new()));
-
-        let mut repo = Repository {
-            backend,
-            objects_path,
-            snapshot_path,
-            index_path,
-            keys_path: PathBuf::from(KEYS_DIR),
-            secure_storage,
-            max_packer_size,
-            data_packer,
-            tree_packer,
-            pack_saver: Arc::new(RwLock::new(None)),
-            index,
-        };
-
-        repo.load_master_index()?;
-
-        Ok(Arc::new(repo))
+        let cache = Cache::from_global_opts(&manifest.id);
+        Self::open_with_cache(backend, secure_storage, manifest, cache)
     }
 
     fn save_blob(
@@ -166,7 +132,7 @@ impl RepositoryBackend for Repository {
 
         let raw_size = data.len() as u64;
         let id = match save_id {
-            SaveID::CalculateID => ID::from_content(&data),
+            SaveID::CalculateID => self.blob_id(&data),
             SaveID::WithID(id) => id,
         };
 
@@ -179,10 +145,18 @@ impl RepositoryBackend for Repository {
             return Ok((id, (0, 0), (0, 0)));
         }
 
-        let data = self.secure_storage.encode(&data)?;
+        let mut compression = *self.compression.read();
+        if self.version < UNCOMPRESSED_BLOBS_VERSION {
+            // Older versions can't flag blobs stored uncompressed, so all blobs are compressed
+            compression.algorithm = CompressionAlgorithm::Zstd;
+            compression.auto = false;
+        }
+        let (data, uncompressed) = self.secure_storage.encode_blob(&data, &compression)?;
         let encoded_size = data.len() as u64;
 
-        packer.write().add_blob(id.clone(), object_type, data);
+        packer
+            .write()
+            .add_blob(id.clone(), object_type, data, uncompressed);
 
         // Flush if the packer is considered full
         let packer_meta_size = if packer.read().size() > self.max_packer_size {
@@ -194,16 +168,69 @@ impl RepositoryBackend for Repository {
         Ok((id, (raw_size, encoded_size), packer_meta_size))
     }
 
+    fn blob_id(&self, data: &[u8]) -> ID {
+        match &self.id_key {
+            Some(id_key) => ID::from_keyed_content(id_key.expose_secret(), data),
+            None => ID::from_content(data),
+        }
+    }
+
     fn load_blob(&self, id: &ID) -> Result<Vec<u8>> {
         let blob_entry = self.index.read().get(id);
         match blob_entry {
-            Some((pack_id, _blob_type, offset, length)) => {
-                self.load_from_pack(&pack_id, offset, length)
+            Some((pack_id, BlobType::Tree, offset, length, uncompressed))
+                if self.cache.is_some() =>
+            {
+                self.load_from_cached_pack(&pack_id, offset, length, uncompressed)
+            }
+            Some((pack_id, _blob_type, offset, length, uncompressed)) => {
+                self.load_from_pack(&pack_id, offset, length, uncompressed)
             }
             None => bail!("Could not find blob {:?} in index", id),
         }
     }
 
+    fn load_from_pack(
+        &self,
+        pack_id: &ID,
+        offset: u32,
+        length: u32,
+        uncompressed: bool,
+    ) -> Result<Vec<u8>> {
+        let object_path = Self::get_object_path(&self.objects_path, pack_id);
+        let data = self
+            .backend
+            .seek_read(&object_path, offset as u64, length as u64)?;
+        self.secure_storage.decode_blob(&data, uncompressed)
+    }
+
+    fn load_pack_header(&self, pack_id: &ID) -> Result<Vec<PackedBlobDescriptor>> {
+        let pack_path = Self::get_object_path(&self.objects_path, pack_id);
+        Packer::read_pack_header(self.backend.as_ref(), &self.secure_storage, &pack_path)
+    }
+
+    fn version(&self) -> RepoVersion {
+        self.version
+    }
+
+    fn compression(&self) -> CompressionSettings {
+        *self.compression.read()
+    }
+
+    fn set_compression(&self, compression: CompressionSettings) -> Result<()> {
+        compression.check_version(self.version)?;
+        *self.compression.write() = compression;
+        Ok(())
+    }
+
+    fn chunker_params(&self) -> ChunkerParams {
+        self.chunker_params
+    }
+
+    fn pack_size(&self) -> u64 {
+        self.max_packer_size
+    }
+
     fn save_file(
         &self,
         file_type: FileType,
@@ -213,6 +240,13 @@ impl RepositoryBackend for Repository {
         assert_ne!(file_type, FileType::Key);
         assert_ne!(file_type, FileType::Manifest);
 
+        // An index file must not be visible before the packs it references are stored
+        if file_type == FileType::Index
+            && let Some(pack_saver) = self.pack_saver.read().as_ref()
+        {
+            pack_saver.wait()?;
+        }
+
         let raw_size = data.len() as u64;
         let id = match save_id {
             SaveID::CalculateID => ID::from_content(data),
@@ -224,6 +258,7 @@ impl RepositoryBackend for Repository {
 
         let path = self.get_path(file_type, &id);
         self.save_with_rename(&path, &data)?;
+        self.save_to_cache(file_type, &id, &data);
 
         Ok((id, raw_size, encoded_size))
     }
@@ -232,14 +267,12 @@ impl RepositoryBackend for Repository {
         assert_ne!(file_type, FileType::Key);
         assert_ne!(file_type, FileType::Manifest);
 
-        let path = self.get_path(file_type, id);
-        let data = self.backend.read(&path)?;
-
         if file_type != FileType::Object {
-            return self.secure_storage.decode(&data);
+            return self.load_cached_file(file_type, id);
         }
 
-        Ok(data)
+        let path = self.get_path(file_type, id);
+        self.backend.read(&path)
     }
 
     fn delete_file(&self, file_type: FileType, id: &ID) -> Result<()> {
@@ -247,7 +280,11 @@ impl RepositoryBackend for Repository {
         assert_ne!(file_type, FileType::Manifest);
 
         let path = self.get_path(file_type, id);
-        self.backend.remove_file(&path)
+        self.backend.remove_file(&path)?;
+        if let Some(cache) = &self.cache {
+            cache.remove(file_type, id);
+        }
+        Ok(())
     }
 
     fn remove_snapshot(&self, id: &ID) -> Result<()> {
@@ -259,7 +296,11 @@ impl RepositoryBackend for Repository {
 
         self.backend
             .remove_file(&snapshot_path)
-            .with_context(|| format!("Could not remove snapshot {id}"))
+            .with_context(|| format!("Could not remove snapshot {id}"))?;
+        if let Some(cache) = &self.cache {
+            cache.remove(FileType::Snapshot, id);
+        }
+        Ok(())
     }
 
     fn load_snapshot(&self, id: &ID) -> Result<Snapshot> {
@@ -279,10 +320,10 @@ impl RepositoryBackend for Repository {
             .with_context(|| "Could not read snapshots")?;
 
         for path in paths {
-            if self.backend.is_file(&path) {
-                if let Some(file_name) = path.file_name().and_then(|s| s.to_str()) {
-                    ids.push(ID::from_hex(file_name)?);
-                }
+            if self.backend.is_file(&path)
+                && let Some(file_name) = path.file_name().and_then(|s| s.to_str())
+            {
+                ids.push(ID::from_hex(file_name)?);
             }
         }
 
@@ -309,8 +350,7 @@ impl RepositoryBackend for Repository {
         let index: Vec<u8> = self
             .load_file(FileType::Index, id)
             .with_context(|| format!("Could not load index {}", id.to_hex()))?;
-        let index = serde_json::from_slice(&index)?;
-        Ok(index)
+        IndexFile::decode(&index)
     }
 
     fn load_manifest(&self) -> Result<Manifest> {
@@ -384,9 +424,9 @@ impl RepositoryBackend for Repository {
             concurrency,
             Arc::new(move |data, id| {
                 let path = Self::get_object_path(&objects_path, &id);
-                if let Err(e) = backend.write(&path, &data) {
+                backend.write(&path, &data).inspect_err(|e| {
                     cli::error!("Could not save pack {}: {}", id.to_hex(), e);
-                }
+                })
             }),
         );
         self.pack_saver.write().replace(pack_saver);
@@ -446,6 +486,88 @@ impl Drop for Repository {
 }
 
 impl Repository {
+    /// Opens an existing repository skipping the index files that cannot be loaded.
+    /// Returns the IDs of the skipped index files. This is only meant to be used to repair
+    /// the index, since the blobs listed in the skipped files are not visible.
+    pub fn open_skipping_broken_indices(
+        backend: Arc<dyn StorageBackend>,
+        secure_storage: Arc<SecureStorage>,
+        manifest: Manifest,
+    ) -> Result<(Arc<Self>, Vec<ID>)> {
+        let mut repo = Self::new(backend, secure_storage, manifest, None)?;
+        let broken_indices = repo.load_master_index(true)?;
+        Ok((Arc::new(repo), broken_indices))
+    }
+
+    /// Opens an existing repository with an optional local cache. Cached files that no longer
+    /// exist in the repository are removed from the cache.
+    pub fn open_with_cache(
+        backend: Arc<dyn StorageBackend>,
+        secure_storage: Arc<SecureStorage>,
+        manifest: Manifest,
+        cache: Option<Cache>,
+    ) -> Result<Arc<Self>> {
+        let mut repo = Self::new(backend, secure_storage, manifest, cache)?;
+        repo.load_master_index(false)?;
+        if let Err(e) = repo.validate_cache() {
+            ui::cli::warning!("Could not validate the cache: {}", e);
+        }
+        Ok(Arc::new(repo))
+    }
+
+    /// Creates the repository object without loading the index
+    fn new(
+        backend: Arc<dyn StorageBackend>,
+        secure_storage: Arc<SecureStorage>,
+        manifest: Manifest,
+        cache: Option<Cache>,
+    ) -> Result<Self> {
+        let id_key = match (manifest.version, &manifest.id_key) {
+            (1, _) => None,
+            (_, Some(id_key)) => {
+                let id_key: Hash256 = general_purpose::STANDARD
+                    .decode(id_key)
+                    .with_context(|| "Could not decode the ID key")?
+                    .try_into()
+                    .map_err(|_| anyhow!("Invalid ID key length"))?;
+                Some(SecretBox::new(Box::new(id_key)))
+            }
+            (version, None) => bail!("Repository version {} requires an ID key", version),
+        };
+
+        let objects_path = PathBuf::from(OBJECTS_DIR);
+        let snapshot_path = PathBuf::from(SNAPSHOTS_DIR);
+        let index_path = PathBuf::from(INDEX_DIR);
+
+        let max_packer_size = manifest.pack_size;
+
+        let data_packer = Arc::new(RwLock::new(Packer::new()));
+        let tree_packer = Arc::new(RwLock::new(Packer::new()));
+
+        let index = Arc::new(RwLock::new(MasterIndex::new()));
+
+        let repo = Repository {
+            backend,
+            objects_path,
+            snapshot_path,
+            index_path,
+            keys_path: PathBuf::from(KEYS_DIR),
+            secure_storage,
+            version: manifest.version,
+            id_key,
+            compression: RwLock::new(manifest.compression),
+            chunker_params: manifest.chunker,
+            max_packer_size,
+            data_packer,
+            tree_packer,
+            pack_saver: Arc::new(RwLock::new(None)),
+            index,
+            cache,
+        };
+
+        Ok(repo)
+    }
+
     /// Returns the path to an object with a given hash in the repository.
     fn get_object_path(objects_path: &Path, id: &ID) -> PathBuf {
         let id_hex = id.to_hex();
@@ -522,9 +644,13 @@ impl Repository {
         }
     }
 
-    fn load_master_index(&mut self) -> Result<()> {
+    /// Loads all index files into the master index. If `skip_broken` is set, index files
+    /// that cannot be loaded are skipped and their IDs are returned. Otherwise, any broken
+    /// index file is an error.
+    fn load_master_index(&mut self, skip_broken: bool) -> Result<Vec<ID>> {
         let files = self.backend.read_dir(&self.index_path)?;
-        let num_index_files = files.len();
+        let mut num_index_files = 0;
+        let mut broken_indices = Vec::new();
 
         for file in files {
             let file_name = file
@@ -532,31 +658,214 @@ impl Repository {
                 .expect("Could not read index file name")
                 .to_string_lossy()
                 .clone();
-            let id = ID::from_hex(&file_name)?;
-            let index_file = self.backend.read(&file)?;
-            let index_file = self.secure_storage.decode(&index_file)?;
-            let index_file = serde_json::from_slice(&index_file)?;
+            let id = match ID::from_hex(&file_name) {
+                Ok(id) => id,
+                Err(e) if skip_broken => {
+                    ui::cli::warning!("Skipping file {} in the index folder: {}", file_name, e);
+                    continue;
+                }
+                Err(e) => {
+                    return Err(e.context(format!("Invalid index file name {file_name}")));
+                }
+            };
 
-            let mut index = Index::from_index_file(index_file);
-            index.finalize();
-            index.set_id(id);
+            match self.load_index_file(&id) {
+                Ok(index_file) => {
+                    let mut index = Index::from_index_file(index_file);
+                    index.finalize();
+                    index.set_id(id);
 
-            self.index.write().add_index(index);
+                    self.index.write().add_index(index);
+                    num_index_files += 1;
+                }
+                Err(e) if skip_broken => {
+                    ui::cli::warning!("Skipping index {}: {}", file_name, e);
+                    broken_indices.push(id);
+                }
+                Err(e) => {
+                    return Err(e.context(format!("Could not load index {file_name}")));
+                }
+            }
         }
 
         ui::cli::verbose_1!("Loaded {} index files", num_index_files);
 
-        Ok(())
+        Ok(broken_indices)
     }
 
-    fn load_from_pack(&self, id: &ID, offset: u32, length: u32) -> Result<Vec<u8>> {
-        let object_path = Self::get_object_path(&self.objects_path, id);
-        let data = self
+    fn load_index_file(&self, id: &ID) -> Result<IndexFile> {
+        let index_file = self.load_cached_file(FileType::Index, id)?;
+        IndexFile::decode(&index_file)
+    }
+
+    /// Loads and decodes a file, reading it from the cache if possible.
+    /// Files read from the backend are added to the cache.
+    fn load_cached_file(&self, file_type: FileType, id: &ID) -> Result<Vec<u8>> {
+        if let Some(data) = self.cache.as_ref().and_then(|c| c.load(file_type, id)) {
+            match self.secure_storage.decode(&data) {
+                Ok(decoded) => return Ok(decoded),
+                // Discard damaged cache files and read them again from the backend
+                Err(_) => self.cache.as_ref().unwrap().remove(file_type, id),
+            }
+        }
+
+        let data = self.backend.read(&self.get_path(file_type, id))?;
+        let decoded = self.secure_storage.decode(&data)?;
+        self.save_to_cache(file_type, id, &data);
+        Ok(decoded)
+    }
+
+    /// Loads a blob from a pack, reading the whole pack from the backend and adding it to
+    /// the cache if it is not cached.
+    fn load_from_cached_pack(
+        &self,
+        pack_id: &ID,
+        offset: u32,
+        length: u32,
+        uncompressed: bool,
+    ) -> Result<Vec<u8>> {
+        if let Some(data) = self
+            .cache
+            .as_ref()
+            .and_then(|c| c.load_range(FileType::Object, pack_id, offset as u64, length as u64))
+        {
+            match self.secure_storage.decode_blob(&data, uncompressed) {
+                Ok(blob) => return Ok(blob),
+                Err(_) => self
+                    .cache
+                    .as_ref()
+                    .unwrap()
+                    .remove(FileType::Object, pack_id),
+            }
+        }
+
+        let object_path = Self::get_object_path(&self.objects_path, pack_id);
+        let pack = self.backend.read(&object_path)?;
+        let range = offset as usize..(offset as usize + length as usize);
+        let data = pack
+            .get(range)
+            .ok_or_else(|| anyhow!("Blob out of bounds in pack {}", pack_id))?;
+        let blob = self.secure_storage.decode_blob(data, uncompressed)?;
+        if utils::calculate_hash(&pack) == pack_id.0[..] {
+            self.save_to_cache(FileType::Object, pack_id, &pack);
+        }
+        Ok(blob)
+    }
+
+    /// Adds a file to the cache. Errors are not fatal, since the file is in the backend.
+    fn save_to_cache(&self, file_type: FileType, id: &ID, data: &[u8]) {
+        if let Some(cache) = &self.cache
+            && let Err(e) = cache.save(file_type, id, data)
+        {
+            ui::cli::verbose_1!("Could not cache {}: {}", id, e);
+        }
+    }
+
+    /// Removes cached files that no longer exist in the repository
+    fn validate_cache(&self) -> Result<()> {
+        let Some(cache) = &self.cache else {
+            return Ok(());
+        };
+
+        let index_ids = self.index.read().ids();
+        let pack_ids: BTreeSet<ID> = self
+            .index
+            .read()
+            .iter_ids()
+            .map(|(_, locator)| locator.pack_id)
+            .collect();
+        let snapshot_ids: BTreeSet<ID> = self
             .backend
-            .seek_read(&object_path, offset as u64, length as u64)?;
-        self.secure_storage.decode(&data)
+            .read_dir(&self.snapshot_path)?
+            .iter()
+            .filter_map(|path| path.file_name()?.to_str())
+            .filter_map(|name| ID::from_hex(name).ok())
+            .collect();
+
+        cache.retain(FileType::Index, &index_ids)?;
+        cache.retain(FileType::Object, &pack_ids)?;
+        cache.retain(FileType::Snapshot, &snapshot_ids)?;
+
+        Ok(())
     }
 }
 
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use tempfile::tempdir;
+
+    use crate::{
+        backend::localfs::LocalFS,
+        repository::{self, tree::Tree, unlock_secure_storage},
+    };
+
+    use super::*;
+
+    /// Test that index, snapshot and tree pack files are cached and that the cache is
+    /// validated against the backend
+    #[test]
+    fn test_cache() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let password = Some(String::from("mapachito"));
+        let repo_path = temp_dir.path().join("repo");
+        let cache_path = temp_dir.path().join("cache");
+        let backend: Arc<dyn StorageBackend> = Arc::new(LocalFS::new(repo_path.clone()));
+        repository::init(password.clone(), None, backend.clone())?;
+
+        let secure_storage = unlock_secure_storage(password, None, backend.clone())?;
+        let manifest = repository::read_manifest(backend.as_ref(), &secure_storage)?;
+        let open = || {
+            let cache = Cache::open(&cache_path, &manifest.id)?;
+            Repository::open_with_cache(
+                backend.clone(),
+                secure_storage.clone(),
+                manifest.clone(),
+                Some(cache),
+            )
+        };
+
+        let repo = open()?;
+        repo.init_pack_saver(1);
+        let (tree_id, _) = Tree::default().save_to_repo(repo.as_ref())?;
+        repo.flush()?;
+        repo.finalize_pack_saver();
+        let (snapshot_id, _, _) =
+            repo.save_file(FileType::Snapshot, b"mapache", SaveID::CalculateID)?;
+        let (pack_id, _, _, _, _) = repo.index().read().get(&tree_id).unwrap();
+        drop(repo);
+
+        // Load everything through the cache
+        let repo = open()?;
+        assert_eq!(
+            repo.load_file(FileType::Snapshot, &snapshot_id)?,
+            b"mapache"
+        );
+        repo.load_blob(&tree_id)?;
+        drop(repo);
+
+        let cache = Cache::open(&cache_path, &manifest.id)?;
+        assert_eq!(cache.list(FileType::Index)?.len(), 1);
+        assert_eq!(cache.list(FileType::Snapshot)?, vec![snapshot_id.clone()]);
+        assert_eq!(cache.list(FileType::Object)?, vec![pack_id]);
+
+        // Cached files are used
+        std::fs::write(
+            repo_path.join(SNAPSHOTS_DIR).join(snapshot_id.to_hex()),
+            b"damaged",
+        )?;
+        let repo = open()?;
+        assert_eq!(
+            repo.load_file(FileType::Snapshot, &snapshot_id)?,
+            b"mapache"
+        );
+        drop(repo);
+
+        // Files removed from the repository are removed from the cache
+        std::fs::remove_file(repo_path.join(SNAPSHOTS_DIR).join(snapshot_id.to_hex()))?;
+        let repo = open()?;
+        assert!(cache.list(FileType::Snapshot)?.is_empty());
+        assert!(repo.load_file(FileType::Snapshot, &snapshot_id).is_err());
+
+        Ok(())
+    }
+}
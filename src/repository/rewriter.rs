@@ -0,0 +1,167 @@
+// mapache is an incremental backup tool
+// Copyright (C) 2025  Javier Lancha Vázquez <javier.lancha@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::{BTreeMap, HashMap};
+
+use anyhow::{Context, Result};
+
+use crate::global::{BlobType, FileType, ID, SaveID, defaults::DEFAULT_WRITE_CONCURRENCY};
+
+use super::{RepositoryBackend, snapshot::Snapshot, tree::Tree};
+
+/// Rewrites the blobs and trees of snapshots from a source repository into a destination
+/// repository. Source and destination can be the same repository object.
+///
+/// By default, all blobs are read and saved with the IDs calculated by the destination.
+/// Rewriting is deterministic, so rewriting an already rewritten tree yields the same ID.
+/// If both repositories calculate the same IDs, the IDs can be kept. In that case, blobs and
+/// whole subtrees already present in the destination are not read again.
+pub struct BlobRewriter<'a> {
+    src: &'a dyn RepositoryBackend,
+    dst: &'a dyn RepositoryBackend,
+    keep_ids: bool,
+
+    // Old ID -> new ID
+    blobs: HashMap<ID, ID>,
+    trees: HashMap<ID, ID>,
+
+    /// Raw and encoded bytes added to the destination
+    pub added_size: (u64, u64),
+}
+
+impl<'a> BlobRewriter<'a> {
+    pub fn new(
+        src: &'a dyn RepositoryBackend,
+        dst: &'a dyn RepositoryBackend,
+        keep_ids: bool,
+    ) -> Self {
+        Self {
+            src,
+            dst,
+            keep_ids,
+            blobs: HashMap::new(),
+            trees: HashMap::new(),
+            added_size: (0, 0),
+        }
+    }
+
+    /// Rewrites the tree of a snapshot and saves the snapshot in the destination.
+    /// The parent is replaced if it is found in `rewritten_snapshots` (old ID -> new ID).
+    /// Returns the new snapshot ID.
+    pub fn rewrite_snapshot(
+        &mut self,
+        snapshot: &mut Snapshot,
+        rewritten_snapshots: &BTreeMap<ID, ID>,
+    ) -> Result<ID> {
+        self.dst.init_pack_saver(DEFAULT_WRITE_CONCURRENCY);
+        let tree = self.rewrite_tree(&snapshot.tree);
+        let flushed = tree.and_then(|tree| {
+            let (raw, encoded) = self.dst.flush()?;
+            self.add_size((raw, encoded));
+            Ok(tree)
+        });
+        self.dst.finalize_pack_saver();
+        snapshot.tree = flushed?;
+
+        if let Some(new_parent) = snapshot
+            .parent
+            .as_ref()
+            .and_then(|parent| rewritten_snapshots.get(parent))
+        {
+            snapshot.parent = Some(new_parent.clone());
+        }
+
+        let (new_id, raw, encoded) = self.dst.save_file(
+            FileType::Snapshot,
+            serde_json::to_string(&snapshot)?.as_bytes(),
+            SaveID::CalculateID,
+        )?;
+        self.add_size((raw, encoded));
+
+        Ok(new_id)
+    }
+
+    fn rewrite_tree(&mut self, id: &ID) -> Result<ID> {
+        if let Some(new_id) = self.trees.get(id) {
+            return Ok(new_id.clone());
+        }
+        if self.keep_ids && self.dst.index().read().contains(id) {
+            return Ok(id.clone());
+        }
+
+        let tree_data = self
+            .src
+            .load_blob(id)
+            .with_context(|| format!("Could not load tree {}", id.to_hex()))?;
+        let mut tree: Tree = serde_json::from_slice(&tree_data)?;
+        for node in tree.nodes.iter_mut() {
+            if let Some(blobs) = node.blobs.as_mut() {
+                for blob_id in blobs.iter_mut() {
+                    *blob_id = self.rewrite_blob(blob_id)?;
+                }
+            }
+            if let Some(subtree_id) = node.tree.as_mut() {
+                *subtree_id = self.rewrite_tree(subtree_id)?;
+            }
+        }
+
+        let new_id = if self.keep_ids {
+            // Keep the original bytes so the tree is an exact copy
+            let (_, data_size, meta_size) =
+                self.dst
+                    .save_blob(BlobType::Tree, tree_data, SaveID::WithID(id.clone()))?;
+            self.add_size(data_size);
+            self.add_size(meta_size);
+            id.clone()
+        } else {
+            let (new_id, size) = tree.save_to_repo(self.dst)?;
+            self.add_size(size);
+            new_id
+        };
+
+        self.trees.insert(id.clone(), new_id.clone());
+        Ok(new_id)
+    }
+
+    fn rewrite_blob(&mut self, id: &ID) -> Result<ID> {
+        if let Some(new_id) = self.blobs.get(id) {
+            return Ok(new_id.clone());
+        }
+        if self.keep_ids && self.dst.index().read().contains(id) {
+            return Ok(id.clone());
+        }
+
+        let data = self
+            .src
+            .load_blob(id)
+            .with_context(|| format!("Could not load blob {}", id.to_hex()))?;
+        let save_id = match self.keep_ids {
+            true => SaveID::WithID(id.clone()),
+            false => SaveID::CalculateID,
+        };
+        let (new_id, data_size, meta_size) = self.dst.save_blob(BlobType::Data, data, save_id)?;
+        self.add_size(data_size);
+        self.add_size(meta_size);
+
+        self.blobs.insert(id.clone(), new_id.clone());
+        Ok(new_id)
+    }
+
+    fn add_size(&mut self, (raw, encoded): (u64, u64)) {
+        self.added_size.0 += raw;
+        self.added_size.1 += encoded;
+    }
+}
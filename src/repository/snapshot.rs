@@ -56,6 +56,18 @@ pub struct Snapshot {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub description: Option<String>,
 
+    /// Name of the host where the snapshot was created
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+
+    /// Name of the user who created the snapshot
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+
+    /// Version of mapache that created the snapshot
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub program_version: Option<String>,
+
     /// Summary of the Snapshot.
     pub summary: SnapshotSummary,
 }
@@ -78,6 +90,12 @@ impl Snapshot {
         }
         false
     }
+
+    /// Returns true if the snapshot was created in a host. Snapshots without a hostname
+    /// don't match any host.
+    pub fn has_host(&self, host: &str) -> bool {
+        self.hostname.as_deref() == Some(host)
+    }
 }
 
 #[derive(Debug, Default, Clone, Serialize, Deserialize)]
@@ -139,6 +157,13 @@ pub struct SnapshotSummary {
     pub total_raw_bytes: u64,     // Total raw bytes
     pub total_encoded_bytes: u64, // Total bytes after encoding
 
+    /// Local time at which the snapshot started
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub start_time: Option<DateTime<Local>>,
+    /// Time spent creating the snapshot, in seconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration_secs: Option<f64>,
+
     #[serde(flatten)]
     pub diff_counts: DiffCounts,
 }
@@ -172,17 +197,27 @@ impl SnapshotStreamer {
 
     /// Consumes the iterator and returns the Snapshot with the latest ID.
     pub fn latest(&mut self) -> Option<(ID, Snapshot)> {
-        let (mut latest_id, mut latest_sn) = self.next()?;
+        self.latest_matching(|_| true)
+    }
 
-        for (mut id, mut snapshot) in self.by_ref() {
-            if snapshot.timestamp > latest_sn.timestamp {
-                std::mem::swap(&mut id, &mut latest_id);
-                std::mem::swap(&mut snapshot, &mut latest_sn);
+    /// Consumes the iterator and returns the latest Snapshot accepted by a filter.
+    pub fn latest_matching<F>(&mut self, filter: F) -> Option<(ID, Snapshot)>
+    where
+        F: Fn(&Snapshot) -> bool,
+    {
+        let mut latest: Option<(ID, Snapshot)> = None;
+
+        for (id, snapshot) in self.by_ref().filter(|(_, snapshot)| filter(snapshot)) {
+            if latest
+                .as_ref()
+                .is_none_or(|(_, latest_sn)| snapshot.timestamp > latest_sn.timestamp)
+            {
+                latest = Some((id, snapshot));
             }
         }
 
         self.snapshot_ids.clear();
-        Some((latest_id, latest_sn))
+        latest
     }
 }
 
@@ -0,0 +1,413 @@
+// mapache is an incremental backup tool
+// Copyright (C) 2025  Javier Lancha Vázquez <javier.lancha@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    path::PathBuf,
+    sync::Arc,
+};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+use indicatif::{ProgressBar, ProgressStyle};
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use serde::Serialize;
+
+use crate::{
+    global::{BlobType, ID},
+    ui::default_bar_draw_target,
+};
+
+use super::{RepositoryBackend, snapshot::SnapshotStreamer, streamers::SerializedNodeStreamer};
+
+/// Repository statistics
+#[derive(Debug, Default, Serialize)]
+pub struct RepositoryStats {
+    /// Statistics of each snapshot, sorted by timestamp
+    pub snapshots: Vec<SnapshotStats>,
+
+    /// Size of all snapshots if they were restored
+    pub restore_size: u64,
+
+    /// Blobs referenced by any snapshot. Each blob is counted once.
+    pub blobs: BlobStats,
+
+    /// Pack statistics
+    pub packs: PackStats,
+}
+
+/// Statistics of a single snapshot
+#[derive(Debug, Serialize)]
+pub struct SnapshotStats {
+    pub id: ID,
+    pub timestamp: DateTime<Local>,
+
+    /// Number of files in the snapshot
+    pub files: u64,
+
+    /// Size of the snapshot if it was restored
+    pub restore_size: u64,
+
+    /// Number of blobs referenced by the snapshot
+    pub blobs: usize,
+
+    /// Number of blobs only referenced by this snapshot
+    pub exclusive_blobs: usize,
+
+    /// Stored size of the blobs only referenced by this snapshot.
+    /// This is roughly the size freed by forgetting the snapshot and cleaning the repository.
+    pub exclusive_size: u64,
+}
+
+/// Statistics of the blobs referenced by snapshots
+#[derive(Debug, Default, Serialize)]
+pub struct BlobStats {
+    pub data_blobs: usize,
+    pub tree_blobs: usize,
+
+    /// Size of the data blobs in the repository (compressed and encrypted)
+    pub data_size: u64,
+    /// Size of the tree blobs in the repository (compressed and encrypted)
+    pub tree_size: u64,
+
+    /// Size of the data blobs before compression. Only calculated if the blobs are read.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub raw_data_size: Option<u64>,
+    /// Size of the tree blobs before compression. Only calculated if the blobs are read.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub raw_tree_size: Option<u64>,
+
+    /// Referenced blobs not found in the index
+    pub missing_blobs: usize,
+}
+
+/// Statistics of the pack files
+#[derive(Debug, Default, Serialize)]
+pub struct PackStats {
+    /// Number of packs listed in the index
+    pub packs: usize,
+
+    /// Number of pack files not listed in the index
+    pub unindexed_packs: usize,
+
+    /// Target pack size
+    pub target_pack_size: u64,
+
+    /// Size of all blobs listed in the index
+    pub stored_size: u64,
+
+    /// Blobs listed in the index but not referenced by any snapshot
+    pub garbage_blobs: usize,
+
+    /// Size of the garbage blobs
+    pub garbage_size: u64,
+
+    /// Number of packs containing garbage blobs
+    pub packs_with_garbage: usize,
+}
+
+impl RepositoryStats {
+    /// Restore size divided by the stored size of the data blobs. This ratio includes the effect
+    /// of deduplication and compression.
+    pub fn storage_ratio(&self) -> Option<f64> {
+        ratio(self.restore_size, self.blobs.data_size)
+    }
+
+    /// Restore size divided by the raw size of the data blobs
+    pub fn dedup_ratio(&self) -> Option<f64> {
+        ratio(self.restore_size, self.blobs.raw_data_size?)
+    }
+
+    /// Raw size of the data blobs divided by their stored size
+    pub fn compression_ratio(&self) -> Option<f64> {
+        ratio(self.blobs.raw_data_size?, self.blobs.data_size)
+    }
+}
+
+impl PackStats {
+    /// Average fill of the packs relative to the target pack size
+    pub fn average_fill(&self) -> Option<f64> {
+        ratio(self.stored_size, self.packs as u64 * self.target_pack_size)
+    }
+}
+
+fn ratio(num: u64, den: u64) -> Option<f64> {
+    (den > 0).then(|| num as f64 / den as f64)
+}
+
+/// Number of snapshots referencing a blob and the first of them
+struct BlobRefs {
+    count: usize,
+    snapshot_index: usize,
+}
+
+/// Walks all snapshots and the index to collect statistics.
+/// If `read_data` is true, all referenced blobs are read to calculate their raw size.
+pub fn collect(repo: Arc<dyn RepositoryBackend>, read_data: bool) -> Result<RepositoryStats> {
+    let mut snapshots: Vec<_> = SnapshotStreamer::new(repo.clone())?.collect();
+    snapshots.sort_by_key(|(_, snapshot)| snapshot.timestamp);
+
+    let mut stats = RepositoryStats::default();
+    let mut blob_refs: HashMap<ID, BlobRefs> = HashMap::new();
+
+    let bar =
+        ProgressBar::with_draw_target(Some(snapshots.len() as u64), default_bar_draw_target())
+            .with_style(
+                ProgressStyle::default_bar()
+                    .template("[{bar:25.cyan/white}] Walking snapshots: {pos}/{len}")
+                    .unwrap()
+                    .progress_chars("=> "),
+            );
+
+    for (i, (id, snapshot)) in snapshots.into_iter().enumerate() {
+        let mut snapshot_blobs = HashSet::new();
+        snapshot_blobs.insert(snapshot.tree.clone());
+        let mut files = 0;
+        let mut restore_size = 0;
+
+        let node_streamer = SerializedNodeStreamer::new(
+            repo.clone(),
+            Some(snapshot.tree.clone()),
+            PathBuf::new(),
+            None,
+            None,
+        )?;
+        for node_res in node_streamer {
+            let (_, stream_node) =
+                node_res.with_context(|| format!("Could not walk snapshot {}", id.to_hex()))?;
+            let node = stream_node.node;
+
+            if node.is_file() {
+                files += 1;
+                restore_size += node.metadata.size;
+            }
+            if let Some(tree) = node.tree {
+                snapshot_blobs.insert(tree);
+            }
+            if let Some(blobs) = node.blobs {
+                snapshot_blobs.extend(blobs);
+            }
+        }
+
+        for blob_id in &snapshot_blobs {
+            blob_refs
+                .entry(blob_id.clone())
+                .and_modify(|refs| refs.count += 1)
+                .or_insert(BlobRefs {
+                    count: 1,
+                    snapshot_index: i,
+                });
+        }
+
+        stats.restore_size += restore_size;
+        stats.snapshots.push(SnapshotStats {
+            id,
+            timestamp: snapshot.timestamp,
+            files,
+            restore_size,
+            blobs: snapshot_blobs.len(),
+            exclusive_blobs: 0,
+            exclusive_size: 0,
+        });
+        bar.inc(1);
+    }
+    bar.finish_and_clear();
+
+    // Referenced blobs
+    let index = repo.index();
+    let mut data_blobs = Vec::new();
+    let mut tree_blobs = Vec::new();
+    for (blob_id, refs) in &blob_refs {
+        let Some((_, blob_type, _, length, _)) = index.read().get(blob_id) else {
+            stats.blobs.missing_blobs += 1;
+            continue;
+        };
+        let length = length as u64;
+
+        match blob_type {
+            BlobType::Data => {
+                stats.blobs.data_blobs += 1;
+                stats.blobs.data_size += length;
+                data_blobs.push(blob_id.clone());
+            }
+            BlobType::Tree => {
+                stats.blobs.tree_blobs += 1;
+                stats.blobs.tree_size += length;
+                tree_blobs.push(blob_id.clone());
+            }
+            BlobType::Padding => continue,
+        }
+
+        if refs.count == 1 {
+            let snapshot_stats = &mut stats.snapshots[refs.snapshot_index];
+            snapshot_stats.exclusive_blobs += 1;
+            snapshot_stats.exclusive_size += length;
+        }
+    }
+
+    if read_data {
+        stats.blobs.raw_data_size = Some(raw_size(repo.as_ref(), data_blobs)?);
+        stats.blobs.raw_tree_size = Some(raw_size(repo.as_ref(), tree_blobs)?);
+    }
+
+    // Packs
+    let mut indexed_packs = BTreeSet::new();
+    let mut garbage_packs = BTreeSet::new();
+    for (blob_id, locator) in index.read().iter_ids() {
+        stats.packs.stored_size += locator.length as u64;
+        if !blob_refs.contains_key(blob_id) {
+            stats.packs.garbage_blobs += 1;
+            stats.packs.garbage_size += locator.length as u64;
+            garbage_packs.insert(locator.pack_id.clone());
+        }
+        indexed_packs.insert(locator.pack_id);
+    }
+    stats.packs.packs = indexed_packs.len();
+    stats.packs.packs_with_garbage = garbage_packs.len();
+    stats.packs.unindexed_packs = repo
+        .list_objects()?
+        .iter()
+        .filter(|id| !indexed_packs.contains(id))
+        .count();
+    stats.packs.target_pack_size = repo.pack_size();
+
+    Ok(stats)
+}
+
+/// Reads blobs in parallel and returns their total raw size
+fn raw_size(repo: &dyn RepositoryBackend, ids: Vec<ID>) -> Result<u64> {
+    let bar = ProgressBar::with_draw_target(Some(ids.len() as u64), default_bar_draw_target())
+        .with_style(
+            ProgressStyle::default_bar()
+                .template("[{bar:25.cyan/white}] Reading blobs: {pos}/{len}")
+                .unwrap()
+                .progress_chars("=> "),
+        );
+
+    let sizes = ids
+        .into_par_iter()
+        .map(|id| {
+            let size = repo
+                .load_blob(&id)
+                .map(|data| data.len() as u64)
+                .with_context(|| format!("Could not read blob {}", id.to_hex()));
+            bar.inc(1);
+            size
+        })
+        .collect::<Result<Vec<u64>>>()?;
+    bar.finish_and_clear();
+
+    Ok(sizes.into_iter().sum())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use chrono::Local;
+    use tempfile::tempdir;
+
+    use crate::{
+        backend::{StorageBackend, localfs::LocalFS},
+        global::{FileType, SaveID},
+        repository::{
+            self,
+            snapshot::{Snapshot, SnapshotSummary},
+            tree::{Metadata, Node, NodeType, Tree},
+            try_open,
+        },
+    };
+
+    use super::*;
+
+    fn save_snapshot(repo: &dyn RepositoryBackend, files: &[(&str, &[u8])]) -> Result<()> {
+        let mut tree = Tree::default();
+        for (name, data) in files {
+            let (blob_id, _, _) =
+                repo.save_blob(BlobType::Data, data.to_vec(), SaveID::CalculateID)?;
+            tree.add_node(Node {
+                name: name.to_string(),
+                node_type: NodeType::File,
+                metadata: Metadata {
+                    size: data.len() as u64,
+                    ..Default::default()
+                },
+                symlink_info: None,
+                blobs: Some(vec![blob_id]),
+                tree: None,
+            });
+        }
+        let (tree_id, _) = tree.save_to_repo(repo)?;
+
+        let snapshot = Snapshot {
+            timestamp: Local::now(),
+            parent: None,
+            tree: tree_id,
+            root: PathBuf::from("/"),
+            paths: Vec::new(),
+            tags: BTreeSet::new(),
+            description: None,
+            hostname: None,
+            username: None,
+            program_version: None,
+            summary: SnapshotSummary::default(),
+        };
+        repo.save_file(
+            FileType::Snapshot,
+            serde_json::to_string(&snapshot)?.as_bytes(),
+            SaveID::CalculateID,
+        )?;
+        Ok(())
+    }
+
+    /// Test that shared blobs are not counted as exclusive
+    #[test]
+    fn test_collect_stats() -> Result<()> {
+        let temp_dir = tempdir()?;
+        let password = Some(String::from("mapachito"));
+        let backend: Arc<dyn StorageBackend> = Arc::new(LocalFS::new(temp_dir.path().join("repo")));
+        repository::init(password.clone(), None, backend.clone())?;
+
+        let (repo, _) = try_open(password, None, backend)?;
+        repo.init_pack_saver(1);
+        save_snapshot(repo.as_ref(), &[("a", b"mapache"), ("b", b"shared")])?;
+        save_snapshot(repo.as_ref(), &[("b", b"shared"), ("c", b"mapachito")])?;
+        repo.flush()?;
+        repo.finalize_pack_saver();
+
+        let stats = collect(repo.clone(), true)?;
+
+        assert_eq!(stats.snapshots.len(), 2);
+        assert_eq!(stats.restore_size, 7 + 6 + 6 + 9);
+        assert_eq!(stats.blobs.data_blobs, 3);
+        assert_eq!(stats.blobs.tree_blobs, 2);
+        assert_eq!(stats.blobs.missing_blobs, 0);
+        assert_eq!(stats.blobs.raw_data_size, Some(7 + 6 + 9));
+        for snapshot in &stats.snapshots {
+            assert_eq!(snapshot.files, 2);
+            assert_eq!(snapshot.blobs, 3);
+            // Its own tree and one data blob
+            assert_eq!(snapshot.exclusive_blobs, 2);
+        }
+        assert_eq!(stats.packs.garbage_blobs, 0);
+        assert_eq!(
+            stats.packs.stored_size,
+            stats.blobs.data_size + stats.blobs.tree_size
+        );
+
+        Ok(())
+    }
+}
@@ -15,21 +15,97 @@
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
 use aes_gcm_siv::{Aes256GcmSiv, Key as AesKey, KeyInit, Nonce, aead::Aead};
-use anyhow::{Result, bail};
-use argon2::Argon2;
+use anyhow::{Result, anyhow, bail};
+use argon2::{Argon2, Params, Version};
 use rand::TryRngCore;
 use rand::rngs::OsRng;
 use secrecy::zeroize::Zeroize;
 use secrecy::{ExposeSecret, SecretBox};
+use serde::{Deserialize, Serialize};
 use std::io::{Read, Write};
 use zstd::stream::read::Decoder as ZstdDecoder;
 use zstd::stream::write::Encoder as ZstdEncoder;
 
-use crate::global;
+use crate::{
+    global,
+    repository::{
+        RepoVersion, UNCOMPRESSED_BLOBS_VERSION,
+        keys::{KdfAlgorithm, KdfParams},
+    },
+};
 
 const AES_GCM_NONCE_LEN: usize = 12;
 const ZSTD_WINDOW_LOG: u32 = global::defaults::AVG_CHUNK_SIZE.ilog2();
 
+/// Compression algorithms for blob data
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum CompressionAlgorithm {
+    /// Blobs are stored uncompressed
+    None,
+    /// Zstandard compression
+    Zstd,
+}
+
+impl std::fmt::Display for CompressionAlgorithm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CompressionAlgorithm::None => write!(f, "none"),
+            CompressionAlgorithm::Zstd => write!(f, "zstd"),
+        }
+    }
+}
+
+/// Compression settings for blob data. Metadata files (index, snapshots, pack headers...)
+/// are always compressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CompressionSettings {
+    pub algorithm: CompressionAlgorithm,
+    pub level: i32,
+
+    /// Store a blob uncompressed if compressing it does not reduce its size.
+    pub auto: bool,
+}
+
+impl Default for CompressionSettings {
+    fn default() -> Self {
+        Self {
+            algorithm: CompressionAlgorithm::Zstd,
+            level: zstd::DEFAULT_COMPRESSION_LEVEL,
+            auto: true,
+        }
+    }
+}
+
+impl CompressionSettings {
+    /// Checks that the compression level is valid for the algorithm
+    pub fn validate(&self) -> Result<()> {
+        if self.algorithm == CompressionAlgorithm::Zstd
+            && !zstd::compression_level_range().contains(&self.level)
+        {
+            bail!(
+                "Invalid zstd compression level {} (valid range: {:?})",
+                self.level,
+                zstd::compression_level_range()
+            );
+        }
+        Ok(())
+    }
+
+    /// Checks that a repository version can store blobs with these settings. Older versions
+    /// compress all blobs and ignore `auto`, since they can't flag blobs stored uncompressed.
+    pub fn check_version(&self, version: RepoVersion) -> Result<()> {
+        if self.algorithm == CompressionAlgorithm::None && version < UNCOMPRESSED_BLOBS_VERSION {
+            bail!(
+                "Repository version {} cannot store blobs uncompressed. Upgrade it to version {} with the migrate command.",
+                version,
+                UNCOMPRESSED_BLOBS_VERSION
+            );
+        }
+        Ok(())
+    }
+}
+
 /// Secure storage is an abstraction for file IO that handles compression and encryption.
 pub struct SecureStorage {
     key: Option<SecretBox<Vec<u8>>>,
@@ -70,6 +146,40 @@ impl SecureStorage {
         Ok(processed_data)
     }
 
+    /// Encodes a blob using the given compression settings.
+    /// Returns the encoded data and `true` if the blob was stored uncompressed.
+    pub fn encode_blob(
+        &self,
+        data: &[u8],
+        compression: &CompressionSettings,
+    ) -> Result<(Vec<u8>, bool)> {
+        let compressed = match compression.algorithm {
+            CompressionAlgorithm::None => None,
+            CompressionAlgorithm::Zstd => {
+                let compressed = Self::compress(data, compression.level)?;
+                if compression.auto && compressed.len() >= data.len() {
+                    None
+                } else {
+                    Some(compressed)
+                }
+            }
+        };
+
+        match compressed {
+            Some(compressed) => Ok((self.encrypt(&compressed)?, false)),
+            None => Ok((self.encrypt(data)?, true)),
+        }
+    }
+
+    /// Decodes a blob. `uncompressed` is the flag returned by `encode_blob`.
+    pub fn decode_blob(&self, data: &[u8], uncompressed: bool) -> Result<Vec<u8>> {
+        let processed_data = self.decrypt(data)?;
+        match uncompressed {
+            true => Ok(processed_data),
+            false => Self::decompress(&processed_data),
+        }
+    }
+
     /// Compress a stream of bytes
     pub fn compress(data: &[u8], compression_level: i32) -> Result<Vec<u8>> {
         let mut compressed = Vec::with_capacity(data.len());
@@ -150,16 +260,22 @@ impl SecureStorage {
         }
     }
 
-    /// Derive a key from a password and a salt
-    pub fn derive_key(password: &str, salt: &[u8]) -> [u8; 32] {
+    /// Derive a key from a password and a salt using the given KDF parameters
+    pub fn derive_key(password: &str, salt: &[u8], kdf: &KdfParams) -> Result<[u8; 32]> {
+        let algorithm = match kdf.algorithm {
+            KdfAlgorithm::Argon2id => argon2::Algorithm::Argon2id,
+            KdfAlgorithm::Argon2i => argon2::Algorithm::Argon2i,
+            KdfAlgorithm::Argon2d => argon2::Algorithm::Argon2d,
+        };
+        let params = Params::new(kdf.memory_cost, kdf.iterations, kdf.parallelism, Some(32))
+            .map_err(|e| anyhow!("Invalid KDF parameters: {e}"))?;
+
         let mut output_key_material = [0u8; 32];
-        let _ = Argon2::default().hash_password_into(
-            password.as_bytes(),
-            salt,
-            &mut output_key_material,
-        );
+        Argon2::new(algorithm, Version::V0x13, params)
+            .hash_password_into(password.as_bytes(), salt, &mut output_key_material)
+            .map_err(|e| anyhow!("Could not derive key: {e}"))?;
 
-        output_key_material
+        Ok(output_key_material)
     }
 
     /// Generate a random salt of a given length
@@ -245,4 +361,47 @@ cupiditat non proident, sunt in culpa qui officia deserunt mollit anim id est la
 
         Ok(())
     }
+
+    #[test]
+    fn test_encode_blob_compression_modes() -> Result<()> {
+        let secure_storage = SecureStorage::build().with_key(generate_new_master_key());
+        let random_data = SecureStorage::generate_salt::<512>();
+
+        let auto = CompressionSettings::default();
+        let (encoded, uncompressed) = secure_storage.encode_blob(TEXT, &auto)?;
+        assert!(!uncompressed);
+        assert_eq!(
+            TEXT,
+            secure_storage.decode_blob(&encoded, false)?.as_slice()
+        );
+
+        // Random data does not compress, so it is stored as is
+        let (encoded, uncompressed) = secure_storage.encode_blob(&random_data, &auto)?;
+        assert!(uncompressed);
+        assert_eq!(
+            random_data,
+            secure_storage.decode_blob(&encoded, true)?.as_slice()
+        );
+
+        let always = CompressionSettings {
+            auto: false,
+            ..Default::default()
+        };
+        let (encoded, uncompressed) = secure_storage.encode_blob(&random_data, &always)?;
+        assert!(!uncompressed);
+        assert_eq!(
+            random_data,
+            secure_storage.decode_blob(&encoded, false)?.as_slice()
+        );
+
+        let none = CompressionSettings {
+            algorithm: CompressionAlgorithm::None,
+            ..Default::default()
+        };
+        let (encoded, uncompressed) = secure_storage.encode_blob(TEXT, &none)?;
+        assert!(uncompressed);
+        assert_eq!(TEXT, secure_storage.decode_blob(&encoded, true)?.as_slice());
+
+        Ok(())
+    }
 }
@@ -14,9 +14,10 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use std::{collections::BTreeSet, path::PathBuf, sync::Arc};
+use std::{collections::BTreeSet, path::PathBuf, str::FromStr, sync::Arc};
 
-use anyhow::{Result, bail};
+use anyhow::{Context, Error, Result, bail};
+use rand::seq::IteratorRandom;
 
 use crate::{
     backend::StorageBackend,
@@ -31,17 +32,21 @@ use crate::{
 /// Verify the checksum and contents of a blob with a known ID in the repository.
 pub fn verify_blob(repo: &dyn RepositoryBackend, id: &ID) -> Result<u64> {
     let blob_data = repo.load_blob(id)?;
-    let checksum = utils::calculate_hash(&blob_data);
-    if checksum != id.0[..] {
+    if repo.blob_id(&blob_data) != *id {
         bail!("Invalid blob checksum");
     }
 
     Ok(blob_data.len() as u64)
 }
 
-pub fn verify_data(id: &ID, data: &[u8], expected_len: Option<u32>) -> Result<u64> {
-    let checksum = utils::calculate_hash(data);
-    if checksum != id.0[..] {
+/// Verify the checksum and length of blob data with a known ID.
+pub fn verify_data(
+    repo: &dyn RepositoryBackend,
+    id: &ID,
+    data: &[u8],
+    expected_len: Option<u32>,
+) -> Result<u64> {
+    if repo.blob_id(data) != *id {
         bail!("Invalid blob checksum");
     }
     if let Some(some_len) = expected_len
@@ -53,6 +58,84 @@ pub fn verify_data(id: &ID, data: &[u8], expected_len: Option<u32>) -> Result<u6
     Ok(data.len() as u64)
 }
 
+/// A subset of the packs in a repository to verify
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DataSubset {
+    /// Bucket `n` of `total` buckets (`n/total`). Packs are assigned to buckets by their ID, so
+    /// verifying all buckets in turn covers the whole repository.
+    Bucket { n: u32, total: u32 },
+
+    /// A random sample with a percentage of the packs (`p%`)
+    Percentage(f64),
+}
+
+impl FromStr for DataSubset {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        if let Some(percentage) = s.strip_suffix('%') {
+            let percentage: f64 = percentage
+                .trim()
+                .parse()
+                .with_context(|| format!("Invalid percentage \'{s}\'"))?;
+            if !(percentage > 0.0 && percentage <= 100.0) {
+                bail!("The percentage must be in (0, 100]");
+            }
+            Ok(DataSubset::Percentage(percentage))
+        } else if let Some((n, total)) = s.split_once('/') {
+            let n: u32 = n
+                .trim()
+                .parse()
+                .with_context(|| format!("Invalid subset \'{s}\'"))?;
+            let total: u32 = total
+                .trim()
+                .parse()
+                .with_context(|| format!("Invalid subset \'{s}\'"))?;
+            if n == 0 || n > total {
+                bail!("Invalid subset \'{s}\'. Use n/t with 1 <= n <= t");
+            }
+            Ok(DataSubset::Bucket { n, total })
+        } else {
+            bail!("Invalid subset \'{s}\'. Use n/t (e.g. 1/7) or a percentage (e.g. 5%)")
+        }
+    }
+}
+
+impl std::fmt::Display for DataSubset {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DataSubset::Bucket { n, total } => write!(f, "{n}/{total}"),
+            DataSubset::Percentage(percentage) => write!(f, "{percentage}%"),
+        }
+    }
+}
+
+impl DataSubset {
+    /// Selects the packs in the subset
+    pub fn select(&self, packs: &BTreeSet<ID>) -> Vec<ID> {
+        match *self {
+            DataSubset::Bucket { n, total } => packs
+                .iter()
+                .filter(|id| {
+                    let prefix = u32::from_be_bytes(id.0[..4].try_into().unwrap());
+                    prefix % total == n - 1
+                })
+                .cloned()
+                .collect(),
+            DataSubset::Percentage(percentage) => {
+                let amount = (packs.len() as f64 * percentage / 100.0).ceil() as usize;
+                let mut selected = packs
+                    .iter()
+                    .cloned()
+                    .choose_multiple(&mut rand::rng(), amount.min(packs.len()));
+                selected.sort();
+                selected
+            }
+        }
+    }
+}
+
 /// Verify the checksum and contents of a pack  with a known ID in the repository.
 pub fn verify_pack(
     repo: &dyn RepositoryBackend,
@@ -115,10 +198,10 @@ pub fn verify_snapshot_links(repo: Arc<dyn RepositoryBackend>, snapshot_id: &ID)
                 }
             }
             NodeType::Directory => {
-                if let Some(tree_id) = node.tree {
-                    if repo.index().read().get(&tree_id).is_none() {
-                        error_counter += 1;
-                    }
+                if let Some(tree_id) = node.tree
+                    && repo.index().read().get(&tree_id).is_none()
+                {
+                    error_counter += 1;
                 }
             }
             NodeType::Symlink
@@ -135,3 +218,50 @@ pub fn verify_snapshot_links(repo: Arc<dyn RepositoryBackend>, snapshot_id: &ID)
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_data_subset() {
+        assert_eq!(
+            "1/7".parse::<DataSubset>().unwrap(),
+            DataSubset::Bucket { n: 1, total: 7 }
+        );
+        assert_eq!(
+            "5%".parse::<DataSubset>().unwrap(),
+            DataSubset::Percentage(5.0)
+        );
+        assert_eq!(
+            "12.5%".parse::<DataSubset>().unwrap(),
+            DataSubset::Percentage(12.5)
+        );
+
+        assert!("0/7".parse::<DataSubset>().is_err());
+        assert!("8/7".parse::<DataSubset>().is_err());
+        assert!("0%".parse::<DataSubset>().is_err());
+        assert!("101%".parse::<DataSubset>().is_err());
+        assert!("7".parse::<DataSubset>().is_err());
+    }
+
+    /// Test that the buckets are disjoint and cover all packs
+    #[test]
+    fn test_select_data_subset() {
+        let packs: BTreeSet<ID> = (0..100).map(|_| ID::new_random()).collect();
+
+        let mut covered = BTreeSet::new();
+        for n in 1..=7 {
+            let selected = DataSubset::Bucket { n, total: 7 }.select(&packs);
+            assert_eq!(selected, DataSubset::Bucket { n, total: 7 }.select(&packs));
+            for id in selected {
+                assert!(covered.insert(id));
+            }
+        }
+        assert_eq!(covered, packs);
+
+        assert_eq!(DataSubset::Percentage(5.0).select(&packs).len(), 5);
+        assert_eq!(DataSubset::Percentage(0.1).select(&packs).len(), 1);
+        assert_eq!(DataSubset::Percentage(100.0).select(&packs).len(), 100);
+    }
+}
@@ -287,14 +287,14 @@ fn restore_node_metadata(node: &Node, dst_path: &Path) -> Result<()> {
             let uid = node.metadata.owner_uid;
             let gid = node.metadata.owner_gid;
 
-            if uid.is_some() || gid.is_some() {
-                if let Err(e) = std::os::unix::fs::chown(dst_path, uid, gid) {
-                    bail!(
-                        "Could not set owner/group for '{}': {}. This operation often requires elevated privileges (e.g., root) and may not be supported for all node types (e.g. symlinks).",
-                        dst_path.display(),
-                        e.to_string()
-                    );
-                }
+            if (uid.is_some() || gid.is_some())
+                && let Err(e) = std::os::unix::fs::chown(dst_path, uid, gid)
+            {
+                bail!(
+                    "Could not set owner/group for '{}': {}. This operation often requires elevated privileges (e.g., root) and may not be supported for all node types (e.g. symlinks).",
+                    dst_path.display(),
+                    e.to_string()
+                );
             }
         }
     }
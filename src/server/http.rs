@@ -0,0 +1,534 @@
+// mapache is an incremental backup tool
+// Copyright (C) 2025  Javier Lancha Vázquez <javier.lancha@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! A minimal HTTP/1.1 server. It supports persistent connections and requests with a
+//! `Content-Length`, which is all the HTTP backends need. Request bodies are streamed to the
+//! handler, so the memory used does not depend on their size.
+
+use std::{
+    collections::BTreeMap,
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+
+use anyhow::{Result, bail};
+
+use crate::{
+    global::defaults::{DEFAULT_MAX_CONNECTIONS, DEFAULT_MAX_REQUEST_BODY_SIZE, SERVER_TIMEOUT},
+    ui,
+};
+
+/// Maximum size of the request line and of each header line
+const MAX_LINE_LENGTH: u64 = 16 * 1024;
+
+/// Limits that protect a server from clients that send too much data or hold connections
+#[derive(Debug, Clone)]
+pub struct ServerLimits {
+    /// Requests with a larger body are rejected without reading it. Bodies are not held in
+    /// memory, so this limits the size of the stored files.
+    pub max_body_size: u64,
+    /// Connections beyond this number are rejected
+    pub max_connections: usize,
+    /// Connections are closed if a read or a write takes longer than this
+    pub timeout: Duration,
+}
+
+impl Default for ServerLimits {
+    fn default() -> Self {
+        Self {
+            max_body_size: DEFAULT_MAX_REQUEST_BODY_SIZE,
+            max_connections: DEFAULT_MAX_CONNECTIONS,
+            timeout: SERVER_TIMEOUT,
+        }
+    }
+}
+
+/// Handles the requests of a server
+pub trait Handler: Send + Sync + 'static {
+    /// Checks a request before its body is read. The body must not be read here.
+    /// Returns the response to send if the request is rejected.
+    fn check(&self, _request: &Request) -> Option<Response> {
+        None
+    }
+
+    fn handle(&self, request: Request) -> Response;
+}
+
+impl<F> Handler for F
+where
+    F: Fn(Request) -> Response + Send + Sync + 'static,
+{
+    fn handle(&self, request: Request) -> Response {
+        self(request)
+    }
+}
+
+pub struct Request<'a> {
+    pub method: String,
+    /// Percent-decoded path
+    pub path: String,
+    /// Percent-decoded query parameters
+    pub query: BTreeMap<String, String>,
+    /// Headers with lowercase names
+    pub headers: BTreeMap<String, String>,
+    /// The body, read from the connection. The connection is closed after the response if
+    /// the handler does not read all of it.
+    pub body: Box<dyn Read + 'a>,
+}
+
+impl Request<'_> {
+    /// Reads the whole body into memory
+    pub fn read_body(&mut self) -> std::io::Result<Vec<u8>> {
+        let mut body = Vec::new();
+        self.body.read_to_end(&mut body)?;
+        Ok(body)
+    }
+}
+
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn new(status: u16, body: Vec<u8>) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+            body,
+        }
+    }
+
+    pub fn empty(status: u16) -> Self {
+        Self::new(status, Vec::new())
+    }
+
+    pub fn with_header(mut self, name: &str, value: impl ToString) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+}
+
+/// Serves the connections of a listener, each one in its own thread. Blocks forever.
+pub fn serve(listener: TcpListener, handler: impl Handler, limits: ServerLimits) -> Result<()> {
+    let handler = Arc::new(handler);
+    let limits = Arc::new(limits);
+    let num_connections = Arc::new(AtomicUsize::new(0));
+
+    for stream in listener.incoming() {
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                ui::cli::warning!("Could not accept connection: {}", e);
+                continue;
+            }
+        };
+
+        if let Err(e) = stream
+            .set_read_timeout(Some(limits.timeout))
+            .and_then(|_| stream.set_write_timeout(Some(limits.timeout)))
+        {
+            ui::cli::warning!("Could not set connection timeouts: {}", e);
+            continue;
+        }
+
+        if num_connections.fetch_add(1, Ordering::SeqCst) >= limits.max_connections {
+            num_connections.fetch_sub(1, Ordering::SeqCst);
+            let _ = write_response(&mut stream, Response::empty(503), false, true);
+            continue;
+        }
+
+        let handler = handler.clone();
+        let limits = limits.clone();
+        let num_connections = num_connections.clone();
+        std::thread::spawn(move || {
+            if let Err(e) = handle_connection(stream, handler.as_ref(), &limits) {
+                ui::cli::verbose_2!("Connection closed: {}", e);
+            }
+            num_connections.fetch_sub(1, Ordering::SeqCst);
+        });
+    }
+
+    Ok(())
+}
+
+/// Serves requests on a free local port in a background thread. Returns the base URL.
+#[cfg(test)]
+pub(crate) fn spawn<F>(handler: F) -> String
+where
+    F: Fn(Request) -> Response + Send + Sync + 'static,
+{
+    spawn_with_limits(handler, ServerLimits::default())
+}
+
+/// Like [`spawn`], with custom limits
+#[cfg(test)]
+pub(crate) fn spawn_with_limits(handler: impl Handler, limits: ServerLimits) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    std::thread::spawn(move || serve(listener, handler, limits));
+    url
+}
+
+fn handle_connection(
+    mut stream: TcpStream,
+    handler: &impl Handler,
+    limits: &ServerLimits,
+) -> Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    loop {
+        let Some((mut request, length)) = read_request_head(&mut reader)? else {
+            return Ok(());
+        };
+        let mut close = request
+            .headers
+            .get("connection")
+            .is_some_and(|c| c.eq_ignore_ascii_case("close"));
+        let head = request.method == "HEAD";
+
+        // Rejected requests close the connection, since their body is not read
+        let rejection = match length > limits.max_body_size {
+            true => Some(Response::empty(413)),
+            false => handler.check(&request),
+        };
+        if let Some(response) = rejection {
+            write_response(&mut stream, response, head, true)?;
+            return Ok(());
+        }
+
+        if request
+            .headers
+            .get("expect")
+            .is_some_and(|e| e.eq_ignore_ascii_case("100-continue"))
+        {
+            stream.write_all(b"HTTP/1.1 100 Continue\r\n\r\n")?;
+        }
+        let mut body = (&mut reader).take(length);
+        request.body = Box::new(&mut body);
+
+        let response = handler.handle(request);
+        // The next request starts after the body. Skipping the unread part could take long.
+        close |= body.limit() > 0;
+        write_response(&mut stream, response, head, close)?;
+        if close {
+            return Ok(());
+        }
+    }
+}
+
+/// Reads the request line and the headers of a request, and the length of its body.
+/// Returns None if the connection was closed before the request.
+fn read_request_head<'a>(reader: &mut impl BufRead) -> Result<Option<(Request<'a>, u64)>> {
+    let request_line = read_line(reader)?;
+    if request_line.is_empty() {
+        return Ok(None);
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        bail!("Malformed request line");
+    };
+
+    let (path, query_str) = target.split_once('?').unwrap_or((target, ""));
+    let query = query_str
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (percent_decode(key), percent_decode(value))
+        })
+        .collect();
+
+    let mut headers = BTreeMap::new();
+    loop {
+        let line = read_line(reader)?;
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.insert(name.trim().to_lowercase(), value.trim().to_string());
+        }
+    }
+
+    if headers.contains_key("transfer-encoding") {
+        bail!("Transfer encodings are not supported");
+    }
+    let length: u64 = match headers.get("content-length") {
+        Some(length) => length.parse()?,
+        None => 0,
+    };
+
+    let request = Request {
+        method: method.to_string(),
+        path: percent_decode(path),
+        query,
+        headers,
+        body: Box::new(std::io::empty()),
+    };
+    Ok(Some((request, length)))
+}
+
+fn read_line(reader: &mut impl BufRead) -> Result<String> {
+    let mut line = String::new();
+    reader.take(MAX_LINE_LENGTH).read_line(&mut line)?;
+    if !line.is_empty() && !line.ends_with('\n') {
+        bail!("Line too long");
+    }
+    Ok(line)
+}
+
+/// Writes a response. The body of responses to HEAD requests is not sent, but its
+/// `Content-Length` can be set explicitly.
+fn write_response(
+    stream: &mut TcpStream,
+    response: Response,
+    head: bool,
+    close: bool,
+) -> Result<()> {
+    let mut out = format!(
+        "HTTP/1.1 {} {}\r\n",
+        response.status,
+        reason(response.status)
+    );
+    let mut has_length = false;
+    for (name, value) in &response.headers {
+        has_length |= name.eq_ignore_ascii_case("content-length");
+        out += &format!("{name}: {value}\r\n");
+    }
+    if !has_length {
+        out += &format!("Content-Length: {}\r\n", response.body.len());
+    }
+    if close {
+        out += "Connection: close\r\n";
+    }
+    out += "\r\n";
+
+    stream.write_all(out.as_bytes())?;
+    if !head {
+        stream.write_all(&response.body)?;
+    }
+    stream.flush()?;
+    Ok(())
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        206 => "Partial Content",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        413 => "Content Too Large",
+        416 => "Range Not Satisfiable",
+        500 => "Internal Server Error",
+        503 => "Service Unavailable",
+        _ => "",
+    }
+}
+
+/// Decodes `%xx` sequences and `+`
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' if i + 2 < bytes.len() => {
+                let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap_or_default();
+                match u8::from_str_radix(hex, 16) {
+                    Ok(byte) => {
+                        decoded.push(byte);
+                        i += 3;
+                        continue;
+                    }
+                    Err(_) => decoded.push(b'%'),
+                }
+            }
+            b'+' => decoded.push(b' '),
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+/// Returns the range `[start, end)` requested by a `Range` header for a file of `size` bytes,
+/// or None if the range is not satisfiable.
+pub fn parse_range(range: &str, size: u64) -> Option<(u64, u64)> {
+    let (start, end) = range.strip_prefix("bytes=")?.split_once('-')?;
+    if start.is_empty() {
+        let suffix: u64 = end.parse().ok()?;
+        return (suffix > 0).then_some((size.saturating_sub(suffix), size));
+    }
+
+    let start: u64 = start.parse().ok()?;
+    let end: u64 = match end.is_empty() {
+        true => size,
+        false => (end.parse::<u64>().ok()? + 1).min(size),
+    };
+    (start < end).then_some((start, end))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+
+    /// Rejects requests without an `authorization` header before reading their body
+    struct AuthHandler;
+
+    impl Handler for AuthHandler {
+        fn check(&self, request: &Request) -> Option<Response> {
+            match request.headers.contains_key("authorization") {
+                true => None,
+                false => Some(Response::empty(401)),
+            }
+        }
+
+        fn handle(&self, mut request: Request) -> Response {
+            Response::new(200, request.read_body().unwrap())
+        }
+    }
+
+    fn test_limits() -> ServerLimits {
+        ServerLimits {
+            max_body_size: 16,
+            max_connections: 1,
+            timeout: Duration::from_millis(500),
+        }
+    }
+
+    /// Sends a raw request and returns the raw response, which must fit in a single read
+    fn send(stream: &mut TcpStream, request: &str) -> String {
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut response = vec![0; 1024];
+        let n = stream.read(&mut response).unwrap();
+        String::from_utf8_lossy(&response[..n]).to_string()
+    }
+
+    /// Sends a raw request and reads the response until the server closes the connection
+    fn send_and_close(stream: &mut TcpStream, request: &str) -> String {
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    fn connect(url: &str) -> TcpStream {
+        TcpStream::connect(url.trim_start_matches("http://")).unwrap()
+    }
+
+    #[test]
+    fn test_reject_before_body() {
+        let limits = ServerLimits {
+            max_connections: 8,
+            ..test_limits()
+        };
+        let url = spawn_with_limits(AuthHandler, limits);
+
+        let mut stream = connect(&url);
+        let response = send_and_close(
+            &mut stream,
+            "POST /file HTTP/1.1\r\nauthorization: x\r\nconnection: close\r\n\
+             content-length: 7\r\n\r\nmapache",
+        );
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.ends_with("mapache"));
+
+        // The body is never sent, so the server would wait for it if it read it
+        let mut stream = connect(&url);
+        let start = Instant::now();
+        let response = send_and_close(
+            &mut stream,
+            "POST /file HTTP/1.1\r\ncontent-length: 8\r\n\r\n",
+        );
+        assert!(response.starts_with("HTTP/1.1 401"));
+        assert!(start.elapsed() < test_limits().timeout);
+
+        let mut stream = connect(&url);
+        let response = send_and_close(
+            &mut stream,
+            "POST /file HTTP/1.1\r\nauthorization: x\r\ncontent-length: 1000000000\r\n\r\n",
+        );
+        assert!(response.starts_with("HTTP/1.1 413"));
+    }
+
+    #[test]
+    fn test_unread_body() {
+        let url = spawn_with_limits(|_request: Request| Response::empty(204), test_limits());
+
+        // The rest of the body can't be parsed as the next request, so the connection is closed
+        let mut stream = connect(&url);
+        let response = send_and_close(
+            &mut stream,
+            "POST /file HTTP/1.1\r\ncontent-length: 7\r\n\r\nmapache",
+        );
+        assert!(response.starts_with("HTTP/1.1 204"));
+        assert!(response.contains("Connection: close"));
+    }
+
+    #[test]
+    fn test_connection_limits() {
+        let url = spawn_with_limits(AuthHandler, test_limits());
+
+        let mut first = connect(&url);
+        let response = send(&mut first, "GET /file HTTP/1.1\r\nauthorization: x\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200"));
+
+        // The first connection is still open
+        let mut second = connect(&url);
+        let mut response = String::new();
+        second.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 503"));
+
+        // Idle connections are closed after the timeout
+        let start = Instant::now();
+        assert_eq!(first.read(&mut [0; 16]).unwrap(), 0);
+        assert!(start.elapsed() >= test_limits().timeout / 2);
+
+        let mut third = connect(&url);
+        let response = send(&mut third, "GET /file HTTP/1.1\r\nauthorization: x\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200"));
+    }
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(parse_range("bytes=2-4", 10), Some((2, 5)));
+        assert_eq!(parse_range("bytes=8-20", 10), Some((8, 10)));
+        assert_eq!(parse_range("bytes=5-", 10), Some((5, 10)));
+        assert_eq!(parse_range("bytes=-4", 10), Some((6, 10)));
+        assert_eq!(parse_range("bytes=-20", 10), Some((0, 10)));
+        assert_eq!(parse_range("bytes=10-12", 10), None);
+        assert_eq!(parse_range("bytes=-0", 10), None);
+        assert_eq!(parse_range("items=0-1", 10), None);
+    }
+
+    #[test]
+    fn test_percent_decode() {
+        assert_eq!(percent_decode("a%2Fb+c"), "a/b c");
+        assert_eq!(percent_decode("100%"), "100%");
+    }
+}
@@ -0,0 +1,713 @@
+// mapache is an incremental backup tool
+// Copyright (C) 2025  Javier Lancha Vázquez <javier.lancha@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::{
+    collections::BTreeMap,
+    io::Read,
+    path::{Component, Path, PathBuf},
+};
+
+use anyhow::{Context, Result, bail};
+use base64::Engine;
+
+use crate::{
+    backend::{
+        StorageBackend,
+        localfs::LocalFS,
+        rest::{CONFIG_PATH, DATA_DIR, REST_V2_MEDIA_TYPE, SHARED_DIRS},
+    },
+    global::ID,
+    repository::{
+        MANIFEST_PATH,
+        repository_v1::{OBJECTS_DIR, OBJECTS_DIR_FANOUT},
+    },
+    ui,
+};
+
+use super::http::{Handler, Request, Response, parse_range};
+
+/// Options of a REST server
+#[derive(Debug, Clone, Default)]
+pub struct ServerOptions {
+    /// Users allowed to access the server. Authentication is disabled if None.
+    pub users: Option<Users>,
+    /// Confine each user to the subdirectory with their username
+    pub private_repos: bool,
+    /// Refuse to delete or overwrite files, except locks and temporary files
+    pub append_only: bool,
+}
+
+/// A request target in the REST layout, mapped to the repository layout on disk
+#[derive(Debug, PartialEq)]
+enum Target {
+    /// The root of a repository
+    Repository(PathBuf),
+    /// The directory of a file type. Objects are spread over subdirectories.
+    Dir {
+        path: PathBuf,
+        objects: bool,
+    },
+    File(PathBuf),
+}
+
+/// Serves the repositories in a local directory with the restic REST protocol spoken by
+/// [`RestBackend`](crate::backend::rest::RestBackend). The URL path of a request is the path
+/// of a repository relative to the root directory followed by the type and name of a file,
+/// so a server can host several repositories.
+pub struct RestServer {
+    backend: LocalFS,
+    options: ServerOptions,
+}
+
+impl RestServer {
+    pub fn new(root: PathBuf, options: ServerOptions) -> Result<Self> {
+        if options.private_repos && options.users.is_none() {
+            bail!("Private repositories require authentication");
+        }
+
+        Ok(Self {
+            backend: LocalFS::new(root),
+            options,
+        })
+    }
+
+    pub fn handle(&self, mut request: Request) -> Response {
+        let target = match self.authorize(&request) {
+            Ok(target) => target,
+            Err(response) => return response,
+        };
+
+        let result = match (target, request.method.as_str()) {
+            (Target::Repository(path), "POST")
+                if request.query.get("create").is_some_and(|c| c == "true") =>
+            {
+                self.create(&path)
+            }
+            (Target::Dir { path, objects }, "GET") => self.list(&path, objects, &request),
+            (Target::File(path), "HEAD") => self.stat(&path),
+            (Target::File(path), "GET") => self.read(&path, &request),
+            (Target::File(path), "POST") => self.write(&path, &mut request.body),
+            (Target::File(path), "DELETE") => self.delete(&path),
+            _ => Ok(Response::empty(405)),
+        };
+
+        result.unwrap_or_else(|e| {
+            ui::cli::warning!("{} {}: {:#}", request.method, request.path, e);
+            Response::empty(500)
+        })
+    }
+
+    /// Returns the target of the request, or the response to send if the request is not
+    /// allowed. It only needs the headers, so requests can be rejected before reading the body.
+    fn authorize(&self, request: &Request) -> Result<Target, Response> {
+        let username = self.authenticate(request)?;
+
+        let Some(path) = sanitize_path(&request.path) else {
+            return Err(Response::empty(400));
+        };
+
+        if self.options.private_repos
+            && let Some(username) = &username
+            && !path.starts_with(username)
+        {
+            return Err(Response::empty(403));
+        }
+
+        locate(&path, request.path.ends_with('/')).ok_or_else(|| Response::empty(404))
+    }
+
+    /// Returns the authenticated username, or the response to send if authentication failed
+    fn authenticate(&self, request: &Request) -> Result<Option<String>, Response> {
+        let Some(users) = &self.options.users else {
+            return Ok(None);
+        };
+
+        let credentials = request
+            .headers
+            .get("authorization")
+            .and_then(|auth| auth.strip_prefix("Basic "))
+            .and_then(|encoded| {
+                base64::engine::general_purpose::STANDARD
+                    .decode(encoded.trim())
+                    .ok()
+            })
+            .and_then(|decoded| String::from_utf8(decoded).ok());
+
+        if let Some(credentials) = credentials
+            && let Some((username, password)) = credentials.split_once(':')
+            && users.verify(username, password)
+        {
+            return Ok(Some(username.to_string()));
+        }
+
+        Err(Response::empty(401).with_header("WWW-Authenticate", "Basic realm=\"mapache\""))
+    }
+
+    /// Creates the directories of a repository, so it can also be opened locally
+    fn create(&self, path: &Path) -> Result<Response> {
+        for dir in SHARED_DIRS {
+            self.backend.create_dir_all(&path.join(dir))?;
+        }
+        let num_folders: usize = 1 << (4 * OBJECTS_DIR_FANOUT);
+        for n in 0..num_folders {
+            self.backend.create_dir_all(
+                &path
+                    .join(OBJECTS_DIR)
+                    .join(format!("{n:0>OBJECTS_DIR_FANOUT$x}")),
+            )?;
+        }
+        Ok(Response::empty(200))
+    }
+
+    /// Lists the files of a directory. The objects in all subdirectories of `objects` are
+    /// listed together.
+    fn list(&self, path: &Path, objects: bool, request: &Request) -> Result<Response> {
+        if !self.backend.is_dir(path) {
+            return Ok(Response::empty(404));
+        }
+
+        let mut files = Vec::new();
+        match objects {
+            true => {
+                for dir in self.backend.read_dir(path)? {
+                    if self.backend.is_dir(&dir) {
+                        self.list_files(&dir, &mut files)?;
+                    }
+                }
+            }
+            false => self.list_files(path, &mut files)?,
+        }
+        files.sort();
+
+        let v2 = request
+            .headers
+            .get("accept")
+            .is_some_and(|accept| accept.contains(REST_V2_MEDIA_TYPE));
+        let body = match v2 {
+            true => serde_json::to_vec(
+                &files
+                    .iter()
+                    .map(|(name, size)| serde_json::json!({"name": name, "size": size}))
+                    .collect::<Vec<_>>(),
+            )?,
+            false => serde_json::to_vec(&files.iter().map(|(name, _)| name).collect::<Vec<_>>())?,
+        };
+
+        let media_type = match v2 {
+            true => REST_V2_MEDIA_TYPE,
+            false => "application/json",
+        };
+        Ok(Response::new(200, body).with_header("Content-Type", media_type))
+    }
+
+    /// Adds the names and sizes of the files in a directory, skipping temporary files
+    fn list_files(&self, path: &Path, files: &mut Vec<(String, u64)>) -> Result<()> {
+        for file_path in self.backend.read_dir(path)? {
+            let hidden = file_path
+                .file_name()
+                .is_some_and(|name| name.to_string_lossy().starts_with('.'));
+            if !hidden && self.backend.is_file(&file_path) {
+                let size = self.backend.file_size(&file_path)?;
+                files.push((file_name(&file_path), size));
+            }
+        }
+        Ok(())
+    }
+
+    fn stat(&self, path: &Path) -> Result<Response> {
+        if !self.backend.is_file(path) {
+            return Ok(Response::empty(404));
+        }
+        let size = self.backend.file_size(path)?;
+        Ok(Response::empty(200).with_header("Content-Length", size))
+    }
+
+    fn read(&self, path: &Path, request: &Request) -> Result<Response> {
+        if !self.backend.is_file(path) {
+            return Ok(Response::empty(404));
+        }
+
+        let Some(range) = request.headers.get("range") else {
+            return Ok(Response::new(200, self.backend.read(path)?));
+        };
+
+        let size = self.backend.file_size(path)?;
+        match parse_range(range, size) {
+            Some((start, end)) => {
+                let data = self.backend.seek_read(path, start, end - start)?;
+                Ok(Response::new(206, data).with_header(
+                    "Content-Range",
+                    format!("bytes {}-{}/{}", start, end - 1, size),
+                ))
+            }
+            None => {
+                Ok(Response::empty(416).with_header("Content-Range", format!("bytes */{size}")))
+            }
+        }
+    }
+
+    /// Writes a file. The data is streamed to a hidden temporary file first, so a file is
+    /// never visible half-written.
+    fn write(&self, path: &Path, data: &mut dyn Read) -> Result<Response> {
+        if self.backend.is_dir(path) {
+            return Ok(Response::empty(400));
+        }
+        if self.options.append_only && self.backend.exists(path) && !is_mutable(path) {
+            return Ok(Response::empty(403));
+        }
+
+        let parent = path.parent().unwrap_or(Path::new(""));
+        self.backend.create_dir_all(parent)?;
+
+        let tmp_path = parent.join(format!(
+            ".{}.{}",
+            file_name(path),
+            ID::new_random().to_short_hex(8)
+        ));
+        self.backend
+            .write_from(&tmp_path, data)
+            .and_then(|_| self.backend.rename(&tmp_path, path))
+            .inspect_err(|_| {
+                let _ = self.backend.remove_file(&tmp_path);
+            })?;
+
+        Ok(Response::empty(200))
+    }
+
+    fn delete(&self, path: &Path) -> Result<Response> {
+        if self.options.append_only && !is_mutable(path) {
+            return Ok(Response::empty(403));
+        }
+        if !self.backend.is_file(path) {
+            return Ok(Response::empty(404));
+        }
+
+        self.backend.remove_file(path)?;
+        Ok(Response::empty(200))
+    }
+}
+
+impl Handler for RestServer {
+    fn check(&self, request: &Request) -> Option<Response> {
+        self.authorize(request).err()
+    }
+
+    fn handle(&self, request: Request) -> Response {
+        RestServer::handle(self, request)
+    }
+}
+
+/// Converts the path of a URL to a relative path. Returns None if the path could escape the
+/// root directory.
+fn sanitize_path(url_path: &str) -> Option<PathBuf> {
+    let mut path = PathBuf::new();
+    for segment in url_path.split('/').filter(|s| !s.is_empty()) {
+        let mut components = Path::new(segment).components();
+        match (components.next(), components.next()) {
+            (Some(Component::Normal(name)), None) if !segment.contains('\\') => path.push(name),
+            _ => return None,
+        }
+    }
+    Some(path)
+}
+
+/// Maps the path of a request to the repository layout. `dir` is whether the URL ends in `/`.
+/// Returns None if the path is not part of the REST layout.
+fn locate(path: &Path, dir: bool) -> Option<Target> {
+    let name = path.file_name().and_then(|name| name.to_str());
+    let parent = path.parent().unwrap_or(Path::new(""));
+
+    if dir {
+        let target = match name {
+            Some(DATA_DIR) => Target::Dir {
+                path: parent.join(OBJECTS_DIR),
+                objects: true,
+            },
+            Some(name) if SHARED_DIRS.contains(&name) => Target::Dir {
+                path: path.to_path_buf(),
+                objects: false,
+            },
+            _ => Target::Repository(path.to_path_buf()),
+        };
+        return Some(target);
+    }
+
+    let name = name?;
+    if name == CONFIG_PATH {
+        return Some(Target::File(parent.join(MANIFEST_PATH)));
+    }
+    match parent.file_name()?.to_str()? {
+        DATA_DIR => Some(Target::File(
+            parent
+                .parent()?
+                .join(OBJECTS_DIR)
+                .join(name.get(..OBJECTS_DIR_FANOUT)?)
+                .join(name),
+        )),
+        dir if SHARED_DIRS.contains(&dir) => Some(Target::File(path.to_path_buf())),
+        _ => None,
+    }
+}
+
+/// Whether a file can be deleted or overwritten in append-only mode. Locks are removed when
+/// released, and temporary files are renamed over their final names.
+fn is_mutable(path: &Path) -> bool {
+    path.components()
+        .any(|component| component.as_os_str() == "locks")
+        || path.extension().is_some_and(|extension| extension == "tmp")
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+/// The users of a server. Only keyed hashes of the passwords are kept in memory, and they
+/// are compared in constant time.
+#[derive(Debug, Clone)]
+pub struct Users {
+    key: [u8; blake3::KEY_LEN],
+    hashes: BTreeMap<String, blake3::Hash>,
+}
+
+impl Users {
+    fn new() -> Self {
+        Self {
+            key: rand::random(),
+            hashes: BTreeMap::new(),
+        }
+    }
+
+    fn insert(&mut self, username: &str, password: &str) {
+        let hash = self.hash(password);
+        self.hashes.insert(username.to_string(), hash);
+    }
+
+    /// Whether the password of a user is correct
+    pub fn verify(&self, username: &str, password: &str) -> bool {
+        // Unknown users are hashed too, so they take as long as wrong passwords
+        let hash = self.hash(password);
+        self.hashes
+            .get(username)
+            .is_some_and(|expected| *expected == hash)
+    }
+
+    fn hash(&self, password: &str) -> blake3::Hash {
+        blake3::keyed_hash(&self.key, password.as_bytes())
+    }
+}
+
+/// Parses a users file. Each line contains a username and a password separated by a colon.
+/// Empty lines and lines starting with `#` are ignored.
+pub fn parse_users(contents: &str) -> Result<Users> {
+    let mut users = Users::new();
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.trim_end_matches('\r');
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (username, password) = line
+            .split_once(':')
+            .with_context(|| format!("Line {} of the users file has no password", i + 1))?;
+        if username.contains('/')
+            || sanitize_path(username).is_none_or(|p| p.as_os_str().is_empty())
+        {
+            bail!("Invalid username \'{}\' in the users file", username);
+        }
+        users.insert(username, password);
+    }
+
+    Ok(users)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    fn request<'a>(method: &str, path: &str, body: &'a [u8]) -> Request<'a> {
+        Request {
+            method: method.to_string(),
+            path: path.to_string(),
+            query: BTreeMap::new(),
+            headers: BTreeMap::new(),
+            body: Box::new(body),
+        }
+    }
+
+    fn with_auth<'a>(mut request: Request<'a>, credentials: &str) -> Request<'a> {
+        let encoded = base64::engine::general_purpose::STANDARD.encode(credentials);
+        request
+            .headers
+            .insert(String::from("authorization"), format!("Basic {encoded}"));
+        request
+    }
+
+    #[test]
+    fn test_sanitize_path() {
+        assert_eq!(
+            sanitize_path("/repo/data/00/"),
+            Some(PathBuf::from("repo/data/00"))
+        );
+        assert_eq!(sanitize_path("/"), Some(PathBuf::new()));
+        assert_eq!(sanitize_path("/repo/../etc/passwd"), None);
+        assert_eq!(sanitize_path("/repo/./file"), None);
+    }
+
+    #[test]
+    fn test_locate() {
+        let file = |path: &str| Some(Target::File(PathBuf::from(path)));
+        assert_eq!(
+            locate(Path::new("repo/config"), false),
+            file("repo/manifest")
+        );
+        assert_eq!(
+            locate(Path::new("repo/data/abcd"), false),
+            file("repo/objects/ab/abcd")
+        );
+        assert_eq!(locate(Path::new("keys/abcd"), false), file("keys/abcd"));
+        assert_eq!(
+            locate(Path::new("repo/data"), true),
+            Some(Target::Dir {
+                path: PathBuf::from("repo/objects"),
+                objects: true
+            })
+        );
+        assert_eq!(
+            locate(Path::new("repo/index"), true),
+            Some(Target::Dir {
+                path: PathBuf::from("repo/index"),
+                objects: false
+            })
+        );
+        assert_eq!(
+            locate(Path::new("repo"), true),
+            Some(Target::Repository(PathBuf::from("repo")))
+        );
+        assert_eq!(locate(Path::new("repo/file"), false), None);
+        assert_eq!(locate(Path::new("repo/objects/ab/abcd"), false), None);
+        assert_eq!(locate(Path::new("repo/data/a"), false), None);
+    }
+
+    #[test]
+    fn test_parse_users() -> Result<()> {
+        let users = parse_users("# comment\nalice:secret\n\nbob:pa:ss\n")?;
+        assert!(users.verify("alice", "secret"));
+        assert!(users.verify("bob", "pa:ss"));
+        assert!(!users.verify("alice", "pa:ss"));
+        assert!(!users.verify("carol", "secret"));
+
+        assert!(parse_users("alice").is_err());
+        assert!(parse_users("../alice:secret").is_err());
+        assert!(parse_users("a/b:secret").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_serve_files() -> Result<()> {
+        let root = tempdir()?;
+        let server = RestServer::new(root.path().to_path_buf(), ServerOptions::default())?;
+
+        assert_eq!(
+            server.handle(request("GET", "/repo/keys/", b"")).status,
+            404
+        );
+        let mut create = request("POST", "/repo/", b"");
+        create
+            .query
+            .insert(String::from("create"), String::from("true"));
+        assert_eq!(server.handle(create).status, 200);
+        assert!(root.path().join("repo/objects/ff").is_dir());
+        assert_eq!(
+            server.handle(request("GET", "/repo/keys/", b"")).body,
+            b"[]"
+        );
+
+        assert_eq!(
+            server
+                .handle(request("POST", "/repo/data/00ff", b"0123456789"))
+                .status,
+            200
+        );
+        assert_eq!(
+            std::fs::read(root.path().join("repo/objects/00/00ff"))?,
+            b"0123456789"
+        );
+        assert_eq!(
+            server
+                .handle(request("POST", "/repo/config", b"manifest"))
+                .status,
+            200
+        );
+        assert!(root.path().join("repo/manifest").is_file());
+
+        let response = server.handle(request("HEAD", "/repo/data/00ff", b""));
+        assert_eq!(response.status, 200);
+        assert!(
+            response
+                .headers
+                .contains(&(String::from("Content-Length"), String::from("10")))
+        );
+
+        let mut range = request("GET", "/repo/data/00ff", b"");
+        range
+            .headers
+            .insert(String::from("range"), String::from("bytes=2-4"));
+        let response = server.handle(range);
+        assert_eq!(
+            (response.status, response.body.as_slice()),
+            (206, &b"234"[..])
+        );
+
+        assert_eq!(
+            server
+                .handle(request("POST", "/repo/data/ffaa", b"data"))
+                .status,
+            200
+        );
+        let mut list = request("GET", "/repo/data/", b"");
+        list.headers
+            .insert(String::from("accept"), REST_V2_MEDIA_TYPE.to_string());
+        assert_eq!(
+            server.handle(list).body,
+            br#"[{"name":"00ff","size":10},{"name":"ffaa","size":4}]"#
+        );
+
+        assert_eq!(
+            server
+                .handle(request("GET", "/repo/../../etc/passwd", b""))
+                .status,
+            400
+        );
+        assert_eq!(
+            server.handle(request("GET", "/repo/manifest", b"")).status,
+            404
+        );
+
+        assert_eq!(
+            server
+                .handle(request("DELETE", "/repo/data/00ff", b""))
+                .status,
+            200
+        );
+        assert_eq!(
+            server
+                .handle(request("DELETE", "/repo/data/00ff", b""))
+                .status,
+            404
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_append_only() -> Result<()> {
+        let root = tempdir()?;
+        let server = RestServer::new(
+            root.path().to_path_buf(),
+            ServerOptions {
+                append_only: true,
+                ..Default::default()
+            },
+        )?;
+
+        let post = |path: &str| server.handle(request("POST", path, b"data")).status;
+        let delete = |path: &str| server.handle(request("DELETE", path, b"")).status;
+
+        assert_eq!(post("/repo/data/00ff"), 200);
+        assert_eq!(post("/repo/data/00ff"), 403);
+        assert_eq!(delete("/repo/data/00ff"), 403);
+
+        // Locks and temporary files can be released and renamed
+        assert_eq!(post("/repo/locks/lock"), 200);
+        assert_eq!(delete("/repo/locks/lock"), 200);
+        assert_eq!(post("/repo/snapshots/snapshot.tmp"), 200);
+        assert_eq!(post("/repo/snapshots/snapshot.tmp"), 200);
+        assert_eq!(delete("/repo/snapshots/snapshot.tmp"), 200);
+        Ok(())
+    }
+
+    #[test]
+    fn test_authentication() -> Result<()> {
+        let root = tempdir()?;
+        let users = parse_users("alice:secret\nbob:hunter2")?;
+        let server = RestServer::new(
+            root.path().to_path_buf(),
+            ServerOptions {
+                users: Some(users),
+                private_repos: true,
+                ..Default::default()
+            },
+        )?;
+
+        let response = server.handle(request("POST", "/alice/config", b"data"));
+        assert_eq!(response.status, 401);
+        assert!(
+            response
+                .headers
+                .iter()
+                .any(|(name, _)| name == "WWW-Authenticate")
+        );
+        assert_eq!(
+            server
+                .handle(with_auth(
+                    request("POST", "/alice/config", b""),
+                    "alice:wrong"
+                ))
+                .status,
+            401
+        );
+
+        assert_eq!(
+            server
+                .handle(with_auth(
+                    request("POST", "/alice/config", b""),
+                    "alice:secret"
+                ))
+                .status,
+            200
+        );
+        assert_eq!(
+            server
+                .handle(with_auth(
+                    request("GET", "/alice/config", b""),
+                    "bob:hunter2"
+                ))
+                .status,
+            403
+        );
+        assert_eq!(
+            server
+                .handle(with_auth(request("GET", "/", b""), "bob:hunter2"))
+                .status,
+            403
+        );
+
+        assert!(
+            RestServer::new(
+                root.path().to_path_buf(),
+                ServerOptions {
+                    private_repos: true,
+                    ..Default::default()
+                }
+            )
+            .is_err()
+        );
+        Ok(())
+    }
+}
@@ -258,6 +258,8 @@ impl SnapshotProgressReporter {
             meta_encoded_bytes: self.meta_encoded_bytes.load(Ordering::SeqCst),
             total_raw_bytes,
             total_encoded_bytes,
+            start_time: None,
+            duration_secs: None,
             diff_counts: self.diff_counts.read().clone(),
         }
     }
@@ -0,0 +1,114 @@
+// mapache is an incremental backup tool
+// Copyright (C) 2025  Javier Lancha Vázquez <javier.lancha@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+/// A fixed-size set of bits. It uses one bit per position, so it is suited for marking
+/// items of large collections that can be enumerated, like the entries of the master index.
+#[derive(Debug, Clone, Default)]
+pub struct Bitmap {
+    words: Vec<u64>,
+    len: usize,
+    count: usize,
+}
+
+impl Bitmap {
+    /// Creates a bitmap with `len` bits, all unset
+    pub fn new(len: usize) -> Self {
+        Self {
+            words: vec![0; len.div_ceil(u64::BITS as usize)],
+            len,
+            count: 0,
+        }
+    }
+
+    /// Sets a bit. Returns `true` if the bit was not set before.
+    ///
+    /// Panics if `pos` is out of bounds.
+    pub fn set(&mut self, pos: usize) -> bool {
+        assert!(pos < self.len, "Bit {pos} out of bounds ({})", self.len);
+        let (word, mask) = Self::locate(pos);
+        let was_set = self.words[word] & mask != 0;
+        self.words[word] |= mask;
+        if !was_set {
+            self.count += 1;
+        }
+        !was_set
+    }
+
+    /// Returns `true` if the bit is set. Positions out of bounds are never set.
+    pub fn get(&self, pos: usize) -> bool {
+        if pos >= self.len {
+            return false;
+        }
+        let (word, mask) = Self::locate(pos);
+        self.words[word] & mask != 0
+    }
+
+    /// Returns the number of bits set
+    #[inline]
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Returns the number of bits in the bitmap
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns `true` if the bitmap has no bits
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    #[inline]
+    fn locate(pos: usize) -> (usize, u64) {
+        let bits = u64::BITS as usize;
+        (pos / bits, 1 << (pos % bits))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_and_get() {
+        let mut bitmap = Bitmap::new(130);
+        assert_eq!(bitmap.len(), 130);
+        assert_eq!(bitmap.count(), 0);
+
+        assert!(bitmap.set(0));
+        assert!(bitmap.set(64));
+        assert!(bitmap.set(129));
+        assert!(!bitmap.set(64));
+        assert_eq!(bitmap.count(), 3);
+
+        assert!(bitmap.get(0));
+        assert!(!bitmap.get(1));
+        assert!(bitmap.get(64));
+        assert!(!bitmap.get(128));
+        assert!(bitmap.get(129));
+        assert!(!bitmap.get(130));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_set_out_of_bounds() {
+        let mut bitmap = Bitmap::new(10);
+        bitmap.set(10);
+    }
+}
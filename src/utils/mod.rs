@@ -14,6 +14,7 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
+pub mod bitmap;
 pub mod indexset;
 pub mod url;
 
@@ -57,6 +58,29 @@ pub fn get_password_from_file(password_file_path: &Option<PathBuf>) -> Result<Op
         .transpose() // Converts Option<Result<T, E>> to Result<Option<T>, E>
 }
 
+// --- System ---
+
+/// Returns the hostname of this machine, or "unknown" if it cannot be determined.
+pub fn get_hostname() -> String {
+    whoami::fallible::hostname().unwrap_or_else(|_| String::from("unknown"))
+}
+
+/// Returns true if a process with the given PID is running on this machine.
+#[cfg(unix)]
+pub fn process_exists(pid: u32) -> bool {
+    // Signal 0 only performs error checking. EPERM means that the process exists
+    // but belongs to another user.
+    let ret = unsafe { libc::kill(pid as libc::pid_t, 0) };
+    ret == 0 || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// Returns true if a process with the given PID is running on this machine.
+/// Without a way to check, the process is assumed to be alive.
+#[cfg(not(unix))]
+pub fn process_exists(_pid: u32) -> bool {
+    true
+}
+
 // --- Hashing ---
 
 /// Calculates the 256-bit BLAKE3 hash of a byte array.
@@ -67,6 +91,14 @@ pub fn calculate_hash<T: AsRef<[u8]>>(data: T) -> Hash256 {
     hasher.finalize().into()
 }
 
+/// Calculates the 256-bit keyed BLAKE3 hash of a byte array.
+#[inline]
+pub fn calculate_keyed_hash<T: AsRef<[u8]>>(key: &Hash256, data: T) -> Hash256 {
+    let mut hasher = Hasher::new_keyed(key);
+    hasher.update(data.as_ref());
+    hasher.finalize().into()
+}
+
 // --- Formatting ---
 
 /// Formats a byte count into a human-readable string with binary prefixes (KiB, MiB, etc.).
@@ -85,6 +117,42 @@ pub fn format_size(bytes: u64, precision: usize) -> String {
     }
 }
 
+/// Parses a size string (e.g., "512", "64KiB", "16MiB", "1GB") into a number of bytes.
+/// Units are case-insensitive. A number without unit is a number of bytes.
+///
+/// # Supported Units:
+/// - `B`: bytes
+/// - `KiB`, `MiB`, `GiB`, `TiB`: binary prefixes (also `K`, `M`, `G`, `T`)
+/// - `KB`, `MB`, `GB`, `TB`: decimal prefixes
+pub fn parse_size_string(s: &str) -> Result<u64> {
+    let s = s.trim();
+    let unit_start = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (num_str, unit) = s.split_at(unit_start);
+
+    if num_str.is_empty() {
+        return Err(anyhow!("Invalid size format: missing number in \"{}\"", s));
+    }
+    let num = num_str
+        .parse::<u64>()
+        .with_context(|| format!("Failed to parse number in \"{s}\""))?;
+
+    let multiplier = match unit.trim().to_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kib" => size::KiB,
+        "m" | "mib" => size::MiB,
+        "g" | "gib" => size::GiB,
+        "t" | "tib" => size::TiB,
+        "kb" => size::KB,
+        "mb" => size::MB,
+        "gb" => size::GB,
+        "tb" => size::TB,
+        _ => return Err(anyhow!("Invalid size unit: '{}' in \"{}\"", unit, s)),
+    };
+
+    num.checked_mul(multiplier)
+        .with_context(|| format!("Size \"{s}\" is too large"))
+}
+
 /// Formats a count with appropriate singular or plural suffix.
 pub fn format_count<T>(count: T, singular: &str, plural: &str) -> String
 where
@@ -476,6 +544,29 @@ mod tests {
         assert_eq!(format_size(2_100_000_100_000, 3), "1.910 TiB");
     }
 
+    #[test]
+    fn test_parse_size_string() -> Result<()> {
+        assert_eq!(parse_size_string("0")?, 0);
+        assert_eq!(parse_size_string("512")?, 512);
+        assert_eq!(parse_size_string("512B")?, 512);
+        assert_eq!(parse_size_string("64KiB")?, 64 * size::KiB);
+        assert_eq!(parse_size_string("64k")?, 64 * size::KiB);
+        assert_eq!(parse_size_string("16MiB")?, 16 * size::MiB);
+        assert_eq!(parse_size_string("16 mib")?, 16 * size::MiB);
+        assert_eq!(parse_size_string("2GiB")?, 2 * size::GiB);
+        assert_eq!(parse_size_string("1TiB")?, size::TiB);
+        assert_eq!(parse_size_string("3MB")?, 3 * size::MB);
+
+        assert!(parse_size_string("").is_err());
+        assert!(parse_size_string("MiB").is_err());
+        assert!(parse_size_string("1.5MiB").is_err());
+        assert!(parse_size_string("12XB").is_err());
+        assert!(parse_size_string("-1").is_err());
+        assert!(parse_size_string("99999999999TiB").is_err());
+
+        Ok(())
+    }
+
     #[test]
     fn test_calculate_lcp() {
         let paths: Vec<PathBuf> = vec![];
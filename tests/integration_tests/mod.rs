@@ -22,9 +22,16 @@ use mapache::{backend::localfs::LocalFS, repository};
 
 mod test_cmd_amend;
 mod test_cmd_clean;
+mod test_cmd_config;
+mod test_cmd_copy;
 mod test_cmd_init;
+mod test_cmd_key;
+mod test_cmd_migrate;
+mod test_cmd_repair;
 mod test_cmd_restore;
+mod test_cmd_serve;
 mod test_cmd_snapshot;
+mod test_cmd_verify;
 
 const BACKUP_DATA_PATH: &str = "backup_data.tar.xz";
 
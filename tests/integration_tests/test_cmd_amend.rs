@@ -22,7 +22,10 @@ mod tests {
     use anyhow::{Context, Result};
     use mapache::{
         backend::localfs::LocalFS,
-        commands::{self, GlobalArgs, UseSnapshot, cmd_amend, cmd_restore, cmd_snapshot},
+        commands::{
+            self, CompressionArgs, GlobalArgs, RetryArgs, UseSnapshot, cmd_amend, cmd_restore,
+            cmd_snapshot,
+        },
         repository::{snapshot::SnapshotStreamer, try_open},
     };
 
@@ -56,6 +59,9 @@ mod tests {
             verbosity: None,
             ssh_pubkey: None,
             ssh_privatekey: None,
+            cache_dir: None,
+            no_cache: true,
+            retry: RetryArgs::default(),
         };
 
         // Init repo
@@ -77,6 +83,9 @@ mod tests {
             read_concurrency: 2,
             write_concurrency: 5,
             dry_run: false,
+            checkpoint_interval: None,
+            host: None,
+            compression: CompressionArgs::default(),
         };
         commands::cmd_snapshot::run(&global, &snapshot_args)
             .with_context(|| "Failed to run cmd_snapshot")?;
@@ -109,6 +118,7 @@ mod tests {
             strip_prefix: false,
             resolution: mapache::restorer::Resolution::Skip,
             no_verify: false,
+            host: None,
         };
         commands::cmd_restore::run(&global, &restore_args)
             .with_context(|| "Failed to run cmd_restore")?;
@@ -181,6 +191,9 @@ mod tests {
             verbosity: None,
             ssh_pubkey: None,
             ssh_privatekey: None,
+            cache_dir: None,
+            no_cache: true,
+            retry: RetryArgs::default(),
         };
 
         // Init repo
@@ -198,6 +211,9 @@ mod tests {
             read_concurrency: 2,
             write_concurrency: 5,
             dry_run: false,
+            checkpoint_interval: None,
+            host: None,
+            compression: CompressionArgs::default(),
         };
         commands::cmd_snapshot::run(&global, &snapshot_args)
             .with_context(|| "Failed to run cmd_snapshot")?;
@@ -20,7 +20,10 @@ mod tests {
     use std::path::PathBuf;
 
     use anyhow::{Context, Result};
-    use mapache::commands::{self, GlobalArgs, UseSnapshot, cmd_clean, cmd_restore, cmd_snapshot};
+    use mapache::commands::{
+        self, CompressionArgs, GlobalArgs, RetryArgs, UseSnapshot, cmd_clean, cmd_restore,
+        cmd_snapshot,
+    };
 
     use tempfile::tempdir;
 
@@ -53,6 +56,9 @@ mod tests {
             verbosity: None,
             ssh_pubkey: None,
             ssh_privatekey: None,
+            cache_dir: None,
+            no_cache: true,
+            retry: RetryArgs::default(),
         };
 
         // Init repo
@@ -74,6 +80,9 @@ mod tests {
             read_concurrency: 2,
             write_concurrency: 5,
             dry_run: false,
+            checkpoint_interval: None,
+            host: None,
+            compression: CompressionArgs::default(),
         };
         commands::cmd_snapshot::run(&global, &snapshot_args)
             .with_context(|| "Failed to run cmd_snapshot (1/2)")?;
@@ -92,6 +101,9 @@ mod tests {
             read_concurrency: 2,
             write_concurrency: 5,
             dry_run: false,
+            checkpoint_interval: None,
+            host: None,
+            compression: CompressionArgs::default(),
         };
         commands::cmd_snapshot::run(&global, &snapshot_args)
             .with_context(|| "Failed to run cmd_snapshot (2/2)")?;
@@ -109,6 +121,7 @@ mod tests {
             dry_run: false,
             tolerance: 0.0_f32,
             tags_str: Some(String::new()),
+            host: None,
             keep_tags_str: Some(String::new()),
             verify: true,
         };
@@ -133,6 +146,7 @@ mod tests {
             strip_prefix: false,
             resolution: mapache::restorer::Resolution::Skip,
             no_verify: false,
+            host: None,
         };
         commands::cmd_restore::run(&global, &restore_args)
             .with_context(|| "Failed to run cmd_restore")?;
@@ -0,0 +1,103 @@
+// mapache is an incremental backup tool
+// Copyright (C) 2025  Javier Lancha Vázquez <javier.lancha@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+#![cfg(test)]
+
+mod tests {
+    use std::sync::Arc;
+
+    use anyhow::{Context, Result};
+    use mapache::{
+        backend::localfs::LocalFS,
+        commands::{
+            self, GlobalArgs, RetryArgs,
+            cmd_config::{CmdArgs, ConfigCommand, ConfigKey, SetArgs},
+        },
+        global::set_global_opts_with_args,
+        repository::{self, storage::CompressionAlgorithm},
+        utils::size,
+    };
+    use tempfile::tempdir;
+
+    use crate::integration_tests::init_repo;
+
+    fn set_args(key: ConfigKey, value: &str) -> CmdArgs {
+        CmdArgs {
+            command: ConfigCommand::Set(SetArgs {
+                key,
+                value: value.to_string(),
+            }),
+        }
+    }
+
+    #[test]
+    fn test_config_set() -> Result<()> {
+        let tmp_dir = tempdir()?;
+        let tmp_path = tmp_dir.path();
+        let password = "mapachito";
+        let password_path = tmp_path.join("password");
+        std::fs::write(&password_path, password)?;
+
+        let repo_path = tmp_path.join("repo");
+        init_repo(password, repo_path.clone())?;
+        let backend = Arc::new(LocalFS::new(repo_path.clone()));
+
+        let global = GlobalArgs {
+            repo: repo_path.to_string_lossy().to_string(),
+            password_file: Some(password_path),
+            key: None,
+            quiet: true,
+            verbosity: None,
+            ssh_pubkey: None,
+            ssh_privatekey: None,
+            cache_dir: None,
+            no_cache: true,
+            retry: RetryArgs::default(),
+        };
+        set_global_opts_with_args(&global);
+
+        // Invalid values are rejected
+        assert!(
+            commands::cmd_config::run(&global, &set_args(ConfigKey::PackSize, "1KiB")).is_err()
+        );
+        assert!(
+            commands::cmd_config::run(&global, &set_args(ConfigKey::CompressionLevel, "99"))
+                .is_err()
+        );
+        assert!(
+            commands::cmd_config::run(&global, &set_args(ConfigKey::Compression, "lz4")).is_err()
+        );
+
+        commands::cmd_config::run(&global, &set_args(ConfigKey::PackSize, "32MiB"))
+            .with_context(|| "Failed to set pack size")?;
+        commands::cmd_config::run(&global, &set_args(ConfigKey::Compression, "none"))
+            .with_context(|| "Failed to set compression")?;
+        commands::cmd_config::run(&global, &set_args(ConfigKey::AutoCompression, "false"))
+            .with_context(|| "Failed to set auto compression")?;
+
+        let (repo, _) = repository::try_open(Some(password.to_string()), None, backend)?;
+        assert_eq!(repo.pack_size(), 32 * size::MiB);
+        let manifest = repo.load_manifest()?;
+        assert_eq!(manifest.pack_size, 32 * size::MiB);
+        assert_eq!(manifest.compression.algorithm, CompressionAlgorithm::None);
+        assert!(!manifest.compression.auto);
+
+        // The config command does not leave locks behind
+        assert_eq!(repo_path.join("locks").read_dir()?.count(), 0);
+
+        Ok(())
+    }
+}
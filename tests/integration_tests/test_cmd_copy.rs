@@ -0,0 +1,184 @@
+// mapache is an incremental backup tool
+// Copyright (C) 2025  Javier Lancha Vázquez <javier.lancha@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+#![cfg(test)]
+
+mod tests {
+    use std::{
+        path::{Path, PathBuf},
+        sync::Arc,
+    };
+
+    use anyhow::{Context, Result};
+    use mapache::{
+        backend::localfs::LocalFS,
+        commands::{
+            self, ChunkerArgs, CompressionArgs, FromRepoArgs, GlobalArgs, KdfArgs, RetryArgs,
+            UseSnapshot, cmd_copy, cmd_init, cmd_restore, cmd_snapshot,
+        },
+        global::{ID, set_global_opts_with_args},
+        repository::{self, snapshot::Snapshot},
+        restorer::Resolution,
+    };
+    use tempfile::tempdir;
+
+    use crate::{integration_tests::BACKUP_DATA_PATH, test_utils};
+
+    fn global_args(repo_path: &Path, password_path: &Path) -> GlobalArgs {
+        GlobalArgs {
+            repo: repo_path.to_string_lossy().to_string(),
+            password_file: Some(password_path.to_path_buf()),
+            key: None,
+            quiet: true,
+            verbosity: None,
+            ssh_pubkey: None,
+            ssh_privatekey: None,
+            cache_dir: None,
+            no_cache: true,
+            retry: RetryArgs::default(),
+        }
+    }
+
+    fn init(global: &GlobalArgs, repository_version: u32) -> Result<()> {
+        let init_args = cmd_init::CmdArgs {
+            repository_version,
+            kdf: KdfArgs::default(),
+            compression: CompressionArgs::default(),
+            chunker: ChunkerArgs::default(),
+            pack_size: None,
+            copy_params_from: None,
+            from: FromRepoArgs::default(),
+        };
+        commands::cmd_init::run(global, &init_args).with_context(|| "Failed to run cmd_init")
+    }
+
+    /// Snapshots sorted by timestamp
+    fn load_snapshots(repo_path: &Path, password: &str) -> Result<Vec<(ID, Snapshot)>> {
+        let backend = Arc::new(LocalFS::new(repo_path.to_path_buf()));
+        let (repo, _) = repository::try_open(Some(password.to_string()), None, backend)?;
+        let mut snapshots: Vec<(ID, Snapshot)> = repo
+            .list_snapshot_ids()?
+            .into_iter()
+            .map(|id| repo.load_snapshot(&id).map(|snapshot| (id, snapshot)))
+            .collect::<Result<_>>()?;
+        snapshots.sort_by_key(|(_, snapshot)| snapshot.timestamp);
+        Ok(snapshots)
+    }
+
+    /// Copy all snapshots from a version 1 repository into a version 1 repository (same IDs)
+    /// and into a version 2 repository (rewritten IDs), then restore from both.
+    #[test]
+    fn test_copy() -> Result<()> {
+        let tmp_dir = tempdir()?;
+        let tmp_path = tmp_dir.path();
+        let password = "mapachito";
+        let password_path = tmp_path.join("password");
+        std::fs::write(&password_path, password)?;
+
+        let backup_data_path = test_utils::get_test_data_path(BACKUP_DATA_PATH);
+        let backup_data_tmp_path = tmp_path.join("backup");
+        test_utils::extract_tar_xz_archive(&backup_data_path, &backup_data_tmp_path)?;
+
+        let src_path = tmp_path.join("src");
+        let src_global = global_args(&src_path, &password_path);
+        set_global_opts_with_args(&src_global);
+        init(&src_global, 1)?;
+
+        // Two snapshots, the second one with a parent
+        let snapshot_args = cmd_snapshot::CmdArgs {
+            paths: vec![backup_data_tmp_path.clone()],
+            exclude: None,
+            tags_str: String::new(),
+            description: None,
+            rescan: false,
+            parent: UseSnapshot::Latest,
+            read_concurrency: 2,
+            write_concurrency: 5,
+            dry_run: false,
+            checkpoint_interval: None,
+            host: None,
+            compression: CompressionArgs::default(),
+        };
+        commands::cmd_snapshot::run(&src_global, &snapshot_args)
+            .with_context(|| "Failed to run cmd_snapshot")?;
+        std::fs::write(backup_data_tmp_path.join("file.txt"), "mapache")?;
+        commands::cmd_snapshot::run(&src_global, &snapshot_args)
+            .with_context(|| "Failed to run cmd_snapshot")?;
+        let src_snapshots = load_snapshots(&src_path, password)?;
+
+        let copy_args = cmd_copy::CmdArgs {
+            from_repo: src_path.to_string_lossy().to_string(),
+            from: FromRepoArgs {
+                from_password_file: Some(password_path.clone()),
+                from_key: None,
+            },
+            snapshots: Vec::new(),
+            dry_run: false,
+        };
+
+        for (name, version) in [("dst_v1", 1), ("dst_v2", 2)] {
+            let dst_path: PathBuf = tmp_path.join(name);
+            let dst_global = global_args(&dst_path, &password_path);
+            init(&dst_global, version)?;
+
+            commands::cmd_copy::run(&dst_global, &copy_args)
+                .with_context(|| "Failed to run cmd_copy")?;
+            // Copied snapshots are skipped
+            commands::cmd_copy::run(&dst_global, &copy_args)
+                .with_context(|| "Failed to run cmd_copy")?;
+
+            let dst_snapshots = load_snapshots(&dst_path, password)?;
+            assert_eq!(dst_snapshots.len(), 2);
+            assert!(dst_snapshots[0].1.parent.is_none());
+            assert_eq!(
+                dst_snapshots[1].1.parent.as_ref(),
+                Some(&dst_snapshots[0].0)
+            );
+            for ((_, src), (_, dst)) in src_snapshots.iter().zip(dst_snapshots.iter()) {
+                assert_eq!(src.timestamp, dst.timestamp);
+                assert_eq!(src.summary.processed_bytes, dst.summary.processed_bytes);
+                // Same IDs only if both repositories calculate them the same way
+                assert_eq!(src.tree == dst.tree, version == 1);
+            }
+
+            let restore_path = tmp_path.join(format!("restore_{name}"));
+            let restore_args = cmd_restore::CmdArgs {
+                target: restore_path.clone(),
+                snapshot: UseSnapshot::Latest,
+                dry_run: false,
+                include: None,
+                exclude: None,
+                strip_prefix: false,
+                resolution: Resolution::Skip,
+                no_verify: false,
+                host: None,
+            };
+            commands::cmd_restore::run(&dst_global, &restore_args)
+                .with_context(|| "Failed to run cmd_restore")?;
+
+            assert_eq!(
+                std::fs::read_to_string(restore_path.join("backup").join("file.txt"))?,
+                "mapache"
+            );
+            assert_eq!(
+                std::fs::read(restore_path.join("backup").join("0").join("file0.txt"))?,
+                std::fs::read(backup_data_tmp_path.join("0").join("file0.txt"))?
+            );
+        }
+
+        Ok(())
+    }
+}
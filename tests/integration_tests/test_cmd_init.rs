@@ -21,9 +21,15 @@ mod tests {
 
     use mapache::{
         backend::localfs::LocalFS,
-        commands::{self, GlobalArgs, cmd_init::CmdArgs},
+        commands::{
+            self, ChunkerArgs, CompressionArgs, FromRepoArgs, GlobalArgs, KdfArgs, RetryArgs,
+            cmd_init::CmdArgs,
+        },
         global::set_global_opts_with_args,
-        repository::{self},
+        repository::{
+            self,
+            storage::{CompressionAlgorithm, CompressionSettings},
+        },
     };
 
     use anyhow::{Context, Result};
@@ -48,9 +54,18 @@ mod tests {
             verbosity: None,
             ssh_pubkey: None,
             ssh_privatekey: None,
+            cache_dir: None,
+            no_cache: true,
+            retry: RetryArgs::default(),
         };
         let args = CmdArgs {
             repository_version: 1,
+            kdf: KdfArgs::default(),
+            compression: CompressionArgs::default(),
+            chunker: ChunkerArgs::default(),
+            pack_size: None,
+            copy_params_from: None,
+            from: FromRepoArgs::default(),
         };
         set_global_opts_with_args(&global);
 
@@ -98,9 +113,18 @@ mod tests {
             verbosity: None,
             ssh_pubkey: None,
             ssh_privatekey: None,
+            cache_dir: None,
+            no_cache: true,
+            retry: RetryArgs::default(),
         };
         let args = CmdArgs {
             repository_version: 1,
+            kdf: KdfArgs::default(),
+            compression: CompressionArgs::default(),
+            chunker: ChunkerArgs::default(),
+            pack_size: None,
+            copy_params_from: None,
+            from: FromRepoArgs::default(),
         };
         set_global_opts_with_args(&global);
 
@@ -128,4 +152,169 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_init_with_repository_params() -> Result<()> {
+        let tmp_dir = tempdir()?;
+        let tmp_path = tmp_dir.path();
+        let password = "mapachito";
+        let password_path = tmp_path.join("password");
+        std::fs::write(&password_path, password)?;
+
+        let repo_path = tmp_path.join("repo");
+
+        let global = GlobalArgs {
+            repo: repo_path.to_string_lossy().to_string(),
+            password_file: Some(password_path),
+            key: None,
+            quiet: true,
+            verbosity: None,
+            ssh_pubkey: None,
+            ssh_privatekey: None,
+            cache_dir: None,
+            no_cache: true,
+            retry: RetryArgs::default(),
+        };
+        set_global_opts_with_args(&global);
+
+        // An invalid compression level is rejected before creating anything
+        let args = CmdArgs {
+            repository_version: 2,
+            kdf: KdfArgs::default(),
+            compression: CompressionArgs {
+                compression: None,
+                compression_level: Some(100),
+                auto_compression: None,
+            },
+            chunker: ChunkerArgs::default(),
+            pack_size: None,
+            copy_params_from: None,
+            from: FromRepoArgs::default(),
+        };
+        assert!(commands::cmd_init::run(&global, &args).is_err());
+        assert!(!repo_path.exists());
+
+        let args = CmdArgs {
+            repository_version: 2,
+            kdf: KdfArgs::default(),
+            compression: CompressionArgs {
+                compression: Some(CompressionAlgorithm::Zstd),
+                compression_level: Some(19),
+                auto_compression: Some(false),
+            },
+            chunker: ChunkerArgs::default(),
+            pack_size: Some(64 * 1024 * 1024),
+            copy_params_from: None,
+            from: FromRepoArgs::default(),
+        };
+        commands::cmd_init::run(&global, &args).with_context(|| "Failed to run cmd_init")?;
+
+        let backend = Arc::new(LocalFS::new(repo_path));
+        let (repo, _) = repository::try_open(Some(password.to_string()), None, backend)
+            .with_context(|| "Failed to open repository")?;
+        assert_eq!(repo.pack_size(), 64 * 1024 * 1024);
+        assert_eq!(
+            repo.load_manifest()?.compression,
+            CompressionSettings {
+                algorithm: CompressionAlgorithm::Zstd,
+                level: 19,
+                auto: false,
+            }
+        );
+
+        Ok(())
+    }
+
+    /// A repository initialized with the parameters of another calculates the same blob IDs
+    #[test]
+    fn test_init_copy_params_from() -> Result<()> {
+        let tmp_dir = tempdir()?;
+        let tmp_path = tmp_dir.path();
+        let password_path = tmp_path.join("password");
+        std::fs::write(&password_path, "mapachito")?;
+        let src_password_path = tmp_path.join("src_password");
+        std::fs::write(&src_password_path, "mapachote")?;
+
+        let src_path = tmp_path.join("src");
+        let src_global = GlobalArgs {
+            repo: src_path.to_string_lossy().to_string(),
+            password_file: Some(src_password_path.clone()),
+            key: None,
+            quiet: true,
+            verbosity: None,
+            ssh_pubkey: None,
+            ssh_privatekey: None,
+            cache_dir: None,
+            no_cache: true,
+            retry: RetryArgs::default(),
+        };
+        set_global_opts_with_args(&src_global);
+        let src_args = CmdArgs {
+            repository_version: 2,
+            kdf: KdfArgs::default(),
+            compression: CompressionArgs::default(),
+            chunker: ChunkerArgs {
+                chunk_min_size: Some(64 * 1024),
+                chunk_avg_size: Some(256 * 1024),
+                chunk_max_size: Some(1024 * 1024),
+                chunk_normalization: Some(2),
+            },
+            pack_size: None,
+            copy_params_from: None,
+            from: FromRepoArgs::default(),
+        };
+        commands::cmd_init::run(&src_global, &src_args)
+            .with_context(|| "Failed to run cmd_init")?;
+
+        let dst_path = tmp_path.join("dst");
+        let dst_global = GlobalArgs {
+            repo: dst_path.to_string_lossy().to_string(),
+            password_file: Some(password_path),
+            key: None,
+            quiet: true,
+            verbosity: None,
+            ssh_pubkey: None,
+            ssh_privatekey: None,
+            cache_dir: None,
+            no_cache: true,
+            retry: RetryArgs::default(),
+        };
+        let dst_args = CmdArgs {
+            repository_version: 2,
+            kdf: KdfArgs::default(),
+            compression: CompressionArgs::default(),
+            chunker: ChunkerArgs::default(),
+            pack_size: Some(32 * 1024 * 1024),
+            copy_params_from: Some(src_path.to_string_lossy().to_string()),
+            from: FromRepoArgs {
+                from_password_file: Some(src_password_path),
+                from_key: None,
+            },
+        };
+        commands::cmd_init::run(&dst_global, &dst_args)
+            .with_context(|| "Failed to run cmd_init")?;
+
+        let (src_repo, _) = repository::try_open(
+            Some(String::from("mapachote")),
+            None,
+            Arc::new(LocalFS::new(src_path)),
+        )?;
+        let (dst_repo, _) = repository::try_open(
+            Some(String::from("mapachito")),
+            None,
+            Arc::new(LocalFS::new(dst_path)),
+        )?;
+        let src_manifest = src_repo.load_manifest()?;
+        let dst_manifest = dst_repo.load_manifest()?;
+
+        assert_ne!(src_manifest.id, dst_manifest.id);
+        assert_eq!(src_manifest.version, dst_manifest.version);
+        assert_eq!(src_manifest.chunker, dst_manifest.chunker);
+        assert_eq!(src_manifest.id_key, dst_manifest.id_key);
+        assert_eq!(src_repo.blob_id(b"mapache"), dst_repo.blob_id(b"mapache"));
+        // Other parameters are not copied
+        assert_eq!(dst_repo.pack_size(), 32 * 1024 * 1024);
+
+        Ok(())
+    }
 }
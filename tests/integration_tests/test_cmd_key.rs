@@ -0,0 +1,157 @@
+// mapache is an incremental backup tool
+// Copyright (C) 2025  Javier Lancha Vázquez <javier.lancha@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+#![cfg(test)]
+
+mod tests {
+    use std::sync::Arc;
+
+    use anyhow::{Context, Result};
+    use mapache::{
+        backend::localfs::LocalFS,
+        commands::{
+            self, GlobalArgs, KdfArgs, RetryArgs,
+            cmd_key::{AddArgs, CmdArgs, KeyCommand, PasswdArgs, RemoveArgs},
+        },
+        global::set_global_opts_with_args,
+        repository::{
+            self,
+            keys::{self, KdfAlgorithm},
+        },
+    };
+    use tempfile::tempdir;
+
+    use crate::integration_tests::init_repo;
+
+    /// Add a second key, change its password and remove the first one
+    #[test]
+    fn test_key_add_passwd_remove() -> Result<()> {
+        let tmp_dir = tempdir()?;
+        let tmp_path = tmp_dir.path();
+        let password = "mapachito";
+        let password_path = tmp_path.join("password");
+        std::fs::write(&password_path, password)?;
+        let new_password = "mapachote";
+        let new_password_path = tmp_path.join("new_password");
+        std::fs::write(&new_password_path, new_password)?;
+        let newer_password = "mapachon";
+        let newer_password_path = tmp_path.join("newer_password");
+        std::fs::write(&newer_password_path, newer_password)?;
+
+        let repo_path = tmp_path.join("repo");
+        init_repo(password, repo_path.clone())?;
+        let backend = Arc::new(LocalFS::new(repo_path.clone()));
+
+        let global = GlobalArgs {
+            repo: repo_path.to_string_lossy().to_string(),
+            password_file: Some(password_path),
+            key: None,
+            quiet: true,
+            verbosity: None,
+            ssh_pubkey: None,
+            ssh_privatekey: None,
+            cache_dir: None,
+            no_cache: true,
+            retry: RetryArgs::default(),
+        };
+        set_global_opts_with_args(&global);
+
+        // Add a new key
+        let add_args = CmdArgs {
+            command: KeyCommand::Add(AddArgs {
+                label: Some(String::from("laptop")),
+                new_password_file: Some(new_password_path.clone()),
+                kdf: KdfArgs {
+                    kdf: KdfAlgorithm::Argon2i,
+                    kdf_memory: 8 * 1024,
+                    kdf_iterations: 3,
+                    kdf_parallelism: 2,
+                },
+            }),
+        };
+        commands::cmd_key::run(&global, &add_args).with_context(|| "Failed to add key")?;
+
+        let key_list = keys::list_keys(backend.clone())?;
+        assert_eq!(key_list.len(), 2);
+        assert_eq!(key_list[1].1.label.as_deref(), Some("laptop"));
+        assert_eq!(key_list[1].1.kdf.algorithm, KdfAlgorithm::Argon2i);
+        assert_eq!(key_list[1].1.kdf.memory_cost, 8 * 1024);
+        let (original_key_id, _) = &key_list[0];
+
+        repository::try_open(Some(new_password.to_string()), None, backend.clone())
+            .with_context(|| "Failed to open repository with the new key")?;
+
+        // Change the password of the new key
+        let new_global = GlobalArgs {
+            password_file: Some(new_password_path),
+            ..global
+        };
+        let passwd_args = CmdArgs {
+            command: KeyCommand::Passwd(PasswdArgs {
+                new_password_file: Some(newer_password_path.clone()),
+            }),
+        };
+        commands::cmd_key::run(&new_global, &passwd_args)
+            .with_context(|| "Failed to change password")?;
+
+        let key_list = keys::list_keys(backend.clone())?;
+        assert_eq!(key_list.len(), 2);
+        assert_eq!(key_list[1].1.label.as_deref(), Some("laptop"));
+        assert_eq!(key_list[1].1.kdf.algorithm, KdfAlgorithm::Argon2i);
+        assert!(
+            repository::try_open(Some(new_password.to_string()), None, backend.clone()).is_err()
+        );
+        repository::try_open(Some(newer_password.to_string()), None, backend.clone())
+            .with_context(|| "Failed to open repository with the changed password")?;
+
+        // The key used to open the repository cannot be removed
+        let newer_global = GlobalArgs {
+            password_file: Some(newer_password_path),
+            ..new_global
+        };
+        let remove_args = CmdArgs {
+            command: KeyCommand::Remove(RemoveArgs {
+                id: key_list[1].0.to_hex(),
+            }),
+        };
+        assert!(commands::cmd_key::run(&newer_global, &remove_args).is_err());
+        assert_eq!(keys::list_keys(backend.clone())?.len(), 2);
+
+        // Remove the original key
+        let remove_args = CmdArgs {
+            command: KeyCommand::Remove(RemoveArgs {
+                id: original_key_id.to_hex(),
+            }),
+        };
+        commands::cmd_key::run(&newer_global, &remove_args)
+            .with_context(|| "Failed to remove key")?;
+
+        let key_list = keys::list_keys(backend.clone())?;
+        assert_eq!(key_list.len(), 1);
+        assert!(repository::try_open(Some(password.to_string()), None, backend.clone()).is_err());
+
+        // The last key is the one in use, so it cannot be removed
+        let remove_args = CmdArgs {
+            command: KeyCommand::Remove(RemoveArgs {
+                id: key_list[0].0.to_hex(),
+            }),
+        };
+        assert!(commands::cmd_key::run(&newer_global, &remove_args).is_err());
+        assert_eq!(keys::list_keys(backend)?.len(), 1);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,166 @@
+// mapache is an incremental backup tool
+// Copyright (C) 2025  Javier Lancha Vázquez <javier.lancha@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+#![cfg(test)]
+
+mod tests {
+    use std::sync::Arc;
+
+    use anyhow::{Context, Result};
+    use mapache::{
+        backend::localfs::LocalFS,
+        commands::{
+            self, ChunkerArgs, CompressionArgs, FromRepoArgs, GlobalArgs, KdfArgs, RetryArgs,
+            UseSnapshot, cmd_clean, cmd_init, cmd_migrate, cmd_restore, cmd_snapshot,
+        },
+        global::{ID, set_global_opts_with_args},
+        repository,
+        restorer::Resolution,
+    };
+    use tempfile::tempdir;
+
+    use crate::{integration_tests::BACKUP_DATA_PATH, test_utils};
+
+    /// Migrate a version 1 repository with two snapshots to version 2 and restore from it
+    #[test]
+    fn test_migrate_keyed_blob_ids() -> Result<()> {
+        let tmp_dir = tempdir()?;
+        let tmp_path = tmp_dir.path();
+        let password = "mapachito";
+        let password_path = tmp_path.join("password");
+        std::fs::write(&password_path, password)?;
+
+        let backup_data_path = test_utils::get_test_data_path(BACKUP_DATA_PATH);
+        let backup_data_tmp_path = tmp_path.join("backup");
+        test_utils::extract_tar_xz_archive(&backup_data_path, &backup_data_tmp_path)?;
+
+        let repo_path = tmp_path.join("repo");
+
+        let global = GlobalArgs {
+            repo: repo_path.to_string_lossy().to_string(),
+            password_file: Some(password_path),
+            key: None,
+            quiet: true,
+            verbosity: None,
+            ssh_pubkey: None,
+            ssh_privatekey: None,
+            cache_dir: None,
+            no_cache: true,
+            retry: RetryArgs::default(),
+        };
+        set_global_opts_with_args(&global);
+
+        let init_args = cmd_init::CmdArgs {
+            repository_version: 1,
+            kdf: KdfArgs::default(),
+            compression: CompressionArgs::default(),
+            chunker: ChunkerArgs::default(),
+            pack_size: None,
+            copy_params_from: None,
+            from: FromRepoArgs::default(),
+        };
+        commands::cmd_init::run(&global, &init_args).with_context(|| "Failed to run cmd_init")?;
+
+        // Two snapshots, the second one with a parent
+        let snapshot_args = cmd_snapshot::CmdArgs {
+            paths: vec![backup_data_tmp_path.clone()],
+            exclude: None,
+            tags_str: String::new(),
+            description: None,
+            rescan: false,
+            parent: UseSnapshot::Latest,
+            read_concurrency: 2,
+            write_concurrency: 5,
+            dry_run: false,
+            checkpoint_interval: None,
+            host: None,
+            compression: CompressionArgs::default(),
+        };
+        commands::cmd_snapshot::run(&global, &snapshot_args)
+            .with_context(|| "Failed to run cmd_snapshot")?;
+        std::fs::write(backup_data_tmp_path.join("file.txt"), "mapache")?;
+        commands::cmd_snapshot::run(&global, &snapshot_args)
+            .with_context(|| "Failed to run cmd_snapshot")?;
+
+        // List and run migrations
+        commands::cmd_migrate::run(&global, &cmd_migrate::CmdArgs { migration: None })
+            .with_context(|| "Failed to list migrations")?;
+        let migrate_args = cmd_migrate::CmdArgs {
+            migration: Some(String::from("keyed-blob-ids")),
+        };
+        commands::cmd_migrate::run(&global, &migrate_args)
+            .with_context(|| "Failed to run migration")?;
+
+        // The migration is not available anymore
+        assert!(commands::cmd_migrate::run(&global, &migrate_args).is_err());
+
+        commands::cmd_clean::run(
+            &global,
+            &cmd_clean::CmdArgs {
+                tolerance: 0.0,
+                verify: true,
+                dry_run: false,
+            },
+        )
+        .with_context(|| "Failed to run cmd_clean")?;
+
+        let backend = Arc::new(LocalFS::new(repo_path));
+        let (repo, _) = repository::try_open(Some(password.to_string()), None, backend)?;
+        assert_eq!(repo.load_manifest()?.version, 2);
+
+        let snapshots: Vec<(ID, _)> = repo
+            .list_snapshot_ids()?
+            .into_iter()
+            .map(|id| repo.load_snapshot(&id).map(|snapshot| (id, snapshot)))
+            .collect::<Result<_>>()?;
+        assert_eq!(snapshots.len(), 2);
+        let (first, second) = if snapshots[0].1.timestamp < snapshots[1].1.timestamp {
+            (&snapshots[0], &snapshots[1])
+        } else {
+            (&snapshots[1], &snapshots[0])
+        };
+        assert!(first.1.parent.is_none());
+        assert_eq!(second.1.parent.as_ref(), Some(&first.0));
+        drop(repo);
+
+        // Restore the latest snapshot
+        let restore_path = tmp_path.join("restore");
+        let restore_args = cmd_restore::CmdArgs {
+            target: restore_path.clone(),
+            snapshot: UseSnapshot::Latest,
+            dry_run: false,
+            include: None,
+            exclude: None,
+            strip_prefix: false,
+            resolution: Resolution::Skip,
+            no_verify: false,
+            host: None,
+        };
+        commands::cmd_restore::run(&global, &restore_args)
+            .with_context(|| "Failed to run cmd_restore")?;
+
+        assert_eq!(
+            std::fs::read_to_string(restore_path.join("backup").join("file.txt"))?,
+            "mapache"
+        );
+        assert_eq!(
+            std::fs::read(restore_path.join("backup").join("0").join("file0.txt"))?,
+            std::fs::read(backup_data_tmp_path.join("0").join("file0.txt"))?
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,241 @@
+// mapache is an incremental backup tool
+// Copyright (C) 2025  Javier Lancha Vázquez <javier.lancha@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+#![cfg(test)]
+
+mod tests {
+    use std::{collections::BTreeSet, sync::Arc};
+
+    use anyhow::{Context, Result};
+    use mapache::{
+        backend::localfs::LocalFS,
+        commands::{
+            self, CompressionArgs, GlobalArgs, RetryArgs, UseSnapshot,
+            cmd_repair::{self, IndexArgs, RepairCommand, SnapshotsArgs},
+            cmd_restore, cmd_snapshot,
+        },
+        global::{BlobType, ID, set_global_opts_with_args},
+        repository::{self, repair::REPAIRED_TAG},
+        restorer::Resolution,
+    };
+    use tempfile::tempdir;
+
+    use crate::{
+        integration_tests::{BACKUP_DATA_PATH, init_repo},
+        test_utils,
+    };
+
+    /// Remove all index files, rebuild the index and restore a snapshot
+    #[test]
+    fn test_repair_index() -> Result<()> {
+        let tmp_dir = tempdir()?;
+        let tmp_path = tmp_dir.path();
+        let password = "mapachito";
+        let password_path = tmp_path.join("password");
+        std::fs::write(&password_path, password)?;
+
+        let backup_data_path = test_utils::get_test_data_path(BACKUP_DATA_PATH);
+        let backup_data_tmp_path = tmp_path.join("backup");
+        test_utils::extract_tar_xz_archive(&backup_data_path, &backup_data_tmp_path)?;
+
+        let repo_path = tmp_path.join("repo");
+
+        let global = GlobalArgs {
+            repo: repo_path.to_string_lossy().to_string(),
+            password_file: Some(password_path),
+            key: None,
+            quiet: true,
+            verbosity: None,
+            ssh_pubkey: None,
+            ssh_privatekey: None,
+            cache_dir: None,
+            no_cache: true,
+            retry: RetryArgs::default(),
+        };
+        set_global_opts_with_args(&global);
+
+        init_repo(password, repo_path.clone())?;
+
+        let snapshot_args = cmd_snapshot::CmdArgs {
+            paths: vec![backup_data_tmp_path.clone()],
+            exclude: None,
+            tags_str: String::new(),
+            description: None,
+            rescan: false,
+            parent: UseSnapshot::Latest,
+            read_concurrency: 2,
+            write_concurrency: 5,
+            dry_run: false,
+            checkpoint_interval: None,
+            host: None,
+            compression: CompressionArgs::default(),
+        };
+        commands::cmd_snapshot::run(&global, &snapshot_args)
+            .with_context(|| "Failed to run cmd_snapshot")?;
+
+        // Lose the whole index
+        for entry in std::fs::read_dir(repo_path.join("index"))? {
+            std::fs::remove_file(entry?.path())?;
+        }
+
+        let repair_args = cmd_repair::CmdArgs {
+            command: RepairCommand::Index(IndexArgs {
+                read_concurrency: 2,
+                dry_run: false,
+            }),
+        };
+        commands::cmd_repair::run(&global, &repair_args)
+            .with_context(|| "Failed to run cmd_repair")?;
+        assert!(std::fs::read_dir(repo_path.join("index"))?.count() > 0);
+
+        let restore_path = tmp_path.join("restore");
+        let restore_args = cmd_restore::CmdArgs {
+            target: restore_path.clone(),
+            snapshot: UseSnapshot::Latest,
+            dry_run: false,
+            include: None,
+            exclude: None,
+            strip_prefix: false,
+            resolution: Resolution::Skip,
+            no_verify: false,
+            host: None,
+        };
+        commands::cmd_restore::run(&global, &restore_args)
+            .with_context(|| "Failed to run cmd_restore")?;
+
+        assert_eq!(
+            std::fs::read(restore_path.join("backup").join("file.txt"))?,
+            std::fs::read(backup_data_tmp_path.join("file.txt"))?
+        );
+
+        Ok(())
+    }
+
+    /// Lose all data packs and salvage the directory structure of the snapshot
+    #[test]
+    fn test_repair_snapshots() -> Result<()> {
+        let tmp_dir = tempdir()?;
+        let tmp_path = tmp_dir.path();
+        let password = "mapachito";
+        let password_path = tmp_path.join("password");
+        std::fs::write(&password_path, password)?;
+
+        let backup_data_path = test_utils::get_test_data_path(BACKUP_DATA_PATH);
+        let backup_data_tmp_path = tmp_path.join("backup");
+        test_utils::extract_tar_xz_archive(&backup_data_path, &backup_data_tmp_path)?;
+
+        let repo_path = tmp_path.join("repo");
+
+        let global = GlobalArgs {
+            repo: repo_path.to_string_lossy().to_string(),
+            password_file: Some(password_path),
+            key: None,
+            quiet: true,
+            verbosity: None,
+            ssh_pubkey: None,
+            ssh_privatekey: None,
+            cache_dir: None,
+            no_cache: true,
+            retry: RetryArgs::default(),
+        };
+        set_global_opts_with_args(&global);
+
+        init_repo(password, repo_path.clone())?;
+
+        let snapshot_args = cmd_snapshot::CmdArgs {
+            paths: vec![backup_data_tmp_path.clone()],
+            exclude: None,
+            tags_str: String::new(),
+            description: None,
+            rescan: false,
+            parent: UseSnapshot::Latest,
+            read_concurrency: 2,
+            write_concurrency: 5,
+            dry_run: false,
+            checkpoint_interval: None,
+            host: None,
+            compression: CompressionArgs::default(),
+        };
+        commands::cmd_snapshot::run(&global, &snapshot_args)
+            .with_context(|| "Failed to run cmd_snapshot")?;
+
+        // Delete the packs with file data. Trees are packed separately.
+        let backend = Arc::new(LocalFS::new(repo_path.clone()));
+        let (repo, _) = repository::try_open(Some(password.to_string()), None, backend.clone())?;
+        let data_packs: BTreeSet<ID> = {
+            let index = repo.index();
+            let index = index.read();
+            index
+                .iter_ids()
+                .filter(|(id, _)| index.get(id).unwrap().1 == BlobType::Data)
+                .map(|(_, locator)| locator.pack_id)
+                .collect()
+        };
+        drop(repo);
+        assert!(!data_packs.is_empty());
+        for pack_id in &data_packs {
+            let hex = pack_id.to_hex();
+            std::fs::remove_file(repo_path.join("objects").join(&hex[..2]).join(&hex))?;
+        }
+
+        // An unreadable snapshot does not stop the others from being repaired
+        let broken_snapshot_path = repo_path.join("snapshots").join(ID::new_random().to_hex());
+        std::fs::write(&broken_snapshot_path, b"mapache")?;
+
+        // The index still lists the lost blobs, so they are only found by reading the data
+        let repair_args = cmd_repair::CmdArgs {
+            command: RepairCommand::Snapshots(SnapshotsArgs {
+                snapshots: Vec::new(),
+                read_data: true,
+                forget: true,
+                dry_run: false,
+            }),
+        };
+        assert!(commands::cmd_repair::run(&global, &repair_args).is_err());
+        std::fs::remove_file(&broken_snapshot_path)?;
+
+        let (repo, _) = repository::try_open(Some(password.to_string()), None, backend)?;
+        let snapshot_ids = repo.list_snapshot_ids()?;
+        assert_eq!(snapshot_ids.len(), 1);
+        assert!(
+            repo.load_snapshot(&snapshot_ids[0])?
+                .tags
+                .contains(REPAIRED_TAG)
+        );
+        drop(repo);
+
+        // The directories survive, the files with data do not
+        let restore_path = tmp_path.join("restore");
+        let restore_args = cmd_restore::CmdArgs {
+            target: restore_path.clone(),
+            snapshot: UseSnapshot::Latest,
+            dry_run: false,
+            include: None,
+            exclude: None,
+            strip_prefix: false,
+            resolution: Resolution::Skip,
+            no_verify: false,
+            host: None,
+        };
+        commands::cmd_restore::run(&global, &restore_args)
+            .with_context(|| "Failed to run cmd_restore")?;
+
+        assert!(restore_path.join("backup").join("0").join("00").is_dir());
+        assert!(!restore_path.join("backup").join("file.txt").exists());
+
+        Ok(())
+    }
+}
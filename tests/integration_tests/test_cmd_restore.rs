@@ -21,7 +21,9 @@ mod tests {
 
     use anyhow::{Context, Result};
     use mapache::{
-        commands::{self, GlobalArgs, UseSnapshot, cmd_restore, cmd_snapshot},
+        commands::{
+            self, CompressionArgs, GlobalArgs, RetryArgs, UseSnapshot, cmd_restore, cmd_snapshot,
+        },
         global::set_global_opts_with_args,
     };
     use tempfile::tempdir;
@@ -54,6 +56,9 @@ mod tests {
             verbosity: None,
             ssh_pubkey: None,
             ssh_privatekey: None,
+            cache_dir: None,
+            no_cache: true,
+            retry: RetryArgs::default(),
         };
         set_global_opts_with_args(&global);
 
@@ -76,6 +81,9 @@ mod tests {
             read_concurrency: 2,
             write_concurrency: 5,
             dry_run: false,
+            checkpoint_interval: None,
+            host: None,
+            compression: CompressionArgs::default(),
         };
         commands::cmd_snapshot::run(&global, &snapshot_args)
             .with_context(|| "Failed to run cmd_snapshot")?;
@@ -91,6 +99,7 @@ mod tests {
             strip_prefix: false,
             resolution: mapache::restorer::Resolution::Skip,
             no_verify: false,
+            host: None,
         };
         commands::cmd_restore::run(&global, &restore_args)
             .with_context(|| "Failed to run cmd_restore")?;
@@ -161,6 +170,9 @@ mod tests {
             verbosity: None,
             ssh_pubkey: None,
             ssh_privatekey: None,
+            cache_dir: None,
+            no_cache: true,
+            retry: RetryArgs::default(),
         };
         set_global_opts_with_args(&global);
 
@@ -183,6 +195,9 @@ mod tests {
             read_concurrency: 2,
             write_concurrency: 5,
             dry_run: false,
+            checkpoint_interval: None,
+            host: None,
+            compression: CompressionArgs::default(),
         };
         commands::cmd_snapshot::run(&global, &snapshot_args)
             .with_context(|| "Failed to run cmd_snapshot")?;
@@ -198,6 +213,7 @@ mod tests {
             strip_prefix: false,
             resolution: mapache::restorer::Resolution::Skip,
             no_verify: false,
+            host: None,
         };
         commands::cmd_restore::run(&global, &restore_args)
             .with_context(|| "Failed to run cmd_restore")?;
@@ -230,6 +246,9 @@ mod tests {
             verbosity: None,
             ssh_pubkey: None,
             ssh_privatekey: None,
+            cache_dir: None,
+            no_cache: true,
+            retry: RetryArgs::default(),
         };
         set_global_opts_with_args(&global);
 
@@ -252,6 +271,9 @@ mod tests {
             read_concurrency: 2,
             write_concurrency: 5,
             dry_run: false,
+            checkpoint_interval: None,
+            host: None,
+            compression: CompressionArgs::default(),
         };
         commands::cmd_snapshot::run(&global, &snapshot_args)
             .with_context(|| "Failed to run cmd_snapshot")?;
@@ -270,6 +292,7 @@ mod tests {
             strip_prefix: true,
             resolution: mapache::restorer::Resolution::Skip,
             no_verify: false,
+            host: None,
         };
         commands::cmd_restore::run(&global, &restore_args)
             .with_context(|| "Failed to run cmd_restore 1")?;
@@ -291,6 +314,7 @@ mod tests {
             strip_prefix: true,
             resolution: mapache::restorer::Resolution::Skip,
             no_verify: false,
+            host: None,
         };
         commands::cmd_restore::run(&global, &restore_args)
             .with_context(|| "Failed to run cmd_restore 2")?;
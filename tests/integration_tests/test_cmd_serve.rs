@@ -0,0 +1,236 @@
+// mapache is an incremental backup tool
+// Copyright (C) 2025  Javier Lancha Vázquez <javier.lancha@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+#![cfg(test)]
+
+mod tests {
+    use std::{
+        io::Write,
+        net::TcpListener,
+        path::Path,
+        process::{Command, Stdio},
+    };
+
+    use anyhow::{Context, Result};
+    use mapache::{
+        backend::{StorageBackend, rest::RestBackend},
+        commands::{
+            self, ChunkerArgs, CompressionArgs, FromRepoArgs, GlobalArgs, KdfArgs, RetryArgs,
+            UseSnapshot, cmd_init, cmd_restore, cmd_snapshot,
+        },
+        global::set_global_opts_with_args,
+        server::{
+            http::{self, ServerLimits},
+            rest::{RestServer, ServerOptions, parse_users},
+        },
+    };
+    use tempfile::tempdir;
+
+    use crate::{integration_tests::BACKUP_DATA_PATH, test_utils};
+
+    /// Initializes a repository, takes a snapshot and restores it
+    fn backup_and_restore(
+        global: &GlobalArgs,
+        backup_path: &Path,
+        restore_path: &Path,
+    ) -> Result<()> {
+        let init_args = cmd_init::CmdArgs {
+            repository_version: 1,
+            kdf: KdfArgs::default(),
+            compression: CompressionArgs::default(),
+            chunker: ChunkerArgs::default(),
+            pack_size: None,
+            copy_params_from: None,
+            from: FromRepoArgs::default(),
+        };
+        commands::cmd_init::run(global, &init_args).with_context(|| "Failed to run cmd_init")?;
+
+        let snapshot_args = cmd_snapshot::CmdArgs {
+            paths: vec![backup_path.join("0")],
+            exclude: None,
+            tags_str: String::new(),
+            description: None,
+            rescan: false,
+            parent: UseSnapshot::Latest,
+            read_concurrency: 2,
+            write_concurrency: 5,
+            dry_run: false,
+            checkpoint_interval: None,
+            host: None,
+            compression: CompressionArgs::default(),
+        };
+        commands::cmd_snapshot::run(global, &snapshot_args)
+            .with_context(|| "Failed to run cmd_snapshot")?;
+
+        let restore_args = cmd_restore::CmdArgs {
+            target: restore_path.to_path_buf(),
+            snapshot: UseSnapshot::Latest,
+            dry_run: false,
+            include: None,
+            exclude: None,
+            strip_prefix: false,
+            resolution: mapache::restorer::Resolution::Skip,
+            no_verify: false,
+            host: None,
+        };
+        commands::cmd_restore::run(global, &restore_args)
+            .with_context(|| "Failed to run cmd_restore")?;
+        assert_eq!(
+            std::fs::read(restore_path.join("0/00/file00.txt"))?,
+            std::fs::read(backup_path.join("0/00/file00.txt"))?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_serve() -> Result<()> {
+        let tmp_dir = tempdir()?;
+        let tmp_path = tmp_dir.path();
+        let password = "mapachito";
+        let password_path = tmp_path.join("password");
+        std::fs::write(&password_path, password)?;
+
+        let backup_data_path = test_utils::get_test_data_path(BACKUP_DATA_PATH);
+        let backup_data_tmp_path = tmp_path.join("backup");
+        test_utils::extract_tar_xz_archive(&backup_data_path, &backup_data_tmp_path)?;
+
+        // Serve a private, append-only directory
+        let server_path = tmp_path.join("server");
+        let server = RestServer::new(
+            server_path.clone(),
+            ServerOptions {
+                users: Some(parse_users("alice:secret\nbob:hunter2")?),
+                private_repos: true,
+                append_only: true,
+            },
+        )?;
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        std::thread::spawn(move || http::serve(listener, server, ServerLimits::default()));
+
+        let global = GlobalArgs {
+            repo: format!("rest://alice:secret@{addr}/alice/repo"),
+            password_file: Some(password_path),
+            key: None,
+            quiet: true,
+            verbosity: None,
+            ssh_pubkey: None,
+            ssh_privatekey: None,
+            cache_dir: None,
+            no_cache: true,
+            retry: RetryArgs::default(),
+        };
+        set_global_opts_with_args(&global);
+
+        backup_and_restore(&global, &backup_data_tmp_path, &tmp_path.join("restore"))?;
+        // The REST layout is stored as a regular repository
+        assert!(server_path.join("alice/repo/manifest").is_file());
+        assert_eq!(
+            std::fs::read_dir(server_path.join("alice/repo/snapshots"))?.count(),
+            1
+        );
+
+        // Snapshots cannot be removed in append-only mode
+        let backend = RestBackend::new(
+            format!("http://{addr}/alice/repo"),
+            Some(String::from("alice")),
+            Some(String::from("secret")),
+        );
+        let snapshots = backend.read_dir(Path::new("snapshots"))?;
+        assert_eq!(snapshots.len(), 1);
+        assert!(backend.remove_file(&snapshots[0]).is_err());
+        assert!(backend.write(&snapshots[0], b"garbage").is_err());
+
+        // Other users cannot access the repository
+        let backend = RestBackend::new(
+            format!("http://{addr}/alice/repo"),
+            Some(String::from("bob")),
+            Some(String::from("hunter2")),
+        );
+        assert!(!backend.root_exists());
+        assert!(backend.read(Path::new("manifest")).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_serve_stdio() -> Result<()> {
+        let tmp_dir = tempdir()?;
+        let tmp_path = tmp_dir.path();
+        let password = "mapachito";
+        let password_path = tmp_path.join("password");
+        std::fs::write(&password_path, password)?;
+
+        let backup_data_path = test_utils::get_test_data_path(BACKUP_DATA_PATH);
+        let backup_data_tmp_path = tmp_path.join("backup");
+        test_utils::extract_tar_xz_archive(&backup_data_path, &backup_data_tmp_path)?;
+
+        // The mapache binary is the reference helper
+        let repo_path = tmp_path.join("repo");
+        let global = GlobalArgs {
+            repo: format!(
+                "exec:'{}' serve --stdio '{}'",
+                env!("CARGO_BIN_EXE_mapache"),
+                repo_path.display()
+            ),
+            password_file: Some(password_path),
+            key: None,
+            quiet: true,
+            verbosity: None,
+            ssh_pubkey: None,
+            ssh_privatekey: None,
+            cache_dir: None,
+            no_cache: true,
+            retry: RetryArgs::default(),
+        };
+        set_global_opts_with_args(&global);
+
+        backup_and_restore(&global, &backup_data_tmp_path, &tmp_path.join("restore"))?;
+        assert!(repo_path.join("manifest").is_file());
+        assert_eq!(std::fs::read_dir(repo_path.join("snapshots"))?.count(), 1);
+
+        Ok(())
+    }
+
+    /// Errors of the stdio mode are reported on stderr, so they don't corrupt the protocol
+    #[test]
+    fn test_serve_stdio_error() -> Result<()> {
+        let tmp_dir = tempdir()?;
+
+        let mut child = Command::new(env!("CARGO_BIN_EXE_mapache"))
+            .arg("serve")
+            .arg("--stdio")
+            .arg(tmp_dir.path())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        // A header frame that is not valid JSON
+        let mut stdin = child.stdin.take().unwrap();
+        stdin.write_all(&7u32.to_be_bytes())?;
+        stdin.write_all(b"mapache")?;
+        drop(stdin);
+
+        let output = child.wait_with_output()?;
+        assert!(!output.status.success());
+        assert!(output.stdout.is_empty());
+        assert!(String::from_utf8_lossy(&output.stderr).contains("Invalid request header"));
+
+        Ok(())
+    }
+}
@@ -17,14 +17,27 @@
 #![cfg(test)]
 
 mod tests {
-    use std::path::PathBuf;
+    use std::{path::PathBuf, sync::Arc, time::Duration};
 
     use anyhow::{Context, Result};
     use mapache::{
-        commands::{self, GlobalArgs, UseSnapshot, cmd_restore, cmd_snapshot},
-        global::set_global_opts_with_args,
+        archiver::{Archiver, CHECKPOINT_TAG, SnapshotOptions},
+        backend::localfs::LocalFS,
+        commands::{
+            self, ChunkerArgs, CompressionArgs, FromRepoArgs, GlobalArgs, KdfArgs, RetryArgs,
+            UseSnapshot, cmd_init, cmd_restore, cmd_snapshot,
+        },
+        global::{BlobType, FileType, ID, SaveID, set_global_opts_with_args},
+        repository::{
+            self,
+            snapshot::{Snapshot, SnapshotStreamer},
+            storage::CompressionAlgorithm,
+        },
         restorer::Resolution,
+        ui::snapshot_progress::SnapshotProgressReporter,
+        utils,
     };
+    use rand::RngCore;
 
     use tempfile::tempdir;
 
@@ -56,6 +69,9 @@ mod tests {
             verbosity: None,
             ssh_pubkey: None,
             ssh_privatekey: None,
+            cache_dir: None,
+            no_cache: true,
+            retry: RetryArgs::default(),
         };
 
         // Init repo
@@ -77,6 +93,9 @@ mod tests {
             read_concurrency: 2,
             write_concurrency: 5,
             dry_run: false,
+            checkpoint_interval: None,
+            host: None,
+            compression: CompressionArgs::default(),
         };
         commands::cmd_snapshot::run(&global, &snapshot_args)
             .with_context(|| "Failed to run cmd_snapshot")?;
@@ -92,6 +111,7 @@ mod tests {
             strip_prefix: false,
             resolution: mapache::restorer::Resolution::Skip,
             no_verify: false,
+            host: None,
         };
         commands::cmd_restore::run(&global, &restore_args)
             .with_context(|| "Failed to run cmd_restore")?;
@@ -158,6 +178,9 @@ mod tests {
             verbosity: None,
             ssh_pubkey: None,
             ssh_privatekey: None,
+            cache_dir: None,
+            no_cache: true,
+            retry: RetryArgs::default(),
         };
 
         // Init repo
@@ -179,6 +202,9 @@ mod tests {
             read_concurrency: 2,
             write_concurrency: 5,
             dry_run: true,
+            checkpoint_interval: None,
+            host: None,
+            compression: CompressionArgs::default(),
         };
         commands::cmd_snapshot::run(&global, &snapshot_args)
             .with_context(|| "Failed to run cmd_snapshot")?;
@@ -202,6 +228,7 @@ mod tests {
             strip_prefix: false,
             resolution: mapache::restorer::Resolution::Skip,
             no_verify: false,
+            host: None,
         };
 
         let restore_result = commands::cmd_restore::run(&global, &restore_args);
@@ -233,6 +260,9 @@ mod tests {
             verbosity: None,
             ssh_pubkey: None,
             ssh_privatekey: None,
+            cache_dir: None,
+            no_cache: true,
+            retry: RetryArgs::default(),
         };
         set_global_opts_with_args(&global);
 
@@ -255,6 +285,9 @@ mod tests {
             read_concurrency: 2,
             write_concurrency: 5,
             dry_run: false,
+            checkpoint_interval: None,
+            host: None,
+            compression: CompressionArgs::default(),
         };
         commands::cmd_snapshot::run(&global, &snapshot_args)
             .with_context(|| "Failed to run cmd_snapshot")?;
@@ -270,6 +303,7 @@ mod tests {
             strip_prefix: false,
             resolution: Resolution::Skip,
             no_verify: false,
+            host: None,
         };
         commands::cmd_restore::run(&global, &restore_args)
             .with_context(|| "Failed to run cmd_restore")?;
@@ -335,6 +369,9 @@ mod tests {
             verbosity: None,
             ssh_pubkey: None,
             ssh_privatekey: None,
+            cache_dir: None,
+            no_cache: true,
+            retry: RetryArgs::default(),
         };
         set_global_opts_with_args(&global);
 
@@ -357,6 +394,9 @@ mod tests {
             read_concurrency: 2,
             write_concurrency: 5,
             dry_run: false,
+            checkpoint_interval: None,
+            host: None,
+            compression: CompressionArgs::default(),
         };
         commands::cmd_snapshot::run(&global, &snapshot_args)
             .with_context(|| "Failed to run cmd_snapshot")?;
@@ -376,6 +416,9 @@ mod tests {
             read_concurrency: 2,
             write_concurrency: 5,
             dry_run: false,
+            checkpoint_interval: None,
+            host: None,
+            compression: CompressionArgs::default(),
         };
         commands::cmd_snapshot::run(&global, &snapshot_args)
             .with_context(|| "Failed to run cmd_snapshot")?;
@@ -390,6 +433,7 @@ mod tests {
             strip_prefix: false,
             resolution: mapache::restorer::Resolution::Skip,
             no_verify: false,
+            host: None,
         };
         commands::cmd_restore::run(&global, &restore_args)
             .with_context(|| "Failed to run cmd_restore")?;
@@ -426,4 +470,561 @@ mod tests {
 
         Ok(())
     }
+
+    /// Snapshot using the chunker parameters chosen at init and a compression override
+    #[test]
+    fn test_snapshot_with_repository_params() -> Result<()> {
+        let tmp_dir = tempdir()?;
+        let tmp_path = tmp_dir.path();
+        let password = "mapachito";
+        let password_path = tmp_path.join("password");
+        std::fs::write(&password_path, password)?;
+
+        let backup_path = tmp_path.join("backup");
+        std::fs::create_dir(&backup_path)?;
+        let mut data = vec![0u8; 512 * 1024];
+        rand::rng().fill_bytes(&mut data);
+        std::fs::write(backup_path.join("random.bin"), &data)?;
+
+        let repo_path = tmp_path.join("repo");
+
+        let global = GlobalArgs {
+            repo: repo_path.to_string_lossy().to_string(),
+            password_file: Some(password_path),
+            key: None,
+            quiet: true,
+            verbosity: None,
+            ssh_pubkey: None,
+            ssh_privatekey: None,
+            cache_dir: None,
+            no_cache: true,
+            retry: RetryArgs::default(),
+        };
+        set_global_opts_with_args(&global);
+
+        // Init repo with small chunks
+        let init_args = cmd_init::CmdArgs {
+            repository_version: 3,
+            kdf: KdfArgs::default(),
+            compression: CompressionArgs::default(),
+            chunker: ChunkerArgs {
+                chunk_min_size: Some(4 * 1024),
+                chunk_avg_size: Some(16 * 1024),
+                chunk_max_size: Some(64 * 1024),
+                chunk_normalization: Some(2),
+            },
+            pack_size: None,
+            copy_params_from: None,
+            from: FromRepoArgs::default(),
+        };
+        commands::cmd_init::run(&global, &init_args).with_context(|| "Failed to run cmd_init")?;
+
+        // Run snapshot without compression
+        let snapshot_args = cmd_snapshot::CmdArgs {
+            paths: vec![backup_path.clone()],
+            exclude: None,
+            tags_str: String::new(),
+            description: None,
+            rescan: false,
+            parent: UseSnapshot::Latest,
+            read_concurrency: 2,
+            write_concurrency: 5,
+            dry_run: false,
+            checkpoint_interval: None,
+            host: None,
+            compression: CompressionArgs {
+                compression: Some(CompressionAlgorithm::None),
+                compression_level: None,
+                auto_compression: None,
+            },
+        };
+        commands::cmd_snapshot::run(&global, &snapshot_args)
+            .with_context(|| "Failed to run cmd_snapshot")?;
+
+        // The file was split in many chunks, all of them stored uncompressed.
+        // The override does not change the repository settings.
+        let backend = Arc::new(LocalFS::new(repo_path));
+        let (repo, _) = repository::try_open(Some(password.to_string()), None, backend)?;
+        assert_eq!(repo.chunker_params().avg_size, 16 * 1024);
+        assert_eq!(repo.compression().algorithm, CompressionAlgorithm::Zstd);
+        let index = repo.index();
+        let index = index.read();
+        let data_blobs: Vec<_> = index
+            .iter_ids()
+            .filter_map(|(id, _)| index.get(id))
+            .filter(|blob| blob.1 == BlobType::Data)
+            .collect();
+        assert!(data_blobs.len() > 8);
+        assert!(data_blobs.iter().all(|blob| blob.4));
+
+        // Run restore
+        let restore_path = tmp_path.join("restore");
+        let restore_args = cmd_restore::CmdArgs {
+            target: restore_path.clone(),
+            snapshot: UseSnapshot::Latest,
+            dry_run: false,
+            include: None,
+            exclude: None,
+            strip_prefix: false,
+            resolution: Resolution::Skip,
+            no_verify: false,
+            host: None,
+        };
+        commands::cmd_restore::run(&global, &restore_args)
+            .with_context(|| "Failed to run cmd_restore")?;
+
+        assert_eq!(
+            std::fs::read(restore_path.join("backup").join("random.bin"))?,
+            data
+        );
+
+        Ok(())
+    }
+
+    /// A snapshot interrupted before saving its index leaves packs that are not indexed.
+    /// The next snapshot reuses them instead of saving the blobs again.
+    #[test]
+    fn test_snapshot_resume() -> Result<()> {
+        let tmp_dir = tempdir()?;
+        let tmp_path = tmp_dir.path();
+        let password = "mapachito";
+        let password_path = tmp_path.join("password");
+        std::fs::write(&password_path, password)?;
+
+        let backup_data_path = test_utils::get_test_data_path(BACKUP_DATA_PATH);
+        let backup_data_tmp_path = tmp_path.join("backup");
+        test_utils::extract_tar_xz_archive(&backup_data_path, &backup_data_tmp_path)?;
+
+        let repo_path = tmp_path.join("repo");
+        let global = GlobalArgs {
+            repo: repo_path.to_string_lossy().to_string(),
+            password_file: Some(password_path),
+            key: None,
+            quiet: true,
+            verbosity: None,
+            ssh_pubkey: None,
+            ssh_privatekey: None,
+            cache_dir: None,
+            no_cache: true,
+            retry: RetryArgs::default(),
+        };
+
+        init_repo(password, repo_path.clone())?;
+
+        let snapshot_args = cmd_snapshot::CmdArgs {
+            paths: vec![backup_data_tmp_path.clone()],
+            exclude: None,
+            tags_str: String::new(),
+            description: None,
+            rescan: false,
+            parent: UseSnapshot::Latest,
+            read_concurrency: 2,
+            write_concurrency: 5,
+            dry_run: false,
+            checkpoint_interval: None,
+            host: None,
+            compression: CompressionArgs::default(),
+        };
+        commands::cmd_snapshot::run(&global, &snapshot_args)
+            .with_context(|| "Failed to run cmd_snapshot")?;
+
+        let list_files = |dir: PathBuf| -> Result<Vec<PathBuf>> {
+            let mut files = Vec::new();
+            for entry in walkdir(&dir)? {
+                if entry.is_file() {
+                    files.push(entry);
+                }
+            }
+            files.sort();
+            Ok(files)
+        };
+        let packs = list_files(repo_path.join("objects"))?;
+        assert!(!packs.is_empty());
+
+        // Simulate the interruption: the packs were saved but the index and snapshot were not
+        for file in list_files(repo_path.join("index"))? {
+            std::fs::remove_file(file)?;
+        }
+        for file in list_files(repo_path.join("snapshots"))? {
+            std::fs::remove_file(file)?;
+        }
+
+        commands::cmd_snapshot::run(&global, &snapshot_args)
+            .with_context(|| "Failed to run cmd_snapshot")?;
+
+        // No new packs were saved and the old packs are indexed again
+        assert_eq!(list_files(repo_path.join("objects"))?, packs);
+        assert!(!list_files(repo_path.join("index"))?.is_empty());
+
+        let restore_path = tmp_path.join("restore");
+        let restore_args = cmd_restore::CmdArgs {
+            target: restore_path.clone(),
+            snapshot: UseSnapshot::Latest,
+            dry_run: false,
+            include: None,
+            exclude: None,
+            strip_prefix: false,
+            resolution: Resolution::Skip,
+            no_verify: false,
+            host: None,
+        };
+        commands::cmd_restore::run(&global, &restore_args)
+            .with_context(|| "Failed to run cmd_restore")?;
+        assert_eq!(
+            std::fs::read(restore_path.join("backup").join("file.txt"))?,
+            std::fs::read(backup_data_tmp_path.join("file.txt"))?
+        );
+
+        Ok(())
+    }
+
+    /// Checkpoints are saved while the snapshot runs and removed when the final snapshot is saved
+    #[test]
+    fn test_snapshot_checkpoints() -> Result<()> {
+        let tmp_dir = tempdir()?;
+        let tmp_path = tmp_dir.path();
+        let password = "mapachito";
+        let password_path = tmp_path.join("password");
+        std::fs::write(&password_path, password)?;
+
+        let backup_data_path = test_utils::get_test_data_path(BACKUP_DATA_PATH);
+        let backup_data_tmp_path = tmp_path.join("backup");
+        test_utils::extract_tar_xz_archive(&backup_data_path, &backup_data_tmp_path)?;
+
+        let repo_path = tmp_path.join("repo");
+        let global = GlobalArgs {
+            repo: repo_path.to_string_lossy().to_string(),
+            password_file: Some(password_path),
+            key: None,
+            quiet: true,
+            verbosity: None,
+            ssh_pubkey: None,
+            ssh_privatekey: None,
+            cache_dir: None,
+            no_cache: true,
+            retry: RetryArgs::default(),
+        };
+
+        init_repo(password, repo_path.clone())?;
+
+        // Save a checkpoint after every processed item
+        let snapshot_args = cmd_snapshot::CmdArgs {
+            paths: vec![backup_data_tmp_path.clone()],
+            exclude: None,
+            tags_str: String::new(),
+            description: None,
+            rescan: false,
+            parent: UseSnapshot::Latest,
+            read_concurrency: 2,
+            write_concurrency: 5,
+            dry_run: false,
+            checkpoint_interval: Some(Duration::from_nanos(1)),
+            host: None,
+            compression: CompressionArgs::default(),
+        };
+        commands::cmd_snapshot::run(&global, &snapshot_args)
+            .with_context(|| "Failed to run cmd_snapshot")?;
+
+        // Only the final snapshot is left
+        let snapshots: Vec<PathBuf> = walkdir(&repo_path.join("snapshots"))?
+            .into_iter()
+            .filter(|path| path.is_file())
+            .collect();
+        assert_eq!(snapshots.len(), 1);
+
+        let restore_path = tmp_path.join("restore");
+        let restore_args = cmd_restore::CmdArgs {
+            target: restore_path.clone(),
+            snapshot: UseSnapshot::Latest,
+            dry_run: false,
+            include: None,
+            exclude: None,
+            strip_prefix: false,
+            resolution: Resolution::Skip,
+            no_verify: false,
+            host: None,
+        };
+        commands::cmd_restore::run(&global, &restore_args)
+            .with_context(|| "Failed to run cmd_restore")?;
+        assert_eq!(
+            std::fs::read(restore_path.join("backup").join("0/01/file01a.txt"))?,
+            std::fs::read(backup_data_tmp_path.join("0/01/file01a.txt"))?
+        );
+
+        // Run the archiver directly to inspect the last checkpoint before the final snapshot
+        // replaces it
+        let backend = Arc::new(LocalFS::new(repo_path.clone()));
+        let (repo, _) = repository::try_open(Some(password.to_string()), None, backend)?;
+        let source_paths = vec![backup_data_tmp_path.canonicalize()?];
+        let archiver = Archiver::new(
+            repo.clone(),
+            SnapshotOptions {
+                snapshot_root_path: utils::calculate_lcp(&source_paths, false),
+                absolute_source_paths: source_paths,
+                exclude_paths: Vec::new(),
+                parent_snapshot: None,
+                tags: Default::default(),
+                description: None,
+                hostname: utils::get_hostname(),
+                checkpoint_interval: Some(Duration::from_nanos(1)),
+            },
+            (2, 5),
+            Arc::new(SnapshotProgressReporter::new(0, 0, 2)),
+        );
+        let (_, checkpoint_id) = archiver.snapshot()?;
+        let checkpoint_id = checkpoint_id.expect("No checkpoint was saved");
+        assert!(
+            repo.load_snapshot(&checkpoint_id)?
+                .tags
+                .contains(CHECKPOINT_TAG)
+        );
+
+        // The checkpoint can be restored. It holds a subset of the files.
+        let checkpoint_restore_path = tmp_path.join("restore_checkpoint");
+        let restore_args = cmd_restore::CmdArgs {
+            target: checkpoint_restore_path.clone(),
+            snapshot: UseSnapshot::SnapshotId(checkpoint_id.to_hex()),
+            dry_run: false,
+            include: None,
+            exclude: None,
+            strip_prefix: false,
+            resolution: Resolution::Skip,
+            no_verify: false,
+            host: None,
+        };
+        commands::cmd_restore::run(&global, &restore_args)
+            .with_context(|| "Failed to restore the checkpoint")?;
+        let restored_backup_path = checkpoint_restore_path.join("backup");
+        for path in walkdir(&restored_backup_path)? {
+            if path.is_file() {
+                let source_path =
+                    backup_data_tmp_path.join(path.strip_prefix(&restored_backup_path)?);
+                assert_eq!(std::fs::read(&path)?, std::fs::read(source_path)?);
+            }
+        }
+
+        // A snapshot of other paths uses the checkpoint as parent, but does not supersede it
+        let other_args = cmd_snapshot::CmdArgs {
+            paths: vec![backup_data_tmp_path.join("0")],
+            checkpoint_interval: None,
+            ..snapshot_args
+        };
+        commands::cmd_snapshot::run(&global, &other_args)
+            .with_context(|| "Failed to run cmd_snapshot")?;
+        let (repo, _) = repository::try_open(
+            Some(password.to_string()),
+            None,
+            Arc::new(LocalFS::new(repo_path.clone())),
+        )?;
+        assert!(repo.load_snapshot(&checkpoint_id).is_ok());
+        let latest = SnapshotStreamer::new(repo.clone())?.latest().unwrap();
+        assert_eq!(latest.1.parent, Some(checkpoint_id.clone()));
+
+        // A full scan of the same paths supersedes the checkpoint left behind
+        let rescan_args = cmd_snapshot::CmdArgs {
+            paths: vec![backup_data_tmp_path.clone()],
+            rescan: true,
+            ..other_args
+        };
+        commands::cmd_snapshot::run(&global, &rescan_args)
+            .with_context(|| "Failed to run cmd_snapshot")?;
+        let (repo, _) = repository::try_open(
+            Some(password.to_string()),
+            None,
+            Arc::new(LocalFS::new(repo_path)),
+        )?;
+        assert!(repo.load_snapshot(&checkpoint_id).is_err());
+        assert_eq!(repo.list_snapshot_ids()?.len(), 3);
+
+        Ok(())
+    }
+
+    /// Snapshots record their host. The parent and 'latest' are resolved per host.
+    #[test]
+    fn test_snapshot_hosts() -> Result<()> {
+        let tmp_dir = tempdir()?;
+        let tmp_path = tmp_dir.path();
+        let password = "mapachito";
+        let password_path = tmp_path.join("password");
+        std::fs::write(&password_path, password)?;
+
+        let backup_data_path = test_utils::get_test_data_path(BACKUP_DATA_PATH);
+        let backup_data_tmp_path = tmp_path.join("backup");
+        test_utils::extract_tar_xz_archive(&backup_data_path, &backup_data_tmp_path)?;
+
+        let repo_path = tmp_path.join("repo");
+        let global = GlobalArgs {
+            repo: repo_path.to_string_lossy().to_string(),
+            password_file: Some(password_path),
+            key: None,
+            quiet: true,
+            verbosity: None,
+            ssh_pubkey: None,
+            ssh_privatekey: None,
+            cache_dir: None,
+            no_cache: true,
+            retry: RetryArgs::default(),
+        };
+
+        init_repo(password, repo_path.clone())?;
+
+        let snapshot_args = |dir: &str, host: &str| cmd_snapshot::CmdArgs {
+            paths: vec![backup_data_tmp_path.join(dir)],
+            exclude: None,
+            tags_str: String::new(),
+            description: Some(format!("{dir}@{host}")),
+            rescan: false,
+            parent: UseSnapshot::Latest,
+            read_concurrency: 2,
+            write_concurrency: 5,
+            dry_run: false,
+            checkpoint_interval: None,
+            host: Some(host.to_string()),
+            compression: CompressionArgs::default(),
+        };
+        for (dir, host) in [("0", "alpha"), ("1", "beta"), ("0", "alpha")] {
+            commands::cmd_snapshot::run(&global, &snapshot_args(dir, host))
+                .with_context(|| "Failed to run cmd_snapshot")?;
+        }
+
+        let backend = Arc::new(LocalFS::new(repo_path));
+        let (repo, _) = repository::try_open(Some(password.to_string()), None, backend)?;
+        let mut snapshots: Vec<(ID, Snapshot)> = SnapshotStreamer::new(repo)?.collect();
+        snapshots.sort_by_key(|(_, snapshot)| snapshot.timestamp);
+        assert_eq!(snapshots.len(), 3);
+        assert!(snapshots.iter().all(|(_, snapshot)| {
+            snapshot.username.is_some()
+                && snapshot.program_version.as_deref() == Some(env!("CARGO_PKG_VERSION"))
+                && snapshot.summary.duration_secs.is_some()
+        }));
+
+        // Each host uses its own snapshots as parent
+        assert!(snapshots[0].1.has_host("alpha"));
+        assert!(snapshots[1].1.has_host("beta"));
+        assert_eq!(snapshots[1].1.parent, None);
+        assert_eq!(snapshots[2].1.parent.as_ref(), Some(&snapshots[0].0));
+
+        // The latest snapshot of another host
+        let restore_path = tmp_path.join("restore");
+        let restore_args = cmd_restore::CmdArgs {
+            target: restore_path.clone(),
+            snapshot: UseSnapshot::Latest,
+            dry_run: false,
+            include: None,
+            exclude: None,
+            strip_prefix: false,
+            resolution: Resolution::Skip,
+            no_verify: false,
+            host: Some(String::from("beta")),
+        };
+        commands::cmd_restore::run(&global, &restore_args)
+            .with_context(|| "Failed to run cmd_restore")?;
+        assert!(restore_path.join("1/10/file10.txt").exists());
+        assert!(!restore_path.join("0").exists());
+
+        Ok(())
+    }
+
+    /// Snapshots saved before hostnames were recorded are used as parent if there are no
+    /// snapshots of the host
+    #[test]
+    fn test_snapshot_parent_without_host() -> Result<()> {
+        let tmp_dir = tempdir()?;
+        let tmp_path = tmp_dir.path();
+        let password = "mapachito";
+        let password_path = tmp_path.join("password");
+        std::fs::write(&password_path, password)?;
+
+        let backup_data_path = test_utils::get_test_data_path(BACKUP_DATA_PATH);
+        let backup_data_tmp_path = tmp_path.join("backup");
+        test_utils::extract_tar_xz_archive(&backup_data_path, &backup_data_tmp_path)?;
+
+        let repo_path = tmp_path.join("repo");
+        let global = GlobalArgs {
+            repo: repo_path.to_string_lossy().to_string(),
+            password_file: Some(password_path),
+            key: None,
+            quiet: true,
+            verbosity: None,
+            ssh_pubkey: None,
+            ssh_privatekey: None,
+            cache_dir: None,
+            no_cache: true,
+            retry: RetryArgs::default(),
+        };
+
+        init_repo(password, repo_path.clone())?;
+
+        let snapshot_args = cmd_snapshot::CmdArgs {
+            paths: vec![backup_data_tmp_path.clone()],
+            exclude: None,
+            tags_str: String::new(),
+            description: None,
+            rescan: false,
+            parent: UseSnapshot::Latest,
+            read_concurrency: 2,
+            write_concurrency: 5,
+            dry_run: false,
+            checkpoint_interval: None,
+            host: Some(String::from("alpha")),
+            compression: CompressionArgs::default(),
+        };
+        let backend = Arc::new(LocalFS::new(repo_path));
+        let latest_snapshot = || -> Result<(ID, Snapshot)> {
+            let (repo, _) =
+                repository::try_open(Some(password.to_string()), None, backend.clone())?;
+            Ok(SnapshotStreamer::new(repo)?.latest().unwrap())
+        };
+
+        // Remove the hostname of the first snapshot, as if it was saved by an older version.
+        // It is also moved to the future so it is the most recent snapshot.
+        commands::cmd_snapshot::run(&global, &snapshot_args)
+            .with_context(|| "Failed to run cmd_snapshot")?;
+        let (id, mut snapshot) = latest_snapshot()?;
+        snapshot.hostname = None;
+        snapshot.timestamp += chrono::Duration::days(1);
+        let (repo, _) = repository::try_open(Some(password.to_string()), None, backend.clone())?;
+        let (no_host_id, _, _) = repo.save_file(
+            FileType::Snapshot,
+            serde_json::to_string(&snapshot)?.as_bytes(),
+            SaveID::CalculateID,
+        )?;
+        repo.remove_snapshot(&id)?;
+        drop(repo);
+
+        // With no snapshots of the host, the snapshot without a hostname is the parent
+        commands::cmd_snapshot::run(&global, &snapshot_args)
+            .with_context(|| "Failed to run cmd_snapshot")?;
+        let (repo, _) = repository::try_open(Some(password.to_string()), None, backend.clone())?;
+        let mut snapshots: Vec<(ID, Snapshot)> = SnapshotStreamer::new(repo)?
+            .filter(|(_, snapshot)| snapshot.has_host("alpha"))
+            .collect();
+        assert_eq!(snapshots.len(), 1);
+        let (host_id, host_snapshot) = snapshots.pop().unwrap();
+        assert_eq!(host_snapshot.parent, Some(no_host_id.clone()));
+
+        // Snapshots of the host are preferred, even if older
+        commands::cmd_snapshot::run(&global, &snapshot_args)
+            .with_context(|| "Failed to run cmd_snapshot")?;
+        let (repo, _) = repository::try_open(Some(password.to_string()), None, backend)?;
+        let (_, snapshot) = SnapshotStreamer::new(repo)?
+            .find(|(id, snapshot)| snapshot.has_host("alpha") && *id != host_id)
+            .unwrap();
+        assert_eq!(snapshot.parent, Some(host_id));
+
+        Ok(())
+    }
+
+    /// Lists all paths under a directory, recursively
+    fn walkdir(dir: &PathBuf) -> Result<Vec<PathBuf>> {
+        let mut paths = Vec::new();
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                paths.extend(walkdir(&path)?);
+            }
+            paths.push(path);
+        }
+        Ok(paths)
+    }
 }
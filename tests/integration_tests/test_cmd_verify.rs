@@ -0,0 +1,115 @@
+// mapache is an incremental backup tool
+// Copyright (C) 2025  Javier Lancha Vázquez <javier.lancha@gmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+#![cfg(test)]
+
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use anyhow::{Context, Result};
+    use mapache::{
+        commands::{
+            self, CompressionArgs, GlobalArgs, RetryArgs, UseSnapshot, cmd_snapshot, cmd_verify,
+        },
+        global::set_global_opts_with_args,
+    };
+    use tempfile::tempdir;
+
+    use crate::{
+        integration_tests::{BACKUP_DATA_PATH, init_repo},
+        test_utils,
+    };
+
+    fn find_file(dir: &Path) -> Result<Option<PathBuf>> {
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_file() {
+                return Ok(Some(path));
+            } else if let Some(file) = find_file(&path)? {
+                return Ok(Some(file));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Verify fails when a pack is damaged
+    #[test]
+    fn test_verify_damaged_pack() -> Result<()> {
+        let tmp_dir = tempdir()?;
+        let tmp_path = tmp_dir.path();
+        let password = "mapachito";
+        let password_path = tmp_path.join("password");
+        std::fs::write(&password_path, password)?;
+
+        let backup_data_path = test_utils::get_test_data_path(BACKUP_DATA_PATH);
+        let backup_data_tmp_path = tmp_path.join("backup");
+        test_utils::extract_tar_xz_archive(&backup_data_path, &backup_data_tmp_path)?;
+
+        let repo_path = tmp_path.join("repo");
+
+        let global = GlobalArgs {
+            repo: repo_path.to_string_lossy().to_string(),
+            password_file: Some(password_path),
+            key: None,
+            quiet: true,
+            verbosity: None,
+            ssh_pubkey: None,
+            ssh_privatekey: None,
+            cache_dir: None,
+            no_cache: true,
+            retry: RetryArgs::default(),
+        };
+        set_global_opts_with_args(&global);
+
+        init_repo(password, repo_path.clone())?;
+
+        let snapshot_args = cmd_snapshot::CmdArgs {
+            paths: vec![backup_data_tmp_path.clone()],
+            exclude: None,
+            tags_str: String::new(),
+            description: None,
+            rescan: false,
+            parent: UseSnapshot::Latest,
+            read_concurrency: 2,
+            write_concurrency: 5,
+            dry_run: false,
+            checkpoint_interval: None,
+            host: None,
+            compression: CompressionArgs::default(),
+        };
+        commands::cmd_snapshot::run(&global, &snapshot_args)
+            .with_context(|| "Failed to run cmd_snapshot")?;
+
+        let verify_args = cmd_verify::CmdArgs {
+            snapshot_data: false,
+            unreferenced: false,
+            read_data_subset: Some("1/1".parse()?),
+        };
+        commands::cmd_verify::run(&global, &verify_args)
+            .with_context(|| "Failed to run cmd_verify")?;
+
+        // Flip a byte in the middle of a pack
+        let pack_path = find_file(&repo_path.join("objects"))?.expect("No packs in repository");
+        let mut pack = std::fs::read(&pack_path)?;
+        let middle = pack.len() / 2;
+        pack[middle] ^= 0xff;
+        std::fs::write(&pack_path, pack)?;
+
+        assert!(commands::cmd_verify::run(&global, &verify_args).is_err());
+
+        Ok(())
+    }
+}